@@ -0,0 +1,295 @@
+//! Downsampling: aggregate samples into time buckets
+//!
+//! [`StorageEngine::aggregate`](crate::StorageEngine::aggregate) reads the
+//! samples of a key or a key prefix in a time window (see
+//! [`StorageEngine::scan_time_range`](crate::StorageEngine::scan_time_range)),
+//! parses their values as decimal numbers and folds them into fixed-width
+//! buckets, so a dashboard gets one point per bucket instead of every raw
+//! sample. Values that are not numbers are skipped. Rollups
+//! ([`crate::rollup`]) keep the same per-bucket summaries up to date at
+//! ingest time.
+
+use crate::{prefix_successor, Key, StorageError, Timestamp};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// How the samples of a bucket are combined
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aggregation {
+    Min,
+    Max,
+    Avg,
+    Sum,
+    Count,
+}
+
+impl FromStr for Aggregation {
+    type Err = StorageError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_ascii_lowercase().as_str() {
+            "min" => Ok(Aggregation::Min),
+            "max" => Ok(Aggregation::Max),
+            "avg" => Ok(Aggregation::Avg),
+            "sum" => Ok(Aggregation::Sum),
+            "count" => Ok(Aggregation::Count),
+            _ => Err(StorageError::InvalidFormat(format!(
+                "Unknown aggregation: {}",
+                name
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for Aggregation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Aggregation::Min => "min",
+            Aggregation::Max => "max",
+            Aggregation::Avg => "avg",
+            Aggregation::Sum => "sum",
+            Aggregation::Count => "count",
+        };
+        f.write_str(name)
+    }
+}
+
+/// What to aggregate: keys in `[start, end)`, samples in `[t_start, t_end)`
+#[derive(Debug, Clone)]
+pub struct AggregateQuery {
+    pub start: Key,
+    pub end: Key,
+    pub t_start: Timestamp,
+    pub t_end: Timestamp,
+    /// Bucket width in nanoseconds; 0 puts the whole window in one bucket
+    pub bucket_width: Timestamp,
+    pub aggregation: Aggregation,
+}
+
+impl AggregateQuery {
+    /// Aggregate the samples of a single key
+    pub fn key(key: &[u8], aggregation: Aggregation) -> Self {
+        let mut end = key.to_vec();
+        end.push(0);
+        Self::range(key.to_vec(), end, aggregation)
+    }
+
+    /// Aggregate the samples of every key starting with `prefix`, as one series
+    ///
+    /// The prefix must not be empty.
+    pub fn prefix(prefix: &[u8], aggregation: Aggregation) -> Self {
+        Self::range(prefix.to_vec(), prefix_successor(prefix), aggregation)
+    }
+
+    fn range(start: Key, end: Key, aggregation: Aggregation) -> Self {
+        AggregateQuery {
+            start,
+            end,
+            t_start: 0,
+            t_end: Timestamp::MAX,
+            bucket_width: 0,
+            aggregation,
+        }
+    }
+
+    pub fn with_time_range(mut self, t_start: Timestamp, t_end: Timestamp) -> Self {
+        self.t_start = t_start;
+        self.t_end = t_end;
+        self
+    }
+
+    pub fn with_bucket_width(mut self, bucket_width: Timestamp) -> Self {
+        self.bucket_width = bucket_width;
+        self
+    }
+
+    /// Start of the bucket holding `timestamp`
+    ///
+    /// Buckets are aligned to multiples of the width since the epoch, so
+    /// the first and last one may be cut short by the window.
+    fn bucket_of(&self, timestamp: Timestamp) -> Timestamp {
+        if self.bucket_width == 0 {
+            self.t_start
+        } else {
+            timestamp - timestamp % self.bucket_width
+        }
+    }
+}
+
+/// One bucket of an aggregation
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bucket {
+    /// Start of the bucket, in nanoseconds
+    pub start: Timestamp,
+    /// Numeric samples that fell in the bucket
+    pub count: u64,
+    /// Aggregated value
+    pub value: f64,
+}
+
+/// Count, sum, min and max of a set of samples
+///
+/// Every [`Aggregation`] can be computed from it, and two summaries combine
+/// into the summary of both sets, which is what rollups store per bucket.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Summary {
+    pub(crate) count: u64,
+    pub(crate) sum: f64,
+    pub(crate) min: f64,
+    pub(crate) max: f64,
+}
+
+impl Summary {
+    /// Summary of no samples
+    pub(crate) fn empty() -> Self {
+        Summary {
+            count: 0,
+            sum: 0.0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+        }
+    }
+
+    pub(crate) fn add(&mut self, number: f64) {
+        self.merge(&Summary {
+            count: 1,
+            sum: number,
+            min: number,
+            max: number,
+        });
+    }
+
+    pub(crate) fn merge(&mut self, other: &Summary) {
+        self.count += other.count;
+        self.sum += other.sum;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+    }
+
+    fn value(&self, aggregation: Aggregation) -> f64 {
+        match aggregation {
+            Aggregation::Min => self.min,
+            Aggregation::Max => self.max,
+            Aggregation::Avg => self.sum / self.count as f64,
+            Aggregation::Sum => self.sum,
+            Aggregation::Count => self.count as f64,
+        }
+    }
+}
+
+/// Parse a sample value as a decimal number
+pub(crate) fn parse_number(value: &[u8]) -> Option<f64> {
+    std::str::from_utf8(value)
+        .ok()
+        .and_then(|text| text.trim().parse::<f64>().ok())
+}
+
+/// Folds samples into the buckets of a query
+#[derive(Debug)]
+pub(crate) struct Aggregator<'a> {
+    query: &'a AggregateQuery,
+    buckets: BTreeMap<Timestamp, Summary>,
+}
+
+impl<'a> Aggregator<'a> {
+    pub(crate) fn new(query: &'a AggregateQuery) -> Self {
+        Aggregator {
+            query,
+            buckets: BTreeMap::new(),
+        }
+    }
+
+    /// Add one sample; values that are not numbers are skipped
+    pub(crate) fn add(&mut self, timestamp: Timestamp, value: &[u8]) {
+        if let Some(number) = parse_number(value) {
+            let start = self.query.bucket_of(timestamp);
+            self.buckets
+                .entry(start)
+                .or_insert_with(Summary::empty)
+                .add(number);
+        }
+    }
+
+    /// Add the summary of samples starting at `timestamp`
+    ///
+    /// The samples must all fall in the bucket holding `timestamp`.
+    pub(crate) fn add_summary(&mut self, timestamp: Timestamp, summary: &Summary) {
+        let start = self.query.bucket_of(timestamp);
+        self.buckets
+            .entry(start)
+            .or_insert_with(Summary::empty)
+            .merge(summary);
+    }
+
+    /// The non-empty buckets, oldest first
+    pub(crate) fn finish(self) -> Vec<Bucket> {
+        let aggregation = self.query.aggregation;
+        self.buckets
+            .into_iter()
+            .filter(|(_, summary)| summary.count > 0)
+            .map(|(start, summary)| Bucket {
+                start,
+                count: summary.count,
+                value: summary.value(aggregation),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn aggregate(aggregation: Aggregation, samples: &[(Timestamp, &str)]) -> Vec<(u64, f64)> {
+        let query = AggregateQuery::key(b"cpu", aggregation).with_bucket_width(100);
+        let mut aggregator = Aggregator::new(&query);
+        for (timestamp, value) in samples {
+            aggregator.add(*timestamp, value.as_bytes());
+        }
+        aggregator
+            .finish()
+            .into_iter()
+            .map(|bucket| (bucket.start, bucket.value))
+            .collect()
+    }
+
+    #[test]
+    fn test_aggregations_over_buckets() {
+        let samples = [
+            (100, "1"),
+            (150, "3"),
+            (199, "not a number"),
+            (230, "2.5"),
+            (420, " 4 "),
+        ];
+
+        assert_eq!(
+            aggregate(Aggregation::Avg, &samples),
+            vec![(100, 2.0), (200, 2.5), (400, 4.0)]
+        );
+        assert_eq!(
+            aggregate(Aggregation::Sum, &samples),
+            vec![(100, 4.0), (200, 2.5), (400, 4.0)]
+        );
+        assert_eq!(
+            aggregate(Aggregation::Min, &samples),
+            vec![(100, 1.0), (200, 2.5), (400, 4.0)]
+        );
+        assert_eq!(
+            aggregate(Aggregation::Max, &samples),
+            vec![(100, 3.0), (200, 2.5), (400, 4.0)]
+        );
+        assert_eq!(
+            aggregate(Aggregation::Count, &samples),
+            vec![(100, 2.0), (200, 1.0), (400, 1.0)]
+        );
+    }
+
+    #[test]
+    fn test_parse_aggregation() {
+        assert_eq!("AVG".parse::<Aggregation>().unwrap(), Aggregation::Avg);
+        assert_eq!(Aggregation::Count.to_string(), "count");
+        assert!("median".parse::<Aggregation>().is_err());
+    }
+}
@@ -0,0 +1,89 @@
+//! Atomic multi-key writes
+//!
+//! A [`WriteBatch`] collects puts, deletes and merges that
+//! [`StorageEngine::write`](crate::StorageEngine::write) applies
+//! all-or-nothing: the batch is logged as one WAL record group and goes into
+//! the MemTable in a single step.
+
+use crate::{Key, Record, Value};
+
+/// Ordered list of puts, deletes and merges applied atomically
+///
+/// Operations are applied in the order they were added, so a later write to
+/// the same key wins.
+///
+/// ```ignore
+/// let mut batch = WriteBatch::new();
+/// batch.put(b"cpu:host1".to_vec(), b"42".to_vec());
+/// batch.put(b"mem:host1".to_vec(), b"1024".to_vec());
+/// batch.delete(b"cpu:host2".to_vec());
+/// engine.write(batch)?;
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct WriteBatch {
+    records: Vec<Record>,
+}
+
+impl WriteBatch {
+    pub fn new() -> Self {
+        WriteBatch::default()
+    }
+
+    /// Queue a put
+    pub fn put(&mut self, key: Key, value: Value) {
+        // Timestamp and sequence number are assigned by the engine
+        self.records.push(Record::put(key, value, 0));
+    }
+
+    /// Queue a delete
+    pub fn delete(&mut self, key: Key) {
+        self.records.push(Record::delete(key, 0));
+    }
+
+    /// Queue a merge operand (see [`StorageEngine::merge`](crate::StorageEngine::merge))
+    pub fn merge(&mut self, key: Key, operand: Value) {
+        self.records.push(Record::merge(key, operand, 0));
+    }
+
+    /// Number of queued operations
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.records.clear();
+    }
+
+    pub(crate) fn into_records(self) -> Vec<Record> {
+        self.records
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::OpType;
+
+    #[test]
+    fn test_batch_keeps_operation_order() {
+        let mut batch = WriteBatch::new();
+        assert!(batch.is_empty());
+
+        batch.put(b"a".to_vec(), b"1".to_vec());
+        batch.delete(b"b".to_vec());
+        batch.put(b"a".to_vec(), b"2".to_vec());
+        assert_eq!(batch.len(), 3);
+
+        let records = batch.clone().into_records();
+        let ops: Vec<OpType> = records.iter().map(|r| r.op).collect();
+        assert_eq!(ops, vec![OpType::Put, OpType::Delete, OpType::Put]);
+        assert_eq!(records[2].value, b"2");
+
+        batch.clear();
+        assert!(batch.is_empty());
+    }
+}
@@ -2,6 +2,7 @@
 //!
 //! Defines all CLI commands and arguments using clap
 
+use cityhall::CompressionType;
 use clap::{Parser, Subcommand};
 use std::path::PathBuf;
 
@@ -31,12 +32,28 @@ pub enum Commands {
         #[arg(long, default_value = "1048576")]
         wal_buffer_size: usize,
 
+        /// SSTable block compression: none, snappy, lz4 or zstd
+        #[arg(long, default_value = "snappy")]
+        compression: CompressionType,
+
+        /// Compression of compaction outputs holding the oldest data (default: --compression)
+        #[arg(long)]
+        bottommost_compression: Option<CompressionType>,
+
+        /// Largest Zstd dictionary to train for each Zstd compaction output, in bytes (0 = none)
+        #[arg(long, default_value = "0")]
+        zstd_dictionary_size: usize,
+
+        /// Keep every sample of each key instead of only its newest value, so AGG can downsample history
+        #[arg(long)]
+        series_mode: bool,
+
         /// Configuration file (TOML or JSON)
         #[arg(long, short = 'c')]
         config: Option<PathBuf>,
     },
 
-    /// Client commands (put, get, delete, metrics)
+    /// Client commands (put, get, delete, delete-range, delete-prefix, cas, setnx, setex, incrby, metrics)
     Client {
         /// Server address (host:port)
         #[arg(long, short = 'a', default_value = "127.0.0.1:7878")]
@@ -57,6 +74,10 @@ pub enum ClientCommand {
 
         /// Value to store
         value: String,
+
+        /// Unix timestamp (nanoseconds) to store the value at, for backfill
+        #[arg(long, short = 't')]
+        timestamp: Option<u64>,
     },
 
     /// Get a value by key
@@ -71,6 +92,84 @@ pub enum ClientCommand {
         key: String,
     },
 
+    /// Delete every key in [start, end)
+    DeleteRange {
+        /// First key to delete
+        start: String,
+
+        /// First key past the range (not deleted)
+        end: String,
+    },
+
+    /// Delete every key starting with a prefix
+    DeletePrefix {
+        /// Key prefix to delete
+        prefix: String,
+    },
+
+    /// Set a key only if its current value matches (compare-and-swap)
+    Cas {
+        /// Key to update
+        key: String,
+
+        /// Value the key must currently hold
+        expected: String,
+
+        /// Value to store
+        new: String,
+    },
+
+    /// Set a key only if it does not exist yet
+    Setnx {
+        /// Key to store
+        key: String,
+
+        /// Value to store
+        value: String,
+    },
+
+    /// Store a key-value pair that expires after a number of seconds
+    Setex {
+        /// Key to store
+        key: String,
+
+        /// Seconds until the key expires
+        seconds: u64,
+
+        /// Value to store
+        value: String,
+    },
+
+    /// Add to a counter without reading it
+    Incrby {
+        /// Counter key
+        key: String,
+
+        /// Amount to add
+        delta: u64,
+    },
+
+    /// Aggregate the samples of a key (or `prefix*`) into time buckets
+    Agg {
+        /// Key, or a prefix followed by `*` to aggregate every key under it
+        key: String,
+
+        /// Aggregation: min, max, avg, sum or count
+        function: String,
+
+        /// Start of the window, Unix nanoseconds (inclusive)
+        #[arg(long)]
+        start: u64,
+
+        /// End of the window, Unix nanoseconds (exclusive)
+        #[arg(long)]
+        end: u64,
+
+        /// Bucket width in nanoseconds (0 = one bucket for the whole window)
+        #[arg(long, default_value_t = 0)]
+        bucket: u64,
+    },
+
     /// Print live metrics from the running server
     Metrics {
         /// Dashboard HTTP address
@@ -106,12 +205,56 @@ mod tests {
         ]);
 
         match cli.command {
-            Commands::Server { data_dir, port, .. } => {
+            Commands::Server {
+                data_dir,
+                port,
+                compression,
+                bottommost_compression,
+                zstd_dictionary_size,
+                series_mode,
+                ..
+            } => {
                 assert_eq!(data_dir, PathBuf::from("/data/server"));
                 assert_eq!(port, 8000);
+                assert_eq!(compression, CompressionType::Snappy);
+                assert_eq!(bottommost_compression, None);
+                assert_eq!(zstd_dictionary_size, 0);
+                assert!(!series_mode);
+            }
+            _ => panic!("Expected Server command"),
+        }
+    }
+
+    #[test]
+    fn test_parse_server_compression() {
+        let cli = Cli::parse_from(&[
+            "cityhall",
+            "server",
+            "--compression",
+            "lz4",
+            "--bottommost-compression",
+            "zstd",
+            "--zstd-dictionary-size",
+            "16384",
+            "--series-mode",
+        ]);
+
+        match cli.command {
+            Commands::Server {
+                compression,
+                bottommost_compression,
+                zstd_dictionary_size,
+                series_mode,
+                ..
+            } => {
+                assert_eq!(compression, CompressionType::Lz4);
+                assert_eq!(bottommost_compression, Some(CompressionType::Zstd));
+                assert_eq!(zstd_dictionary_size, 16384);
+                assert!(series_mode);
             }
             _ => panic!("Expected Server command"),
         }
+        assert!(Cli::try_parse_from(&["cityhall", "server", "--compression", "gzip"]).is_err());
     }
 
     #[test]
@@ -120,9 +263,37 @@ mod tests {
 
         match cli.command {
             Commands::Client { command, .. } => match command {
-                ClientCommand::Put { key, value } => {
+                ClientCommand::Put {
+                    key,
+                    value,
+                    timestamp,
+                } => {
                     assert_eq!(key, "test.key");
                     assert_eq!(value, "test_value");
+                    assert_eq!(timestamp, None);
+                }
+                _ => panic!("Expected Put command"),
+            },
+            _ => panic!("Expected Client command"),
+        }
+    }
+
+    #[test]
+    fn test_parse_client_put_with_timestamp() {
+        let cli = Cli::parse_from(&[
+            "cityhall",
+            "client",
+            "put",
+            "sensor.temp",
+            "21.5",
+            "--timestamp",
+            "1700000000000000000",
+        ]);
+
+        match cli.command {
+            Commands::Client { command, .. } => match command {
+                ClientCommand::Put { timestamp, .. } => {
+                    assert_eq!(timestamp, Some(1_700_000_000_000_000_000));
                 }
                 _ => panic!("Expected Put command"),
             },
@@ -130,6 +301,43 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_client_agg() {
+        let cli = Cli::parse_from(&[
+            "cityhall",
+            "client",
+            "agg",
+            "system.cpu.load",
+            "avg",
+            "--start",
+            "1700000000000000000",
+            "--end",
+            "1700086400000000000",
+            "--bucket",
+            "300000000000",
+        ]);
+
+        match cli.command {
+            Commands::Client { command, .. } => match command {
+                ClientCommand::Agg {
+                    key,
+                    function,
+                    start,
+                    end,
+                    bucket,
+                } => {
+                    assert_eq!(key, "system.cpu.load");
+                    assert_eq!(function, "avg");
+                    assert_eq!(start, 1_700_000_000_000_000_000);
+                    assert_eq!(end, 1_700_086_400_000_000_000);
+                    assert_eq!(bucket, 300_000_000_000);
+                }
+                _ => panic!("Expected Agg command"),
+            },
+            _ => panic!("Expected Client command"),
+        }
+    }
+
     #[test]
     fn test_parse_client_get() {
         let cli = Cli::parse_from(&[
@@ -155,6 +363,39 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_client_delete_range() {
+        let cli = Cli::parse_from(&["cityhall", "client", "delete-range", "a", "m"]);
+
+        match cli.command {
+            Commands::Client { command, .. } => match command {
+                ClientCommand::DeleteRange { start, end } => {
+                    assert_eq!(start, "a");
+                    assert_eq!(end, "m");
+                }
+                _ => panic!("Expected DeleteRange command"),
+            },
+            _ => panic!("Expected Client command"),
+        }
+    }
+
+    #[test]
+    fn test_parse_client_cas() {
+        let cli = Cli::parse_from(&["cityhall", "client", "cas", "dev1", "old", "new"]);
+
+        match cli.command {
+            Commands::Client { command, .. } => match command {
+                ClientCommand::Cas { key, expected, new } => {
+                    assert_eq!(key, "dev1");
+                    assert_eq!(expected, "old");
+                    assert_eq!(new, "new");
+                }
+                _ => panic!("Expected Cas command"),
+            },
+            _ => panic!("Expected Client command"),
+        }
+    }
+
     #[test]
     fn test_parse_client_metrics() {
         let cli = Cli::parse_from(&["cityhall", "client", "metrics"]);
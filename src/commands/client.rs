@@ -1,30 +1,22 @@
 //! Client command implementation
 //!
-//! Provides PUT, GET, DELETE, and METRICS operations against a running CityHall server.
+//! Provides PUT (and PUTAT), GET, DELETE, DELETE_RANGE, DELETE_PREFIX, CAS, SETNX, SETEX,
+//! INCRBY, AGG and METRICS operations against a running CityHall server.
 
 use cityhall::Result;
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::net::TcpStream;
 
 /// Execute a PUT command: store a key-value pair on the server
-pub async fn put(addr: &str, key: String, value: String) -> Result<()> {
-    let mut stream = TcpStream::connect(addr).await?;
-    let command = format!("PUT {} {}\n", key, value);
-    stream.write_all(command.as_bytes()).await?;
-
-    let mut reader = BufReader::new(&mut stream);
-    let mut response = String::new();
-    reader.read_line(&mut response).await?;
-
-    let response = response.trim();
-    if response == "OK" {
-        println!("OK");
-    } else {
-        eprintln!("{}", response);
-        std::process::exit(1);
-    }
-
-    Ok(())
+///
+/// With a timestamp it sends PUTAT instead, and the value is stored at that
+/// time instead of now.
+pub async fn put(addr: &str, key: String, value: String, timestamp: Option<u64>) -> Result<()> {
+    let command = match timestamp {
+        Some(timestamp) => format!("PUTAT {} {} {}\n", key, timestamp, value),
+        None => format!("PUT {} {}\n", key, value),
+    };
+    send_expecting_ok(addr, command).await
 }
 
 /// Execute a GET command: retrieve a value by key from the server
@@ -51,22 +43,99 @@ pub async fn get(addr: &str, key: String) -> Result<()> {
     Ok(())
 }
 
-/// Execute a DELETE command.
-///
-/// DELETE is defined in the protocol but requires tombstone support in the
-/// storage engine, which is not yet implemented. The server will return an
-/// informative error until that work is complete.
+/// Execute a DELETE command: remove a key from the server
 pub async fn delete(addr: &str, key: String) -> Result<()> {
+    send_expecting_ok(addr, format!("DELETE {}\n", key)).await
+}
+
+/// Execute a DELETE_RANGE command: remove every key in [start, end)
+pub async fn delete_range(addr: &str, start: String, end: String) -> Result<()> {
+    send_expecting_ok(addr, format!("DELETE_RANGE {} {}\n", start, end)).await
+}
+
+/// Execute a DELETE_PREFIX command: remove every key starting with prefix
+pub async fn delete_prefix(addr: &str, prefix: String) -> Result<()> {
+    send_expecting_ok(addr, format!("DELETE_PREFIX {}\n", prefix)).await
+}
+
+/// Execute a CAS command: set key to new only if it currently holds expected
+///
+/// Prints MISMATCH and exits 1 if the current value differs.
+pub async fn cas(addr: &str, key: String, expected: String, new: String) -> Result<()> {
+    send_expecting_ok(addr, format!("CAS {} {} {}\n", key, expected, new)).await
+}
+
+/// Execute a SETNX command: store a key-value pair only if the key is missing
+///
+/// Prints EXISTS and exits 1 if the key already has a value.
+pub async fn setnx(addr: &str, key: String, value: String) -> Result<()> {
+    send_expecting_ok(addr, format!("SETNX {} {}\n", key, value)).await
+}
+
+/// Execute a SETEX command: store a key-value pair that expires after seconds
+pub async fn setex(addr: &str, key: String, seconds: u64, value: String) -> Result<()> {
+    send_expecting_ok(addr, format!("SETEX {} {} {}\n", key, seconds, value)).await
+}
+
+/// Execute an INCRBY command: add delta to a counter key
+pub async fn incrby(addr: &str, key: String, delta: u64) -> Result<()> {
+    send_expecting_ok(addr, format!("INCRBY {} {}\n", key, delta)).await
+}
+
+/// Execute an AGG command: print one `<bucket start> <value>` line per bucket
+pub async fn agg(
+    addr: &str,
+    key: String,
+    function: String,
+    start: u64,
+    end: u64,
+    bucket: u64,
+) -> Result<()> {
+    let mut stream = TcpStream::connect(addr).await?;
+    let command = format!("AGG {} {} {} {} {}\n", key, start, end, bucket, function);
+    stream.write_all(command.as_bytes()).await?;
+
+    let mut reader = BufReader::new(&mut stream);
+    let mut response = String::new();
+    loop {
+        response.clear();
+        if reader.read_line(&mut response).await? == 0 {
+            eprintln!("connection closed before END");
+            std::process::exit(1);
+        }
+
+        let response = response.trim();
+        if let Some(bucket) = response.strip_prefix("BUCKET ") {
+            println!("{}", bucket);
+        } else if response == "END" {
+            break;
+        } else {
+            eprintln!("{}", response);
+            std::process::exit(1);
+        }
+    }
+
+    Ok(())
+}
+
+/// Send a write command and print OK, or print the error and exit 1
+async fn send_expecting_ok(addr: &str, command: String) -> Result<()> {
     let mut stream = TcpStream::connect(addr).await?;
-    let command = format!("DELETE {}\n", key);
     stream.write_all(command.as_bytes()).await?;
 
     let mut reader = BufReader::new(&mut stream);
     let mut response = String::new();
     reader.read_line(&mut response).await?;
 
-    eprintln!("{}", response.trim());
-    std::process::exit(1);
+    let response = response.trim();
+    if response == "OK" {
+        println!("OK");
+    } else {
+        eprintln!("{}", response);
+        std::process::exit(1);
+    }
+
+    Ok(())
 }
 
 /// Fetch and pretty-print live metrics from the dashboard HTTP server.
@@ -119,7 +188,12 @@ pub async fn metrics(dashboard_addr: &str) -> Result<()> {
         s["memtable_size_mb"].as_f64().unwrap_or(0.0),
         fmt_u64(&s["memtable_entries"]),
     );
-    println!("   SSTables:    {:>12}", fmt_u64(&s["sstable_count"]));
+    println!(
+        "   SSTables:    {:>12}  ({} open)",
+        fmt_u64(&s["sstable_count"]),
+        fmt_u64(&s["open_sstables"]),
+    );
+    println!("   Pruned:      {:>12}  (time-range reads)", fmt_u64(&s["sstables_pruned"]));
     println!("   WAL:         {:>9.2} MB", s["wal_size_mb"].as_f64().unwrap_or(0.0));
     println!("   Disk:        {:>9.2} MB", s["disk_usage_mb"].as_f64().unwrap_or(0.0));
     println!();
@@ -129,6 +203,16 @@ pub async fn metrics(dashboard_addr: &str) -> Result<()> {
     println!("   FP rate:     {:>9.2}%", s["bloom_filter_fp_rate"].as_f64().unwrap_or(0.0) * 100.0);
     println!();
 
+    println!("🧊 Block Cache");
+    println!("   Hit rate:    {:>9.2}%", s["block_cache_hit_rate"].as_f64().unwrap_or(0.0) * 100.0);
+    println!(
+        "   Hits:        {:>12}  (misses: {})",
+        fmt_u64(&s["block_cache_hits"]),
+        fmt_u64(&s["block_cache_misses"]),
+    );
+    println!("   Size:        {:>9.2} MB", s["block_cache_size_mb"].as_f64().unwrap_or(0.0));
+    println!();
+
     println!("🔄 Compaction");
     println!("   Space saved: {:>9.2}%", s["compaction_space_savings"].as_f64().unwrap_or(0.0) * 100.0);
     println!("   Write amp:   {:>9.2}x", s["write_amplification"].as_f64().unwrap_or(0.0));
@@ -15,13 +15,53 @@ pub async fn dispatch(command: Commands) -> Result<()> {
             data_dir,
             port,
             wal_buffer_size,
+            compression,
+            bottommost_compression,
+            zstd_dictionary_size,
+            series_mode,
             config: _, // config file support is reserved for a future release
-        } => server::run_server(data_dir, port, wal_buffer_size).await,
+        } => {
+            server::run_server(
+                data_dir,
+                port,
+                wal_buffer_size,
+                compression,
+                bottommost_compression,
+                zstd_dictionary_size,
+                series_mode,
+            )
+            .await
+        }
 
         Commands::Client { addr, command } => match command {
-            ClientCommand::Put { key, value } => client::put(&addr, key, value).await,
+            ClientCommand::Put {
+                key,
+                value,
+                timestamp,
+            } => client::put(&addr, key, value, timestamp).await,
             ClientCommand::Get { key } => client::get(&addr, key).await,
             ClientCommand::Delete { key } => client::delete(&addr, key).await,
+            ClientCommand::DeleteRange { start, end } => {
+                client::delete_range(&addr, start, end).await
+            }
+            ClientCommand::DeletePrefix { prefix } => client::delete_prefix(&addr, prefix).await,
+            ClientCommand::Cas { key, expected, new } => {
+                client::cas(&addr, key, expected, new).await
+            }
+            ClientCommand::Setnx { key, value } => client::setnx(&addr, key, value).await,
+            ClientCommand::Setex {
+                key,
+                seconds,
+                value,
+            } => client::setex(&addr, key, seconds, value).await,
+            ClientCommand::Incrby { key, delta } => client::incrby(&addr, key, delta).await,
+            ClientCommand::Agg {
+                key,
+                function,
+                start,
+                end,
+                bucket,
+            } => client::agg(&addr, key, function, start, end, bucket).await,
             ClientCommand::Metrics { dashboard_addr } => client::metrics(&dashboard_addr).await,
         },
     }
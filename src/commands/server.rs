@@ -4,10 +4,12 @@
 //! - Client server for writes/reads (using full StorageEngine)
 //! - Shared WAL between StorageEngine and compaction
 use cityhall::Result;
-use cityhall::{http_server, StorageEngine};
-use parking_lot::Mutex;
+use cityhall::{
+    http_server, AggregateQuery, CompressionType, StorageEngine, U64AddOperator, WriteBatch,
+};
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::net::{TcpListener, TcpStream};
 use tokio::signal;
@@ -21,6 +23,10 @@ pub async fn run_server(
     data_dir: PathBuf,
     port: u16,
     wal_buffer_size: usize,
+    compression: CompressionType,
+    bottommost_compression: Option<CompressionType>,
+    zstd_dictionary_size: usize,
+    series_mode: bool,
 ) -> Result<()> {
     println!("🏙️  Starting CityHall");
     println!("━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━");
@@ -28,6 +34,17 @@ pub async fn run_server(
     println!("🌐 Client port:    {}", port);
     println!("💾 WAL buffer:     {} bytes", wal_buffer_size);
     println!("📊 MemTable size:  {} MB", DEFAULT_MEMTABLE_SIZE / 1_048_576);
+    println!(
+        "🗜️  Compression:    {} (bottom-most: {})",
+        compression,
+        bottommost_compression.unwrap_or(compression)
+    );
+    if zstd_dictionary_size > 0 {
+        println!("📖 Zstd dictionary: up to {} bytes", zstd_dictionary_size);
+    }
+    if series_mode {
+        println!("📈 Series mode:    keeping every sample for AGG");
+    }
     println!();
 
     let start_time = std::time::Instant::now();
@@ -41,10 +58,17 @@ pub async fn run_server(
     let wal = Arc::new(parking_lot::RwLock::new(wal));
     println!("✓ WAL initialized at {:?}", wal_path);
 
-    // Create StorageEngine with shared WAL
-    let storage_engine =
-        StorageEngine::new(data_dir.clone(), DEFAULT_MEMTABLE_SIZE, Arc::clone(&wal))?;
-    let storage = Arc::new(Mutex::new(storage_engine));
+    // Create StorageEngine with shared WAL; merges are counter increments (INCRBY)
+    let mut storage_engine =
+        StorageEngine::new(data_dir.clone(), DEFAULT_MEMTABLE_SIZE, Arc::clone(&wal))?
+            .with_merge_operator(Arc::new(U64AddOperator))
+            .with_series_mode(series_mode)
+            .with_compression(compression)
+            .with_zstd_dictionary(zstd_dictionary_size);
+    if let Some(compression) = bottommost_compression {
+        storage_engine = storage_engine.with_bottommost_compression(compression);
+    }
+    let storage = Arc::new(storage_engine);
     println!("✓ StorageEngine initialized");
 
     // Initialize shared current WAL segment (for dashboard)
@@ -81,7 +105,7 @@ pub async fn run_server(
     println!("━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━");
     println!("✅ CityHall is running");
     println!("━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━");
-    println!("   Supported commands: PUT, GET");
+    println!("   Supported commands: PUT, PUTAT, GET, DELETE, DELETE_RANGE, DELETE_PREFIX, CAS, SETNX, SETEX, INCRBY, AGG, BATCH");
     println!("   Press Ctrl+C to stop");
     println!();
 
@@ -127,11 +151,30 @@ pub async fn run_server(
 ///
 /// Supported commands:
 ///   PUT <key> <value>  — write a key-value pair
+///   PUTAT <key> <ts> <value> — write a key-value pair stamped with the
+///                        given Unix time (nanoseconds) instead of now
 ///   GET <key>          — read a value by key
-///   DELETE <key>       — not yet implemented (tombstone support pending)
+///   DELETE <key>       — delete a key (writes a tombstone)
+///   DELETE_RANGE <start> <end> — delete every key in [start, end)
+///   DELETE_PREFIX <prefix>     — delete every key starting with prefix
+///   CAS <key> <expected> <new> — set key to new only if its value is
+///                                expected (replies OK or MISMATCH)
+///   SETNX <key> <value>        — set key only if it is missing
+///                                (replies OK or EXISTS)
+///   SETEX <key> <seconds> <value> — write a key that expires after seconds
+///   INCRBY <key> <delta>       — add a non-negative integer to a counter
+///                                without reading it (merge operand)
+///   BATCH              — start a block of PUT/DELETE lines ended by END,
+///                        applied atomically with a single reply
+///   AGG <key>[*] <start> <end> <bucket> <fn> — aggregate the samples of a
+///                        key (or every key under a prefix, with a trailing
+///                        `*`) in [start, end) into buckets of <bucket>
+///                        nanoseconds with min, max, avg, sum or count;
+///                        replies one `BUCKET <start> <value>` line per
+///                        non-empty bucket, then END
 async fn handle_client_connection(
     stream: TcpStream,
-    storage: Arc<Mutex<StorageEngine>>,
+    storage: Arc<StorageEngine>,
 ) -> std::io::Result<()> {
     let mut reader = BufReader::new(stream);
     let mut line = String::new();
@@ -151,13 +194,7 @@ async fn handle_client_connection(
         match cmd.as_deref() {
             Some("PUT") => {
                 if let (Some(key), Some(value)) = (parts.get(1), parts.get(2)) {
-                    let key = key.as_bytes().to_vec();
-                    let value = value.as_bytes().to_vec();
-
-                    let result = {
-                        let mut engine = storage.lock();
-                        engine.put(key.clone(), value.clone())
-                    };
+                    let result = storage.put(key.as_bytes().to_vec(), value.as_bytes().to_vec());
 
                     match result {
                         Ok(_) => {
@@ -170,19 +207,44 @@ async fn handle_client_connection(
                             eprintln!("❌ PUT failed: {}", e);
                         }
                     }
+                } else {
+                    writer.write_all(b"ERROR usage: PUT <key> <value>\n").await?;
+                }
+            }
+
+            Some("PUTAT") => {
+                let args = parts.get(2).and_then(|rest| rest.split_once(' '));
+                let args = args.and_then(|(timestamp, value)| {
+                    Some((timestamp.parse::<u64>().ok()?, value))
+                });
+                if let (Some(key), Some((timestamp, value))) = (parts.get(1), args) {
+                    let result = storage.put_with_timestamp(
+                        key.as_bytes().to_vec(),
+                        value.as_bytes().to_vec(),
+                        timestamp,
+                    );
+
+                    match result {
+                        Ok(_) => {
+                            writer.write_all(b"OK\n").await?;
+                        }
+                        Err(e) => {
+                            writer
+                                .write_all(format!("ERROR {}\n", e).as_bytes())
+                                .await?;
+                            eprintln!("❌ PUTAT failed: {}", e);
+                        }
+                    }
                 } else {
                     writer
-                        .write_all(b"ERROR usage: PUT <key> <value>\n")
+                        .write_all(b"ERROR usage: PUTAT <key> <timestamp> <value>\n")
                         .await?;
                 }
             }
 
             Some("GET") => {
                 if let Some(key) = parts.get(1) {
-                    let result = {
-                        let mut engine = storage.lock();
-                        engine.get(key.as_bytes())
-                    };
+                    let result = storage.get(key.as_bytes());
 
                     match result {
                         Ok(Some(value)) => {
@@ -206,12 +268,241 @@ async fn handle_client_connection(
                 }
             }
 
-            // DELETE is defined in the CLI and client but requires tombstone
-            // support in the storage engine. Tracked in the project roadmap.
             Some("DELETE") => {
-                writer
-                    .write_all(b"ERROR DELETE not yet implemented (tombstone support pending)\n")
-                    .await?;
+                if let Some(key) = parts.get(1) {
+                    let result = storage.delete(key.as_bytes().to_vec());
+
+                    match result {
+                        Ok(_) => {
+                            writer.write_all(b"OK\n").await?;
+                        }
+                        Err(e) => {
+                            writer
+                                .write_all(format!("ERROR {}\n", e).as_bytes())
+                                .await?;
+                            eprintln!("❌ DELETE failed: {}", e);
+                        }
+                    }
+                } else {
+                    writer.write_all(b"ERROR usage: DELETE <key>\n").await?;
+                }
+            }
+
+            Some("DELETE_RANGE") => {
+                if let (Some(start), Some(end)) = (parts.get(1), parts.get(2)) {
+                    let result =
+                        storage.delete_range(start.as_bytes().to_vec(), end.as_bytes().to_vec());
+
+                    match result {
+                        Ok(_) => {
+                            writer.write_all(b"OK\n").await?;
+                        }
+                        Err(e) => {
+                            writer
+                                .write_all(format!("ERROR {}\n", e).as_bytes())
+                                .await?;
+                            eprintln!("❌ DELETE_RANGE failed: {}", e);
+                        }
+                    }
+                } else {
+                    writer
+                        .write_all(b"ERROR usage: DELETE_RANGE <start> <end>\n")
+                        .await?;
+                }
+            }
+
+            Some("DELETE_PREFIX") => {
+                if let Some(prefix) = parts.get(1) {
+                    let result = storage.delete_prefix(prefix.as_bytes());
+
+                    match result {
+                        Ok(_) => {
+                            writer.write_all(b"OK\n").await?;
+                        }
+                        Err(e) => {
+                            writer
+                                .write_all(format!("ERROR {}\n", e).as_bytes())
+                                .await?;
+                            eprintln!("❌ DELETE_PREFIX failed: {}", e);
+                        }
+                    }
+                } else {
+                    writer
+                        .write_all(b"ERROR usage: DELETE_PREFIX <prefix>\n")
+                        .await?;
+                }
+            }
+
+            Some("CAS") => {
+                let args = (parts.get(1), parts.get(2).and_then(|rest| rest.split_once(' ')));
+                if let (Some(key), Some((expected, new))) = args {
+                    let result = storage.compare_and_swap(
+                        key.as_bytes().to_vec(),
+                        Some(expected.as_bytes()),
+                        new.as_bytes().to_vec(),
+                    );
+
+                    match result {
+                        Ok(true) => {
+                            writer.write_all(b"OK\n").await?;
+                        }
+                        Ok(false) => {
+                            writer.write_all(b"MISMATCH\n").await?;
+                        }
+                        Err(e) => {
+                            writer
+                                .write_all(format!("ERROR {}\n", e).as_bytes())
+                                .await?;
+                            eprintln!("❌ CAS failed: {}", e);
+                        }
+                    }
+                } else {
+                    writer
+                        .write_all(b"ERROR usage: CAS <key> <expected> <new>\n")
+                        .await?;
+                }
+            }
+
+            Some("SETNX") => {
+                if let (Some(key), Some(value)) = (parts.get(1), parts.get(2)) {
+                    let result =
+                        storage.put_if_absent(key.as_bytes().to_vec(), value.as_bytes().to_vec());
+
+                    match result {
+                        Ok(true) => {
+                            writer.write_all(b"OK\n").await?;
+                        }
+                        Ok(false) => {
+                            writer.write_all(b"EXISTS\n").await?;
+                        }
+                        Err(e) => {
+                            writer
+                                .write_all(format!("ERROR {}\n", e).as_bytes())
+                                .await?;
+                            eprintln!("❌ SETNX failed: {}", e);
+                        }
+                    }
+                } else {
+                    writer
+                        .write_all(b"ERROR usage: SETNX <key> <value>\n")
+                        .await?;
+                }
+            }
+
+            Some("SETEX") => {
+                let args = parts.get(2).and_then(|rest| rest.split_once(' '));
+                let args = args.and_then(|(seconds, value)| {
+                    let seconds = seconds.parse::<u64>().ok().filter(|&s| s > 0)?;
+                    Some((seconds, value))
+                });
+                if let (Some(key), Some((seconds, value))) = (parts.get(1), args) {
+                    let result = storage.put_with_ttl(
+                        key.as_bytes().to_vec(),
+                        value.as_bytes().to_vec(),
+                        Duration::from_secs(seconds),
+                    );
+
+                    match result {
+                        Ok(_) => {
+                            writer.write_all(b"OK\n").await?;
+                        }
+                        Err(e) => {
+                            writer
+                                .write_all(format!("ERROR {}\n", e).as_bytes())
+                                .await?;
+                            eprintln!("❌ SETEX failed: {}", e);
+                        }
+                    }
+                } else {
+                    writer
+                        .write_all(b"ERROR usage: SETEX <key> <seconds> <value>\n")
+                        .await?;
+                }
+            }
+
+            Some("INCRBY") => {
+                let delta = parts.get(2).and_then(|delta| delta.parse::<u64>().ok());
+                if let (Some(key), Some(delta)) = (parts.get(1), delta) {
+                    let result = storage.merge(
+                        key.as_bytes().to_vec(),
+                        delta.to_string().into_bytes(),
+                    );
+
+                    match result {
+                        Ok(_) => {
+                            writer.write_all(b"OK\n").await?;
+                        }
+                        Err(e) => {
+                            writer
+                                .write_all(format!("ERROR {}\n", e).as_bytes())
+                                .await?;
+                            eprintln!("❌ INCRBY failed: {}", e);
+                        }
+                    }
+                } else {
+                    writer
+                        .write_all(b"ERROR usage: INCRBY <key> <delta>\n")
+                        .await?;
+                }
+            }
+
+            Some("BATCH") => {
+                let batch = match read_batch(&mut reader).await? {
+                    Some(batch) => batch,
+                    None => return Ok(()), // connection closed before END
+                };
+
+                let result = match batch {
+                    Ok(batch) => storage.write(batch).map_err(|e| e.to_string()),
+                    Err(e) => Err(e),
+                };
+
+                let writer = reader.get_mut();
+                match result {
+                    Ok(_) => {
+                        writer.write_all(b"OK\n").await?;
+                    }
+                    Err(e) => {
+                        writer
+                            .write_all(format!("ERROR {}\n", e).as_bytes())
+                            .await?;
+                        eprintln!("❌ BATCH failed: {}", e);
+                    }
+                }
+            }
+
+            Some("AGG") => {
+                let query = match (parts.get(1), parts.get(2)) {
+                    (Some(key), Some(args)) => parse_aggregate(key, args),
+                    _ => None,
+                };
+                if let Some(query) = query {
+                    match storage.aggregate(&query) {
+                        Ok(buckets) => {
+                            let mut reply = String::new();
+                            for bucket in buckets {
+                                reply.push_str(&format!(
+                                    "BUCKET {} {}\n",
+                                    bucket.start, bucket.value
+                                ));
+                            }
+                            reply.push_str("END\n");
+                            writer.write_all(reply.as_bytes()).await?;
+                        }
+                        Err(e) => {
+                            writer
+                                .write_all(format!("ERROR {}\n", e).as_bytes())
+                                .await?;
+                            eprintln!("❌ AGG failed: {}", e);
+                        }
+                    }
+                } else {
+                    writer
+                        .write_all(
+                            b"ERROR usage: AGG <key>[*] <start> <end> <bucket> <min|max|avg|sum|count>\n",
+                        )
+                        .await?;
+                }
             }
 
             Some("") | None => {
@@ -220,13 +511,88 @@ async fn handle_client_connection(
 
             _ => {
                 writer
-                    .write_all(b"ERROR unknown command-supported: PUT, GET, DELETE\n")
+                    .write_all(
+                        b"ERROR unknown command-supported: PUT, PUTAT, GET, DELETE, DELETE_RANGE, DELETE_PREFIX, CAS, SETNX, SETEX, INCRBY, BATCH, AGG\n",
+                    )
                     .await?;
             }
         }
     }
 }
 
+/// Parse the arguments of `AGG`: a key (or `prefix*`), then
+/// `<start> <end> <bucket> <fn>`
+fn parse_aggregate(key: &str, args: &str) -> Option<AggregateQuery> {
+    let args: Vec<&str> = args.split(' ').collect();
+    let [start, end, bucket, function] = args.as_slice() else {
+        return None;
+    };
+    let aggregation = function.parse().ok()?;
+
+    let query = match key.strip_suffix('*') {
+        Some("") => return None,
+        Some(prefix) => AggregateQuery::prefix(prefix.as_bytes(), aggregation),
+        None => AggregateQuery::key(key.as_bytes(), aggregation),
+    };
+    Some(
+        query
+            .with_time_range(start.parse().ok()?, end.parse().ok()?)
+            .with_bucket_width(bucket.parse().ok()?),
+    )
+}
+
+/// Read the body of a `BATCH` block up to its `END` line
+///
+/// Only PUT and DELETE lines are allowed. A malformed line fails the whole
+/// batch, but the rest of the block is still consumed so the next command
+/// starts on a fresh line. Returns `None` if the connection closes first.
+async fn read_batch(
+    reader: &mut BufReader<TcpStream>,
+) -> std::io::Result<Option<std::result::Result<WriteBatch, String>>> {
+    let mut batch = WriteBatch::new();
+    let mut error = None;
+    let mut line = String::new();
+
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).await? == 0 {
+            return Ok(None);
+        }
+
+        let parts: Vec<&str> = line.trim().splitn(3, ' ').collect();
+        let cmd = parts.first().map(|s| s.to_uppercase());
+
+        match cmd.as_deref() {
+            Some("END") => break,
+            Some("PUT") => match (parts.get(1), parts.get(2)) {
+                (Some(key), Some(value)) => {
+                    batch.put(key.as_bytes().to_vec(), value.as_bytes().to_vec())
+                }
+                _ => {
+                    error.get_or_insert_with(|| "usage: PUT <key> <value>".to_string());
+                }
+            },
+            Some("DELETE") => match parts.get(1) {
+                Some(key) => batch.delete(key.as_bytes().to_vec()),
+                None => {
+                    error.get_or_insert_with(|| "usage: DELETE <key>".to_string());
+                }
+            },
+            Some("") | None => {
+                // ignore empty lines
+            }
+            Some(other) => {
+                error.get_or_insert_with(|| format!("{} not allowed in BATCH", other));
+            }
+        }
+    }
+
+    Ok(Some(match error {
+        Some(e) => Err(e),
+        None => Ok(batch),
+    }))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -245,8 +611,7 @@ mod tests {
             StorageEngine::new(data_dir.clone(), DEFAULT_MEMTABLE_SIZE, Arc::clone(&wal))
                 .unwrap();
 
-        let storage = Arc::new(Mutex::new(storage_engine));
-        let mut engine = storage.lock();
+        let engine = Arc::new(storage_engine);
         assert!(engine.put(b"test".to_vec(), b"value".to_vec()).is_ok());
         assert_eq!(engine.get(b"test").unwrap(), Some(b"value".to_vec()));
     }
@@ -262,9 +627,8 @@ mod tests {
 
         let storage_engine =
             StorageEngine::new(data_dir.clone(), DEFAULT_MEMTABLE_SIZE, wal).unwrap();
-        let storage = Arc::new(Mutex::new(storage_engine));
+        let engine = Arc::new(storage_engine);
 
-        let mut engine = storage.lock();
         engine.put(b"k1".to_vec(), b"v1".to_vec()).unwrap();
         engine.put(b"k2".to_vec(), b"v2".to_vec()).unwrap();
 
@@ -272,4 +636,118 @@ mod tests {
         assert_eq!(engine.get(b"k2").unwrap(), Some(b"v2".to_vec()));
         assert_eq!(engine.get(b"k3").unwrap(), None);
     }
+
+    #[tokio::test]
+    async fn test_delete_command() {
+        let temp_dir = TempDir::new().unwrap();
+        let data_dir = temp_dir.path().to_path_buf();
+
+        let wal_path = data_dir.join("wal");
+        let wal = cityhall::Wal::new(&wal_path, 1024).unwrap();
+        let wal = Arc::new(parking_lot::RwLock::new(wal));
+
+        let storage_engine = StorageEngine::new(data_dir.clone(), DEFAULT_MEMTABLE_SIZE, wal)
+            .unwrap()
+            .with_merge_operator(Arc::new(U64AddOperator));
+        let storage = Arc::new(storage_engine);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server_storage = Arc::clone(&storage);
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let _ = handle_client_connection(stream, server_storage).await;
+        });
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut client = BufReader::new(stream);
+        let mut response = String::new();
+        for (command, expected) in [
+            ("PUT k1 v1\n", "OK"),
+            ("DELETE k1\n", "OK"),
+            ("GET k1\n", "NOT_FOUND"),
+            ("DELETE\n", "ERROR usage: DELETE <key>"),
+            ("PUT user:1 a\n", "OK"),
+            ("PUT user:2 b\n", "OK"),
+            ("PUT video:1 c\n", "OK"),
+            ("DELETE_PREFIX user:\n", "OK"),
+            ("GET user:1\n", "NOT_FOUND"),
+            ("GET video:1\n", "VALUE c"),
+            ("DELETE_RANGE a z\n", "OK"),
+            ("GET video:1\n", "NOT_FOUND"),
+            (
+                "DELETE_RANGE z a\n",
+                "ERROR Invalid format: Range delete start must be before end",
+            ),
+            (
+                "DELETE_RANGE a\n",
+                "ERROR usage: DELETE_RANGE <start> <end>",
+            ),
+            ("BATCH\nPUT m1 1\nPUT m2 2\nDELETE m1\nEND\n", "OK"),
+            ("GET m1\n", "NOT_FOUND"),
+            ("GET m2\n", "VALUE 2"),
+            (
+                "BATCH\nPUT m3 3\nGET m2\nEND\n",
+                "ERROR GET not allowed in BATCH",
+            ),
+            ("GET m3\n", "NOT_FOUND"),
+            ("SETNX dev1 owner-a\n", "OK"),
+            ("SETNX dev1 owner-b\n", "EXISTS"),
+            ("CAS dev1 owner-b owner-c\n", "MISMATCH"),
+            ("CAS dev1 owner-a owner c\n", "OK"),
+            ("GET dev1\n", "VALUE owner c"),
+            ("CAS dev1 owner\n", "ERROR usage: CAS <key> <expected> <new>"),
+            ("SETNX dev2\n", "ERROR usage: SETNX <key> <value>"),
+            ("INCRBY hits 5\n", "OK"),
+            ("INCRBY hits 2\n", "OK"),
+            ("GET hits\n", "VALUE 7"),
+            ("PUT hits 100\n", "OK"),
+            ("INCRBY hits 1\n", "OK"),
+            ("GET hits\n", "VALUE 101"),
+            ("INCRBY hits -1\n", "ERROR usage: INCRBY <key> <delta>"),
+            ("PUTAT zone:1 1000 21.5\n", "OK"),
+            ("GET zone:1\n", "VALUE 21.5"),
+            ("PUTAT zone:1 900 19.0\n", "OK"),
+            ("GET zone:1\n", "VALUE 21.5"),
+            ("PUT zone:note price @100\n", "OK"),
+            ("GET zone:note\n", "VALUE price @100"),
+            (
+                "PUTAT zone:1 soon 1\n",
+                "ERROR usage: PUTAT <key> <timestamp> <value>",
+            ),
+            (
+                "AGG zone:1 0 2000 100 avg\n",
+                "BUCKET 900 19\nBUCKET 1000 21.5\nEND",
+            ),
+            ("AGG zone:* 0 2000 0 count\n", "BUCKET 0 2\nEND"),
+            ("AGG zone:1 5000 6000 100 max\n", "END"),
+            (
+                "AGG zone:1 0 2000 100 median\n",
+                "ERROR usage: AGG <key>[*] <start> <end> <bucket> <min|max|avg|sum|count>",
+            ),
+            (
+                "AGG * 0 2000 100 sum\n",
+                "ERROR usage: AGG <key>[*] <start> <end> <bucket> <min|max|avg|sum|count>",
+            ),
+            (
+                "PUTAT zone:1 9999999999999999999 1\n",
+                "ERROR Invalid format: Timestamp 9999999999999999999 is in the future",
+            ),
+            ("SETEX session 60 token a\n", "OK"),
+            ("GET session\n", "VALUE token a"),
+            ("SETEX session 0 b\n", "ERROR usage: SETEX <key> <seconds> <value>"),
+            ("SETEX session soon b\n", "ERROR usage: SETEX <key> <seconds> <value>"),
+        ] {
+            client
+                .get_mut()
+                .write_all(command.as_bytes())
+                .await
+                .unwrap();
+            response.clear();
+            for _ in expected.lines() {
+                client.read_line(&mut response).await.unwrap();
+            }
+            assert_eq!(response.trim(), expected, "response to {:?}", command);
+        }
+    }
 }
\ No newline at end of file
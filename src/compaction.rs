@@ -8,31 +8,43 @@
 //! # Algorithm: K-Way Merge
 //!
 //! 1. Select N SSTables to compact (similar size)
-//! 2. Open all SSTables, scan in sorted order
-//! 3. Merge entries, keeping newest version of each key
-//! 4. Write merged SSTable
-//! 5. Delete old SSTables
-
-use crate::sstable::{SsTableReader, SsTableWriter, DEFAULT_BLOCK_SIZE};
-use crate::Result;
+//! 2. Open all SSTables, stream them block by block in sorted order
+//! 3. Merge entries, keeping the newest version of each key plus any older
+//!    version a live snapshot still reads, and fold runs of merge operands
+//! 4. Drop versions shadowed by a range tombstone
+//! 5. Turn expired puts into tombstones, and drop tombstones that nothing
+//!    outside the compaction can still need: no older SSTable, and no newer
+//!    SSTable or MemTable holding a backfilled write older than them
+//! 6. Write merged SSTable (with Zstd, optionally through a dictionary
+//!    trained on its first blocks)
+//! 7. Delete old SSTables
+
+use crate::memtable::MemTable;
+use crate::merge::{collapse_merges, MergeOperator};
+use crate::snapshot::{history_versions, needed_versions};
+use crate::sstable::{
+    CompressionType, SsTableHandle, SsTableIterator, SsTableReader, SsTableWriter,
+    DEFAULT_BLOCK_SIZE,
+};
+use crate::{now_timestamp, RangeTombstone, Record, Result, SequenceNumber, Timestamp};
+use parking_lot::RwLock;
 use std::cmp::Ordering;
 use std::collections::BinaryHeap;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 /// Entry from an SSTable with source tracking
 #[derive(Debug, Clone)]
 struct CompactionEntry {
-    key: Vec<u8>,
-    value: Vec<u8>,
-    timestamp: u64,
-    sstable_id: usize, // Which SSTable this came from
+    record: Record,
+    sstable_id: usize, // Which SSTable this came from (higher = newer)
 }
 
 /// Implement reverse ordering for min-heap
 /// (BinaryHeap is max-heap, we want min-heap)
 impl PartialEq for CompactionEntry {
     fn eq(&self, other: &Self) -> bool {
-        self.key == other.key
+        self.record.key == other.record.key
     }
 }
 
@@ -48,10 +60,13 @@ impl Ord for CompactionEntry {
     fn cmp(&self, other: &Self) -> Ordering {
         // Reverse key ordering (BinaryHeap is max-heap)
         other
+            .record
             .key
-            .cmp(&self.key)
-            // Reverse timestamp ordering: prefer NEWER timestamps
-            .then(self.timestamp.cmp(&other.timestamp))
+            .cmp(&self.record.key)
+            // Reverse version ordering: prefer NEWER versions
+            .then(self.record.version().cmp(&other.record.version()))
+            // Same version (only unsequenced data): prefer the newer input
+            .then(self.sstable_id.cmp(&other.sstable_id))
     }
 }
 
@@ -62,18 +77,172 @@ pub struct CompactionStats {
     pub output_bytes: u64,
     pub entries_merged: usize,
     pub duplicates_removed: usize,
+    pub tombstones_dropped: usize,
+    pub range_deleted: usize,
+    pub operands_folded: usize,
+    pub expired: usize,
     pub duration_ms: u64,
 }
 
+impl CompactionStats {
+    /// Share of the input size the compaction saved, in percent
+    ///
+    /// Negative when the output is larger than the inputs, e.g. once a
+    /// Zstd dictionary is stored in it.
+    pub fn space_saved_percent(&self) -> f64 {
+        if self.input_bytes == 0 {
+            return 0.0;
+        }
+        (self.input_bytes as f64 - self.output_bytes as f64) * 100.0 / self.input_bytes as f64
+    }
+}
+
+/// Engine state a compaction has to respect
+#[derive(Clone, Default)]
+pub struct CompactionOptions {
+    /// Sequence numbers of live snapshots; the version each of them reads is kept
+    pub snapshots: Vec<SequenceNumber>,
+    /// Folds merge operands; without one they are kept as written
+    pub merge_operator: Option<Arc<dyn MergeOperator>>,
+    /// Series mode: keep every sample of a key instead of only the newest
+    pub keep_history: bool,
+    /// Codec of the output's data blocks
+    pub compression: CompressionType,
+    /// With Zstd, train a dictionary of up to this many bytes on the output
+    /// (0 = none)
+    pub zstd_dictionary_size: usize,
+    /// SSTables and MemTables outside the compaction that are newer than its
+    /// inputs (see [`NewerSource`])
+    pub newer: Vec<Arc<dyn NewerSource>>,
+}
+
+/// A table or MemTable outside a compaction that is newer than its inputs
+///
+/// Newest timestamp wins, whatever the order of the writes, so a backfilled
+/// write (see [`crate::StorageEngine::put_with_timestamp`]) can land here
+/// with a version older than a tombstone in the inputs. The tombstone then
+/// has to stay to keep hiding it.
+pub trait NewerSource: Send + Sync {
+    /// Lowest timestamp of any version it holds
+    fn min_timestamp(&self) -> Timestamp;
+
+    /// Could it hold a version of `key`? False only if it definitely does not
+    fn may_contain(&self, key: &[u8]) -> bool;
+
+    /// Could it hold a version of a key in `[start, end]`?
+    fn may_overlap(&self, start: &[u8], end: &[u8]) -> bool;
+}
+
+impl NewerSource for SsTableHandle {
+    fn min_timestamp(&self) -> Timestamp {
+        SsTableHandle::min_timestamp(self)
+    }
+
+    fn may_contain(&self, key: &[u8]) -> bool {
+        // A table that cannot be opened is assumed to hold the key
+        self.may_overlap(key, key) && self.reader().map_or(true, |r| r.may_contain(key))
+    }
+
+    fn may_overlap(&self, start: &[u8], end: &[u8]) -> bool {
+        SsTableHandle::may_overlap(self, start, end)
+    }
+}
+
+impl NewerSource for RwLock<MemTable> {
+    fn min_timestamp(&self) -> Timestamp {
+        self.read().min_timestamp().unwrap_or(Timestamp::MAX)
+    }
+
+    fn may_contain(&self, key: &[u8]) -> bool {
+        self.read().contains_key(key)
+    }
+
+    fn may_overlap(&self, start: &[u8], end: &[u8]) -> bool {
+        self.read().overlaps(start, end)
+    }
+}
+
+impl CompactionOptions {
+    pub fn new() -> Self {
+        CompactionOptions::default()
+    }
+
+    pub fn with_snapshots(mut self, snapshots: Vec<SequenceNumber>) -> Self {
+        self.snapshots = snapshots;
+        self
+    }
+
+    pub fn with_merge_operator(mut self, operator: Arc<dyn MergeOperator>) -> Self {
+        self.merge_operator = Some(operator);
+        self
+    }
+
+    pub fn with_history(mut self, keep_history: bool) -> Self {
+        self.keep_history = keep_history;
+        self
+    }
+
+    pub fn with_newer(mut self, newer: Vec<Arc<dyn NewerSource>>) -> Self {
+        self.newer = newer;
+        self
+    }
+
+    pub fn with_compression(mut self, compression: CompressionType) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    pub fn with_zstd_dictionary(mut self, max_size: usize) -> Self {
+        self.zstd_dictionary_size = max_size;
+        self
+    }
+}
+
+/// Compact multiple SSTables into one, with no live snapshots
+///
+/// See [`compact_sstables_with_snapshots`].
+pub fn compact_sstables(
+    input_paths: &[PathBuf],
+    older_paths: &[PathBuf],
+    output_path: PathBuf,
+) -> Result<CompactionStats> {
+    compact_sstables_with_snapshots(input_paths, older_paths, &[], output_path)
+}
+
 /// Compact multiple SSTables into one
 ///
 /// # Arguments
-/// * `input_paths` - SSTables to compact
+/// * `input_paths` - SSTables to compact, ordered oldest to newest
+/// * `older_paths` - SSTables outside the compaction that may hold older
+///   versions of the input keys. A tombstone is only dropped when none of
+///   them can contain its key, and range tombstones are only dropped when
+///   there are none; pass an empty slice when the inputs already hold the
+///   oldest data. Newer sources go in [`CompactionOptions::newer`].
+/// * `snapshots` - Sequence numbers of live snapshots; the version each of
+///   them reads is kept
 /// * `output_path` - Where to write merged SSTable
 ///
 /// # Returns
 /// Statistics about the compaction
-pub fn compact_sstables(input_paths: &[PathBuf], output_path: PathBuf) -> Result<CompactionStats> {
+pub fn compact_sstables_with_snapshots(
+    input_paths: &[PathBuf],
+    older_paths: &[PathBuf],
+    snapshots: &[SequenceNumber],
+    output_path: PathBuf,
+) -> Result<CompactionStats> {
+    let options = CompactionOptions::new().with_snapshots(snapshots.to_vec());
+    compact_sstables_with_options(input_paths, older_paths, &options, output_path)
+}
+
+/// Compact multiple SSTables into one under the given [`CompactionOptions`]
+///
+/// See [`compact_sstables_with_snapshots`] for the other arguments.
+pub fn compact_sstables_with_options(
+    input_paths: &[PathBuf],
+    older_paths: &[PathBuf],
+    options: &CompactionOptions,
+    output_path: PathBuf,
+) -> Result<CompactionStats> {
     use std::time::Instant;
     let start = Instant::now();
 
@@ -84,17 +253,22 @@ pub fn compact_sstables(input_paths: &[PathBuf], output_path: PathBuf) -> Result
     );
 
     // Open all input SSTables
-    let mut readers: Vec<SsTableReader> = Vec::new();
+    let mut readers: Vec<Arc<SsTableReader>> = Vec::new();
     let mut input_bytes = 0u64;
 
     for path in input_paths {
         let reader = SsTableReader::open(path.clone())?;
         input_bytes += std::fs::metadata(path)?.len();
-        readers.push(reader);
+        readers.push(Arc::new(reader));
     }
 
+    let older = older_paths
+        .iter()
+        .map(|path| SsTableReader::open(path.clone()))
+        .collect::<Result<Vec<_>>>()?;
+
     // Perform k-way merge
-    let (entries_merged, duplicates_removed) = merge_sstables(&mut readers, &output_path)?;
+    let merge = merge_sstables(&readers, &older, options, &output_path)?;
 
     let output_bytes = std::fs::metadata(&output_path)?.len();
     let duration_ms = start.elapsed().as_millis() as u64;
@@ -103,8 +277,12 @@ pub fn compact_sstables(input_paths: &[PathBuf], output_path: PathBuf) -> Result
         input_sstables: input_paths.len(),
         input_bytes,
         output_bytes,
-        entries_merged,
-        duplicates_removed,
+        entries_merged: merge.entries_merged,
+        duplicates_removed: merge.duplicates_removed,
+        tombstones_dropped: merge.tombstones_dropped,
+        range_deleted: merge.range_deleted,
+        operands_folded: merge.operands_folded,
+        expired: merge.expired,
         duration_ms,
     };
 
@@ -119,94 +297,265 @@ pub fn compact_sstables(input_paths: &[PathBuf], output_path: PathBuf) -> Result
         (1.0 - stats.output_bytes as f64 / stats.input_bytes as f64) * 100.0
     );
     println!(
-        "   Entries: {} merged, {} duplicates removed",
-        stats.entries_merged, stats.duplicates_removed
+        "   Entries: {} merged, {} duplicates removed, {} tombstones dropped, {} range-deleted, {} operands folded, {} expired",
+        stats.entries_merged,
+        stats.duplicates_removed,
+        stats.tombstones_dropped,
+        stats.range_deleted,
+        stats.operands_folded,
+        stats.expired
     );
     println!("   Duration: {}ms", stats.duration_ms);
 
     Ok(stats)
 }
 
+/// Counters gathered while merging
+struct MergeOutcome {
+    entries_merged: usize,
+    duplicates_removed: usize,
+    tombstones_dropped: usize,
+    range_deleted: usize,
+    operands_folded: usize,
+    expired: usize,
+}
+
 /// Perform k-way merge of SSTables
-fn merge_sstables(readers: &mut [SsTableReader], output_path: &Path) -> Result<(usize, usize)> {
-    let mut writer = SsTableWriter::new(output_path.to_path_buf(), DEFAULT_BLOCK_SIZE)?;
+///
+/// All versions of a key are gathered, newest first. The newest one is kept,
+/// along with the version each live snapshot reads; the rest are dropped,
+/// as are versions shadowed by a range tombstone. Expired puts are first
+/// turned into tombstones, and merge operands folded as far as the operator
+/// and the snapshots allow. If the oldest kept
+/// version is a tombstone, no table in `older` can contain the key and no
+/// newer source can hold a version of it older than the tombstone, the
+/// tombstone has nothing left to shadow and is dropped instead of written.
+///
+/// `readers` must be ordered oldest to newest.
+fn merge_sstables(
+    readers: &[Arc<SsTableReader>],
+    older: &[SsTableReader],
+    options: &CompactionOptions,
+    output_path: &Path,
+) -> Result<MergeOutcome> {
+    let snapshots = options.snapshots.as_slice();
+    let now = now_timestamp()?;
+    let mut writer = SsTableWriter::new(output_path.to_path_buf(), DEFAULT_BLOCK_SIZE)?
+        .with_compression(options.compression);
+    if options.compression == CompressionType::Zstd {
+        // Trained on the first blocks of the merged output
+        writer = writer.with_zstd_dictionary(options.zstd_dictionary_size);
+    }
 
     // Initialize heap with first entry from each SSTable
     let mut heap = BinaryHeap::new();
-    let mut iterators: Vec<Vec<(Vec<u8>, Vec<u8>, u64)>> = Vec::new();
 
-    for (i, reader) in readers.iter_mut().enumerate() {
-        // Scan entire SSTable (from first to last key)
-        match reader.scan(&[], &[0xFF; 1024]) {
-            Ok(entries) => {
-                if !entries.is_empty() {
+    let range_tombstones: Vec<RangeTombstone> = readers
+        .iter()
+        .flat_map(|reader| reader.range_tombstones().iter().cloned())
+        .collect();
+
+    // Stream each SSTable block by block, tombstones included
+    let mut iterators: Vec<SsTableIterator> =
+        readers.iter().cloned().map(SsTableIterator::new).collect();
+
+    for (i, iterator) in iterators.iter_mut().enumerate() {
+        match iterator.seek_to_first() {
+            Ok(()) => {
+                if let Some(record) = iterator.record() {
                     // Push first entry to heap
-                    let (key, value, timestamp) = &entries[0];
                     heap.push(CompactionEntry {
-                        key: key.clone(),
-                        value: value.clone(),
-                        timestamp: *timestamp,
+                        record: record.clone(),
                         sstable_id: i,
                     });
-                    iterators.push(entries);
                 }
             }
             Err(e) => {
                 eprintln!("Warning: Failed to scan SSTable {}: {}", i, e);
-                iterators.push(Vec::new());
             }
         }
     }
 
-    // Track position in each SSTable's entries
-    let mut positions = vec![0usize; iterators.len()];
+    let mut outcome = MergeOutcome {
+        entries_merged: 0,
+        duplicates_removed: 0,
+        tombstones_dropped: 0,
+        range_deleted: 0,
+        operands_folded: 0,
+        expired: 0,
+    };
+    let mut merger = KeyMerger {
+        writer: &mut writer,
+        range_tombstones: &range_tombstones,
+        older,
+        newer: &options.newer,
+        snapshots,
+        now,
+        merge_operator: options.merge_operator.as_deref(),
+        keep_history: options.keep_history,
+        outcome: &mut outcome,
+    };
 
-    let mut entries_merged = 0;
-    let mut duplicates_removed = 0;
-    let mut last_key: Option<Vec<u8>> = None;
+    // Versions of the key currently being merged, newest first
+    let mut versions: Vec<Record> = Vec::new();
 
     // K-way merge using min-heap
     while let Some(entry) = heap.pop() {
-        // Check if this is a duplicate key
-        let is_duplicate = last_key.as_ref() == Some(&entry.key);
-
-        if !is_duplicate {
-            // Write unique entry
-            writer.add(&entry.key, &entry.value, entry.timestamp)?;
-            entries_merged += 1;
-            last_key = Some(entry.key.clone());
-        } else {
-            // Skip duplicate (we already wrote the newest version)
-            duplicates_removed += 1;
-
-            if duplicates_removed <= 5 {
-                // Only show first few
-                println!(
-                    "   Skipping duplicate: {:?} @ t{} (older version)",
-                    String::from_utf8_lossy(&entry.key),
-                    entry.timestamp
-                );
-            }
+        let sstable_id = entry.sstable_id;
+
+        if versions
+            .last()
+            .is_some_and(|last| last.key != entry.record.key)
+        {
+            merger.merge_key(&mut versions)?;
         }
+        versions.push(entry.record);
 
         // Get next entry from the same SSTable
-        let sstable_id = entry.sstable_id;
-        positions[sstable_id] += 1;
-
-        if positions[sstable_id] < iterators[sstable_id].len() {
-            let (key, value, timestamp) = &iterators[sstable_id][positions[sstable_id]];
+        iterators[sstable_id].next()?;
+        if let Some(record) = iterators[sstable_id].record() {
             heap.push(CompactionEntry {
-                key: key.clone(),
-                value: value.clone(),
-                timestamp: *timestamp,
+                record: record.clone(),
                 sstable_id,
             });
         }
     }
+    merger.merge_key(&mut versions)?;
+
+    // Range tombstones still shadow the older tables, backfilled versions in
+    // newer ones and whatever versions older snapshots kept alive; without
+    // any of them they are obsolete
+    for tombstone in range_tombstones {
+        let backfilled = options.newer.iter().any(|source| {
+            source.min_timestamp() < tombstone.timestamp
+                && source.may_overlap(&tombstone.start, &tombstone.end)
+        });
+        if !older.is_empty() || backfilled || snapshots.iter().any(|&seq| seq < tombstone.seq) {
+            writer.add_range_tombstone(tombstone);
+        }
+    }
 
     writer.finish()?;
 
-    Ok((entries_merged, duplicates_removed))
+    Ok(outcome)
+}
+
+/// Decides, key by key, which versions make it into the merged SSTable
+struct KeyMerger<'a> {
+    writer: &'a mut SsTableWriter,
+    range_tombstones: &'a [RangeTombstone],
+    older: &'a [SsTableReader],
+    newer: &'a [Arc<dyn NewerSource>],
+    snapshots: &'a [SequenceNumber],
+    now: Timestamp,
+    merge_operator: Option<&'a dyn MergeOperator>,
+    keep_history: bool,
+    outcome: &'a mut MergeOutcome,
+}
+
+impl KeyMerger<'_> {
+    /// Could a source outside the compaction hold a version of `key` older
+    /// than `timestamp`? Any older table may; a newer one only if it holds
+    /// something that old.
+    fn held_outside(&self, key: &[u8], timestamp: Timestamp) -> bool {
+        self.older.iter().any(|reader| reader.may_contain(key))
+            || self
+                .newer
+                .iter()
+                .any(|source| source.min_timestamp() < timestamp && source.may_contain(key))
+    }
+
+    /// Write the versions of one key that are still needed, draining `versions`
+    fn merge_key(&mut self, versions: &mut Vec<Record>) -> Result<()> {
+        let key = match versions.first() {
+            Some(record) => record.key.clone(),
+            None => return Ok(()),
+        };
+
+        let covering: Vec<&RangeTombstone> = self
+            .range_tombstones
+            .iter()
+            .filter(|rt| rt.covers(&key))
+            .collect();
+        // Nothing outside holds a version older than the oldest one here
+        let oldest = versions.last().map_or(0, |record| record.timestamp);
+        let bottommost = !self.held_outside(&key, oldest);
+
+        self.outcome.expired += expire_versions(versions, self.now);
+        let mut keep = if self.keep_history {
+            // Operands are samples in their own right, so they stay unfolded
+            keep_either(
+                needed_versions(versions, &covering, self.snapshots),
+                history_versions(versions, &covering),
+            )
+        } else {
+            if let Some(operator) = self.merge_operator {
+                self.outcome.operands_folded +=
+                    collapse_merges(versions, &covering, self.snapshots, operator, bottommost);
+            }
+            needed_versions(versions, &covering, self.snapshots)
+        };
+
+        // Tombstones at the bottom are obsolete once nothing outside can hold
+        // a version they hide
+        let mut obsolete = Vec::new();
+        while let Some(last) = keep.iter().rposition(|&kept| kept) {
+            let record = &versions[last];
+            if !record.is_tombstone() || self.held_outside(&key, record.timestamp) {
+                break;
+            }
+            keep[last] = false;
+            obsolete.push(last);
+        }
+
+        for (i, (record, kept)) in versions.drain(..).zip(keep).enumerate() {
+            if kept {
+                // Write needed version
+                self.writer.add_record(&record)?;
+                self.outcome.entries_merged += 1;
+            } else if obsolete.contains(&i) {
+                self.outcome.tombstones_dropped += 1;
+            } else if covering.iter().any(|rt| rt.shadows(&record)) {
+                // Deleted by a range tombstone written after this version
+                self.outcome.range_deleted += 1;
+            } else {
+                // Skip duplicate (a newer version is kept)
+                self.outcome.duplicates_removed += 1;
+
+                if self.outcome.duplicates_removed <= 5 {
+                    // Only show first few
+                    println!(
+                        "   Skipping duplicate: {:?} @ t{} (older version)",
+                        String::from_utf8_lossy(&record.key),
+                        record.timestamp
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Turn the expired puts among `versions` into tombstones
+///
+/// An expired put already reads as deleted; as a tombstone it is dropped
+/// like any other once nothing older needs shadowing. Returns how many
+/// puts expired.
+pub(crate) fn expire_versions(versions: &mut [Record], now: Timestamp) -> usize {
+    versions
+        .iter_mut()
+        .map(|record| record.expire(now))
+        .filter(|&expired| expired)
+        .count()
+}
+
+/// Combine two sets of keep flags: a version is kept if either keeps it
+pub(crate) fn keep_either(mut keep: Vec<bool>, also: Vec<bool>) -> Vec<bool> {
+    for (kept, also_kept) in keep.iter_mut().zip(also) {
+        *kept |= also_kept;
+    }
+    keep
 }
 
 /// Select SSTables for compaction (size-tiered strategy)
@@ -236,16 +585,13 @@ pub fn select_sstables_for_compaction(
     // Find largest group of similar-sized SSTables
     let mut best_group = Vec::new();
 
-    for i in 0..sstables_with_size.len() {
-        let base_size = sstables_with_size[i].1;
-        let mut group = vec![sstables_with_size[i].0.clone()];
-
-        for j in (i + 1)..sstables_with_size.len() {
-            let size = sstables_with_size[j].1;
+    for (i, (base_path, base_size)) in sstables_with_size.iter().enumerate() {
+        let mut group = vec![base_path.clone()];
 
+        for (path, size) in &sstables_with_size[i + 1..] {
             // Within 50% of base size?
-            if size as f64 <= base_size as f64 * 1.5 {
-                group.push(sstables_with_size[j].0.clone());
+            if *size as f64 <= *base_size as f64 * 1.5 {
+                group.push(path.clone());
             } else {
                 break;
             }
@@ -263,6 +609,7 @@ pub fn select_sstables_for_compaction(
 mod tests {
     use super::*;
     use crate::sstable::SsTableWriter;
+    use crate::NANOS_PER_SECOND;
     use tempfile::TempDir;
 
     #[test]
@@ -285,12 +632,12 @@ mod tests {
 
         // Compact
         let output = temp_dir.path().join("merged.sst");
-        let stats = compact_sstables(&[path1, path2], output.clone())?;
+        let stats = compact_sstables(&[path1, path2], &[], output.clone())?;
 
         println!("Compaction stats: {:?}", stats);
 
         // Verify merged SSTable
-        let mut reader = SsTableReader::open(output)?;
+        let reader = SsTableReader::open(output)?;
 
         assert_eq!(reader.get(b"key1")?, Some((b"value1_new".to_vec(), 200)));
         assert_eq!(reader.get(b"key2")?, Some((b"value2".to_vec(), 200)));
@@ -303,6 +650,311 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_space_saved_percent() {
+        let mut stats = CompactionStats {
+            input_sstables: 2,
+            input_bytes: 400,
+            output_bytes: 300,
+            entries_merged: 0,
+            duplicates_removed: 0,
+            tombstones_dropped: 0,
+            range_deleted: 0,
+            operands_folded: 0,
+            expired: 0,
+            duration_ms: 0,
+        };
+        assert_eq!(stats.space_saved_percent(), 25.0);
+
+        // A larger output does not underflow
+        stats.output_bytes = 500;
+        assert_eq!(stats.space_saved_percent(), -25.0);
+
+        stats.input_bytes = 0;
+        assert_eq!(stats.space_saved_percent(), 0.0);
+    }
+
+    #[test]
+    fn test_compaction_drops_obsolete_tombstones() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+
+        // Oldest table, left out of the compaction
+        let base = temp_dir.path().join("001.sst");
+        let mut writer = SsTableWriter::new(base.clone(), DEFAULT_BLOCK_SIZE)?;
+        writer.add(b"shadowed", b"old", 100)?;
+        writer.finish()?;
+
+        let path2 = temp_dir.path().join("002.sst");
+        let mut writer = SsTableWriter::new(path2.clone(), DEFAULT_BLOCK_SIZE)?;
+        writer.add(b"gone", b"value", 200)?;
+        writer.add(b"kept", b"value", 200)?;
+        writer.finish()?;
+
+        let path3 = temp_dir.path().join("003.sst");
+        let mut writer = SsTableWriter::new(path3.clone(), DEFAULT_BLOCK_SIZE)?;
+        writer.add_record(&Record::delete(b"gone".to_vec(), 300))?;
+        writer.add_record(&Record::delete(b"shadowed".to_vec(), 300))?;
+        writer.finish()?;
+
+        let output = temp_dir.path().join("merged.sst");
+        let stats = compact_sstables(&[path2, path3], &[base], output.clone())?;
+
+        let reader = SsTableReader::open(output)?;
+
+        // "gone" only ever lived in the inputs: both versions disappear
+        assert_eq!(reader.get_record(b"gone")?, None);
+        // "shadowed" still exists in 001.sst, so its tombstone must survive
+        assert!(reader.get_record(b"shadowed")?.unwrap().is_tombstone());
+        assert_eq!(reader.get(b"kept")?, Some((b"value".to_vec(), 200)));
+
+        assert_eq!(stats.tombstones_dropped, 1);
+        assert_eq!(stats.entries_merged, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compaction_applies_range_tombstones() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+
+        let base = temp_dir.path().join("001.sst");
+        let mut writer = SsTableWriter::new(base.clone(), DEFAULT_BLOCK_SIZE)?;
+        writer.add(b"user:0", b"old", 100)?;
+        writer.finish()?;
+
+        let path2 = temp_dir.path().join("002.sst");
+        let mut writer = SsTableWriter::new(path2.clone(), DEFAULT_BLOCK_SIZE)?;
+        writer.add(b"other", b"value", 200)?;
+        writer.add(b"user:1", b"value", 200)?;
+        writer.finish()?;
+
+        // Range delete of "user:", then a fresh write inside the range
+        let path3 = temp_dir.path().join("003.sst");
+        let mut writer = SsTableWriter::new(path3.clone(), DEFAULT_BLOCK_SIZE)?;
+        writer.add(b"user:2", b"new", 300)?;
+        writer.add_range_tombstone(RangeTombstone::for_prefix(b"user:", 300));
+        writer.finish()?;
+
+        let output = temp_dir.path().join("merged.sst");
+        let stats = compact_sstables(&[path2.clone(), path3.clone()], &[base], output.clone())?;
+
+        let reader = SsTableReader::open(output)?;
+        assert_eq!(reader.get_record(b"user:1")?, None);
+        assert_eq!(reader.get(b"user:2")?, Some((b"new".to_vec(), 300)));
+        assert_eq!(reader.get(b"other")?, Some((b"value".to_vec(), 200)));
+        // 001.sst still holds "user:0", so the range tombstone is kept
+        assert_eq!(reader.range_tombstones().len(), 1);
+        assert_eq!(stats.range_deleted, 1);
+
+        // With nothing older left, the range tombstone is dropped
+        let output = temp_dir.path().join("merged_all.sst");
+        compact_sstables(&[path2, path3], &[], output.clone())?;
+        assert!(SsTableReader::open(output)?.range_tombstones().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compaction_keeps_versions_for_snapshots() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+
+        let path1 = temp_dir.path().join("001.sst");
+        let mut writer = SsTableWriter::new(path1.clone(), DEFAULT_BLOCK_SIZE)?;
+        writer.add_record(&Record::put(b"key".to_vec(), b"v1".to_vec(), 100).with_seq(1))?;
+        writer.add_record(&Record::put(b"other".to_vec(), b"o1".to_vec(), 100).with_seq(2))?;
+        writer.finish()?;
+
+        let path2 = temp_dir.path().join("002.sst");
+        let mut writer = SsTableWriter::new(path2.clone(), DEFAULT_BLOCK_SIZE)?;
+        writer.add_record(&Record::put(b"key".to_vec(), b"v3".to_vec(), 100).with_seq(5))?;
+        writer.add_record(&Record::put(b"key".to_vec(), b"v2".to_vec(), 100).with_seq(3))?;
+        writer.add_record(&Record::delete(b"other".to_vec(), 100).with_seq(4))?;
+        writer.finish()?;
+
+        // A snapshot taken at sequence 2 still reads v1 and o1
+        let output = temp_dir.path().join("merged.sst");
+        let stats = compact_sstables_with_snapshots(&[path1, path2], &[], &[2], output.clone())?;
+
+        let reader = SsTableReader::open(output)?;
+        assert_eq!(reader.get(b"key")?, Some((b"v3".to_vec(), 100)));
+        assert_eq!(reader.get_record_at(b"key", 2)?.unwrap().value, b"v1");
+        assert_eq!(reader.get_record_at(b"other", 2)?.unwrap().value, b"o1");
+        // The tombstone must stay: the snapshot's version of "other" sits below it
+        assert!(reader.get_record(b"other")?.unwrap().is_tombstone());
+
+        // Only v2 is invisible to every reader
+        assert_eq!(stats.duplicates_removed, 1);
+        assert_eq!(stats.tombstones_dropped, 0);
+        assert_eq!(stats.entries_merged, 4);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compaction_folds_merge_operands() -> Result<()> {
+        use crate::merge::U64AddOperator;
+        use crate::OpType;
+
+        let temp_dir = TempDir::new()?;
+
+        let path1 = temp_dir.path().join("001.sst");
+        let mut writer = SsTableWriter::new(path1.clone(), DEFAULT_BLOCK_SIZE)?;
+        writer.add_record(&Record::put(b"hits".to_vec(), b"10".to_vec(), 100).with_seq(1))?;
+        writer.add_record(&Record::merge(b"new".to_vec(), b"4".to_vec(), 100).with_seq(2))?;
+        writer.finish()?;
+
+        let path2 = temp_dir.path().join("002.sst");
+        let mut writer = SsTableWriter::new(path2.clone(), DEFAULT_BLOCK_SIZE)?;
+        writer.add_record(&Record::merge(b"hits".to_vec(), b"2".to_vec(), 100).with_seq(4))?;
+        writer.add_record(&Record::merge(b"hits".to_vec(), b"3".to_vec(), 100).with_seq(3))?;
+        writer.add_record(&Record::merge(b"new".to_vec(), b"1".to_vec(), 100).with_seq(5))?;
+        writer.finish()?;
+
+        // Without an operator the operands are kept as written
+        let output = temp_dir.path().join("kept.sst");
+        compact_sstables(&[path1.clone(), path2.clone()], &[], output.clone())?;
+        let reader = SsTableReader::open(output)?;
+        assert_eq!(reader.get_record(b"hits")?.unwrap().op, OpType::Merge);
+
+        // With one, "hits" folds onto its put and "new" has nothing under it
+        let output = temp_dir.path().join("merged.sst");
+        let options = CompactionOptions::new().with_merge_operator(Arc::new(U64AddOperator));
+        let stats = compact_sstables_with_options(&[path1, path2], &[], &options, output.clone())?;
+
+        let reader = SsTableReader::open(output)?;
+        let hits = reader.get_record(b"hits")?.unwrap();
+        assert_eq!(
+            (hits.op, hits.value, hits.seq),
+            (OpType::Put, b"15".to_vec(), 4)
+        );
+        let new = reader.get_record(b"new")?.unwrap();
+        assert_eq!((new.op, new.value), (OpType::Put, b"5".to_vec()));
+        assert_eq!(stats.operands_folded, 3);
+        assert_eq!(stats.entries_merged, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compaction_keeps_history_in_series_mode() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+
+        let path1 = temp_dir.path().join("001.sst");
+        let mut writer = SsTableWriter::new(path1.clone(), DEFAULT_BLOCK_SIZE)?;
+        writer.add_record(&Record::put(b"cpu".to_vec(), b"0.2".to_vec(), 200).with_seq(2))?;
+        writer.add_record(&Record::put(b"cpu".to_vec(), b"0.1".to_vec(), 100).with_seq(1))?;
+        writer.finish()?;
+
+        let path2 = temp_dir.path().join("002.sst");
+        let mut writer = SsTableWriter::new(path2.clone(), DEFAULT_BLOCK_SIZE)?;
+        writer.add_record(&Record::put(b"cpu".to_vec(), b"0.3".to_vec(), 300).with_seq(4))?;
+        // Corrects the sample at 200
+        writer.add_record(&Record::put(b"cpu".to_vec(), b"0.25".to_vec(), 200).with_seq(3))?;
+        writer.finish()?;
+
+        let output = temp_dir.path().join("series.sst");
+        let options = CompactionOptions::new().with_history(true);
+        let stats = compact_sstables_with_options(
+            &[path1.clone(), path2.clone()],
+            &[],
+            &options,
+            output.clone(),
+        )?;
+
+        let samples: Vec<(Timestamp, Vec<u8>)> = SsTableReader::open(output)?
+            .versions_of(b"cpu")?
+            .into_iter()
+            .map(|record| (record.timestamp, record.value))
+            .collect();
+        assert_eq!(
+            samples,
+            vec![
+                (300, b"0.3".to_vec()),
+                (200, b"0.25".to_vec()),
+                (100, b"0.1".to_vec()),
+            ]
+        );
+        assert_eq!(stats.duplicates_removed, 1);
+
+        // Without series mode only the newest sample survives
+        let output = temp_dir.path().join("latest.sst");
+        compact_sstables(&[path1, path2], &[], output.clone())?;
+        assert_eq!(SsTableReader::open(output)?.versions_of(b"cpu")?.len(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compaction_drops_expired_entries() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let now = now_timestamp()?;
+
+        // Oldest table, left out of the compaction
+        let base = temp_dir.path().join("001.sst");
+        let mut writer = SsTableWriter::new(base.clone(), DEFAULT_BLOCK_SIZE)?;
+        writer.add(b"shadowed", b"old", 100)?;
+        writer.finish()?;
+
+        let path2 = temp_dir.path().join("002.sst");
+        let mut writer = SsTableWriter::new(path2.clone(), DEFAULT_BLOCK_SIZE)?;
+        writer.add_record(&Record::put(b"expired".to_vec(), b"v".to_vec(), 100).with_ttl(10))?;
+        writer.add_record(
+            &Record::put(b"live".to_vec(), b"v".to_vec(), now).with_ttl(3600 * NANOS_PER_SECOND),
+        )?;
+        writer.add_record(&Record::put(b"shadowed".to_vec(), b"v".to_vec(), 200).with_ttl(10))?;
+        writer.finish()?;
+
+        let path3 = temp_dir.path().join("003.sst");
+        let mut writer = SsTableWriter::new(path3.clone(), DEFAULT_BLOCK_SIZE)?;
+        writer.add(b"other", b"v", 300)?;
+        writer.finish()?;
+
+        let output = temp_dir.path().join("merged.sst");
+        let stats = compact_sstables(&[path2, path3], &[base], output.clone())?;
+
+        let reader = SsTableReader::open(output)?;
+
+        // Nothing older holds "expired", so it is gone from disk
+        assert_eq!(reader.get_record(b"expired")?, None);
+        // 001.sst still holds "shadowed": the expired put stays as a tombstone
+        assert!(reader.get_record(b"shadowed")?.unwrap().is_tombstone());
+        assert_eq!(
+            reader.get_record(b"live")?.unwrap().ttl,
+            3600 * NANOS_PER_SECOND
+        );
+
+        assert_eq!(stats.expired, 2);
+        assert_eq!(stats.tombstones_dropped, 1);
+        assert_eq!(stats.entries_merged, 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compaction_orders_same_second_writes_by_sequence() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+
+        // Same timestamp everywhere; the later input holds the older write
+        let path1 = temp_dir.path().join("001.sst");
+        let mut writer = SsTableWriter::new(path1.clone(), DEFAULT_BLOCK_SIZE)?;
+        writer.add_record(&Record::put(b"key".to_vec(), b"new".to_vec(), 100).with_seq(9))?;
+        writer.finish()?;
+
+        let path2 = temp_dir.path().join("002.sst");
+        let mut writer = SsTableWriter::new(path2.clone(), DEFAULT_BLOCK_SIZE)?;
+        writer.add_record(&Record::put(b"key".to_vec(), b"old".to_vec(), 100).with_seq(4))?;
+        writer.finish()?;
+
+        let output = temp_dir.path().join("merged.sst");
+        let stats = compact_sstables(&[path1, path2], &[], output.clone())?;
+
+        let reader = SsTableReader::open(output)?;
+        assert_eq!(reader.get(b"key")?, Some((b"new".to_vec(), 100)));
+        assert_eq!(stats.duplicates_removed, 1);
+
+        Ok(())
+    }
+
     #[test]
     fn test_select_sstables() -> Result<()> {
         let temp_dir = TempDir::new()?;
@@ -17,6 +17,9 @@ pub enum StorageError {
     #[error("Resource not found: {0}")]
     NotFound(String),
 
+    #[error("Merge failed: {0}")]
+    MergeFailed(String),
+
     #[error("Key not found")]
     KeyNotFound,
 
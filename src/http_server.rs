@@ -62,6 +62,7 @@ pub struct DashboardMetrics {
 pub struct StorageMetrics {
     // Operations
     pub writes_total: u64,
+    pub deletes_total: u64,
     pub reads_total: u64,
     pub reads_hits: u64,
     pub reads_misses: u64,
@@ -79,6 +80,7 @@ pub struct StorageMetrics {
     pub memtable_size_mb: f64,
     pub memtable_entries: u64,
     pub sstable_count: u64,
+    pub open_sstables: u64,
     pub disk_usage_mb: f64,
     pub wal_size_mb: f64,
 
@@ -86,6 +88,15 @@ pub struct StorageMetrics {
     pub bloom_filter_hit_rate: f64,
     pub bloom_filter_fp_rate: f64,
 
+    // Block cache
+    pub block_cache_hits: u64,
+    pub block_cache_misses: u64,
+    pub block_cache_hit_rate: f64,
+    pub block_cache_size_mb: f64,
+
+    // Time-range pruning
+    pub sstables_pruned: u64,
+
     // Compaction
     pub compaction_space_savings: f64,
     pub write_amplification: f64,
@@ -100,6 +111,7 @@ async fn get_metrics(State(state): State<AppState>) -> Json<DashboardMetrics> {
 
     let storage_metrics = StorageMetrics {
         writes_total: metrics.writes_total.get(),
+        deletes_total: metrics.deletes_total.get(),
         reads_total: metrics.reads_total.get(),
         reads_hits: metrics.reads_hits.get(),
         reads_misses: metrics.reads_misses.get(),
@@ -115,12 +127,20 @@ async fn get_metrics(State(state): State<AppState>) -> Json<DashboardMetrics> {
         memtable_size_mb: metrics.memtable_size_bytes.get() as f64 / 1_048_576.0,
         memtable_entries: metrics.memtable_entries.get(),
         sstable_count: metrics.sstable_count.get(),
+        open_sstables: metrics.open_sstables.get(),
         disk_usage_mb: metrics.disk_usage_bytes.get() as f64 / 1_048_576.0,
         wal_size_mb: metrics.wal_size_bytes.get() as f64 / 1_048_576.0,
 
         bloom_filter_hit_rate: metrics.bloom_filter_hit_rate(),
         bloom_filter_fp_rate: metrics.bloom_filter_fp_rate(),
 
+        block_cache_hits: metrics.block_cache_hits.get(),
+        block_cache_misses: metrics.block_cache_misses.get(),
+        block_cache_hit_rate: metrics.block_cache_hit_rate(),
+        block_cache_size_mb: metrics.block_cache_size_bytes.get() as f64 / 1_048_576.0,
+
+        sstables_pruned: metrics.sstables_pruned.get(),
+
         compaction_space_savings: metrics.compaction_space_savings(),
         write_amplification: metrics.write_amplification(),
     };
@@ -176,11 +196,15 @@ async fn get_prometheus_metrics(State(state): State<AppState>) -> String {
 
     // Operations
     counter!("cityhall_writes_total",      "Total write operations",      m.writes_total.get());
+    counter!("cityhall_deletes_total",     "Total delete operations",     m.deletes_total.get());
     counter!("cityhall_reads_total",       "Total read operations",       m.reads_total.get());
     counter!("cityhall_reads_hits_total",  "Total read hits",             m.reads_hits.get());
     counter!("cityhall_reads_misses_total","Total read misses",           m.reads_misses.get());
     counter!("cityhall_flushes_total",     "Total MemTable flushes",      m.flushes_total.get());
     counter!("cityhall_compactions_total", "Total compaction runs",       m.compactions_total.get());
+    counter!("cityhall_block_cache_hits_total",   "Block cache hits",   m.block_cache_hits.get());
+    counter!("cityhall_block_cache_misses_total", "Block cache misses", m.block_cache_misses.get());
+    counter!("cityhall_sstables_pruned_total",    "SSTables skipped by time-range reads", m.sstables_pruned.get());
 
     // Latency
     gauge!("cityhall_write_latency_p50_us", "Write latency 50th percentile microseconds",
@@ -196,13 +220,16 @@ async fn get_prometheus_metrics(State(state): State<AppState>) -> String {
     gauge!("cityhall_memtable_size_bytes",  "Current MemTable size in bytes",   m.memtable_size_bytes.get());
     gauge!("cityhall_memtable_entries",     "Current MemTable entry count",      m.memtable_entries.get());
     gauge!("cityhall_sstable_count",        "Number of SSTables on disk",        m.sstable_count.get());
+    gauge!("cityhall_open_sstables",        "SSTable readers held open",         m.open_sstables.get());
     gauge!("cityhall_disk_usage_bytes",     "Total disk usage in bytes",         m.disk_usage_bytes.get());
     gauge!("cityhall_wal_size_bytes",       "Current WAL size in bytes",         m.wal_size_bytes.get());
+    gauge!("cityhall_block_cache_size_bytes", "Decoded blocks held in the block cache", m.block_cache_size_bytes.get());
 
     // Computed rates
     gauge!("cityhall_read_hit_rate",              "Read hit rate (0.0 to 1.0)",             m.read_hit_rate());
     gauge!("cityhall_bloom_filter_hit_rate",      "Bloom filter hit rate (0.0 to 1.0)",     m.bloom_filter_hit_rate());
     gauge!("cityhall_bloom_filter_fp_rate",       "Bloom filter false positive rate",        m.bloom_filter_fp_rate());
+    gauge!("cityhall_block_cache_hit_rate",       "Block cache hit rate (0.0 to 1.0)",      m.block_cache_hit_rate());
     gauge!("cityhall_compaction_space_savings",   "Compaction space savings (0.0 to 1.0)",  m.compaction_space_savings());
     gauge!("cityhall_write_amplification",        "Write amplification factor",              m.write_amplification());
 
@@ -0,0 +1,374 @@
+//! Streaming merge iterator over the whole engine
+//!
+//! `DbIterator` merges one cursor per source (active MemTable, immutable
+//! MemTable, then each SSTable newest to oldest) through a min-heap and
+//! yields the newest live version of each key visible at the iterator's
+//! sequence number. SSTables are read block by block, so memory stays
+//! bounded no matter how large the scanned range is, and callers can stop
+//! early without paying for the rest of the range. Each cursor shares its
+//! source, so the iterator holds no engine lock and keeps working through
+//! flushes and compactions. Merge operands are folded into a value as their
+//! key is reached, and expired puts are skipped like tombstones.
+
+use crate::memtable::SharedMemTableIterator;
+use crate::merge::{merge_chain, MergeOperator};
+use crate::sstable::{SsTableHandle, SsTableIterator};
+use crate::{
+    prefix_successor, Key, OpType, RangeTombstone, Record, Result, SequenceNumber, StorageError,
+    Timestamp,
+};
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::sync::Arc;
+
+/// Cursor over a single source
+pub(crate) enum SourceIterator {
+    MemTable(SharedMemTableIterator),
+    /// The table's reader is only opened (through the table cache) on the
+    /// first seek, so creating an iterator opens no files
+    SsTable(Arc<SsTableHandle>, Option<SsTableIterator>),
+}
+
+impl SourceIterator {
+    fn seek(&mut self, key: &[u8]) -> Result<()> {
+        match self {
+            SourceIterator::MemTable(iter) => {
+                iter.seek(key);
+                Ok(())
+            }
+            SourceIterator::SsTable(table, iter) => Self::table_cursor(table, iter)?.seek(key),
+        }
+    }
+
+    fn seek_to_first(&mut self) -> Result<()> {
+        match self {
+            SourceIterator::MemTable(iter) => {
+                iter.seek_to_first();
+                Ok(())
+            }
+            SourceIterator::SsTable(table, iter) => {
+                Self::table_cursor(table, iter)?.seek_to_first()
+            }
+        }
+    }
+
+    fn record(&self) -> Option<&Record> {
+        match self {
+            SourceIterator::MemTable(iter) => iter.record(),
+            SourceIterator::SsTable(_, iter) => iter.as_ref().and_then(|iter| iter.record()),
+        }
+    }
+
+    fn next(&mut self) -> Result<()> {
+        match self {
+            SourceIterator::MemTable(iter) => {
+                iter.next();
+                Ok(())
+            }
+            SourceIterator::SsTable(_, Some(iter)) => iter.next(),
+            SourceIterator::SsTable(_, None) => Ok(()),
+        }
+    }
+
+    /// The table's cursor, opening the reader on first use
+    fn table_cursor<'a>(
+        table: &SsTableHandle,
+        iter: &'a mut Option<SsTableIterator>,
+    ) -> Result<&'a mut SsTableIterator> {
+        if iter.is_none() {
+            *iter = Some(SsTableIterator::new(table.reader()?));
+        }
+        Ok(iter.as_mut().expect("cursor was just opened"))
+    }
+}
+
+/// Current version of one source, as stored in the merge heap
+struct HeapEntry {
+    key: Vec<u8>,
+    timestamp: Timestamp,
+    seq: SequenceNumber,
+    source: usize, // Index into DbIterator::sources (lower = newer)
+}
+
+/// Implement reverse ordering for min-heap
+/// (BinaryHeap is max-heap, we want the smallest key on top)
+impl PartialEq for HeapEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for HeapEntry {}
+
+impl PartialOrd for HeapEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for HeapEntry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Smallest key first
+        other
+            .key
+            .cmp(&self.key)
+            // Same key: newest version first
+            .then((self.timestamp, self.seq).cmp(&(other.timestamp, other.seq)))
+            // Same version (only unsequenced data): newest source first
+            .then(other.source.cmp(&self.source))
+    }
+}
+
+/// Lazy iterator over the live keys of a [`StorageEngine`](crate::StorageEngine)
+///
+/// Sees the writes up to the sequence number it was created at: the state
+/// at the time of [`StorageEngine::iter`](crate::StorageEngine::iter), or a
+/// snapshot's for [`StorageEngine::iter_at`](crate::StorageEngine::iter_at).
+/// Writes made while it is open are not visible through it.
+/// Starts unpositioned: call `seek` or `seek_to_first`, then read the
+/// current entry while `valid()` and move on with `next`.
+///
+/// ```ignore
+/// let mut iter = engine.iter();
+/// iter.seek(b"sensor:")?;
+/// while iter.valid() && iter.key().starts_with(b"sensor:") {
+///     println!("{:?} = {:?}", iter.key(), iter.value());
+///     iter.next()?;
+/// }
+/// ```
+pub struct DbIterator {
+    sources: Vec<SourceIterator>,
+    range_tombstones: Vec<RangeTombstone>,
+    seq: SequenceNumber,
+    merge_operator: Option<Arc<dyn MergeOperator>>,
+    /// Puts that expired by this time read as deleted
+    now: Timestamp,
+    heap: BinaryHeap<HeapEntry>,
+    current: Option<Record>,
+    /// Keys under these prefixes are skipped
+    hidden: Vec<Key>,
+}
+
+impl DbIterator {
+    /// Build from sources ordered newest to oldest and the range tombstones
+    /// of all of them, reading the writes up to `seq` as of time `now` and
+    /// folding merge operands with `merge_operator`
+    pub(crate) fn new(
+        sources: Vec<SourceIterator>,
+        mut range_tombstones: Vec<RangeTombstone>,
+        seq: SequenceNumber,
+        merge_operator: Option<Arc<dyn MergeOperator>>,
+        now: Timestamp,
+    ) -> Self {
+        range_tombstones.retain(|rt| rt.seq <= seq);
+
+        DbIterator {
+            sources,
+            range_tombstones,
+            seq,
+            merge_operator,
+            now,
+            heap: BinaryHeap::new(),
+            current: None,
+            hidden: Vec::new(),
+        }
+    }
+
+    /// Skip every key starting with one of `prefixes`
+    pub(crate) fn hiding(mut self, prefixes: &[&[u8]]) -> Self {
+        self.hidden = prefixes.iter().map(|prefix| prefix.to_vec()).collect();
+        self
+    }
+
+    /// Position at the first live key >= `key`
+    pub fn seek(&mut self, key: &[u8]) -> Result<()> {
+        for source in &mut self.sources {
+            source.seek(key)?;
+        }
+        self.rebuild_heap();
+        self.advance()
+    }
+
+    /// Position at the first live key
+    pub fn seek_to_first(&mut self) -> Result<()> {
+        for source in &mut self.sources {
+            source.seek_to_first()?;
+        }
+        self.rebuild_heap();
+        self.advance()
+    }
+
+    /// True while the iterator points at an entry
+    pub fn valid(&self) -> bool {
+        self.current.is_some()
+    }
+
+    /// Current key
+    ///
+    /// # Panics
+    /// If the iterator is not `valid()`
+    pub fn key(&self) -> &[u8] {
+        &self.current().key
+    }
+
+    /// Current value
+    ///
+    /// # Panics
+    /// If the iterator is not `valid()`
+    pub fn value(&self) -> &[u8] {
+        &self.current().value
+    }
+
+    /// Timestamp of the current version
+    ///
+    /// # Panics
+    /// If the iterator is not `valid()`
+    pub fn timestamp(&self) -> Timestamp {
+        self.current().timestamp
+    }
+
+    /// Move to the next live key
+    // Cursor-style and fallible, so not a std::iter::Iterator
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<()> {
+        self.advance()
+    }
+
+    fn current(&self) -> &Record {
+        self.current
+            .as_ref()
+            .expect("DbIterator accessed while not valid")
+    }
+
+    fn rebuild_heap(&mut self) {
+        self.heap.clear();
+        for source in 0..self.sources.len() {
+            self.push_source(source);
+        }
+    }
+
+    /// Push the current version of a source onto the heap, if it has one
+    fn push_source(&mut self, source: usize) {
+        if let Some(record) = self.sources[source].record() {
+            self.heap.push(HeapEntry {
+                key: record.key.clone(),
+                timestamp: record.timestamp,
+                seq: record.seq,
+                source,
+            });
+        }
+    }
+
+    /// Advance a source past its current version
+    fn step(&mut self, source: usize) -> Result<()> {
+        self.sources[source].next()?;
+        self.push_source(source);
+        Ok(())
+    }
+
+    /// Pop versions off the heap until a key has a live newest visible version
+    fn advance(&mut self) -> Result<()> {
+        self.current = None;
+
+        loop {
+            // Jump over hidden keys instead of reading them one by one
+            let top = self.heap.peek();
+            if let Some(hidden) = self
+                .hidden
+                .iter()
+                .find(|hidden| top.is_some_and(|top| top.key.starts_with(hidden)))
+            {
+                let past = prefix_successor(hidden);
+                if past.is_empty() {
+                    self.heap.clear();
+                    break;
+                }
+                for source in &mut self.sources {
+                    source.seek(&past)?;
+                }
+                self.rebuild_heap();
+                continue;
+            }
+
+            let top = match self.heap.pop() {
+                Some(top) => top,
+                None => break,
+            };
+            // The top entry is the newest version of the smallest key
+            let mut record = match self.sources[top.source].record() {
+                Some(record) => record.clone(),
+                None => continue,
+            };
+            self.step(top.source)?;
+
+            // Written after our sequence number: look at the next version
+            if record.seq > self.seq {
+                continue;
+            }
+
+            // An expired put reads as the tombstone it will become
+            record.expire(self.now);
+
+            // Skip older versions of the same key, collecting the ones a
+            // merge operand applies to down to its base
+            let mut chain = vec![record];
+            let mut complete = chain[0].op != OpType::Merge;
+            while self
+                .heap
+                .peek()
+                .is_some_and(|next| next.key == chain[0].key)
+            {
+                if let Some(older) = self.heap.pop() {
+                    if !complete {
+                        if let Some(version) = self.sources[older.source].record() {
+                            if version.seq <= self.seq {
+                                if self.range_deleted(version) || version.is_expired(self.now) {
+                                    complete = true;
+                                } else {
+                                    complete = version.op != OpType::Merge;
+                                    chain.push(version.clone());
+                                }
+                            }
+                        }
+                    }
+                    self.step(older.source)?;
+                }
+            }
+
+            let record = &chain[0];
+            if record.is_tombstone() || self.range_deleted(record) {
+                continue;
+            }
+
+            self.current = Some(if record.op == OpType::Merge {
+                self.fold(&chain)?
+            } else {
+                chain.swap_remove(0)
+            });
+            break;
+        }
+
+        Ok(())
+    }
+
+    /// Fold a chain of merge operands, newest first, into a put
+    fn fold(&self, chain: &[Record]) -> Result<Record> {
+        let newest = &chain[0];
+        let value = self
+            .merge_operator
+            .as_deref()
+            .and_then(|operator| merge_chain(operator, chain))
+            .ok_or_else(|| {
+                StorageError::MergeFailed(format!(
+                    "cannot merge operands of {:?}",
+                    String::from_utf8_lossy(&newest.key)
+                ))
+            })?;
+        Ok(Record::put(newest.key.clone(), value, newest.timestamp).with_seq(newest.seq))
+    }
+
+    /// Is `record` shadowed by a visible range tombstone?
+    fn range_deleted(&self, record: &Record) -> bool {
+        self.range_tombstones.iter().any(|rt| rt.shadows(record))
+    }
+}
@@ -1,17 +1,37 @@
+pub mod aggregate;
+pub mod batch;
 pub mod compaction;
 pub mod error;
 pub mod http_server;
+pub mod iterator;
 pub mod memtable;
+pub mod merge;
 pub mod metrics;
+pub mod rollup;
+pub mod series;
+pub mod snapshot;
 
 pub mod sstable;
 pub mod storage_engine;
+mod version;
 pub mod wal;
 
-pub use compaction::{compact_sstables, select_sstables_for_compaction, CompactionStats};
+pub use aggregate::{AggregateQuery, Aggregation, Bucket};
+pub use batch::WriteBatch;
+pub use compaction::{
+    compact_sstables, compact_sstables_with_options, compact_sstables_with_snapshots,
+    select_sstables_for_compaction, CompactionOptions, CompactionStats, NewerSource,
+};
 pub use error::{Result, StorageError};
+pub use iterator::DbIterator;
 pub use memtable::MemTable;
-pub use sstable::{SsTableReader, SsTableWriter};
+pub use merge::{
+    AppendOperator, F64AddOperator, MaxOperator, MergeOperator, MinOperator, U64AddOperator,
+};
+pub use rollup::RollupRule;
+pub use series::{SeriesFilter, SeriesId, SeriesKey, SeriesSamples};
+pub use snapshot::Snapshot;
+pub use sstable::{CompressionType, SsTableReader, SsTableWriter};
 pub use storage_engine::StorageEngine;
 pub use wal::Wal;
 
@@ -20,7 +40,20 @@ use serde::{Deserialize, Serialize};
 // Core types that everything uses
 pub type Key = Vec<u8>;
 pub type Value = Vec<u8>;
-pub type Timestamp = u64; // Unix timestamp in seconds
+pub type Timestamp = u64; // Unix timestamp in nanoseconds
+
+/// Nanoseconds per second, the unit of files and WAL records written before
+/// nanosecond timestamps
+pub const NANOS_PER_SECOND: Timestamp = 1_000_000_000;
+
+/// Engine-wide write counter; every write gets the next one
+///
+/// 0 is reserved for records written without one (older SSTables and
+/// standalone MemTable use).
+pub type SequenceNumber = u64;
+
+/// (key, value, timestamp) triple returned by range scans
+pub type ScanEntry = (Key, Value, Timestamp);
 
 /// Entry in the storage system
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -40,12 +73,202 @@ impl Entry {
     }
 }
 
+/// A single version of a key as stored in the WAL, MemTables and SSTables
+///
+/// Unlike [`Entry`], a record carries the operation that produced it, so a
+/// deletion can be kept around as a tombstone that shadows older versions.
+///
+/// Versions of a key are ordered by `(timestamp, seq)`: the newer timestamp
+/// wins and the sequence number breaks ties.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub key: Key,
+    pub value: Value,
+    pub timestamp: Timestamp,
+    pub op: OpType,
+    pub seq: SequenceNumber,
+    /// Nanoseconds after `timestamp` at which a put expires; 0 never expires
+    pub ttl: Timestamp,
+}
+
+impl Record {
+    pub fn put(key: Key, value: Value, timestamp: Timestamp) -> Self {
+        Record {
+            key,
+            value,
+            timestamp,
+            op: OpType::Put,
+            seq: 0,
+            ttl: 0,
+        }
+    }
+
+    /// Create a tombstone (a deletion marker with an empty value)
+    pub fn delete(key: Key, timestamp: Timestamp) -> Self {
+        Record {
+            key,
+            value: Vec::new(),
+            timestamp,
+            op: OpType::Delete,
+            seq: 0,
+            ttl: 0,
+        }
+    }
+
+    /// Create a merge operand, folded into the key's value on read
+    pub fn merge(key: Key, operand: Value, timestamp: Timestamp) -> Self {
+        Record {
+            key,
+            value: operand,
+            timestamp,
+            op: OpType::Merge,
+            seq: 0,
+            ttl: 0,
+        }
+    }
+
+    /// Create a range deletion record (WAL form of a [`RangeTombstone`])
+    pub fn range_delete(tombstone: RangeTombstone) -> Self {
+        Record {
+            key: tombstone.start,
+            value: tombstone.end,
+            timestamp: tombstone.timestamp,
+            op: OpType::RangeDelete,
+            seq: tombstone.seq,
+            ttl: 0,
+        }
+    }
+
+    /// Set the sequence number
+    pub fn with_seq(mut self, seq: SequenceNumber) -> Self {
+        self.seq = seq;
+        self
+    }
+
+    /// Set the time to live, in nanoseconds after the record's timestamp
+    pub fn with_ttl(mut self, ttl: Timestamp) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Position among the versions of the same key (greater = newer)
+    pub fn version(&self) -> (Timestamp, SequenceNumber) {
+        (self.timestamp, self.seq)
+    }
+
+    pub fn is_tombstone(&self) -> bool {
+        self.op == OpType::Delete
+    }
+
+    /// Time at which this version stops being visible, if it has a TTL
+    pub fn expires_at(&self) -> Option<Timestamp> {
+        (self.ttl != 0).then(|| self.timestamp.saturating_add(self.ttl))
+    }
+
+    /// Has this version outlived its TTL at time `now`?
+    ///
+    /// An expired put reads as a tombstone: it hides the key, including
+    /// any older versions, from every reader.
+    pub fn is_expired(&self, now: Timestamp) -> bool {
+        self.expires_at()
+            .is_some_and(|expires_at| expires_at <= now)
+    }
+
+    /// Turn an expired put into the tombstone it reads as
+    ///
+    /// Returns whether the record was expired.
+    pub(crate) fn expire(&mut self, now: Timestamp) -> bool {
+        if self.op != OpType::Put || !self.is_expired(now) {
+            return false;
+        }
+        self.op = OpType::Delete;
+        self.value.clear();
+        self.ttl = 0;
+        true
+    }
+}
+
+/// Deletion of every key in `[start, end)`
+///
+/// An empty `end` means the range is unbounded above. A range tombstone
+/// shadows the versions it is newer than, wherever they are stored; writes
+/// made after it are kept.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RangeTombstone {
+    pub start: Key,
+    pub end: Key,
+    pub timestamp: Timestamp,
+    pub seq: SequenceNumber,
+}
+
+impl RangeTombstone {
+    pub fn new(start: Key, end: Key, timestamp: Timestamp) -> Self {
+        RangeTombstone {
+            start,
+            end,
+            timestamp,
+            seq: 0,
+        }
+    }
+
+    /// Set the sequence number
+    pub fn with_seq(mut self, seq: SequenceNumber) -> Self {
+        self.seq = seq;
+        self
+    }
+
+    /// Position relative to point versions (see [`Record::version`])
+    pub fn version(&self) -> (Timestamp, SequenceNumber) {
+        (self.timestamp, self.seq)
+    }
+
+    /// Does this tombstone delete `record`?
+    pub fn shadows(&self, record: &Record) -> bool {
+        self.covers(&record.key) && self.version() > record.version()
+    }
+
+    /// Range tombstone covering every key that starts with `prefix`
+    pub fn for_prefix(prefix: &[u8], timestamp: Timestamp) -> Self {
+        RangeTombstone::new(prefix.to_vec(), prefix_successor(prefix), timestamp)
+    }
+
+    pub fn covers(&self, key: &[u8]) -> bool {
+        key >= self.start.as_slice() && (self.end.is_empty() || key < self.end.as_slice())
+    }
+}
+
+/// Current wall-clock time as a `Timestamp`
+pub(crate) fn now_timestamp() -> Result<Timestamp> {
+    Ok(std::time::SystemTime::now()
+        .duration_since(std::time::SystemTime::UNIX_EPOCH)?
+        .as_nanos() as Timestamp)
+}
+
+/// Smallest key greater than every key starting with `prefix`
+///
+/// Returns an empty key (unbounded) when no such key exists, e.g. for an
+/// empty prefix or one made only of 0xFF bytes.
+pub fn prefix_successor(prefix: &[u8]) -> Key {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < 0xFF {
+            end.push(last + 1);
+            return end;
+        }
+    }
+    Vec::new()
+}
+
 /// Operation types for WAL
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
 pub enum OpType {
     Put = 1,
     Delete = 2,
+    /// Range deletion: the record key is the range start, the value its end
+    RangeDelete = 3,
+    /// Merge operand: combined with older versions by the `MergeOperator`
+    Merge = 4,
 }
 
 impl OpType {
@@ -53,6 +276,8 @@ impl OpType {
         match value {
             1 => Some(OpType::Put),
             2 => Some(OpType::Delete),
+            3 => Some(OpType::RangeDelete),
+            4 => Some(OpType::Merge),
             _ => None,
         }
     }
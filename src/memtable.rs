@@ -1,50 +1,126 @@
-use crate::{Result, Timestamp};
+use crate::snapshot::resolve_visible;
+use crate::{OpType, RangeTombstone, Record, Result, ScanEntry, SequenceNumber, Timestamp};
+use parking_lot::RwLock;
+use std::cmp::Reverse;
+use std::collections::btree_map::Range;
 use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::sync::Arc;
+
+/// Map key: (key, timestamp, seq), ordered so the newest version of a key comes first
+type VersionKey = (Vec<u8>, Reverse<Timestamp>, Reverse<SequenceNumber>);
+
+/// Value slot stored per version: (value, op, ttl)
+type Slot = (Vec<u8>, OpType, Timestamp);
+
+/// MemTable shared by the writer and concurrent readers
+///
+/// The writer holds the write lock only while inserting; readers hold the
+/// read lock only for the duration of a lookup or a cursor step.
+pub(crate) type SharedMemTable = Arc<RwLock<MemTable>>;
 
 /// In-memory sorted table with timestamp support
 ///
-/// Stores (key -> (value, timestamp)) pairs for time-series data.
+/// Stores every version of each key, ordered newest first within the key,
+/// so snapshots taken while the MemTable is live can still read the
+/// versions they see. Deleted keys are kept as tombstones (`OpType::Delete`)
+/// so they can shadow older versions living in SSTables once the MemTable
+/// is flushed. Range deletions are kept as range tombstones for the same
+/// reason; later writes to the range stay visible.
 /// When MemTable reaches max_size, it should be flushed to disk as an SSTable.
+#[derive(Clone)]
 pub struct MemTable {
-    data: BTreeMap<Vec<u8>, (Vec<u8>, Timestamp)>,
+    data: BTreeMap<VersionKey, Slot>,
+    range_tombstones: Vec<RangeTombstone>,
+    /// Lowest timestamp of any point entry (None while there is none)
+    min_timestamp: Option<Timestamp>,
+    /// Highest timestamp of any point entry (None while there is none)
+    max_timestamp: Option<Timestamp>,
     size_bytes: usize,
     max_size: usize,
 }
 
+/// Approximate per-entry bookkeeping cost: BTreeMap overhead + timestamp + sequence number + TTL
+const ENTRY_OVERHEAD: usize = 40 + 8 + 8 + 8;
+
 impl MemTable {
     pub fn new(max_size: usize) -> Self {
         MemTable {
             data: BTreeMap::new(),
+            range_tombstones: Vec::new(),
+            min_timestamp: None,
+            max_timestamp: None,
             size_bytes: 0,
             max_size,
         }
     }
 
     /// Insert a key-value pair with timestamp
+    ///
+    /// The entry has no sequence number, so it replaces a version of the key
+    /// with the same timestamp; the engine writes through [`MemTable::apply`].
     /// Returns true if MemTable is now full and should be flushed
     pub fn put(&mut self, key: Vec<u8>, value: Vec<u8>, timestamp: Timestamp) -> Result<bool> {
-        let key_size = key.len();
-        let value_size = value.len();
-        let entry_overhead = 40 + 8; // BTreeMap overhead + timestamp size
-
-        // Check if we're updating an existing key
-        let old_value_size = if let Some((old_value, _)) = self.data.get(&key) {
-            old_value.len()
-        } else {
-            0
-        };
-
-        // Insert the entr
-        self.data.insert(key, (value, timestamp));
-
-        // Update size tracking
-        if old_value_size > 0 {
-            // Updating existing key - only value size changed
-            self.size_bytes = self.size_bytes - old_value_size + value_size;
-        } else {
-            // New key - add full entry size
-            let new_size = key_size + value_size + entry_overhead;
-            self.size_bytes += new_size;
+        self.insert(Record::put(key, value, timestamp))
+    }
+
+    /// Record a tombstone for a key
+    /// Returns true if MemTable is now full and should be flushed
+    pub fn delete(&mut self, key: Vec<u8>, timestamp: Timestamp) -> Result<bool> {
+        self.insert(Record::delete(key, timestamp))
+    }
+
+    /// Delete every key in the tombstone's range
+    ///
+    /// Covered entries stay in place for snapshots that still see them; the
+    /// tombstone hides them from newer reads and shadows older MemTables and
+    /// SSTables.
+    /// Returns true if MemTable is now full and should be flushed
+    pub fn delete_range(&mut self, tombstone: RangeTombstone) -> Result<bool> {
+        self.size_bytes += tombstone.start.len() + tombstone.end.len() + ENTRY_OVERHEAD;
+        self.range_tombstones.push(tombstone);
+
+        Ok(self.size_bytes >= self.max_size)
+    }
+
+    /// Apply a record recovered from the WAL or built by the engine
+    pub fn apply(&mut self, record: Record) -> Result<bool> {
+        match record.op {
+            OpType::RangeDelete => self.delete_range(
+                RangeTombstone::new(record.key, record.value, record.timestamp)
+                    .with_seq(record.seq),
+            ),
+            _ => self.insert(record),
+        }
+    }
+
+    fn insert(&mut self, record: Record) -> Result<bool> {
+        let key_size = record.key.len();
+        let value_size = record.value.len();
+        self.min_timestamp = Some(
+            self.min_timestamp
+                .map_or(record.timestamp, |min| min.min(record.timestamp)),
+        );
+        self.max_timestamp = Some(
+            self.max_timestamp
+                .map_or(record.timestamp, |max| max.max(record.timestamp)),
+        );
+
+        let version_key = (record.key, Reverse(record.timestamp), Reverse(record.seq));
+
+        // Insert the entry; only an unsequenced write can replace a version
+        match self
+            .data
+            .insert(version_key, (record.value, record.op, record.ttl))
+        {
+            Some((old_value, _, _)) => {
+                // Same version rewritten - only value size changed
+                self.size_bytes = self.size_bytes - old_value.len() + value_size;
+            }
+            None => {
+                // New version - add full entry size
+                self.size_bytes += key_size + value_size + ENTRY_OVERHEAD;
+            }
         }
 
         // Return true if memtable is full
@@ -52,67 +128,334 @@ impl MemTable {
     }
 
     /// Get the value for a key (ignores timestamp)
+    ///
+    /// Returns None for both missing and deleted keys; use [`MemTable::get_record`]
+    /// to tell them apart.
     pub fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
-        self.data.get(key).map(|(v, _)| v.clone())
+        self.get_with_timestamp(key).map(|(v, _)| v)
     }
 
     /// Get the value and timestamp for a key
     pub fn get_with_timestamp(&self, key: &[u8]) -> Option<(Vec<u8>, Timestamp)> {
-        self.data.get(key).map(|(v, t)| (v.clone(), *t))
+        match self.get_record(key) {
+            Some(record) if record.op == OpType::Put => Some((record.value, record.timestamp)),
+            _ => None,
+        }
+    }
+
+    /// Get the latest record for a key, including tombstones
+    ///
+    /// A key hidden by one of this MemTable's range tombstones comes back
+    /// as a point tombstone.
+    pub fn get_record(&self, key: &[u8]) -> Option<Record> {
+        self.get_record_at(key, SequenceNumber::MAX)
+    }
+
+    /// Get the newest record for a key among the writes up to `seq`
+    pub fn get_record_at(&self, key: &[u8], seq: SequenceNumber) -> Option<Record> {
+        let point = self
+            .data
+            .range(first_version(key)..)
+            .take_while(|((k, _, _), _)| k.as_slice() == key)
+            .map(|(version_key, slot)| to_record(version_key, slot))
+            .find(|record| record.seq <= seq);
+
+        resolve_visible(key, point, &self.range_tombstones, seq)
+    }
+
+    /// Every stored version of a key, newest first, tombstones included
+    ///
+    /// Only point records; range tombstones are left to the caller.
+    pub fn versions_of(&self, key: &[u8]) -> Vec<Record> {
+        self.data
+            .range(first_version(key)..)
+            .take_while(|((k, _, _), _)| k.as_slice() == key)
+            .map(|(version_key, slot)| to_record(version_key, slot))
+            .collect()
+    }
+
+    /// Is a version of `key` stored at exactly `timestamp`, tombstones
+    /// included?
+    pub fn has_version(&self, key: &[u8], timestamp: Timestamp) -> bool {
+        let first = (
+            key.to_vec(),
+            Reverse(timestamp),
+            Reverse(SequenceNumber::MAX),
+        );
+        self.data
+            .range(first..)
+            .next()
+            .is_some_and(|((k, Reverse(ts), _), _)| k.as_slice() == key && *ts == timestamp)
+    }
+
+    /// Every stored version of the keys in `[start, end)`, in key order
+    /// and newest first within a key, tombstones included
+    pub fn versions_in(&self, start: &[u8], end: &[u8]) -> Vec<Record> {
+        self.data
+            .range(first_version(start)..first_version(end))
+            .map(|(version_key, slot)| to_record(version_key, slot))
+            .collect()
     }
 
     /// Scan a range of keys
     pub fn scan(&self, start: &[u8], end: &[u8]) -> Vec<(Vec<u8>, Vec<u8>)> {
-        self.data
-            .range(start.to_vec()..end.to_vec())
-            .map(|(k, (v, _))| (k.clone(), v.clone()))
+        self.scan_with_timestamps(start, end)
+            .into_iter()
+            .map(|(k, v, _)| (k, v))
             .collect()
     }
 
     /// Scan a range with timestamps (useful for time-series queries)
-    pub fn scan_with_timestamps(
-        &self,
-        start: &[u8],
-        end: &[u8],
-    ) -> Vec<(Vec<u8>, Vec<u8>, Timestamp)> {
-        self.data
-            .range(start.to_vec()..end.to_vec())
-            .map(|(k, (v, t))| (k.clone(), v.clone(), *t))
+    pub fn scan_with_timestamps(&self, start: &[u8], end: &[u8]) -> Vec<ScanEntry> {
+        self.scan_records(start, end)
+            .into_iter()
+            .filter(|record| record.op == OpType::Put)
+            .map(|record| (record.key, record.value, record.timestamp))
             .collect()
     }
 
+    /// Scan the latest record of each key in a range, including tombstones
+    pub fn scan_records(&self, start: &[u8], end: &[u8]) -> Vec<Record> {
+        self.latest_records(self.data.range(first_version(start)..first_version(end)))
+    }
+
+    /// Cursor over every version (tombstones included) in key order
+    ///
+    /// Versions of the same key come newest first. The cursor starts
+    /// unpositioned; call `seek` or `seek_to_first`.
+    pub fn iter(&self) -> MemTableIterator<'_> {
+        MemTableIterator {
+            memtable: self,
+            range: None,
+            current: None,
+        }
+    }
+
+    /// Does any version of `key` (tombstones included) live here?
+    pub fn contains_key(&self, key: &[u8]) -> bool {
+        self.data
+            .range(first_version(key)..)
+            .next()
+            .is_some_and(|((k, _, _), _)| k.as_slice() == key)
+    }
+
+    /// Does a version of any key in `[start, end]` live here?
+    pub fn overlaps(&self, start: &[u8], end: &[u8]) -> bool {
+        self.data
+            .range(first_version(start)..)
+            .next()
+            .is_some_and(|((k, _, _), _)| k.as_slice() <= end)
+    }
+
+    /// Lowest timestamp of any point entry, None if there is none
+    ///
+    /// With client-supplied timestamps this can be older than what is
+    /// already on disk.
+    pub fn min_timestamp(&self) -> Option<Timestamp> {
+        self.min_timestamp
+    }
+
+    /// Highest timestamp of any point entry, None if there is none
+    pub fn max_timestamp(&self) -> Option<Timestamp> {
+        self.max_timestamp
+    }
+
+    /// Range tombstones recorded in this MemTable
+    pub fn range_tombstones(&self) -> &[RangeTombstone] {
+        &self.range_tombstones
+    }
+
     /// Get current size in bytes
     pub fn size_bytes(&self) -> usize {
         self.size_bytes
     }
 
-    /// Get number of entries
+    /// Get number of entries (every stored version counts)
     pub fn len(&self) -> usize {
         self.data.len()
     }
 
-    /// Check if empty
+    /// Check if empty (no entries and no range tombstones)
     pub fn is_empty(&self) -> bool {
-        self.data.is_empty()
+        self.data.is_empty() && self.range_tombstones.is_empty()
     }
 
-    /// Get all entries (for flushing to SSTable)
+    /// Get all entries
     pub fn entries(&self) -> Vec<(Vec<u8>, Vec<u8>)> {
-        self.data
-            .iter()
-            .map(|(k, (v, _))| (k.clone(), v.clone()))
+        self.entries_with_timestamps()
+            .into_iter()
+            .map(|(k, v, _)| (k, v))
             .collect()
     }
 
-    /// Get all entries with timestamps (for flushing to SSTable)
-    pub fn entries_with_timestamps(&self) -> Vec<(Vec<u8>, Vec<u8>, Timestamp)> {
+    /// Get all live entries with timestamps
+    pub fn entries_with_timestamps(&self) -> Vec<ScanEntry> {
+        self.records()
+            .into_iter()
+            .filter(|record| record.op == OpType::Put)
+            .map(|record| (record.key, record.value, record.timestamp))
+            .collect()
+    }
+
+    /// Get the latest record of every key, including tombstones
+    pub fn records(&self) -> Vec<Record> {
+        self.latest_records(self.data.iter())
+    }
+
+    /// Every version (newest first within a key) in key order
+    pub(crate) fn versions(&self) -> impl Iterator<Item = Record> + '_ {
         self.data
             .iter()
-            .map(|(k, (v, t))| (k.clone(), v.clone(), *t))
+            .map(|(version_key, slot)| to_record(version_key, slot))
+    }
+
+    /// Keep the newest version of each key, resolved against range tombstones
+    fn latest_records<'a>(
+        &self,
+        versions: impl Iterator<Item = (&'a VersionKey, &'a Slot)>,
+    ) -> Vec<Record> {
+        let mut records: Vec<Record> = Vec::new();
+
+        for (version_key, slot) in versions {
+            if records.last().is_some_and(|last| last.key == version_key.0) {
+                continue; // Older version of the previous key
+            }
+            records.push(to_record(version_key, slot));
+        }
+
+        records
+            .into_iter()
+            .filter_map(|record| {
+                let key = record.key.clone();
+                resolve_visible(
+                    &key,
+                    Some(record),
+                    &self.range_tombstones,
+                    SequenceNumber::MAX,
+                )
+            })
             .collect()
     }
 }
 
+/// Seekable cursor over a [`MemTable`], yielding every version in order
+pub struct MemTableIterator<'a> {
+    memtable: &'a MemTable,
+    range: Option<Range<'a, VersionKey, Slot>>,
+    current: Option<Record>,
+}
+
+impl MemTableIterator<'_> {
+    /// Position at the first key >= `key`
+    pub fn seek(&mut self, key: &[u8]) {
+        self.range = Some(self.memtable.data.range(first_version(key)..));
+        self.next();
+    }
+
+    /// Position at the first key
+    pub fn seek_to_first(&mut self) {
+        self.range = Some(self.memtable.data.range::<VersionKey, _>(..));
+        self.next();
+    }
+
+    pub fn valid(&self) -> bool {
+        self.current.is_some()
+    }
+
+    /// Current record; only meaningful while `valid()`
+    pub fn record(&self) -> Option<&Record> {
+        self.current.as_ref()
+    }
+
+    /// Advance to the next version
+    pub fn next(&mut self) {
+        self.current = self
+            .range
+            .as_mut()
+            .and_then(|range| range.next())
+            .map(|(version_key, slot)| to_record(version_key, slot));
+    }
+}
+
+/// Seekable cursor over a [`SharedMemTable`] that holds no lock between steps
+///
+/// Each step takes the read lock briefly and re-seeks past the current
+/// version, so a long-lived cursor never blocks the writer. Versions the
+/// writer inserts meanwhile may or may not be seen; readers pinned to a
+/// sequence number skip them anyway.
+pub(crate) struct SharedMemTableIterator {
+    memtable: SharedMemTable,
+    current: Option<Record>,
+}
+
+impl SharedMemTableIterator {
+    pub(crate) fn new(memtable: SharedMemTable) -> Self {
+        SharedMemTableIterator {
+            memtable,
+            current: None,
+        }
+    }
+
+    /// Position at the first key >= `key`
+    pub(crate) fn seek(&mut self, key: &[u8]) {
+        self.current = self.first_after(Bound::Included(first_version(key)));
+    }
+
+    /// Position at the first key
+    pub(crate) fn seek_to_first(&mut self) {
+        self.current = self.first_after(Bound::Unbounded);
+    }
+
+    /// Current record; `None` once exhausted
+    pub(crate) fn record(&self) -> Option<&Record> {
+        self.current.as_ref()
+    }
+
+    /// Advance to the next version
+    pub(crate) fn next(&mut self) {
+        if let Some(current) = self.current.take() {
+            let position = (
+                current.key,
+                Reverse(current.timestamp),
+                Reverse(current.seq),
+            );
+            self.current = self.first_after(Bound::Excluded(position));
+        }
+    }
+
+    fn first_after(&self, bound: Bound<VersionKey>) -> Option<Record> {
+        let memtable = self.memtable.read();
+        memtable
+            .data
+            .range((bound, Bound::Unbounded))
+            .next()
+            .map(|(version_key, slot)| to_record(version_key, slot))
+    }
+}
+
+/// Smallest map key for `key`: sorts before all of its versions
+fn first_version(key: &[u8]) -> VersionKey {
+    (
+        key.to_vec(),
+        Reverse(Timestamp::MAX),
+        Reverse(SequenceNumber::MAX),
+    )
+}
+
+fn to_record(
+    (key, Reverse(timestamp), Reverse(seq)): &VersionKey,
+    (value, op, ttl): &Slot,
+) -> Record {
+    Record {
+        key: key.clone(),
+        value: value.clone(),
+        timestamp: *timestamp,
+        op: *op,
+        seq: *seq,
+        ttl: *ttl,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -268,4 +611,168 @@ mod tests {
         assert_eq!(entries[0], (b"key1".to_vec(), b"value1".to_vec(), 1000));
         assert_eq!(entries[1], (b"key2".to_vec(), b"value2".to_vec(), 2000));
     }
+
+    #[test]
+    fn test_delete_leaves_tombstone() {
+        let mut memtable = MemTable::new(1024);
+
+        memtable
+            .put(b"key1".to_vec(), b"value1".to_vec(), 1000)
+            .unwrap();
+        memtable.delete(b"key1".to_vec(), 2000).unwrap();
+
+        assert_eq!(memtable.get(b"key1"), None);
+        assert!(memtable.get_record(b"key1").unwrap().is_tombstone());
+        assert!(memtable.entries_with_timestamps().is_empty());
+
+        // Tombstones must be flushed so they shadow older SSTable versions
+        let records = memtable.records();
+        assert_eq!(records, vec![Record::delete(b"key1".to_vec(), 2000)]);
+    }
+
+    #[test]
+    fn test_iterator_seek_and_next() {
+        let mut memtable = MemTable::new(1024);
+
+        for key in [b"key1", b"key3", b"key5"] {
+            memtable.put(key.to_vec(), b"v".to_vec(), 1000).unwrap();
+        }
+        memtable.delete(b"key4".to_vec(), 2000).unwrap();
+
+        let mut iter = memtable.iter();
+        assert!(!iter.valid());
+
+        // Seek lands on the first key >= target; tombstones are yielded too
+        iter.seek(b"key2");
+        let mut keys = Vec::new();
+        while let Some(record) = iter.record() {
+            keys.push(record.key.clone());
+            iter.next();
+        }
+        assert_eq!(
+            keys,
+            vec![b"key3".to_vec(), b"key4".to_vec(), b"key5".to_vec()]
+        );
+
+        iter.seek_to_first();
+        assert_eq!(iter.record().unwrap().key, b"key1");
+    }
+
+    #[test]
+    fn test_delete_range() {
+        let mut memtable = MemTable::new(1024);
+
+        for key in [b"a1", b"b1", b"b2", b"c1"] {
+            memtable.put(key.to_vec(), b"v".to_vec(), 1000).unwrap();
+        }
+
+        memtable
+            .apply(Record::range_delete(RangeTombstone::new(
+                b"b".to_vec(),
+                b"c".to_vec(),
+                2000,
+            )))
+            .unwrap();
+
+        // Covered entries are hidden; the tombstone stays to shadow older data
+        assert_eq!(memtable.get(b"b1"), None);
+        assert!(memtable.get_record(b"b2").unwrap().is_tombstone());
+        assert_eq!(memtable.get(b"c1"), Some(b"v".to_vec()));
+        assert_eq!(
+            memtable.range_tombstones(),
+            &[RangeTombstone::new(b"b".to_vec(), b"c".to_vec(), 2000)]
+        );
+        assert_eq!(memtable.scan(b"a", b"z").len(), 2);
+
+        // Writes after the range delete are visible
+        memtable.put(b"b3".to_vec(), b"new".to_vec(), 3000).unwrap();
+        assert_eq!(memtable.get(b"b3"), Some(b"new".to_vec()));
+    }
+
+    #[test]
+    fn test_versions_and_get_record_at() {
+        let mut memtable = MemTable::new(1024);
+
+        memtable
+            .apply(Record::put(b"key".to_vec(), b"v1".to_vec(), 1000).with_seq(1))
+            .unwrap();
+        memtable
+            .apply(Record::put(b"key".to_vec(), b"v2".to_vec(), 1000).with_seq(2))
+            .unwrap();
+        memtable
+            .apply(Record::delete(b"key".to_vec(), 2000).with_seq(3))
+            .unwrap();
+
+        // Every version is kept, newest first
+        assert_eq!(memtable.len(), 3);
+        let mut iter = memtable.iter();
+        iter.seek(b"key");
+        let mut seqs = Vec::new();
+        while let Some(record) = iter.record() {
+            seqs.push(record.seq);
+            iter.next();
+        }
+        assert_eq!(seqs, vec![3, 2, 1]);
+
+        // Reads at a sequence number see the state as of that write
+        assert_eq!(memtable.get(b"key"), None);
+        assert_eq!(memtable.get_record_at(b"key", 2).unwrap().value, b"v2");
+        assert_eq!(memtable.get_record_at(b"key", 1).unwrap().value, b"v1");
+        assert_eq!(memtable.get_record_at(b"key", 0), None);
+
+        // Versions are found by exact timestamp, tombstones included
+        assert!(memtable.has_version(b"key", 1000));
+        assert!(memtable.has_version(b"key", 2000));
+        assert!(!memtable.has_version(b"key", 1500));
+        assert!(!memtable.has_version(b"ke", 1000));
+
+        // A range tombstone only hides versions written before it
+        memtable
+            .apply(Record::range_delete(
+                RangeTombstone::new(b"a".to_vec(), b"z".to_vec(), 2000).with_seq(4),
+            ))
+            .unwrap();
+        memtable
+            .apply(Record::put(b"new".to_vec(), b"v".to_vec(), 2000).with_seq(5))
+            .unwrap();
+        assert!(memtable.get_record_at(b"key", 4).unwrap().is_tombstone());
+        assert_eq!(memtable.get_record_at(b"key", 3).unwrap().seq, 3);
+        assert_eq!(memtable.get(b"new"), Some(b"v".to_vec()));
+    }
+
+    #[test]
+    fn test_shared_iterator_keeps_going_across_writes() {
+        let memtable: SharedMemTable = Arc::new(RwLock::new(MemTable::new(1024 * 1024)));
+        for key in [b"a", b"c", b"e"] {
+            memtable
+                .write()
+                .put(key.to_vec(), b"v".to_vec(), 1000)
+                .unwrap();
+        }
+
+        let mut iter = SharedMemTableIterator::new(Arc::clone(&memtable));
+        iter.seek_to_first();
+        assert_eq!(iter.record().unwrap().key, b"a");
+
+        // The cursor holds no lock, so the writer is never blocked
+        memtable
+            .write()
+            .put(b"b".to_vec(), b"v".to_vec(), 1000)
+            .unwrap();
+        memtable
+            .write()
+            .put(b"a".to_vec(), b"v2".to_vec(), 2000)
+            .unwrap();
+
+        let mut keys = Vec::new();
+        while let Some(record) = iter.record() {
+            keys.push(record.key.clone());
+            iter.next();
+        }
+        // Keys behind the cursor are not revisited; keys ahead of it are seen
+        assert_eq!(
+            keys,
+            vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec(), b"e".to_vec()]
+        );
+    }
 }
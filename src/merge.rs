@@ -0,0 +1,393 @@
+//! Merge operators: read-modify-write without the read
+//!
+//! [`StorageEngine::merge`](crate::StorageEngine::merge) logs an operand
+//! (`OpType::Merge`) instead of a new value, so a counter bump is a single
+//! blind write that cannot race with other bumps. Operands are folded into
+//! the value they apply to lazily: on `get` and scans, and while flushing and
+//! compacting, where runs of operands are collapsed so they do not pile up.
+//!
+//! The engine has one operator, registered with
+//! [`StorageEngine::with_merge_operator`](crate::StorageEngine::with_merge_operator).
+//! The built-in operators work on values as text, so they compose with
+//! values written by a plain `PUT`: numbers are decimal strings.
+
+use crate::snapshot::visible_to_same_readers;
+use crate::{OpType, RangeTombstone, Record, SequenceNumber};
+
+/// Combines merge operands with the value they apply to
+///
+/// Operands are always passed oldest first.
+pub trait MergeOperator: Send + Sync {
+    /// Name, shown in logs
+    fn name(&self) -> &str;
+
+    /// Fold `operands` onto `existing` (`None` if the key is missing or deleted)
+    ///
+    /// Returns `None` if the value or an operand cannot be merged; reads of
+    /// the key then fail, and flush and compaction keep the operands as-is.
+    fn full_merge(
+        &self,
+        key: &[u8],
+        existing: Option<&[u8]>,
+        operands: &[&[u8]],
+    ) -> Option<Vec<u8>>;
+
+    /// Combine consecutive operands into one without knowing the base value
+    ///
+    /// Used by flush and compaction when the base is in an older table.
+    /// Returns `None` if the operator cannot, which keeps them separate.
+    fn partial_merge(&self, _key: &[u8], _operands: &[&[u8]]) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+/// Adds unsigned integers, e.g. counters (wrapping on overflow)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct U64AddOperator;
+
+impl MergeOperator for U64AddOperator {
+    fn name(&self) -> &str {
+        "u64_add"
+    }
+
+    fn full_merge(
+        &self,
+        _key: &[u8],
+        existing: Option<&[u8]>,
+        operands: &[&[u8]],
+    ) -> Option<Vec<u8>> {
+        let mut sum = existing.map_or(Some(0), parse::<u64>)?;
+        for operand in operands {
+            sum = sum.wrapping_add(parse::<u64>(operand)?);
+        }
+        Some(sum.to_string().into_bytes())
+    }
+
+    fn partial_merge(&self, key: &[u8], operands: &[&[u8]]) -> Option<Vec<u8>> {
+        self.full_merge(key, None, operands)
+    }
+}
+
+/// Adds floating point numbers, e.g. running totals of readings
+#[derive(Debug, Clone, Copy, Default)]
+pub struct F64AddOperator;
+
+impl MergeOperator for F64AddOperator {
+    fn name(&self) -> &str {
+        "f64_add"
+    }
+
+    fn full_merge(
+        &self,
+        _key: &[u8],
+        existing: Option<&[u8]>,
+        operands: &[&[u8]],
+    ) -> Option<Vec<u8>> {
+        let mut sum = existing.map_or(Some(0.0), parse::<f64>)?;
+        for operand in operands {
+            sum += parse::<f64>(operand)?;
+        }
+        Some(sum.to_string().into_bytes())
+    }
+
+    fn partial_merge(&self, key: &[u8], operands: &[&[u8]]) -> Option<Vec<u8>> {
+        self.full_merge(key, None, operands)
+    }
+}
+
+/// Keeps the largest number seen, e.g. a peak reading
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MaxOperator;
+
+impl MergeOperator for MaxOperator {
+    fn name(&self) -> &str {
+        "max"
+    }
+
+    fn full_merge(
+        &self,
+        _key: &[u8],
+        existing: Option<&[u8]>,
+        operands: &[&[u8]],
+    ) -> Option<Vec<u8>> {
+        pick_number(existing, operands, |candidate, best| candidate > best)
+    }
+
+    fn partial_merge(&self, key: &[u8], operands: &[&[u8]]) -> Option<Vec<u8>> {
+        self.full_merge(key, None, operands)
+    }
+}
+
+/// Keeps the smallest number seen
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MinOperator;
+
+impl MergeOperator for MinOperator {
+    fn name(&self) -> &str {
+        "min"
+    }
+
+    fn full_merge(
+        &self,
+        _key: &[u8],
+        existing: Option<&[u8]>,
+        operands: &[&[u8]],
+    ) -> Option<Vec<u8>> {
+        pick_number(existing, operands, |candidate, best| candidate < best)
+    }
+
+    fn partial_merge(&self, key: &[u8], operands: &[&[u8]]) -> Option<Vec<u8>> {
+        self.full_merge(key, None, operands)
+    }
+}
+
+/// Appends operands to the value, separated by a delimiter
+#[derive(Debug, Clone, Default)]
+pub struct AppendOperator {
+    delimiter: Vec<u8>,
+}
+
+impl AppendOperator {
+    /// Append operator that puts `delimiter` between values (may be empty)
+    pub fn new(delimiter: &[u8]) -> Self {
+        AppendOperator {
+            delimiter: delimiter.to_vec(),
+        }
+    }
+}
+
+impl MergeOperator for AppendOperator {
+    fn name(&self) -> &str {
+        "append"
+    }
+
+    fn full_merge(
+        &self,
+        _key: &[u8],
+        existing: Option<&[u8]>,
+        operands: &[&[u8]],
+    ) -> Option<Vec<u8>> {
+        let parts: Vec<&[u8]> = existing
+            .into_iter()
+            .chain(operands.iter().copied())
+            .collect();
+        Some(parts.join(self.delimiter.as_slice()))
+    }
+
+    fn partial_merge(&self, key: &[u8], operands: &[&[u8]]) -> Option<Vec<u8>> {
+        self.full_merge(key, None, operands)
+    }
+}
+
+fn parse<T: std::str::FromStr>(bytes: &[u8]) -> Option<T> {
+    std::str::from_utf8(bytes).ok()?.trim().parse().ok()
+}
+
+/// The value or operand that wins `better`, returned as written
+fn pick_number(
+    existing: Option<&[u8]>,
+    operands: &[&[u8]],
+    better: impl Fn(f64, f64) -> bool,
+) -> Option<Vec<u8>> {
+    let mut best: Option<(f64, &[u8])> = None;
+    for candidate in existing.into_iter().chain(operands.iter().copied()) {
+        let number = parse::<f64>(candidate)?;
+        if best.is_none_or(|(best, _)| better(number, best)) {
+            best = Some((number, candidate));
+        }
+    }
+    best.map(|(_, bytes)| bytes.to_vec())
+}
+
+/// Fold a chain of versions of one key into a single value
+///
+/// `chain` runs newest first from a merge operand down to the version the
+/// operands apply to: a put or tombstone, or the last operand if the base
+/// is missing or range-deleted.
+pub(crate) fn merge_chain(operator: &dyn MergeOperator, chain: &[Record]) -> Option<Vec<u8>> {
+    let (base, operands) = match chain.split_last() {
+        Some((last, rest)) if last.op == OpType::Put => (Some(last.value.as_slice()), rest),
+        Some((last, rest)) if last.op == OpType::Delete => (None, rest),
+        _ => (None, chain),
+    };
+    let operands: Vec<&[u8]> = operands.iter().rev().map(|r| r.value.as_slice()).collect();
+    operator.full_merge(&chain[0].key, base, &operands)
+}
+
+/// Collapse runs of merge operands among the versions of one key
+///
+/// `versions` holds every version of a single key in a flush or compaction,
+/// newest first. A run of operands no reader can see apart (no snapshot or
+/// range tombstone between them) is folded onto the put or tombstone under
+/// it into a single put, or, when the base is not in `versions`, combined
+/// into one operand. With `bottommost`, nothing older holds the key, so a
+/// run with no base is folded into a put as well. Operands the operator
+/// refuses are kept as they are.
+///
+/// Returns the number of versions folded away.
+pub(crate) fn collapse_merges(
+    versions: &mut Vec<Record>,
+    range_tombstones: &[&RangeTombstone],
+    snapshots: &[SequenceNumber],
+    operator: &dyn MergeOperator,
+    bottommost: bool,
+) -> usize {
+    let together = |newer: &Record, older: &Record| {
+        visible_to_same_readers(newer, older, snapshots)
+            && !range_tombstones
+                .iter()
+                .any(|rt| rt.shadows(older) && !rt.shadows(newer))
+    };
+
+    let before = versions.len();
+    let mut collapsed = Vec::with_capacity(versions.len());
+    let mut rest = std::mem::take(versions).into_iter().peekable();
+
+    while let Some(first) = rest.next() {
+        if first.op != OpType::Merge {
+            collapsed.push(first);
+            continue;
+        }
+
+        // The run, then the version under it if it can be its base
+        let mut chain = vec![first];
+        let mut has_base = false;
+        while let Some(next) = rest.next_if(|next| together(chain.last().unwrap(), next)) {
+            has_base = next.op != OpType::Merge;
+            chain.push(next);
+            if has_base {
+                break;
+            }
+        }
+
+        // A base that expires takes only itself away, not the operands on
+        // top: keep it separate so the fold cannot inherit its expiry
+        let mut expiring_base = None;
+        if has_base && chain.last().is_some_and(|base| base.ttl != 0) {
+            expiring_base = chain.pop();
+            has_base = false;
+        }
+
+        // Only a run that reaches the oldest version can count on nothing under it
+        let at_bottom = expiring_base.is_none() && rest.peek().is_none();
+        let folded = if has_base || (bottommost && at_bottom) {
+            merge_chain(operator, &chain).map(|value| (value, OpType::Put))
+        } else if chain.len() > 1 {
+            let operands: Vec<&[u8]> = chain.iter().rev().map(|r| r.value.as_slice()).collect();
+            operator
+                .partial_merge(&chain[0].key, &operands)
+                .map(|value| (value, OpType::Merge))
+        } else {
+            None
+        };
+
+        match folded {
+            Some((value, op)) => {
+                let newest = &chain[0];
+                collapsed.push(Record {
+                    key: newest.key.clone(),
+                    value,
+                    timestamp: newest.timestamp,
+                    op,
+                    seq: newest.seq,
+                    ttl: 0,
+                });
+            }
+            None => collapsed.extend(chain),
+        }
+        collapsed.extend(expiring_base);
+    }
+
+    *versions = collapsed;
+    before - versions.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn operand(value: &[u8], seq: SequenceNumber) -> Record {
+        Record::merge(b"k".to_vec(), value.to_vec(), 100).with_seq(seq)
+    }
+
+    #[test]
+    fn test_builtin_operators() {
+        let ops: &[&[u8]] = &[b"3", b"4"];
+        assert_eq!(
+            U64AddOperator.full_merge(b"k", Some(b"10"), ops),
+            Some(b"17".to_vec())
+        );
+        assert_eq!(
+            U64AddOperator.full_merge(b"k", None, ops),
+            Some(b"7".to_vec())
+        );
+        assert_eq!(U64AddOperator.full_merge(b"k", Some(b"x"), ops), None);
+        assert_eq!(
+            F64AddOperator.full_merge(b"k", Some(b"1.5"), &[b"2.25"]),
+            Some(b"3.75".to_vec())
+        );
+        assert_eq!(
+            MaxOperator.full_merge(b"k", Some(b"20.5"), &[b"3", b"21"]),
+            Some(b"21".to_vec())
+        );
+        assert_eq!(
+            MinOperator.full_merge(b"k", Some(b"20.5"), &[b"30", b"21"]),
+            Some(b"20.5".to_vec())
+        );
+        assert_eq!(
+            AppendOperator::new(b",").full_merge(b"k", Some(b"a"), &[b"b", b"c"]),
+            Some(b"a,b,c".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_collapse_folds_runs_onto_their_base() {
+        let mut versions = vec![
+            operand(b"2", 4),
+            operand(b"3", 3),
+            Record::put(b"k".to_vec(), b"10".to_vec(), 100).with_seq(2),
+            Record::put(b"k".to_vec(), b"1".to_vec(), 100).with_seq(1),
+        ];
+        let folded = collapse_merges(&mut versions, &[], &[], &U64AddOperator, false);
+        assert_eq!(folded, 2);
+        assert_eq!(versions[0].op, OpType::Put);
+        assert_eq!(versions[0].value, b"15");
+        assert_eq!(versions[0].seq, 4);
+        assert_eq!(versions[1].value, b"1");
+    }
+
+    #[test]
+    fn test_collapse_respects_snapshots_and_missing_base() {
+        // A snapshot at 3 reads 3 + 5 and must not see the operand at 4
+        let mut versions = vec![operand(b"2", 4), operand(b"3", 3), operand(b"5", 2)];
+        collapse_merges(&mut versions, &[], &[3], &U64AddOperator, false);
+        let values: Vec<&[u8]> = versions.iter().map(|r| r.value.as_slice()).collect();
+        assert_eq!(values, vec![&b"2"[..], &b"8"[..]]);
+        assert!(versions.iter().all(|r| r.op == OpType::Merge));
+
+        // Nothing older: the run becomes a put
+        let mut versions = vec![operand(b"2", 4), operand(b"3", 3)];
+        collapse_merges(&mut versions, &[], &[], &U64AddOperator, true);
+        assert_eq!(
+            versions,
+            vec![Record::put(b"k".to_vec(), b"5".to_vec(), 100).with_seq(4)]
+        );
+
+        // ...but only the run at the bottom: the one above the snapshot
+        // still applies to what the snapshot reads
+        let mut versions = vec![operand(b"2", 4), operand(b"3", 3)];
+        collapse_merges(&mut versions, &[], &[3], &U64AddOperator, true);
+        let ops: Vec<OpType> = versions.iter().map(|r| r.op).collect();
+        assert_eq!(ops, vec![OpType::Merge, OpType::Put]);
+
+        // A range tombstone between the operands cuts off what is under it
+        let rt = RangeTombstone::new(b"a".to_vec(), b"z".to_vec(), 100).with_seq(3);
+        let mut versions = vec![
+            operand(b"2", 4),
+            Record::put(b"k".to_vec(), b"10".to_vec(), 100).with_seq(2),
+        ];
+        collapse_merges(&mut versions, &[&rt], &[], &U64AddOperator, false);
+        assert_eq!(versions.len(), 2);
+        assert_eq!(versions[0].op, OpType::Merge);
+    }
+}
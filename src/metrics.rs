@@ -23,6 +23,7 @@ pub struct Metrics {
     // Writes
     pub writes_total: Counter,
     pub writes_bytes: Counter, // NEW: Total bytes written
+    pub deletes_total: Counter,
 
     // Reads
     pub reads_total: Counter,
@@ -44,6 +45,13 @@ pub struct Metrics {
     pub bloom_filter_misses: Counter,
     pub bloom_filter_false_positives: Counter,
 
+    // Block cache effectiveness
+    pub block_cache_hits: Counter,
+    pub block_cache_misses: Counter,
+
+    // Time-range pruning
+    pub sstables_pruned: Counter,
+
     // === Performance Metrics ===
     pub write_latency: Histogram,
     pub read_latency: Histogram,
@@ -55,6 +63,8 @@ pub struct Metrics {
     pub sstable_count: Gauge,
     pub disk_usage_bytes: Gauge, // NEW: Total disk usage
     pub wal_size_bytes: Gauge,
+    pub block_cache_size_bytes: Gauge,
+    pub open_sstables: Gauge,
 }
 
 impl Metrics {
@@ -62,6 +72,7 @@ impl Metrics {
         Self {
             writes_total: Counter::new(),
             writes_bytes: Counter::new(),
+            deletes_total: Counter::new(),
             reads_total: Counter::new(),
             reads_hits: Counter::new(),
             reads_misses: Counter::new(),
@@ -74,6 +85,11 @@ impl Metrics {
             bloom_filter_misses: Counter::new(),
             bloom_filter_false_positives: Counter::new(),
 
+            block_cache_hits: Counter::new(),
+            block_cache_misses: Counter::new(),
+
+            sstables_pruned: Counter::new(),
+
             write_latency: Histogram::new(),
             read_latency: Histogram::new(),
             flush_duration: Histogram::new(),
@@ -85,6 +101,8 @@ impl Metrics {
             sstable_count: Gauge::new(),
             disk_usage_bytes: Gauge::new(),
             wal_size_bytes: Gauge::new(),
+            block_cache_size_bytes: Gauge::new(),
+            open_sstables: Gauge::new(),
         }
     }
 
@@ -126,6 +144,16 @@ impl Metrics {
         fps as f64 / total_checks as f64
     }
 
+    /// Block cache hit rate (0.0 to 1.0)
+    pub fn block_cache_hit_rate(&self) -> f64 {
+        let hits = self.block_cache_hits.get();
+        let total = hits + self.block_cache_misses.get();
+        if total == 0 {
+            return 0.0;
+        }
+        hits as f64 / total as f64
+    }
+
     /// Compaction space savings (0.0 to 1.0)
     pub fn compaction_space_savings(&self) -> f64 {
         let bytes_in = self.compaction_bytes_in.get();
@@ -154,6 +182,7 @@ impl Metrics {
 
 Operations:
   Writes:      {:>12}  ({} MB)
+  Deletes:     {:>12}
   Reads:       {:>12}  (hits: {}, misses: {})
   Flushes:     {:>12}
   Compactions: {:>12}
@@ -169,6 +198,15 @@ Bloom Filter:
   Misses:      {:>12}
   False Pos:   {:>12}
 
+Block Cache:
+  Hit Rate:    {:>11.2}%
+  Hits:        {:>12}
+  Misses:      {:>12}
+  Size:        {:>9} MB
+
+Time Pruning:
+  SSTables:    {:>12}
+
 Compaction:
   Input:       {:>9} MB
   Output:      {:>9} MB
@@ -186,13 +224,14 @@ Latency (μs):
 System State:
   MemTable:    {:>9} MB  ({} entries)
   Immutable:   {:>12}
-  SSTables:    {:>12}
+  SSTables:    {:>12}  ({} open)
   WAL Size:    {:>9} MB
   Disk Usage:  {:>9} MB
 "#,
             // Operations
             self.writes_total.get(),
             self.writes_bytes.get() / 1_048_576,
+            self.deletes_total.get(),
             self.reads_total.get(),
             self.reads_hits.get(),
             self.reads_misses.get(),
@@ -207,6 +246,13 @@ System State:
             self.bloom_filter_hits.get(),
             self.bloom_filter_misses.get(),
             self.bloom_filter_false_positives.get(),
+            // Block cache
+            self.block_cache_hit_rate() * 100.0,
+            self.block_cache_hits.get(),
+            self.block_cache_misses.get(),
+            self.block_cache_size_bytes.get() / 1_048_576,
+            // Time-range pruning
+            self.sstables_pruned.get(),
             // Compaction
             self.compaction_bytes_in.get() / 1_048_576,
             self.compaction_bytes_out.get() / 1_048_576,
@@ -224,6 +270,7 @@ System State:
             self.memtable_entries.get(),
             self.immutable_count.get(),
             self.sstable_count.get(),
+            self.open_sstables.get(),
             self.wal_size_bytes.get() / 1_048_576,
             self.disk_usage_bytes.get() / 1_048_576,
         )
@@ -233,6 +280,7 @@ System State:
     pub fn reset(&self) {
         self.writes_total.reset();
         self.writes_bytes.reset();
+        self.deletes_total.reset();
         self.reads_total.reset();
         self.reads_hits.reset();
         self.reads_misses.reset();
@@ -243,6 +291,9 @@ System State:
         self.bloom_filter_hits.reset();
         self.bloom_filter_misses.reset();
         self.bloom_filter_false_positives.reset();
+        self.block_cache_hits.reset();
+        self.block_cache_misses.reset();
+        self.sstables_pruned.reset();
         self.write_latency.reset();
         self.read_latency.reset();
         self.flush_duration.reset();
@@ -253,6 +304,8 @@ System State:
         self.sstable_count.set(0);
         self.disk_usage_bytes.set(0);
         self.wal_size_bytes.set(0);
+        self.block_cache_size_bytes.set(0);
+        self.open_sstables.set(0);
     }
 }
 
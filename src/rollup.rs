@@ -0,0 +1,255 @@
+//! Rollups: aggregates maintained at ingest time
+//!
+//! A [`RollupRule`] names a key prefix and one or more bucket widths. Every
+//! numeric put under the prefix is folded, as it is written, into the count,
+//! sum, min and max of its bucket for each width. Those summaries live under
+//! derived keys in a reserved keyspace ([`ROLLUP_KEYSPACE`]), one key per
+//! source key, width and bucket:
+//!
+//! ```text
+//! ROLLUP_KEYSPACE | width (u64 BE) | source key | bucket start (u64 BE)
+//! ```
+//!
+//! [`StorageEngine::aggregate`](crate::StorageEngine::aggregate) answers a
+//! query from the coarsest rollup whose buckets tile the query's, reading a
+//! few summaries instead of every raw sample.
+//!
+//! Rollups only ever add, so they are only read where they provably match
+//! the raw samples. The same keyspace, under width 0 (never a resolution),
+//! records where that holds:
+//!
+//! ```text
+//! ROLLUP_KEYSPACE | 0 (u64 BE) | 's' | width (u64 BE) | rule prefix  → first covered timestamp
+//! ROLLUP_KEYSPACE | 0 (u64 BE) | 'd' | start key                     → end key
+//! ```
+//!
+//! The first kind says from which timestamp on every sample of a rule's keys
+//! was rolled up at a width; older buckets are read raw. The second marks
+//! keys in `[start, end)` whose samples were deleted, rewritten, merged into
+//! or given a TTL, whose rollups no longer match.
+
+use crate::aggregate::Summary;
+use crate::{prefix_successor, Key, StorageError, Timestamp, Value};
+use std::time::Duration;
+
+/// Prefix of every derived rollup key; keys under it are never rolled up
+pub const ROLLUP_KEYSPACE: &[u8] = b"\x00rollup\x00";
+
+/// Roll up the numeric samples of keys under a prefix into fixed-width buckets
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RollupRule {
+    prefix: Key,
+    /// Bucket widths in nanoseconds
+    resolutions: Vec<Timestamp>,
+}
+
+impl RollupRule {
+    /// Rule for every key starting with `prefix`, with no resolutions yet
+    pub fn new(prefix: &[u8]) -> Self {
+        RollupRule {
+            prefix: prefix.to_vec(),
+            resolutions: Vec::new(),
+        }
+    }
+
+    /// Also maintain buckets `width` wide, aligned to multiples of it since the epoch
+    ///
+    /// Widths below a nanosecond are ignored.
+    pub fn with_resolution(mut self, width: Duration) -> Self {
+        let width = width.as_nanos().min(Timestamp::MAX as u128) as Timestamp;
+        if width > 0 && !self.resolutions.contains(&width) {
+            self.resolutions.push(width);
+        }
+        self
+    }
+
+    pub fn prefix(&self) -> &[u8] {
+        &self.prefix
+    }
+
+    /// Bucket widths in nanoseconds
+    pub fn resolutions(&self) -> &[Timestamp] {
+        &self.resolutions
+    }
+
+    /// Is `key` rolled up by this rule?
+    pub fn matches(&self, key: &[u8]) -> bool {
+        key.starts_with(&self.prefix) && !key.starts_with(ROLLUP_KEYSPACE)
+    }
+
+    /// Does this rule roll up every key in `[start, end)`?
+    pub(crate) fn covers(&self, start: &[u8], end: &[u8]) -> bool {
+        let limit = prefix_successor(&self.prefix);
+        start.starts_with(&self.prefix)
+            && (end.starts_with(&self.prefix) || limit.is_empty() || end <= limit.as_slice())
+    }
+
+    /// Can a key in `[start, end)` be rolled up by this rule? An empty `end` has no bound
+    pub(crate) fn overlaps(&self, start: &[u8], end: &[u8]) -> bool {
+        let limit = prefix_successor(&self.prefix);
+        (limit.is_empty() || start < limit.as_slice())
+            && (end.is_empty() || end > self.prefix.as_slice())
+    }
+}
+
+/// Derived key holding the summary of `key`'s samples in the bucket at `bucket`
+pub(crate) fn rollup_key(width: Timestamp, key: &[u8], bucket: Timestamp) -> Key {
+    let mut derived = width_prefix(width);
+    derived.extend_from_slice(key);
+    derived.extend_from_slice(&bucket.to_be_bytes());
+    derived
+}
+
+/// Split a derived key into its width, source key and bucket start
+pub(crate) fn parse_rollup_key(derived: &[u8]) -> Option<(Timestamp, &[u8], Timestamp)> {
+    let rest = derived.strip_prefix(ROLLUP_KEYSPACE)?;
+    if rest.len() < 16 {
+        return None;
+    }
+    let (width, rest) = rest.split_at(8);
+    let (key, bucket) = rest.split_at(rest.len() - 8);
+    Some((
+        Timestamp::from_be_bytes(width.try_into().ok()?),
+        key,
+        Timestamp::from_be_bytes(bucket.try_into().ok()?),
+    ))
+}
+
+/// Derived key range holding every bucket of the keys in `[start, end)`
+///
+/// The range can also hold other keys sharing a prefix with them, so
+/// callers check the source key of what they read.
+pub(crate) fn rollup_range(width: Timestamp, start: &[u8], end: &[u8]) -> (Key, Key) {
+    let common = start.iter().zip(end).take_while(|(a, b)| a == b).count();
+
+    let mut lower = width_prefix(width);
+    lower.extend_from_slice(start);
+    let mut upper = width_prefix(width);
+    upper.extend_from_slice(&start[..common]);
+    (lower, prefix_successor(&upper))
+}
+
+/// Key recording from which timestamp on `prefix` is rolled up `width` wide
+pub(crate) fn since_key(prefix: &[u8], width: Timestamp) -> Key {
+    let mut derived = width_prefix(0);
+    derived.push(b's');
+    derived.extend_from_slice(&width.to_be_bytes());
+    derived.extend_from_slice(prefix);
+    derived
+}
+
+/// Key marking the keys from `start` on as not matching their rollups
+///
+/// Its value is the end of the marked range, exclusive; empty for no end.
+pub(crate) fn dirty_key(start: &[u8]) -> Key {
+    let mut derived = width_prefix(0);
+    derived.push(b'd');
+    derived.extend_from_slice(start);
+    derived
+}
+
+fn width_prefix(width: Timestamp) -> Key {
+    let mut prefix = ROLLUP_KEYSPACE.to_vec();
+    prefix.extend_from_slice(&width.to_be_bytes());
+    prefix
+}
+
+/// Encode a bucket summary as `count sum min max`
+pub(crate) fn encode_summary(summary: &Summary) -> Value {
+    format!(
+        "{} {} {} {}",
+        summary.count, summary.sum, summary.min, summary.max
+    )
+    .into_bytes()
+}
+
+pub(crate) fn encode_since(since: Timestamp) -> Value {
+    since.to_string().into_bytes()
+}
+
+pub(crate) fn decode_since(value: &[u8]) -> Result<Timestamp, StorageError> {
+    std::str::from_utf8(value)
+        .ok()
+        .and_then(|text| text.parse().ok())
+        .ok_or_else(|| StorageError::InvalidFormat("Invalid rollup start".to_string()))
+}
+
+pub(crate) fn decode_summary(value: &[u8]) -> Result<Summary, StorageError> {
+    let invalid = || StorageError::InvalidFormat("Invalid rollup summary".to_string());
+
+    let text = std::str::from_utf8(value).map_err(|_| invalid())?;
+    let mut fields = text.split(' ');
+    let mut next = || fields.next().ok_or_else(invalid);
+    let count = next()?.parse().map_err(|_| invalid())?;
+    let sum = next()?.parse().map_err(|_| invalid())?;
+    let min = next()?.parse().map_err(|_| invalid())?;
+    let max = next()?.parse().map_err(|_| invalid())?;
+    Ok(Summary {
+        count,
+        sum,
+        min,
+        max,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rollup_key_roundtrip() {
+        let derived = rollup_key(60, b"system.cpu", 120);
+        assert!(derived.starts_with(ROLLUP_KEYSPACE));
+        assert_eq!(
+            parse_rollup_key(&derived),
+            Some((60, b"system.cpu".as_slice(), 120))
+        );
+        assert_eq!(parse_rollup_key(b"system.cpu"), None);
+
+        // Every bucket of a key falls in its range, even with a key it prefixes
+        let (lower, upper) = rollup_range(60, b"a", b"a\0");
+        for derived in [rollup_key(60, b"a", 0), rollup_key(60, b"a", u64::MAX - 1)] {
+            assert!(derived >= lower && derived < upper);
+        }
+        assert!(rollup_key(3600, b"a", 0) >= upper);
+
+        // Bookkeeping keys are never read as summaries of any width
+        for derived in [since_key(b"a", 60), dirty_key(b"a")] {
+            assert!(derived.starts_with(ROLLUP_KEYSPACE));
+            assert!(derived < rollup_range(1, b"", b"\xff").0);
+        }
+        assert_eq!(decode_since(&encode_since(120)).unwrap(), 120);
+        assert!(decode_since(b"soon").is_err());
+    }
+
+    #[test]
+    fn test_rule_matching() {
+        let rule = RollupRule::new(b"system.")
+            .with_resolution(Duration::from_secs(60))
+            .with_resolution(Duration::from_secs(60));
+        assert_eq!(rule.resolutions(), &[60_000_000_000]);
+
+        assert!(rule.matches(b"system.cpu"));
+        assert!(!rule.matches(b"app.cpu"));
+        assert!(!RollupRule::new(b"").matches(&rollup_key(60, b"x", 0)));
+
+        assert!(rule.covers(b"system.cpu", b"system.cpu\0"));
+        assert!(rule.covers(b"system.", b"system/"));
+        assert!(!rule.covers(b"system", b"systen"));
+
+        assert!(rule.overlaps(b"a", b""));
+        assert!(rule.overlaps(b"system.cpu", b"system.cpu\0"));
+        assert!(!rule.overlaps(b"a", b"system."));
+        assert!(!rule.overlaps(b"system/", b""));
+    }
+
+    #[test]
+    fn test_summary_roundtrip() {
+        let mut summary = Summary::empty();
+        summary.add(1.5);
+        summary.add(-2.0);
+        let decoded = decode_summary(&encode_summary(&summary)).unwrap();
+        assert_eq!(decoded, summary);
+        assert!(decode_summary(b"1 2 3").is_err());
+    }
+}
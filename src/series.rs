@@ -0,0 +1,294 @@
+//! Series: a metric name plus a tag set, stored under a numeric ID
+//!
+//! Instead of packing everything into one dotted key, a sample belongs to a
+//! [`SeriesKey`] such as `cpu.load{host=a,region=eu}`. The first write to a
+//! series assigns it the next [`SeriesId`], and its samples are stored under
+//! a short key derived from that ID.
+//!
+//! Everything lives in the reserved [`SERIES_KEYSPACE`], as ordinary keys
+//! that are flushed and compacted like any other, but hidden from scans and
+//! closed to writes through the rest of the engine's API:
+//!
+//! ```text
+//! SERIES_KEYSPACE | 'n' | canonical name               -> id (u64 BE)
+//! SERIES_KEYSPACE | 'i' | id (u64 BE)                  -> canonical name
+//! SERIES_KEYSPACE | 'm' | metric \0 id                 -> (empty)
+//! SERIES_KEYSPACE | 't' | tag \0 value \0 id           -> (empty)
+//! SERIES_KEYSPACE | 'd' | id (u64 BE)                  -> samples
+//! SERIES_KEYSPACE | 'c'                                -> next id (u64 BE)
+//! ```
+//!
+//! The `m` and `t` keys are the inverted index: the IDs of every series
+//! with a metric name or a `tag=value` pair are the keys under one prefix,
+//! so a [`SeriesFilter`] is resolved by scanning one prefix per condition
+//! and intersecting the sorted ID lists, without reading any samples.
+
+use crate::{prefix_successor, Key, Result, StorageError, Timestamp, Value};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Prefix of every key the series API writes
+pub const SERIES_KEYSPACE: &[u8] = b"\x00series\x00";
+
+/// Compact numeric ID of a series, assigned on its first write
+pub type SeriesId = u64;
+
+/// A series and its (timestamp, value) samples, returned by series queries
+pub type SeriesSamples = (SeriesKey, Vec<(Timestamp, Value)>);
+
+/// A metric name and its tags
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SeriesKey {
+    metric: String,
+    tags: BTreeMap<String, String>,
+}
+
+impl SeriesKey {
+    pub fn new(metric: &str) -> Self {
+        SeriesKey {
+            metric: metric.to_string(),
+            tags: BTreeMap::new(),
+        }
+    }
+
+    /// Add a tag, replacing any earlier value for the same name
+    pub fn with_tag(mut self, name: &str, value: &str) -> Self {
+        self.tags.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    pub fn metric(&self) -> &str {
+        &self.metric
+    }
+
+    pub fn tags(&self) -> &BTreeMap<String, String> {
+        &self.tags
+    }
+
+    /// Check the metric is set and no name or value contains a NUL byte
+    pub(crate) fn validate(&self) -> Result<()> {
+        if self.metric.is_empty() {
+            return Err(StorageError::InvalidFormat(
+                "Series metric name cannot be empty".into(),
+            ));
+        }
+        let parts = self.tags.iter().flat_map(|(name, value)| [name, value]);
+        if std::iter::once(&self.metric)
+            .chain(parts)
+            .any(|part| part.contains('\0'))
+        {
+            return Err(StorageError::InvalidFormat(
+                "Series names and tags cannot contain NUL bytes".into(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// `metric \0 name \0 value ...`, tags in name order
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut encoded = self.metric.as_bytes().to_vec();
+        for (name, value) in &self.tags {
+            encoded.push(0);
+            encoded.extend_from_slice(name.as_bytes());
+            encoded.push(0);
+            encoded.extend_from_slice(value.as_bytes());
+        }
+        encoded
+    }
+
+    pub(crate) fn decode(encoded: &[u8]) -> Result<Self> {
+        let invalid = || StorageError::InvalidFormat("Invalid series name".into());
+
+        let text = std::str::from_utf8(encoded).map_err(|_| invalid())?;
+        let mut parts = text.split('\0');
+        let mut series = SeriesKey::new(parts.next().ok_or_else(invalid)?);
+        while let Some(name) = parts.next() {
+            let value = parts.next().ok_or_else(invalid)?;
+            series = series.with_tag(name, value);
+        }
+        Ok(series)
+    }
+}
+
+impl fmt::Display for SeriesKey {
+    /// `metric{name=value,...}`, or just the metric without tags
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.metric)?;
+        if self.tags.is_empty() {
+            return Ok(());
+        }
+        let tags: Vec<String> = self
+            .tags
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect();
+        write!(f, "{{{}}}", tags.join(","))
+    }
+}
+
+/// Which series a query reads: a metric name and/or `tag=value` conditions
+///
+/// Every condition must hold. A filter without any matches every series.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SeriesFilter {
+    metric: Option<String>,
+    tags: Vec<(String, String)>,
+}
+
+impl SeriesFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only series with this metric name
+    pub fn with_metric(mut self, metric: &str) -> Self {
+        self.metric = Some(metric.to_string());
+        self
+    }
+
+    /// Only series tagged `name=value`
+    pub fn with_tag(mut self, name: &str, value: &str) -> Self {
+        self.tags.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Key prefix of the index entries for each condition
+    pub(crate) fn index_prefixes(&self) -> Vec<Key> {
+        let metric = self
+            .metric
+            .iter()
+            .map(|metric| index_prefix(b'm', &[metric.as_bytes()]));
+        let tags = self
+            .tags
+            .iter()
+            .map(|(name, value)| index_prefix(b't', &[name.as_bytes(), value.as_bytes()]));
+        metric.chain(tags).collect()
+    }
+}
+
+fn keyspace(kind: u8) -> Key {
+    let mut key = SERIES_KEYSPACE.to_vec();
+    key.push(kind);
+    key
+}
+
+/// `kind` followed by each part and a NUL
+fn index_prefix(kind: u8, parts: &[&[u8]]) -> Key {
+    let mut key = keyspace(kind);
+    for part in parts {
+        key.extend_from_slice(part);
+        key.push(0);
+    }
+    key
+}
+
+/// Key mapping a series' canonical name to its ID
+pub(crate) fn name_key(series: &SeriesKey) -> Key {
+    let mut key = keyspace(b'n');
+    key.extend_from_slice(&series.encode());
+    key
+}
+
+/// Key mapping an ID back to the series' canonical name
+pub(crate) fn id_key(id: SeriesId) -> Key {
+    let mut key = keyspace(b'i');
+    key.extend_from_slice(&id.to_be_bytes());
+    key
+}
+
+/// Key range of every [`id_key`]
+pub(crate) fn id_key_range() -> (Key, Key) {
+    let start = keyspace(b'i');
+    let end = prefix_successor(&start);
+    (start, end)
+}
+
+/// Key the samples of a series are stored under
+pub(crate) fn data_key(id: SeriesId) -> Key {
+    let mut key = keyspace(b'd');
+    key.extend_from_slice(&id.to_be_bytes());
+    key
+}
+
+/// Key holding the next ID to assign
+pub(crate) fn next_id_key() -> Key {
+    keyspace(b'c')
+}
+
+/// Index entries of a series: one for its metric, one per tag
+pub(crate) fn index_keys(series: &SeriesKey, id: SeriesId) -> Vec<Key> {
+    let metric = index_prefix(b'm', &[series.metric.as_bytes()]);
+    let tags = series
+        .tags
+        .iter()
+        .map(|(name, value)| index_prefix(b't', &[name.as_bytes(), value.as_bytes()]));
+    std::iter::once(metric)
+        .chain(tags)
+        .map(|mut key| {
+            key.extend_from_slice(&id.to_be_bytes());
+            key
+        })
+        .collect()
+}
+
+/// The ID at the end of an index entry or [`id_key`]
+pub(crate) fn parse_id(key: &[u8]) -> Option<SeriesId> {
+    let start = key.len().checked_sub(8)?;
+    Some(SeriesId::from_be_bytes(key[start..].try_into().ok()?))
+}
+
+/// IDs present in every list; each list must be sorted
+pub(crate) fn intersect(lists: Vec<Vec<SeriesId>>) -> Vec<SeriesId> {
+    let mut lists = lists.into_iter();
+    let mut result = match lists.next() {
+        Some(first) => first,
+        None => return Vec::new(),
+    };
+    for list in lists {
+        result.retain(|id| list.binary_search(id).is_ok());
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_series_key_roundtrip() {
+        let series = SeriesKey::new("cpu.load")
+            .with_tag("region", "eu")
+            .with_tag("host", "a");
+        assert_eq!(series.to_string(), "cpu.load{host=a,region=eu}");
+        assert_eq!(SeriesKey::decode(&series.encode()).unwrap(), series);
+        assert_eq!(SeriesKey::new("up").to_string(), "up");
+
+        assert!(SeriesKey::new("").validate().is_err());
+        assert!(SeriesKey::new("cpu")
+            .with_tag("host", "a\0b")
+            .validate()
+            .is_err());
+    }
+
+    #[test]
+    fn test_index_keys_match_filter_prefixes() {
+        let series = SeriesKey::new("cpu").with_tag("host", "a");
+        let keys = index_keys(&series, 7);
+        let filter = SeriesFilter::new().with_metric("cpu").with_tag("host", "a");
+        for (key, prefix) in keys.iter().zip(filter.index_prefixes()) {
+            assert!(key.starts_with(&prefix));
+            assert_eq!(parse_id(key), Some(7));
+        }
+
+        // A value that extends another is a different posting list
+        let other = index_keys(&SeriesKey::new("cpu").with_tag("host", "ab"), 8);
+        assert!(!other[1].starts_with(&filter.index_prefixes()[1]));
+    }
+
+    #[test]
+    fn test_intersect() {
+        assert_eq!(intersect(vec![vec![1, 3, 5, 7], vec![3, 4, 7]]), vec![3, 7]);
+        assert_eq!(intersect(vec![vec![1, 2], vec![]]), Vec::<SeriesId>::new());
+        assert!(intersect(Vec::new()).is_empty());
+    }
+}
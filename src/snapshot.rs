@@ -0,0 +1,286 @@
+//! Consistent read snapshots
+//!
+//! A [`Snapshot`] pins the engine-wide sequence number at the time it was
+//! taken. Reads through it only see versions with a sequence number at or
+//! below that point, however many writes, flushes and compactions happen
+//! afterwards. While a snapshot is alive, flush and compaction keep every
+//! version it can still see; dropping it releases them.
+
+use crate::{OpType, RangeTombstone, Record, SequenceNumber};
+use parking_lot::Mutex;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Live snapshot sequence numbers with their reference counts
+type Registry = Arc<Mutex<BTreeMap<SequenceNumber, usize>>>;
+
+/// Read view pinned to a sequence number
+///
+/// Created by [`StorageEngine::snapshot`](crate::StorageEngine::snapshot)
+/// and released on drop.
+#[derive(Debug)]
+pub struct Snapshot {
+    seq: SequenceNumber,
+    registry: Registry,
+}
+
+impl Snapshot {
+    /// Sequence number of the newest write visible through this snapshot
+    pub fn sequence(&self) -> SequenceNumber {
+        self.seq
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        let mut live = self.registry.lock();
+        if let Some(count) = live.get_mut(&self.seq) {
+            *count -= 1;
+            if *count == 0 {
+                live.remove(&self.seq);
+            }
+        }
+    }
+}
+
+/// Registry of the snapshots handed out by an engine
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SnapshotList {
+    registry: Registry,
+}
+
+impl SnapshotList {
+    /// Register a snapshot at the current value of `last_sequence`
+    ///
+    /// The sequence number is read under the registry lock, so a flush or
+    /// compaction that has already listed the live snapshots cannot miss one
+    /// taken at a sequence number it then drops versions for.
+    pub(crate) fn acquire(&self, last_sequence: &AtomicU64) -> Snapshot {
+        let mut live = self.registry.lock();
+        let seq = last_sequence.load(Ordering::SeqCst);
+        *live.entry(seq).or_insert(0) += 1;
+        Snapshot {
+            seq,
+            registry: Arc::clone(&self.registry),
+        }
+    }
+
+    /// Sequence numbers of the live snapshots, oldest first
+    pub(crate) fn sequences(&self) -> Vec<SequenceNumber> {
+        self.registry.lock().keys().copied().collect()
+    }
+}
+
+/// Newest version of a key visible at `seq` within one source
+///
+/// `point` is the newest point record visible at `seq`; a visible range
+/// tombstone from the same source that is newer wins and comes back as a
+/// point tombstone.
+pub(crate) fn resolve_visible(
+    key: &[u8],
+    point: Option<Record>,
+    range_tombstones: &[RangeTombstone],
+    seq: SequenceNumber,
+) -> Option<Record> {
+    let newest_range = range_tombstones
+        .iter()
+        .filter(|rt| rt.seq <= seq && rt.covers(key))
+        .max_by_key(|rt| rt.version());
+
+    match (point, newest_range) {
+        (Some(point), Some(rt)) if rt.version() <= point.version() => Some(point),
+        (_, Some(rt)) => Some(Record::delete(key.to_vec(), rt.timestamp).with_seq(rt.seq)),
+        (point, None) => point,
+    }
+}
+
+/// Which versions of one key some reader can still see
+///
+/// `versions` holds every stored version of a single key, newest first.
+/// A reader at sequence number `h` sees the first version with `seq <= h`,
+/// unless a range tombstone visible at `h` shadows it. If that version is a
+/// merge operand, the reader also needs the visible versions under it, down
+/// to the put or tombstone it applies to. The current state counts as a
+/// reader at `SequenceNumber::MAX`, so its version is always kept; each
+/// live snapshot in `snapshots` keeps the one it sees as well.
+///
+/// Returns one flag per version: true if it must be kept.
+pub(crate) fn needed_versions(
+    versions: &[Record],
+    range_tombstones: &[&RangeTombstone],
+    snapshots: &[SequenceNumber],
+) -> Vec<bool> {
+    let mut keep = vec![false; versions.len()];
+
+    let horizons = snapshots
+        .iter()
+        .copied()
+        .chain(std::iter::once(SequenceNumber::MAX));
+
+    for horizon in horizons {
+        let visible = versions
+            .iter()
+            .enumerate()
+            .filter(|(_, v)| v.seq <= horizon);
+        for (i, version) in visible {
+            let range_deleted = range_tombstones
+                .iter()
+                .any(|rt| rt.seq <= horizon && rt.shadows(version));
+            if range_deleted {
+                break;
+            }
+            keep[i] = true;
+            if version.op != OpType::Merge {
+                break;
+            }
+        }
+    }
+
+    keep
+}
+
+/// Which versions of one key make up its history, for series mode
+///
+/// `versions` holds every stored version of a single key, newest first.
+/// Every version back to the newest tombstone (the tombstone included, so it
+/// keeps shadowing older tables) or range delete is a sample. A put replaces
+/// the older versions at its own timestamp, so each timestamp keeps one
+/// value, plus any merge operands written on top of it.
+///
+/// Returns one flag per version: true if it belongs to the history.
+pub(crate) fn history_versions(
+    versions: &[Record],
+    range_tombstones: &[&RangeTombstone],
+) -> Vec<bool> {
+    let mut keep = vec![false; versions.len()];
+    let mut replaced_at = None;
+
+    for (i, version) in versions.iter().enumerate() {
+        if range_tombstones.iter().any(|rt| rt.shadows(version)) {
+            break;
+        }
+        if replaced_at == Some(version.timestamp) {
+            continue;
+        }
+        keep[i] = true;
+        match version.op {
+            OpType::Put => replaced_at = Some(version.timestamp),
+            OpType::Merge => {}
+            _ => break,
+        }
+    }
+
+    keep
+}
+
+/// Does every live snapshot see both versions or neither?
+///
+/// If so, no reader can tell them apart, and flush or compaction may fold
+/// one into the other.
+pub(crate) fn visible_to_same_readers(
+    newer: &Record,
+    older: &Record,
+    snapshots: &[SequenceNumber],
+) -> bool {
+    snapshots
+        .iter()
+        .all(|&seq| (newer.seq <= seq) == (older.seq <= seq))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_registry_releases_on_drop() {
+        let list = SnapshotList::default();
+        let last_sequence = AtomicU64::new(5);
+
+        let first = list.acquire(&last_sequence);
+        let second = list.acquire(&last_sequence);
+        last_sequence.store(9, Ordering::SeqCst);
+        let third = list.acquire(&last_sequence);
+        assert_eq!(list.sequences(), vec![5, 9]);
+
+        drop(first);
+        assert_eq!(list.sequences(), vec![5, 9]);
+        drop(second);
+        assert_eq!(list.sequences(), vec![9]);
+        assert_eq!(third.sequence(), 9);
+        drop(third);
+        assert!(list.sequences().is_empty());
+    }
+
+    #[test]
+    fn test_needed_versions() {
+        // Newest first: three writes of the same key
+        let versions = vec![
+            Record::put(b"k".to_vec(), b"v3".to_vec(), 300).with_seq(30),
+            Record::put(b"k".to_vec(), b"v2".to_vec(), 200).with_seq(20),
+            Record::put(b"k".to_vec(), b"v1".to_vec(), 100).with_seq(10),
+        ];
+
+        // No snapshots: only the newest survives
+        assert_eq!(
+            needed_versions(&versions, &[], &[]),
+            vec![true, false, false]
+        );
+
+        // A snapshot between v1 and v2 still needs v1
+        assert_eq!(
+            needed_versions(&versions, &[], &[15]),
+            vec![true, false, true]
+        );
+
+        // A range tombstone at seq 25 hides v2 from the snapshot at 27,
+        // and the snapshot at 22 still sees v2
+        let rt = RangeTombstone::new(b"a".to_vec(), b"z".to_vec(), 250).with_seq(25);
+        assert_eq!(
+            needed_versions(&versions, &[&rt], &[22, 27]),
+            vec![true, true, false]
+        );
+
+        // Merge operands keep everything down to their base
+        let versions = vec![
+            Record::merge(b"k".to_vec(), b"1".to_vec(), 400).with_seq(40),
+            Record::merge(b"k".to_vec(), b"2".to_vec(), 300).with_seq(30),
+            Record::put(b"k".to_vec(), b"v2".to_vec(), 200).with_seq(20),
+            Record::put(b"k".to_vec(), b"v1".to_vec(), 100).with_seq(10),
+        ];
+        assert_eq!(
+            needed_versions(&versions, &[], &[]),
+            vec![true, true, true, false]
+        );
+        assert_eq!(
+            needed_versions(&versions, &[&rt], &[]),
+            vec![true, true, false, false]
+        );
+    }
+
+    #[test]
+    fn test_history_versions() {
+        let versions = vec![
+            Record::put(b"k".to_vec(), b"v4".to_vec(), 400).with_seq(50),
+            Record::merge(b"k".to_vec(), b"1".to_vec(), 300).with_seq(40),
+            Record::put(b"k".to_vec(), b"v3".to_vec(), 300).with_seq(30),
+            // Rewritten at the same timestamp by seq 30
+            Record::put(b"k".to_vec(), b"old".to_vec(), 300).with_seq(20),
+            Record::delete(b"k".to_vec(), 200).with_seq(15),
+            Record::put(b"k".to_vec(), b"v1".to_vec(), 100).with_seq(10),
+        ];
+
+        // Every sample down to the tombstone, which stays to shadow v1
+        assert_eq!(
+            history_versions(&versions, &[]),
+            vec![true, true, true, false, true, false]
+        );
+
+        // A range tombstone between the merge and v3 cuts the history there
+        let rt = RangeTombstone::new(b"a".to_vec(), b"z".to_vec(), 300).with_seq(35);
+        assert_eq!(
+            history_versions(&versions, &[&rt]),
+            vec![true, true, false, false, false, false]
+        );
+    }
+}
@@ -1,14 +1,45 @@
 //! Block builder and reader for SSTable data blocks
 
-use crate::Result;
+use super::format::{BLOCK_TYPE_ENTRIES, BLOCK_TYPE_NUMERIC};
+use super::gorilla::{canonical_f64, encode_numeric_block, NumericSample};
+use crate::{OpType, Record, Result};
 use bytes::{BufMut, BytesMut};
 
+/// Samples of one key worth closing a block for, so they can be stored as
+/// a numeric block instead of sharing an entry block with the next key
+pub const MIN_NUMERIC_BLOCK_SAMPLES: usize = 16;
+
+/// Entries between restart points, as in LevelDB
+///
+/// Smaller intervals make lookups decode fewer entries but store more
+/// whole keys and restart offsets.
+pub const RESTART_INTERVAL: usize = 16;
+
 /// Builds a data block with prefix compression
+///
+/// Every [`RESTART_INTERVAL`]th entry is a restart point: it stores its
+/// whole key instead of sharing a prefix with the previous one, and its
+/// offset goes in an array at the end of the block (`FLAG_RESTART_POINTS`):
+///
+/// ```text
+/// entries... | restart offset (u32 LE)... | restart count (u32 LE)
+/// ```
+///
+/// A reader can then binary-search the restart points and decode only the
+/// run of entries after one, instead of the whole block.
+///
+/// While every entry is a numeric sample of the same key, the samples are
+/// also collected, and the block is written in the numeric encoding (see
+/// [`super::gorilla`]) if that comes out smaller.
 pub struct BlockBuilder {
     buffer: BytesMut,
     last_key: Vec<u8>,
     first_key: Option<Vec<u8>>,
     entries_count: usize,
+    /// Offsets of the restart points in `buffer`
+    restarts: Vec<u32>,
+    /// Every entry so far as a numeric sample, or None once one is not
+    samples: Option<Vec<NumericSample>>,
 }
 
 impl Default for BlockBuilder {
@@ -24,18 +55,29 @@ impl BlockBuilder {
             last_key: Vec::new(),
             first_key: None,
             entries_count: 0,
+            restarts: Vec::new(),
+            samples: Some(Vec::new()),
         }
     }
 
-    /// Add an entry to the block (key must be sorted!)
-    pub fn add(&mut self, key: &[u8], value: &[u8], timestamp: u64) {
+    /// Add an entry to the block (keys must be sorted, versions of a key newest first!)
+    pub fn add(&mut self, record: &Record) {
+        let key = record.key.as_slice();
+        let value = record.value.as_slice();
+
         // Save first key
         if self.first_key.is_none() {
             self.first_key = Some(key.to_vec());
         }
 
-        // Calculate shared prefix length with previous key
-        let shared = common_prefix_len(&self.last_key, key);
+        // Calculate shared prefix length with previous key; a restart
+        // point shares nothing
+        let shared = if self.entries_count.is_multiple_of(RESTART_INTERVAL) {
+            self.restarts.push(self.buffer.len() as u32);
+            0
+        } else {
+            common_prefix_len(&self.last_key, key)
+        };
         let unshared = key.len() - shared;
 
         // Encode entry with prefix compression
@@ -50,7 +92,29 @@ impl BlockBuilder {
         self.buffer.put_slice(value);
 
         // Write timestamp
-        self.buffer.put_u64_le(timestamp);
+        self.buffer.put_u64_le(record.timestamp);
+
+        // Write value type (put or tombstone)
+        self.buffer.put_u8(record.op as u8);
+
+        // Write sequence number
+        self.buffer.put_u64_le(record.seq);
+
+        // Write TTL (usually 0, so one byte)
+        encode_varint(&mut self.buffer, record.ttl as usize);
+
+        // Still a numeric run of the block's first key?
+        let sample = canonical_f64(value)
+            .filter(|_| record.op == OpType::Put && record.ttl == 0)
+            .filter(|_| self.entries_count == 0 || self.last_key == key);
+        match (&mut self.samples, sample) {
+            (Some(samples), Some(value)) => samples.push(NumericSample {
+                timestamp: record.timestamp,
+                seq: record.seq,
+                value,
+            }),
+            (samples, _) => *samples = None,
+        }
 
         // Update last key
         self.last_key.clear();
@@ -59,9 +123,14 @@ impl BlockBuilder {
         self.entries_count += 1;
     }
 
-    /// Get current size in bytes
+    /// Number of entries if they are all numeric samples of one key, else 0
+    pub fn numeric_samples(&self) -> usize {
+        self.samples.as_ref().map_or(0, Vec::len)
+    }
+
+    /// Get current size in bytes, restart array included
     pub fn size(&self) -> usize {
-        self.buffer.len()
+        self.buffer.len() + 4 * (self.restarts.len() + 1)
     }
 
     /// Check if empty
@@ -74,20 +143,35 @@ impl BlockBuilder {
         self.first_key.as_deref()
     }
 
-    /// Finish building and return compressed data
-    pub fn finish(&mut self) -> Result<Vec<u8>> {
+    /// Finish building and return the block, not yet compressed
+    ///
+    /// The data starts with the block type (`FLAG_BLOCK_TYPES`). Entry
+    /// blocks end with their restart array.
+    pub fn finish(&mut self) -> Vec<u8> {
         if self.is_empty() {
-            return Ok(Vec::new());
+            return Vec::new();
         }
 
-        // Compress with Snappy
-        let compressed = snap::raw::Encoder::new()
-            .compress_vec(&self.buffer)
-            .map_err(|e| {
-                crate::StorageError::InvalidFormat(format!("Compression failed: {}", e))
-            })?;
-
-        Ok(compressed)
+        let numeric = match (&self.samples, &self.first_key) {
+            (Some(samples), Some(key)) => Some(encode_numeric_block(key, samples)),
+            _ => None,
+        };
+        let mut block = Vec::with_capacity(self.size() + 1);
+        match numeric {
+            Some(numeric) if numeric.len() < self.size() => {
+                block.push(BLOCK_TYPE_NUMERIC);
+                block.extend_from_slice(&numeric);
+            }
+            _ => {
+                block.push(BLOCK_TYPE_ENTRIES);
+                block.extend_from_slice(&self.buffer);
+                for offset in &self.restarts {
+                    block.extend_from_slice(&offset.to_le_bytes());
+                }
+                block.extend_from_slice(&(self.restarts.len() as u32).to_le_bytes());
+            }
+        }
+        block
     }
 
     /// Reset for reuse
@@ -96,6 +180,8 @@ impl BlockBuilder {
         self.last_key.clear();
         self.first_key = None;
         self.entries_count = 0;
+        self.restarts.clear();
+        self.samples = Some(Vec::new());
     }
 }
 
@@ -111,7 +197,7 @@ fn common_prefix_len(a: &[u8], b: &[u8]) -> usize {
 }
 
 /// Encode unsigned integer as varint (variable-length encoding)
-fn encode_varint(buf: &mut BytesMut, mut value: usize) {
+pub(crate) fn encode_varint(buf: &mut BytesMut, mut value: usize) {
     while value >= 0x80 {
         buf.put_u8((value as u8) | 0x80);
         value >>= 7;
@@ -0,0 +1,257 @@
+//! Shared LRU cache of SSTable blocks
+//!
+//! Every `get` or scan that lands in a block would otherwise read it from
+//! disk and decompress it. Hot keys (dashboards polling the same series) hit
+//! the same few blocks over and over, so blocks are kept here, keyed by
+//! (table id, block offset), in the form their readers need
+//! (`BlockData`): point lookups keep the decompressed bytes and seek their
+//! restart points, scans keep every record decoded.
+//!
+//! The cache is bounded by the memory the blocks take (for decoded ones,
+//! see `decoded_size`) and shared by all readers of an engine. Table ids are
+//! handed out by the cache itself, so they never collide, even across
+//! compacted file names.
+
+use crate::metrics::metrics;
+use crate::Record;
+use lru::LruCache;
+use parking_lot::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Default block cache capacity: 64MB of blocks
+pub const DEFAULT_BLOCK_CACHE_SIZE: usize = 64 * 1024 * 1024;
+
+/// Cache key: (table id, block offset in the file)
+type BlockKey = (u64, u64);
+
+/// A cached block
+#[derive(Clone)]
+pub(crate) enum BlockData {
+    /// Decompressed bytes, which lookups seek without decoding the rest
+    Raw(Arc<Vec<u8>>),
+    /// Every record of the block, as scans walk them
+    Decoded(Arc<Vec<Record>>),
+}
+
+/// Block with the bytes it is charged for
+struct CachedBlock {
+    data: BlockData,
+    charge: usize,
+}
+
+struct CacheState {
+    blocks: LruCache<BlockKey, CachedBlock>,
+    capacity: usize,
+    size: usize,
+}
+
+impl CacheState {
+    /// Drop least recently used blocks until the cache fits its capacity
+    fn evict_to_capacity(&mut self) {
+        while self.size > self.capacity {
+            match self.blocks.pop_lru() {
+                Some((_, block)) => self.size -= block.charge,
+                None => break,
+            }
+        }
+    }
+}
+
+/// Size-bounded LRU cache of data blocks
+///
+/// A capacity of 0 disables caching.
+pub struct BlockCache {
+    state: Mutex<CacheState>,
+    next_table_id: AtomicU64,
+}
+
+impl BlockCache {
+    /// Create a cache holding up to `capacity_bytes` of blocks
+    pub fn new(capacity_bytes: usize) -> Self {
+        BlockCache {
+            state: Mutex::new(CacheState {
+                blocks: LruCache::unbounded(),
+                capacity: capacity_bytes,
+                size: 0,
+            }),
+            next_table_id: AtomicU64::new(1),
+        }
+    }
+
+    /// Hand out the id a newly opened table caches its blocks under
+    pub fn next_table_id(&self) -> u64 {
+        self.next_table_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Look up a block, marking it most recently used
+    pub(crate) fn get(&self, table_id: u64, offset: u64) -> Option<BlockData> {
+        let mut state = self.state.lock();
+        match state.blocks.get(&(table_id, offset)) {
+            Some(block) => {
+                metrics().block_cache_hits.inc();
+                Some(block.data.clone())
+            }
+            None => {
+                metrics().block_cache_misses.inc();
+                None
+            }
+        }
+    }
+
+    /// Cache a block charged at `charge` bytes, replacing any other form of it
+    ///
+    /// Blocks larger than the whole cache are not kept.
+    pub(crate) fn insert(&self, table_id: u64, offset: u64, data: BlockData, charge: usize) {
+        let mut state = self.state.lock();
+        if charge > state.capacity {
+            return;
+        }
+
+        let block = CachedBlock { data, charge };
+        if let Some(old) = state.blocks.put((table_id, offset), block) {
+            state.size -= old.charge;
+        }
+        state.size += charge;
+        state.evict_to_capacity();
+
+        metrics().block_cache_size_bytes.set(state.size as u64);
+    }
+
+    /// Drop every cached block of a table, e.g. once its file is deleted
+    pub fn remove_table(&self, table_id: u64) {
+        let mut state = self.state.lock();
+        let keys: Vec<BlockKey> = state
+            .blocks
+            .iter()
+            .map(|(key, _)| *key)
+            .filter(|(id, _)| *id == table_id)
+            .collect();
+        for key in keys {
+            if let Some(block) = state.blocks.pop(&key) {
+                state.size -= block.charge;
+            }
+        }
+
+        metrics().block_cache_size_bytes.set(state.size as u64);
+    }
+
+    /// Change the capacity, evicting blocks if the cache no longer fits
+    pub fn set_capacity(&self, capacity_bytes: usize) {
+        let mut state = self.state.lock();
+        state.capacity = capacity_bytes;
+        state.evict_to_capacity();
+
+        metrics().block_cache_size_bytes.set(state.size as u64);
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.state.lock().capacity
+    }
+
+    /// Bytes currently charged for cached blocks
+    pub fn size_bytes(&self) -> usize {
+        self.state.lock().size
+    }
+
+    /// Number of cached blocks
+    pub fn len(&self) -> usize {
+        self.state.lock().blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Memory a decoded block takes: the record array plus every key and value
+///
+/// Each record owns two allocations, so a block of small records takes
+/// several times its decompressed size.
+pub(crate) fn decoded_size(records: &[Record]) -> usize {
+    records
+        .iter()
+        .map(|record| size_of::<Record>() + record.key.capacity() + record.value.capacity())
+        .sum()
+}
+
+impl Default for BlockCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_BLOCK_CACHE_SIZE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(key: &[u8]) -> BlockData {
+        BlockData::Decoded(Arc::new(vec![Record::put(
+            key.to_vec(),
+            b"v".to_vec(),
+            100,
+        )]))
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used_by_bytes() {
+        let cache = BlockCache::new(300);
+
+        cache.insert(1, 0, block(b"a"), 100);
+        cache.insert(1, 100, block(b"b"), 100);
+        cache.insert(1, 200, block(b"c"), 100);
+        assert_eq!(cache.size_bytes(), 300);
+
+        // Touch the first block so the second is the oldest
+        assert!(cache.get(1, 0).is_some());
+        cache.insert(2, 0, block(b"d"), 100);
+
+        assert!(cache.get(1, 100).is_none());
+        assert!(cache.get(1, 0).is_some());
+        assert!(cache.get(2, 0).is_some());
+        assert_eq!(cache.size_bytes(), 300);
+
+        // Too large to ever fit: not cached, nothing evicted
+        cache.insert(3, 0, block(b"e"), 400);
+        assert!(cache.get(3, 0).is_none());
+        assert_eq!(cache.len(), 3);
+
+        // Shrinking evicts down to the new capacity
+        cache.set_capacity(100);
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.size_bytes(), 100);
+    }
+
+    #[test]
+    fn test_remove_table_drops_only_its_blocks() {
+        let cache = BlockCache::new(1024);
+        let first = cache.next_table_id();
+        let second = cache.next_table_id();
+        assert_ne!(first, second);
+
+        cache.insert(first, 0, block(b"a"), 10);
+        cache.insert(first, 64, block(b"b"), 10);
+        cache.insert(second, 0, block(b"c"), 10);
+
+        cache.remove_table(first);
+        assert!(cache.get(first, 0).is_none());
+        assert!(cache.get(first, 64).is_none());
+        match cache.get(second, 0) {
+            Some(BlockData::Decoded(records)) => assert_eq!(records[0].key, b"c"),
+            _ => panic!("Expected the decoded block"),
+        }
+        assert_eq!(cache.size_bytes(), 10);
+
+        // Caching another form of a block replaces it
+        cache.insert(second, 0, BlockData::Raw(Arc::new(vec![0; 4])), 4);
+        assert!(matches!(cache.get(second, 0), Some(BlockData::Raw(_))));
+        assert_eq!((cache.len(), cache.size_bytes()), (1, 4));
+    }
+
+    #[test]
+    fn test_zero_capacity_caches_nothing() {
+        let cache = BlockCache::new(0);
+        cache.insert(1, 0, block(b"a"), 10);
+        assert!(cache.is_empty());
+    }
+}
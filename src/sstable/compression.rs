@@ -0,0 +1,216 @@
+//! Block compression codecs
+//!
+//! Every data block of an SSTable is compressed with the codec recorded in
+//! its header (`FLAG_COMPRESSION_TYPE`); files without one use Snappy. The
+//! engine picks the codec per file, so flushes can favour speed (LZ4, or
+//! none) while the bottom-most compaction output, which is rewritten least
+//! often, favours size (Zstd).
+//!
+//! Blocks are compressed independently, so a 16KB block cannot reuse the
+//! key prefixes and value patterns of the blocks around it. A
+//! [`ZstdDictionary`] trained on a sample of a file's blocks carries them
+//! instead: it is stored once in the file and primes the compression of
+//! every block.
+
+use crate::{Result, StorageError};
+use std::fmt;
+use std::io::Read;
+use std::str::FromStr;
+use zstd::dict::{DecoderDictionary, EncoderDictionary};
+
+/// Zstd level for data blocks: the library default, a good size/speed balance
+const ZSTD_LEVEL: i32 = 3;
+
+/// Codec applied to each data block
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[repr(u8)]
+pub enum CompressionType {
+    None = 0,
+    #[default]
+    Snappy = 1,
+    Lz4 = 2,
+    Zstd = 3,
+}
+
+impl CompressionType {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(CompressionType::None),
+            1 => Some(CompressionType::Snappy),
+            2 => Some(CompressionType::Lz4),
+            3 => Some(CompressionType::Zstd),
+            _ => None,
+        }
+    }
+
+    pub fn compress(self, data: &[u8]) -> Result<Vec<u8>> {
+        let failed = |e: &dyn fmt::Display| {
+            StorageError::InvalidFormat(format!("{} compression failed: {}", self, e))
+        };
+        match self {
+            CompressionType::None => Ok(data.to_vec()),
+            CompressionType::Snappy => snap::raw::Encoder::new()
+                .compress_vec(data)
+                .map_err(|e| failed(&e)),
+            CompressionType::Lz4 => Ok(lz4_flex::compress_prepend_size(data)),
+            CompressionType::Zstd => zstd::bulk::compress(data, ZSTD_LEVEL).map_err(|e| failed(&e)),
+        }
+    }
+
+    pub fn decompress(self, data: &[u8]) -> Result<Vec<u8>> {
+        let failed = |e: &dyn fmt::Display| {
+            StorageError::CorruptedData(format!("{} decompression failed: {}", self, e))
+        };
+        match self {
+            CompressionType::None => Ok(data.to_vec()),
+            CompressionType::Snappy => snap::raw::Decoder::new()
+                .decompress_vec(data)
+                .map_err(|e| failed(&e)),
+            CompressionType::Lz4 => {
+                lz4_flex::decompress_size_prepended(data).map_err(|e| failed(&e))
+            }
+            CompressionType::Zstd => zstd::stream::decode_all(data).map_err(|e| failed(&e)),
+        }
+    }
+}
+
+/// A Zstd dictionary shared by every data block of one file
+pub struct ZstdDictionary {
+    bytes: Vec<u8>,
+    /// Digested once, instead of for every block
+    encoder: EncoderDictionary<'static>,
+    decoder: DecoderDictionary<'static>,
+}
+
+impl ZstdDictionary {
+    /// Train a dictionary of at most `max_size` bytes on sample blocks
+    ///
+    /// Fails if the samples are too few or too small to train on.
+    pub fn train<S: AsRef<[u8]>>(samples: &[S], max_size: usize) -> Result<Self> {
+        let bytes = zstd::dict::from_samples(samples, max_size).map_err(|e| {
+            StorageError::InvalidFormat(format!("Zstd dictionary training failed: {}", e))
+        })?;
+        Ok(Self::from_bytes(bytes))
+    }
+
+    /// Load a dictionary as stored in a file
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        ZstdDictionary {
+            encoder: EncoderDictionary::copy(&bytes, ZSTD_LEVEL),
+            decoder: DecoderDictionary::copy(&bytes),
+            bytes,
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn compress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        zstd::bulk::Compressor::with_prepared_dictionary(&self.encoder)
+            .and_then(|mut compressor| compressor.compress(data))
+            .map_err(|e| StorageError::InvalidFormat(format!("zstd compression failed: {}", e)))
+    }
+
+    pub fn decompress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let mut decompressed = Vec::new();
+        zstd::stream::read::Decoder::with_prepared_dictionary(data, &self.decoder)
+            .and_then(|mut decoder| decoder.read_to_end(&mut decompressed))
+            .map_err(|e| {
+                StorageError::CorruptedData(format!("zstd decompression failed: {}", e))
+            })?;
+        Ok(decompressed)
+    }
+}
+
+impl fmt::Debug for ZstdDictionary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ZstdDictionary")
+            .field("size", &self.bytes.len())
+            .finish()
+    }
+}
+
+impl FromStr for CompressionType {
+    type Err = StorageError;
+
+    fn from_str(name: &str) -> std::result::Result<Self, Self::Err> {
+        match name.to_ascii_lowercase().as_str() {
+            "none" => Ok(CompressionType::None),
+            "snappy" => Ok(CompressionType::Snappy),
+            "lz4" => Ok(CompressionType::Lz4),
+            "zstd" => Ok(CompressionType::Zstd),
+            _ => Err(StorageError::InvalidFormat(format!(
+                "Unknown compression type: {}",
+                name
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for CompressionType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            CompressionType::None => "none",
+            CompressionType::Snappy => "snappy",
+            CompressionType::Lz4 => "lz4",
+            CompressionType::Zstd => "zstd",
+        };
+        f.write_str(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codecs_roundtrip() {
+        let data: Vec<u8> = (0..4096u32)
+            .flat_map(|i| format!("sensor.{}:{}", i % 7, i).into_bytes())
+            .collect();
+        for codec in [
+            CompressionType::None,
+            CompressionType::Snappy,
+            CompressionType::Lz4,
+            CompressionType::Zstd,
+        ] {
+            let compressed = codec.compress(&data).unwrap();
+            assert_eq!(codec.decompress(&compressed).unwrap(), data, "{}", codec);
+            assert_eq!(CompressionType::from_u8(codec as u8), Some(codec));
+            assert_eq!(codec.to_string().parse::<CompressionType>().unwrap(), codec);
+        }
+        assert!(CompressionType::Zstd.decompress(b"not zstd").is_err());
+        assert!("gzip".parse::<CompressionType>().is_err());
+    }
+
+    #[test]
+    fn test_dictionary_roundtrip() {
+        // Short blocks that mostly repeat each other, not themselves
+        let block = |i: u32| -> Vec<u8> {
+            format!(
+                "sensor.temperature.building{}.floor{}.room{}|value={}.25|unit=celsius|status=ok",
+                i % 7,
+                i % 3,
+                i,
+                i * 31 % 97
+            )
+            .into_bytes()
+        };
+        let samples: Vec<Vec<u8>> = (0..100).map(block).collect();
+        let dictionary = ZstdDictionary::train(&samples, 4096).unwrap();
+        assert!(!dictionary.as_bytes().is_empty() && dictionary.as_bytes().len() <= 4096);
+
+        // A block it was not trained on still benefits
+        let data = block(1000);
+        let compressed = dictionary.compress(&data).unwrap();
+        assert!(compressed.len() < CompressionType::Zstd.compress(&data).unwrap().len());
+
+        let loaded = ZstdDictionary::from_bytes(dictionary.as_bytes().to_vec());
+        assert_eq!(loaded.decompress(&compressed).unwrap(), data);
+        // The frame needs its dictionary
+        assert!(CompressionType::Zstd.decompress(&compressed).is_err());
+
+        assert!(ZstdDictionary::train(&samples[..1], 4096).is_err());
+    }
+}
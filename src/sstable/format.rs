@@ -1,13 +1,26 @@
 //! SSTable file format constants and structures
 
-use crate::Result;
+use super::checksum::crc32c;
+use super::compression::CompressionType;
+use crate::{RangeTombstone, Result, SequenceNumber, Timestamp, NANOS_PER_SECOND};
 use bytes::{Buf, BufMut, BytesMut};
 
 /// Magic number to identify SSTable files (ASCII: "SSTB")
 pub const MAGIC_NUMBER: u32 = 0x53535442;
 
 /// Current format version
-pub const VERSION: u32 = 1;
+///
+/// - 1: no checksums
+/// - 2: every section (data block, bloom filter, index, meta block, meta
+///   index) is followed by a CRC32C trailer of [`CHECKSUM_SIZE`] bytes,
+///   and the header and footer each carry the CRC32C of their other bytes
+///
+/// Sizes and offsets in the index, footer and meta index never include the
+/// trailer.
+pub const VERSION: u32 = 2;
+
+/// Size of the CRC32C trailer after each section of a v2 file
+pub const CHECKSUM_SIZE: usize = 4;
 
 /// Size of header in bytes
 pub const HEADER_SIZE: usize = 64;
@@ -18,6 +31,73 @@ pub const FOOTER_SIZE: usize = 64;
 /// Default block size (16KB)
 pub const DEFAULT_BLOCK_SIZE: usize = 16 * 1024;
 
+/// Header flag: every block entry is followed by a one-byte `OpType`
+///
+/// Files written before tombstone support leave this unset; all of their
+/// entries are puts.
+pub const FLAG_OP_TYPES: u32 = 1 << 0;
+
+/// Header flag: every block entry ends with a u64 sequence number, and so
+/// does every range tombstone
+///
+/// Versions without one (older files) get sequence number 0. A table with
+/// this flag may hold several versions of a key, newest first.
+pub const FLAG_SEQUENCE_NUMBERS: u32 = 1 << 1;
+
+/// Header flag: every block entry ends with a varint TTL (0 = never
+/// expires)
+///
+/// Entries in files without it never expire.
+pub const FLAG_TTLS: u32 = 1 << 2;
+
+/// Header flag: timestamps and TTLs (entries, range tombstones and the
+/// header's min/max) are in nanoseconds
+///
+/// Files without it count in seconds; readers scale them up on load.
+pub const FLAG_NANOSECOND_TIMESTAMPS: u32 = 1 << 3;
+
+/// Header flag: the header counts the table's point tombstones and
+/// expiring puts
+///
+/// Either can hide older versions of a key. Without the flag the count is
+/// unknown and readers must assume the table has some.
+pub const FLAG_TOMBSTONE_COUNT: u32 = 1 << 4;
+
+/// Header flag: every data block starts, once decompressed, with a
+/// block-type byte
+///
+/// Blocks in files without it are all lists of entries.
+pub const FLAG_BLOCK_TYPES: u32 = 1 << 5;
+
+/// Header flag: the header records the codec of the data blocks
+///
+/// Blocks in files without it are Snappy-compressed.
+pub const FLAG_COMPRESSION_TYPE: u32 = 1 << 6;
+
+/// Header flag: every entry block ends with its restart points (see
+/// [`super::block::BlockBuilder`])
+///
+/// Entry blocks in files without it are only entries, each sharing a
+/// prefix with the one before.
+pub const FLAG_RESTART_POINTS: u32 = 1 << 7;
+
+/// Block type: prefix-compressed entries
+pub const BLOCK_TYPE_ENTRIES: u8 = 0;
+
+/// Block type: numeric samples of one key (see [`super::gorilla`])
+pub const BLOCK_TYPE_NUMERIC: u8 = 1;
+
+/// Name of the meta block holding a table's range tombstones
+pub const META_RANGE_TOMBSTONES: &str = "cityhall.range_tombstones";
+
+/// Name of the meta block holding a table's smallest and largest key
+pub const META_KEY_RANGE: &str = "cityhall.key_range";
+
+/// Name of the meta block holding the Zstd dictionary of the data blocks
+///
+/// Only Zstd tables have one; every data block is compressed with it.
+pub const META_COMPRESSION_DICTIONARY: &str = "cityhall.compression_dictionary";
+
 /// File header
 #[derive(Debug, Clone)]
 pub struct Header {
@@ -26,6 +106,10 @@ pub struct Header {
     pub num_blocks: u32,
     pub min_timestamp: u64,
     pub max_timestamp: u64,
+    pub flags: u32,
+    pub max_sequence: SequenceNumber, // Highest sequence number in the table
+    pub tombstones: u32,              // Point tombstones and expiring puts (FLAG_TOMBSTONE_COUNT)
+    pub compression: u8,              // CompressionType of the data blocks (FLAG_COMPRESSION_TYPE)
 }
 
 impl Default for Header {
@@ -42,7 +126,46 @@ impl Header {
             num_blocks: 0,
             min_timestamp: u64::MAX,
             max_timestamp: 0,
+            flags: FLAG_OP_TYPES
+                | FLAG_SEQUENCE_NUMBERS
+                | FLAG_TTLS
+                | FLAG_NANOSECOND_TIMESTAMPS
+                | FLAG_TOMBSTONE_COUNT
+                | FLAG_BLOCK_TYPES
+                | FLAG_COMPRESSION_TYPE
+                | FLAG_RESTART_POINTS,
+            max_sequence: 0,
+            tombstones: 0,
+            compression: CompressionType::default() as u8,
+        }
+    }
+
+    /// Check whether a format feature flag is set
+    pub fn has_flag(&self, flag: u32) -> bool {
+        self.flags & flag != 0
+    }
+
+    /// Does the file checksum its sections (format version 2 and up)?
+    pub fn has_checksums(&self) -> bool {
+        self.version >= 2
+    }
+
+    /// Codec of this file's data blocks
+    pub fn compression_type(&self) -> Result<CompressionType> {
+        if !self.has_flag(FLAG_COMPRESSION_TYPE) {
+            return Ok(CompressionType::Snappy);
         }
+        CompressionType::from_u8(self.compression).ok_or_else(|| {
+            crate::StorageError::InvalidFormat(format!(
+                "Unknown compression type: {}",
+                self.compression
+            ))
+        })
+    }
+
+    /// Factor that turns this file's stored timestamps into nanoseconds
+    pub fn timestamp_scale(&self) -> Timestamp {
+        timestamp_scale(self.flags)
     }
 
     pub fn encode(&self) -> Vec<u8> {
@@ -53,13 +176,21 @@ impl Header {
         buf.put_u32_le(self.num_blocks);
         buf.put_u64_le(self.min_timestamp);
         buf.put_u64_le(self.max_timestamp);
+        buf.put_u32_le(self.flags);
+        buf.put_u64_le(self.max_sequence);
+        buf.put_u32_le(self.tombstones);
+        buf.put_u8(self.compression);
 
-        // Pad to HEADER_SIZE
+        // Pad to HEADER_SIZE; from v2 the last bytes are the checksum
         while buf.len() < HEADER_SIZE {
             buf.put_u8(0);
         }
-
-        buf.to_vec()
+        let mut buf = buf.to_vec();
+        if self.has_checksums() {
+            let checksum = crc32c(&buf[..HEADER_SIZE - CHECKSUM_SIZE]);
+            buf[HEADER_SIZE - CHECKSUM_SIZE..].copy_from_slice(&checksum.to_le_bytes());
+        }
+        buf
     }
 
     pub fn decode(data: &[u8]) -> Result<Self> {
@@ -80,9 +211,24 @@ impl Header {
         }
 
         let version = buf.get_u32_le();
+        if !(1..=VERSION).contains(&version) {
+            return Err(crate::StorageError::InvalidFormat(format!(
+                "Unsupported format version: {}",
+                version
+            )));
+        }
+        if version >= 2 {
+            verify_checksum(&data[..HEADER_SIZE], "header")?;
+        }
+
         let num_blocks = buf.get_u32_le();
         let min_timestamp = buf.get_u64_le();
         let max_timestamp = buf.get_u64_le();
+        // Older files zero-padded this area, so they decode with no flags set
+        let flags = buf.get_u32_le();
+        let max_sequence = buf.get_u64_le();
+        let tombstones = buf.get_u32_le();
+        let compression = buf.get_u8();
 
         Ok(Header {
             magic,
@@ -90,21 +236,88 @@ impl Header {
             num_blocks,
             min_timestamp,
             max_timestamp,
+            flags,
+            max_sequence,
+            tombstones,
+            compression,
         })
     }
 }
 
+/// Check data whose last [`CHECKSUM_SIZE`] bytes are the CRC32C of the rest
+///
+/// `what` names the data in the error.
+pub fn verify_checksum(data: &[u8], what: &str) -> Result<()> {
+    let split = data.len().checked_sub(CHECKSUM_SIZE).ok_or_else(|| {
+        crate::StorageError::CorruptedData(format!("{} too short for its checksum", what))
+    })?;
+    let (body, trailer) = data.split_at(split);
+    let stored = u32::from_le_bytes(trailer.try_into().expect("CHECKSUM_SIZE bytes"));
+    check(crc32c(body), stored, what)
+}
+
+fn check(computed: u32, stored: u32, what: &str) -> Result<()> {
+    if computed != stored {
+        return Err(crate::StorageError::CorruptedData(format!(
+            "Checksum mismatch in {}: stored {:08x}, computed {:08x}",
+            what, stored, computed
+        )));
+    }
+    Ok(())
+}
+
+/// Factor that turns timestamps stored under header `flags` into nanoseconds
+pub fn timestamp_scale(flags: u32) -> Timestamp {
+    if flags & FLAG_NANOSECOND_TIMESTAMPS != 0 {
+        1
+    } else {
+        NANOS_PER_SECOND
+    }
+}
+
 /// File footer
 #[derive(Debug, Clone)]
 pub struct Footer {
-    pub index_offset: u64, // Where index block starts
-    pub bloom_offset: u64, // Where bloom filter starts
-    pub index_size: u32,   // Size of index block
-    pub bloom_size: u32,   // Size of bloom filter
-    pub checksum: u32,     // Checksum of footer
+    pub index_offset: u64,      // Where index block starts
+    pub bloom_offset: u64,      // Where bloom filter starts
+    pub index_size: u32,        // Size of index block
+    pub bloom_size: u32,        // Size of bloom filter
+    pub checksum: u32,          // CRC32C of the rest of the footer (v2; 0 in v1)
+    pub meta_index_offset: u64, // Where the meta index starts
+    pub meta_index_size: u32,   // Size of meta index (0 = no meta blocks)
 }
 
+/// Offset of `checksum` in an encoded footer
+const FOOTER_CHECKSUM_OFFSET: usize = 24;
+
 impl Footer {
+    /// Encode with `checksum` set to the CRC32C of the footer (v2)
+    pub fn encode_with_checksum(&self) -> Vec<u8> {
+        let mut buf = Footer {
+            checksum: 0,
+            ..self.clone()
+        }
+        .encode();
+        let checksum = crc32c(&buf);
+        buf[FOOTER_CHECKSUM_OFFSET..FOOTER_CHECKSUM_OFFSET + 4]
+            .copy_from_slice(&checksum.to_le_bytes());
+        buf
+    }
+
+    /// Check the checksum of an encoded v2 footer
+    pub fn verify(data: &[u8]) -> Result<()> {
+        if data.len() < FOOTER_SIZE {
+            return Err(crate::StorageError::InvalidFormat(
+                "Footer too short".into(),
+            ));
+        }
+        let mut buf = data[..FOOTER_SIZE].to_vec();
+        let field = FOOTER_CHECKSUM_OFFSET..FOOTER_CHECKSUM_OFFSET + 4;
+        let stored = u32::from_le_bytes(buf[field.clone()].try_into().expect("4 bytes"));
+        buf[field].fill(0);
+        check(crc32c(&buf), stored, "footer")
+    }
+
     pub fn encode(&self) -> Vec<u8> {
         let mut buf = BytesMut::with_capacity(FOOTER_SIZE);
 
@@ -113,6 +326,8 @@ impl Footer {
         buf.put_u32_le(self.index_size);
         buf.put_u32_le(self.bloom_size);
         buf.put_u32_le(self.checksum);
+        buf.put_u64_le(self.meta_index_offset);
+        buf.put_u32_le(self.meta_index_size);
 
         // Pad to FOOTER_SIZE
         while buf.len() < FOOTER_SIZE {
@@ -136,6 +351,9 @@ impl Footer {
         let index_size = buf.get_u32_le();
         let bloom_size = buf.get_u32_le();
         let checksum = buf.get_u32_le();
+        // Older files zero-padded this area, so they decode with no meta index
+        let meta_index_offset = buf.get_u64_le();
+        let meta_index_size = buf.get_u32_le();
 
         Ok(Footer {
             index_offset,
@@ -143,6 +361,8 @@ impl Footer {
             index_size,
             bloom_size,
             checksum,
+            meta_index_offset,
+            meta_index_size,
         })
     }
 }
@@ -204,3 +424,156 @@ impl IndexEntry {
         })
     }
 }
+
+/// Meta index entry (points to a named meta block)
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetaIndexEntry {
+    pub name: String, // Meta block name, e.g. META_RANGE_TOMBSTONES
+    pub offset: u64,  // File offset of meta block
+    pub size: u32,    // Size of meta block
+}
+
+impl MetaIndexEntry {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = BytesMut::new();
+
+        buf.put_u16_le(self.name.len() as u16);
+        buf.put_slice(self.name.as_bytes());
+        buf.put_u64_le(self.offset);
+        buf.put_u32_le(self.size);
+
+        buf.to_vec()
+    }
+
+    pub fn decode(data: &mut &[u8]) -> Result<Self> {
+        if data.remaining() < 2 {
+            return Err(crate::StorageError::InvalidFormat(
+                "Meta index entry too short for name length".into(),
+            ));
+        }
+
+        let name_len = data.get_u16_le() as usize;
+
+        // After reading name_len, we need: name + offset (8) + size (4)
+        if data.remaining() < name_len + 12 {
+            return Err(crate::StorageError::InvalidFormat(format!(
+                "Meta index entry truncated: need {} bytes, have {}",
+                name_len + 12,
+                data.remaining()
+            )));
+        }
+
+        let name = String::from_utf8(data[..name_len].to_vec()).map_err(|_| {
+            crate::StorageError::InvalidFormat("Meta block name is not UTF-8".into())
+        })?;
+        data.advance(name_len);
+
+        let offset = data.get_u64_le();
+        let size = data.get_u32_le();
+
+        Ok(MetaIndexEntry { name, offset, size })
+    }
+}
+
+/// Encode range tombstones for the META_RANGE_TOMBSTONES block
+///
+/// Each tombstone: [start_len: u16][start][end_len: u16][end][timestamp: u64][seq: u64]
+///
+/// The trailing sequence number is only present in tables with
+/// FLAG_SEQUENCE_NUMBERS, which every new table has.
+pub fn encode_range_tombstones(tombstones: &[RangeTombstone]) -> Vec<u8> {
+    let mut buf = BytesMut::new();
+
+    for tombstone in tombstones {
+        buf.put_u16_le(tombstone.start.len() as u16);
+        buf.put_slice(&tombstone.start);
+        buf.put_u16_le(tombstone.end.len() as u16);
+        buf.put_slice(&tombstone.end);
+        buf.put_u64_le(tombstone.timestamp);
+        buf.put_u64_le(tombstone.seq);
+    }
+
+    buf.to_vec()
+}
+
+/// Decode a META_RANGE_TOMBSTONES block
+///
+/// `has_sequence_numbers` is whether the table has FLAG_SEQUENCE_NUMBERS.
+pub fn decode_range_tombstones(
+    data: &[u8],
+    has_sequence_numbers: bool,
+) -> Result<Vec<RangeTombstone>> {
+    let mut tombstones = Vec::new();
+    let mut cursor = data;
+
+    while cursor.remaining() > 0 {
+        let start = decode_short_bytes(&mut cursor, "range tombstone key")?;
+        let end = decode_short_bytes(&mut cursor, "range tombstone key")?;
+
+        if cursor.remaining() < 8 {
+            return Err(crate::StorageError::CorruptedData(
+                "Truncated range tombstone timestamp".into(),
+            ));
+        }
+        let timestamp = cursor.get_u64_le();
+
+        let seq = if has_sequence_numbers {
+            if cursor.remaining() < 8 {
+                return Err(crate::StorageError::CorruptedData(
+                    "Truncated range tombstone sequence number".into(),
+                ));
+            }
+            cursor.get_u64_le()
+        } else {
+            0
+        };
+
+        tombstones.push(RangeTombstone::new(start, end, timestamp).with_seq(seq));
+    }
+
+    Ok(tombstones)
+}
+
+/// Encode the META_KEY_RANGE block
+///
+/// Format: [smallest_len: u16][smallest][largest_len: u16][largest]
+pub fn encode_key_range(smallest: &[u8], largest: &[u8]) -> Vec<u8> {
+    let mut buf = BytesMut::new();
+
+    buf.put_u16_le(smallest.len() as u16);
+    buf.put_slice(smallest);
+    buf.put_u16_le(largest.len() as u16);
+    buf.put_slice(largest);
+
+    buf.to_vec()
+}
+
+/// Decode a META_KEY_RANGE block into (smallest, largest)
+pub fn decode_key_range(data: &[u8]) -> Result<(Vec<u8>, Vec<u8>)> {
+    let mut cursor = data;
+    let smallest = decode_short_bytes(&mut cursor, "key range")?;
+    let largest = decode_short_bytes(&mut cursor, "key range")?;
+    Ok((smallest, largest))
+}
+
+/// Read a u16 length-prefixed byte string; `what` names it in errors
+fn decode_short_bytes(data: &mut &[u8], what: &str) -> Result<Vec<u8>> {
+    if data.remaining() < 2 {
+        return Err(crate::StorageError::CorruptedData(format!(
+            "Truncated {} length",
+            what
+        )));
+    }
+    let len = data.get_u16_le() as usize;
+
+    if data.remaining() < len {
+        return Err(crate::StorageError::CorruptedData(format!(
+            "Truncated {}",
+            what
+        )));
+    }
+    let bytes = data[..len].to_vec();
+    data.advance(len);
+
+    Ok(bytes)
+}
@@ -0,0 +1,145 @@
+//! Block-at-a-time SSTable iterator
+//!
+//! Only the current data block is decoded and held in memory, so iterating
+//! a large table costs one block of memory rather than the whole file.
+
+use crate::sstable::reader::SsTableReader;
+use crate::{Record, Result};
+use std::sync::Arc;
+
+/// Seekable cursor over an [`SsTableReader`], yielding records in key order
+///
+/// Tombstones are yielded like any other record. The cursor shares the
+/// reader, so it stays usable after the table leaves the engine's SSTable
+/// list. It starts unpositioned; call `seek` or `seek_to_first`.
+pub struct SsTableIterator {
+    reader: Arc<SsTableReader>,
+    block_idx: usize,
+    /// Current block (possibly shared with the block cache) and the
+    /// position of the next record in it
+    block: Arc<Vec<Record>>,
+    next_in_block: usize,
+    current: Option<Record>,
+}
+
+impl SsTableIterator {
+    pub fn new(reader: Arc<SsTableReader>) -> Self {
+        SsTableIterator {
+            reader,
+            block_idx: 0,
+            block: Arc::new(Vec::new()),
+            next_in_block: 0,
+            current: None,
+        }
+    }
+
+    /// Position at the first key >= `key`
+    pub fn seek(&mut self, key: &[u8]) -> Result<()> {
+        // Key before the first block: start from the beginning
+        let block_idx = self.reader.find_block_for_key(key).unwrap_or(0);
+        self.load_block(block_idx)?;
+
+        while let Some(record) = &self.current {
+            if record.key.as_slice() >= key {
+                break;
+            }
+            self.next()?;
+        }
+
+        Ok(())
+    }
+
+    /// Position at the first key
+    pub fn seek_to_first(&mut self) -> Result<()> {
+        self.load_block(0)
+    }
+
+    pub fn valid(&self) -> bool {
+        self.current.is_some()
+    }
+
+    /// Current record; only meaningful while `valid()`
+    pub fn record(&self) -> Option<&Record> {
+        self.current.as_ref()
+    }
+
+    /// Advance to the next key, reading the next block when needed
+    // Cursor-style and fallible, so not a std::iter::Iterator
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<()> {
+        self.current = self.next_record();
+        if self.current.is_none() && self.block_idx + 1 < self.reader.block_count() {
+            self.load_block(self.block_idx + 1)?;
+        }
+        Ok(())
+    }
+
+    /// Decode a block and position at its first record
+    fn load_block(&mut self, block_idx: usize) -> Result<()> {
+        self.block_idx = block_idx;
+        if block_idx >= self.reader.block_count() {
+            self.block = Arc::new(Vec::new());
+            self.next_in_block = 0;
+            self.current = None;
+            return Ok(());
+        }
+
+        self.block = self.reader.read_block(block_idx)?;
+        self.next_in_block = 0;
+        self.current = self.next_record();
+        Ok(())
+    }
+
+    fn next_record(&mut self) -> Option<Record> {
+        let record = self.block.get(self.next_in_block).cloned();
+        self.next_in_block += 1;
+        record
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sstable::SsTableWriter;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_iterator_crosses_blocks() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let path = temp_dir.path().join("test.sst");
+
+        // Small blocks so the keys span many of them
+        let mut writer = SsTableWriter::new(path.clone(), 256)?;
+        for i in 0..200 {
+            let key = format!("key{:04}", i);
+            writer.add(key.as_bytes(), b"value", i)?;
+        }
+        writer.finish()?;
+
+        let reader = Arc::new(SsTableReader::open(path)?);
+        assert!(reader.block_count() > 1);
+
+        let mut iter = SsTableIterator::new(reader);
+        assert!(!iter.valid());
+
+        iter.seek(b"key0150")?;
+        let mut count = 0;
+        while let Some(record) = iter.record() {
+            assert_eq!(record.key, format!("key{:04}", 150 + count).into_bytes());
+            count += 1;
+            iter.next()?;
+        }
+        assert_eq!(count, 50);
+
+        // Seeking between keys lands on the next one; past the end is invalid
+        iter.seek(b"key0099x")?;
+        assert_eq!(iter.record().unwrap().key, b"key0100");
+        iter.seek(b"zzz")?;
+        assert!(!iter.valid());
+
+        iter.seek_to_first()?;
+        assert_eq!(iter.record().unwrap().key, b"key0000");
+
+        Ok(())
+    }
+}
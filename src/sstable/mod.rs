@@ -4,10 +4,20 @@
 
 pub mod block;
 pub mod bloom;
+pub mod cache;
+pub mod checksum;
+pub mod compression;
 pub mod format;
+pub mod gorilla;
+pub mod iterator;
 pub mod reader;
+pub mod table_cache;
 pub mod writer;
 
+pub use cache::{BlockCache, DEFAULT_BLOCK_CACHE_SIZE};
+pub use compression::{CompressionType, ZstdDictionary};
 pub use format::DEFAULT_BLOCK_SIZE;
+pub use iterator::SsTableIterator;
 pub use reader::SsTableReader;
+pub use table_cache::{SsTableHandle, TableCache, DEFAULT_MAX_OPEN_FILES};
 pub use writer::SsTableWriter;
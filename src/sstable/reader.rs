@@ -1,18 +1,26 @@
 //! SSTable reader implementation
 //!
 //! Key design decisions:
-//! - Loads index + bloom filter into memory for fast lookups
-//! - Reads data blocks on-demand from disk
-//! - Handles corruption gracefully (returns Error, doesn't panic)
+//! - Loads index + bloom filter (and any range tombstones or compression
+//!   dictionary) into memory for fast lookups
+//! - Reads data blocks on-demand from disk with positional reads, so one
+//!   reader can serve many threads through `&self`
+//! - Optionally keeps decoded blocks in a shared [`BlockCache`]
+//! - Handles corruption gracefully (returns Error, doesn't panic), and
+//!   detects it through the CRC32C checksums of format v2 files
 //! - Uses prefix decompression with validation
 
 use crate::error::{Result, StorageError};
 use crate::sstable::bloom::BloomFilter;
+use crate::sstable::cache::{decoded_size, BlockCache, BlockData};
+use crate::sstable::compression::{CompressionType, ZstdDictionary};
 use crate::sstable::format::*;
+use crate::sstable::gorilla::decode_numeric_block;
+use crate::{OpType, RangeTombstone, Record, ScanEntry, SequenceNumber, Timestamp};
 use bytes::Buf;
 use std::fs::File;
-use std::io::{Read, Seek, SeekFrom};
 use std::path::PathBuf;
+use std::sync::Arc;
 
 /// SSTable reader
 ///
@@ -20,14 +28,25 @@ use std::path::PathBuf;
 /// - Header: ~64 bytes
 /// - Index: ~40 bytes per block (e.g., 2.5MB for 1GB file with 16KB blocks)
 /// - Bloom filter: ~1-2KB
-/// - Data blocks: read on-demand
+/// - Data blocks: read on-demand, or from the block cache if attached
 pub struct SsTableReader {
     file: File,
     file_size: u64,
     path: PathBuf,
     index: Vec<IndexEntry>,
     bloom_filter: BloomFilter,
+    range_tombstones: Vec<RangeTombstone>,
+    /// Smallest and largest point key (None for older files)
+    key_range: Option<(Vec<u8>, Vec<u8>)>,
     header: Header,
+    /// Codec of the data blocks
+    compression: CompressionType,
+    /// Zstd dictionary every data block is compressed with, if any
+    dictionary: Option<ZstdDictionary>,
+    /// Check the CRC32C of each data block read (v2 files)
+    verify_checksums: bool,
+    /// Shared block cache and this table's id in it
+    block_cache: Option<(Arc<BlockCache>, u64)>,
 }
 
 impl SsTableReader {
@@ -36,20 +55,28 @@ impl SsTableReader {
     /// Loads metadata (header, index, bloom filter) into memory
     /// but reads data blocks on-demand during get/scan operations
     pub fn open(path: PathBuf) -> Result<Self> {
-        let mut file = File::open(&path)?;
+        let file = File::open(&path)?;
         let file_size = file.metadata()?.len();
 
         // 1. Read and validate header
-        let header = Self::read_header(&mut file)?;
+        let header = Self::read_header(&file)?;
+        let compression = header.compression_type()?;
 
         // 2. Read footer (contains pointers to index and bloom filter)
-        let footer = Self::read_footer(&mut file)?;
+        let footer = Self::read_footer(&file, file_size, &header)?;
 
         // 3. Load bloom filter into memory
-        let bloom_filter = Self::read_bloom_filter(&mut file, &footer)?;
+        let bloom_filter = Self::read_bloom_filter(&file, &footer, &header)?;
 
         // 4. Load index into memory (critical for fast lookups)
-        let index = Self::read_index(&mut file, &footer)?;
+        let index = Self::read_index(&file, &footer, &header)?;
+
+        // 5. Load range tombstones (meta block, absent in most tables), the
+        //    key range and the compression dictionary
+        let meta_index = Self::read_meta_index(&file, &footer, &header)?;
+        let range_tombstones = Self::read_range_tombstones(&file, &meta_index, &header)?;
+        let key_range = Self::read_key_range(&file, &meta_index, &header)?;
+        let dictionary = Self::read_dictionary(&file, &meta_index, &header, compression)?;
 
         Ok(Self {
             file,
@@ -57,10 +84,41 @@ impl SsTableReader {
             path,
             index,
             bloom_filter,
+            range_tombstones,
+            key_range,
             header,
+            compression,
+            dictionary,
+            verify_checksums: true,
+            block_cache: None,
         })
     }
 
+    /// Open an SSTable whose decoded blocks go through a shared cache
+    ///
+    /// `table_id` keys this table's blocks in the cache; take it from
+    /// [`BlockCache::next_table_id`] and reuse it when reopening the file.
+    pub fn open_with_cache(path: PathBuf, cache: Arc<BlockCache>, table_id: u64) -> Result<Self> {
+        let mut reader = Self::open(path)?;
+        reader.block_cache = Some((cache, table_id));
+        Ok(reader)
+    }
+
+    /// Check the checksum of every data block read from disk (the default)
+    ///
+    /// Only format v2 files have checksums. The header, footer, index,
+    /// bloom filter and meta blocks are read once and always checked; this
+    /// only saves the cost of checking each block. Blocks served from the
+    /// block cache were checked when they were read.
+    pub fn set_verify_checksums(&mut self, verify: bool) {
+        self.verify_checksums = verify;
+    }
+
+    /// Id this table's blocks are cached under, if it uses a block cache
+    pub fn cache_id(&self) -> Option<u64> {
+        self.block_cache.as_ref().map(|(_, id)| *id)
+    }
+
     pub fn file_size(&self) -> u64 {
         // ADD THIS METHOD
         self.file_size
@@ -68,61 +126,270 @@ impl SsTableReader {
 
     /// Get a value by key
     ///
+    /// Returns None for missing keys and for keys whose latest version in
+    /// this table is a tombstone; use [`SsTableReader::get_record`] to tell
+    /// them apart.
+    pub fn get(&self, key: &[u8]) -> Result<Option<(Vec<u8>, u64)>> {
+        match self.get_record(key)? {
+            Some(record) if !record.is_tombstone() => Ok(Some((record.value, record.timestamp))),
+            _ => Ok(None),
+        }
+    }
+
+    /// Get the newest record stored for a key, including tombstones
+    ///
+    /// Only point entries are considered; the table's own range tombstones
+    /// are left to the caller (see [`SsTableReader::range_tombstones`]).
+    pub fn get_record(&self, key: &[u8]) -> Result<Option<Record>> {
+        self.get_record_at(key, SequenceNumber::MAX)
+    }
+
+    /// Get the newest record for a key among the writes up to `seq`
+    ///
     /// Algorithm:
-    /// 1. Check bloom filter (fast negative test)
-    /// 2. Binary search index to find candidate block
-    /// 3. Read and decompress block
-    /// 4. Search within block using prefix decompression
-    pub fn get(&mut self, key: &[u8]) -> Result<Option<(Vec<u8>, u64)>> {
-        // Fast path: bloom filter says key doesn't exist
-        // This saves expensive disk I/O for missing keys
-        if !self.bloom_filter.contains(key) {
+    /// 1. Check the key range and bloom filter (fast negative tests)
+    /// 2. Binary search index to find the first candidate block
+    /// 3. Read and decompress block, and seek its restart points (see
+    ///    `SsTableReader::block_versions`)
+    /// 4. Walk the key's versions (newest first), continuing into the next
+    ///    block if they run past the end of this one
+    pub fn get_record_at(&self, key: &[u8], seq: SequenceNumber) -> Result<Option<Record>> {
+        // Fast path: outside the key range, or bloom filter says key doesn't
+        // exist. This saves expensive disk I/O for missing keys
+        if !self.may_overlap(key, key) || !self.bloom_filter.contains(key) {
             return Ok(None);
         }
 
-        // Find which block might contain this key
-        let block_idx = match self.find_block_for_key(key) {
+        // Find the first block that might contain this key
+        let start_block = match self.find_block_for_key(key) {
             Some(idx) => idx,
             None => return Ok(None), // Key is before first block
         };
 
-        // Read and decompress the block
-        let entries = self.read_and_decompress_block(block_idx)?;
+        for block_idx in start_block..self.index.len() {
+            // The key's versions in this block, newest first
+            let versions = self.block_versions(block_idx, key)?;
+            if let Some(record) = versions.into_iter().find(|record| record.seq <= seq) {
+                return Ok(Some(record));
+            }
+
+            // Versions can only continue if the next block starts with this key
+            let continues = self
+                .index
+                .get(block_idx + 1)
+                .is_some_and(|next| next.first_key.as_slice() == key);
+            if !continues {
+                break;
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Every version stored for a key, newest first, tombstones included
+    ///
+    /// Like [`SsTableReader::get_record_at`], this only covers point
+    /// entries.
+    pub fn versions_of(&self, key: &[u8]) -> Result<Vec<Record>> {
+        let mut versions = Vec::new();
+        if !self.may_overlap(key, key) || !self.bloom_filter.contains(key) {
+            return Ok(versions);
+        }
+        let start_block = match self.find_block_for_key(key) {
+            Some(idx) => idx,
+            None => return Ok(versions),
+        };
+
+        for block_idx in start_block..self.index.len() {
+            versions.extend(self.block_versions(block_idx, key)?);
 
-        // Binary search within the decompressed block
-        // (entries are sorted by key)
-        match entries.binary_search_by(|entry| entry.key.as_slice().cmp(key)) {
-            Ok(idx) => Ok(Some((entries[idx].value.clone(), entries[idx].timestamp))),
-            Err(_) => Ok(None),
+            let continues = self
+                .index
+                .get(block_idx + 1)
+                .is_some_and(|next| next.first_key.as_slice() == key);
+            if !continues {
+                break;
+            }
         }
+
+        Ok(versions)
+    }
+
+    /// Smallest and largest key with a point entry in this table
+    ///
+    /// None for tables written before key ranges were stored. Range
+    /// tombstones may reach outside it.
+    pub fn key_range(&self) -> Option<(&[u8], &[u8])> {
+        self.key_range
+            .as_ref()
+            .map(|(smallest, largest)| (smallest.as_slice(), largest.as_slice()))
+    }
+
+    /// Could this table hold a point entry for a key in `[start, end]`?
+    ///
+    /// False only if the stored key range rules it out.
+    pub fn may_overlap(&self, start: &[u8], end: &[u8]) -> bool {
+        may_overlap(self.key_range(), start, end)
+    }
+
+    /// Check the bloom filter only: false means the key is definitely absent
+    pub fn may_contain(&self, key: &[u8]) -> bool {
+        self.bloom_filter.contains(key)
+    }
+
+    /// Highest timestamp of any entry or range tombstone in this table
+    pub fn max_timestamp(&self) -> Timestamp {
+        self.header.max_timestamp
+    }
+
+    /// Number of point tombstones and expiring puts, if the table records it
+    pub fn tombstone_count(&self) -> Option<u32> {
+        self.header
+            .has_flag(FLAG_TOMBSTONE_COUNT)
+            .then_some(self.header.tombstones)
+    }
+
+    /// Highest sequence number in this table (0 for tables written before
+    /// sequence numbers existed)
+    pub fn max_sequence(&self) -> SequenceNumber {
+        self.header.max_sequence
+    }
+
+    /// Number of data blocks
+    pub fn block_count(&self) -> usize {
+        self.index.len()
+    }
+
+    /// Read and decode one data block into records
+    ///
+    /// Goes through the block cache when the reader has one; a block cached
+    /// raw by a lookup is cached decoded from then on.
+    pub(crate) fn read_block(&self, block_idx: usize) -> Result<Arc<Vec<Record>>> {
+        let (cache, table_id) = match &self.block_cache {
+            Some((cache, table_id)) => (cache, *table_id),
+            None => {
+                let data = self.read_decompressed(block_idx)?;
+                return Ok(Arc::new(self.decode_records(&data)?));
+            }
+        };
+
+        let offset = self.index_entry(block_idx)?.offset;
+        let records = match cache.get(table_id, offset) {
+            Some(BlockData::Decoded(records)) => return Ok(records),
+            Some(BlockData::Raw(data)) => self.decode_records(&data)?,
+            None => self.decode_records(&self.read_decompressed(block_idx)?)?,
+        };
+        let charge = decoded_size(&records);
+        let records = Arc::new(records);
+        cache.insert(
+            table_id,
+            offset,
+            BlockData::Decoded(Arc::clone(&records)),
+            charge,
+        );
+        Ok(records)
+    }
+
+    /// Decode a decompressed block into records
+    fn decode_records(&self, data: &[u8]) -> Result<Vec<Record>> {
+        let entries = Self::decode_block(data, self.header.flags)?;
+        Ok(entries.into_iter().map(BlockEntry::into_record).collect())
+    }
+
+    /// Every version of a key in one data block, newest first
+    ///
+    /// Only the run of entries after the nearest restart point is decoded
+    /// (see [`SsTableReader::seek_block`]). With a block cache, a lookup
+    /// that misses caches the decompressed block, so later lookups seek it
+    /// without reading the disk; a block already cached decoded, or one
+    /// without restart points, is binary-searched as records instead.
+    fn block_versions(&self, block_idx: usize, key: &[u8]) -> Result<Vec<Record>> {
+        let cache = self
+            .block_cache
+            .as_ref()
+            .filter(|(cache, _)| cache.capacity() > 0);
+        let data = match cache {
+            None => Arc::new(self.read_decompressed(block_idx)?),
+            Some((cache, table_id)) => {
+                let offset = self.index_entry(block_idx)?.offset;
+                match cache.get(*table_id, offset) {
+                    Some(BlockData::Raw(data)) => data,
+                    Some(BlockData::Decoded(records)) => return Ok(key_versions(&records, key)),
+                    None => {
+                        let data = self.read_decompressed(block_idx)?;
+                        if !Self::seekable(&data, self.header.flags) {
+                            let records = self.decode_records(&data)?;
+                            let charge = decoded_size(&records);
+                            let versions = key_versions(&records, key);
+                            let records = BlockData::Decoded(Arc::new(records));
+                            cache.insert(*table_id, offset, records, charge);
+                            return Ok(versions);
+                        }
+                        let data = Arc::new(data);
+                        let raw = BlockData::Raw(Arc::clone(&data));
+                        cache.insert(*table_id, offset, raw, data.len());
+                        data
+                    }
+                }
+            }
+        };
+
+        let entries = Self::seek_block(&data, self.header.flags, key)?;
+        Ok(entries.into_iter().map(BlockEntry::into_record).collect())
+    }
+
+    /// Range tombstones stored in this table
+    ///
+    /// Each one shadows the versions it is newer than, in this table and in
+    /// older ones (see [`RangeTombstone::shadows`]).
+    pub fn range_tombstones(&self) -> &[RangeTombstone] {
+        &self.range_tombstones
     }
 
     /// Scan a range of keys [start, end] inclusive
     ///
-    /// Returns all entries where start <= key <= end
-    pub fn scan(&mut self, start: &[u8], end: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>, u64)>> {
+    /// Returns all live entries where start <= key <= end, newest version
+    /// of each key only
+    pub fn scan(&self, start: &[u8], end: &[u8]) -> Result<Vec<ScanEntry>> {
+        let mut records = self.scan_records(start, end)?;
+        // Versions of a key are adjacent and newest first
+        records.dedup_by(|older, newer| older.key == newer.key);
+
+        Ok(records
+            .into_iter()
+            .filter(|record| !record.is_tombstone())
+            .map(|record| (record.key, record.value, record.timestamp))
+            .collect())
+    }
+
+    /// Scan a range of records [start, end] inclusive, including tombstones
+    /// and every stored version
+    pub fn scan_records(&self, start: &[u8], end: &[u8]) -> Result<Vec<Record>> {
         let mut results = Vec::new();
+        if !self.may_overlap(start, end) {
+            return Ok(results);
+        }
 
         // Find first block that might contain start key
         let start_block = self.find_block_for_key(start).unwrap_or(0); // If before first block, start from beginning
 
         // Read consecutive blocks until we pass end key
         for block_idx in start_block..self.index.len() {
-            let entries = self.read_and_decompress_block(block_idx)?;
+            let records = self.read_block(block_idx)?;
 
-            for entry in entries {
+            for record in records.iter() {
                 // Skip keys before start
-                if entry.key.as_slice() < start {
+                if record.key.as_slice() < start {
                     continue;
                 }
 
                 // Stop when we pass end
-                if entry.key.as_slice() > end {
+                if record.key.as_slice() > end {
                     return Ok(results);
                 }
 
                 // This key is in range
-                results.push((entry.key, entry.value, entry.timestamp));
+                results.push(record.clone());
             }
         }
 
@@ -131,47 +398,59 @@ impl SsTableReader {
 
     // === Helper Methods ===
 
-    /// Read header from file
-    fn read_header(file: &mut File) -> Result<Header> {
-        file.seek(SeekFrom::Start(0))?;
-
-        let mut buf = vec![0u8; HEADER_SIZE];
-        file.read_exact(&mut buf)?;
+    /// Read header from file, with its min/max timestamps in nanoseconds
+    fn read_header(file: &File) -> Result<Header> {
+        let buf = read_exact_at(file, 0, HEADER_SIZE)?;
+        let mut header = Header::decode(&buf)?;
 
-        Header::decode(&buf)
+        // An empty table keeps its u64::MAX minimum
+        let scale = header.timestamp_scale();
+        header.min_timestamp = header.min_timestamp.saturating_mul(scale);
+        header.max_timestamp = header.max_timestamp.saturating_mul(scale);
+        Ok(header)
     }
 
     /// Read footer from file (at the end)
-    fn read_footer(file: &mut File) -> Result<Footer> {
-        file.seek(SeekFrom::End(-(FOOTER_SIZE as i64)))?;
-
-        let mut buf = vec![0u8; FOOTER_SIZE];
-        file.read_exact(&mut buf)?;
-
+    fn read_footer(file: &File, file_size: u64, header: &Header) -> Result<Footer> {
+        let offset = file_size.checked_sub(FOOTER_SIZE as u64).ok_or_else(|| {
+            StorageError::InvalidFormat(format!("File too small: {} bytes", file_size))
+        })?;
+        let buf = read_exact_at(file, offset, FOOTER_SIZE)?;
+        if header.has_checksums() {
+            Footer::verify(&buf)?;
+        }
         Footer::decode(&buf)
     }
 
     /// Read bloom filter from file
-    fn read_bloom_filter(file: &mut File, footer: &Footer) -> Result<BloomFilter> {
+    fn read_bloom_filter(file: &File, footer: &Footer, header: &Header) -> Result<BloomFilter> {
         if footer.bloom_size == 0 {
             // No bloom filter in file (placeholder writer)
             return Ok(BloomFilter::new(1000, 0.01));
         }
 
-        file.seek(SeekFrom::Start(footer.bloom_offset))?;
-
-        let mut buf = vec![0u8; footer.bloom_size as usize];
-        file.read_exact(&mut buf)?;
+        let buf = read_section(
+            file,
+            header,
+            footer.bloom_offset,
+            footer.bloom_size,
+            true,
+            "bloom filter",
+        )?;
 
         BloomFilter::decode(&buf)
     }
 
     /// Read index from file
-    fn read_index(file: &mut File, footer: &Footer) -> Result<Vec<IndexEntry>> {
-        file.seek(SeekFrom::Start(footer.index_offset))?;
-
-        let mut buf = vec![0u8; footer.index_size as usize];
-        file.read_exact(&mut buf)?;
+    fn read_index(file: &File, footer: &Footer, header: &Header) -> Result<Vec<IndexEntry>> {
+        let buf = read_section(
+            file,
+            header,
+            footer.index_offset,
+            footer.index_size,
+            true,
+            "index",
+        )?;
 
         let mut entries = Vec::new();
         let mut cursor = &buf[..];
@@ -184,19 +463,113 @@ impl SsTableReader {
         Ok(entries)
     }
 
-    /// Find which block might contain the given key
+    /// Read the meta index, if the table has one
+    fn read_meta_index(
+        file: &File,
+        footer: &Footer,
+        header: &Header,
+    ) -> Result<Vec<MetaIndexEntry>> {
+        if footer.meta_index_size == 0 {
+            return Ok(Vec::new());
+        }
+
+        let buf = read_section(
+            file,
+            header,
+            footer.meta_index_offset,
+            footer.meta_index_size,
+            true,
+            "meta index",
+        )?;
+
+        let mut entries = Vec::new();
+        let mut cursor = &buf[..];
+
+        while cursor.remaining() > 0 {
+            entries.push(MetaIndexEntry::decode(&mut cursor)?);
+        }
+
+        Ok(entries)
+    }
+
+    /// Read the range tombstone meta block
+    fn read_range_tombstones(
+        file: &File,
+        meta_index: &[MetaIndexEntry],
+        header: &Header,
+    ) -> Result<Vec<RangeTombstone>> {
+        let entry = match meta_index
+            .iter()
+            .find(|entry| entry.name == META_RANGE_TOMBSTONES)
+        {
+            Some(entry) => entry,
+            None => return Ok(Vec::new()),
+        };
+
+        let buf = read_meta_block(file, header, entry)?;
+        let mut tombstones = decode_range_tombstones(&buf, header.has_flag(FLAG_SEQUENCE_NUMBERS))?;
+        for tombstone in &mut tombstones {
+            tombstone.timestamp = tombstone.timestamp.saturating_mul(header.timestamp_scale());
+        }
+        Ok(tombstones)
+    }
+
+    /// Read the key range meta block, if the table has one
+    fn read_key_range(
+        file: &File,
+        meta_index: &[MetaIndexEntry],
+        header: &Header,
+    ) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        match meta_index.iter().find(|entry| entry.name == META_KEY_RANGE) {
+            Some(entry) => {
+                let buf = read_meta_block(file, header, entry)?;
+                decode_key_range(&buf).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Read the compression dictionary meta block, if the table has one
+    fn read_dictionary(
+        file: &File,
+        meta_index: &[MetaIndexEntry],
+        header: &Header,
+        compression: CompressionType,
+    ) -> Result<Option<ZstdDictionary>> {
+        let entry = match meta_index
+            .iter()
+            .find(|entry| entry.name == META_COMPRESSION_DICTIONARY)
+        {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        if compression != CompressionType::Zstd {
+            return Err(StorageError::CorruptedData(format!(
+                "Compression dictionary in a {} table",
+                compression
+            )));
+        }
+
+        let buf = read_meta_block(file, header, entry)?;
+        Ok(Some(ZstdDictionary::from_bytes(buf)))
+    }
+
+    /// Find the first block that might contain the given key
     ///
-    /// Returns the index of the last block where first_key <= key
+    /// Returns the index of the last block where first_key < key. Versions
+    /// of a key can span blocks, so when a block starts with the key itself
+    /// the newer versions may be at the end of the block before it.
     ///
     /// Example:
     ///   Block 0: first_key = "a"
     ///   Block 1: first_key = "m"
     ///   Block 2: first_key = "z"
     ///   
-    ///   find_block_for_key("p") -> Some(1)  (block 1: "m" <= "p" < "z")
-    ///   find_block_for_key("m") -> Some(1)  (exact match)
+    ///   find_block_for_key("p") -> Some(1)  (block 1: "m" < "p" < "z")
+    ///   find_block_for_key("m") -> Some(0)  (versions of "m" may start in block 0)
+    ///   find_block_for_key("a") -> Some(0)
     ///   find_block_for_key("0") -> None     (before first block)
-    fn find_block_for_key(&self, key: &[u8]) -> Option<usize> {
+    pub(crate) fn find_block_for_key(&self, key: &[u8]) -> Option<usize> {
         if self.index.is_empty() {
             return None;
         }
@@ -207,112 +580,296 @@ impl SsTableReader {
         }
 
         // Use partition_point to find the insertion point
-        // This returns the first index where first_key > key
+        // This returns the first index where first_key >= key
         let idx = self
             .index
-            .partition_point(|entry| entry.first_key.as_slice() <= key);
+            .partition_point(|entry| entry.first_key.as_slice() < key);
 
         // We want the block BEFORE the insertion point
-        // (the last block where first_key <= key)
-        if idx > 0 {
-            Some(idx - 1)
-        } else {
-            None
-        }
+        // (the last block where first_key < key), or block 0 for its first key
+        Some(idx.saturating_sub(1))
+    }
+
+    /// Index entry of a data block
+    fn index_entry(&self, block_idx: usize) -> Result<&IndexEntry> {
+        self.index.get(block_idx).ok_or_else(|| {
+            StorageError::InvalidFormat(format!("Block index {} out of range", block_idx))
+        })
     }
 
     /// Read a block from disk and decompress it
     ///
-    /// This is the hot path for reads - optimize carefully!
-    fn read_and_decompress_block(&mut self, block_idx: usize) -> Result<Vec<BlockEntry>> {
-        if block_idx >= self.index.len() {
-            return Err(StorageError::InvalidFormat(format!(
-                "Block index {} out of range",
-                block_idx
-            )));
+    /// This is the hot path for uncached reads - optimize carefully!
+    fn read_decompressed(&self, block_idx: usize) -> Result<Vec<u8>> {
+        let entry = self.index_entry(block_idx)?;
+
+        // Read compressed block from disk
+        let compressed = read_section(
+            &self.file,
+            &self.header,
+            entry.offset,
+            entry.size,
+            self.verify_checksums,
+            &format!("data block at offset {}", entry.offset),
+        )?;
+
+        match &self.dictionary {
+            Some(dictionary) => dictionary.decompress(&compressed),
+            None => self.compression.decompress(&compressed),
         }
+    }
 
-        let entry = &self.index[block_idx];
+    /// Decode a decompressed block into entries
+    ///
+    /// With `FLAG_BLOCK_TYPES` set in the header `flags`, the first byte
+    /// says how the rest is encoded: a list of entries or a column of
+    /// numeric samples. Older files only have entry blocks.
+    fn decode_block(data: &[u8], flags: u32) -> Result<Vec<BlockEntry>> {
+        if flags & FLAG_BLOCK_TYPES == 0 {
+            return Self::decode_entries(data, flags);
+        }
 
-        // Read compressed block from disk
-        self.file.seek(SeekFrom::Start(entry.offset))?;
-        let mut compressed = vec![0u8; entry.size as usize];
-        self.file.read_exact(&mut compressed)?;
+        let (&block_type, data) = data
+            .split_first()
+            .ok_or_else(|| StorageError::CorruptedData("Empty data block".into()))?;
+        match block_type {
+            BLOCK_TYPE_ENTRIES => Self::decode_entries(Self::split_restarts(data, flags)?.0, flags),
+            BLOCK_TYPE_NUMERIC => Self::decode_numeric(data, flags),
+            _ => Err(StorageError::CorruptedData(format!(
+                "Invalid block type: {}",
+                block_type
+            ))),
+        }
+    }
+
+    /// Decode a numeric block: samples of one key, all puts without a TTL
+    fn decode_numeric(data: &[u8], flags: u32) -> Result<Vec<BlockEntry>> {
+        let scale = timestamp_scale(flags);
+        let (key, samples) = decode_numeric_block(data)?;
+        Ok(samples
+            .into_iter()
+            .map(|sample| BlockEntry {
+                key: key.clone(),
+                value: sample.value.to_string().into_bytes(),
+                timestamp: sample.timestamp.saturating_mul(scale),
+                op: OpType::Put,
+                seq: sample.seq,
+                ttl: 0,
+            })
+            .collect())
+    }
 
-        // Decompress with Snappy
-        let decompressed = snap::raw::Decoder::new()
-            .decompress_vec(&compressed)
-            .map_err(|e| {
-                StorageError::CorruptedData(format!("Snappy decompression failed: {}", e))
-            })?;
+    /// Split the body of an entry block into its entries and restart array
+    ///
+    /// Blocks without restart points (no `FLAG_RESTART_POINTS` in the header
+    /// `flags`) are all entries.
+    fn split_restarts(data: &[u8], flags: u32) -> Result<(&[u8], &[u8])> {
+        if flags & FLAG_RESTART_POINTS == 0 {
+            return Ok((data, &[]));
+        }
 
-        // Decode entries with prefix decompression
-        Self::decode_block(&decompressed)
+        let invalid = || StorageError::CorruptedData("Invalid block restart array".into());
+        let count_at = data.len().checked_sub(4).ok_or_else(invalid)?;
+        let count = u32::from_le_bytes(data[count_at..].try_into().expect("4 bytes")) as usize;
+        let restarts_at = count
+            .checked_mul(4)
+            .and_then(|size| count_at.checked_sub(size))
+            .ok_or_else(invalid)?;
+        Ok((&data[..restarts_at], &data[restarts_at..count_at]))
     }
 
-    /// Decode a decompressed block into entries
+    /// Entries of one key in a decompressed block, newest first
+    ///
+    /// In an entry block with restart points, binary-searches the restart
+    /// points for the last one before the key and decodes forward from
+    /// there, stopping past the key: at most a few restart intervals of
+    /// entries instead of the whole block. Other blocks are decoded whole.
+    fn seek_block(data: &[u8], flags: u32, key: &[u8]) -> Result<Vec<BlockEntry>> {
+        if !Self::seekable(data, flags) {
+            let mut entries = Self::decode_block(data, flags)?;
+            entries.retain(|entry| entry.key.as_slice() == key);
+            return Ok(entries);
+        }
+
+        let (data, restarts) = Self::split_restarts(&data[1..], flags)?;
+        let restart_offset = |i: usize| {
+            u32::from_le_bytes(restarts[i * 4..i * 4 + 4].try_into().expect("4 bytes")) as usize
+        };
+
+        // First restart point whose key is not below `key`: the key's
+        // versions can start in the run before it
+        let (mut low, mut high) = (0, restarts.len() / 4);
+        while low < high {
+            let mid = (low + high) / 2;
+            if Self::restart_key(data, restart_offset(mid))? < key {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        let start = low.checked_sub(1).map_or(0, restart_offset);
+
+        let format = EntryFormat::new(flags);
+        let mut cursor = &data[start..];
+        let mut previous_key = Vec::new();
+        let mut entries = Vec::new();
+        while cursor.remaining() > 0 {
+            let entry = Self::decode_entry(&mut cursor, &previous_key, &format)?;
+            match entry.key.as_slice().cmp(key) {
+                std::cmp::Ordering::Less => previous_key = entry.key,
+                std::cmp::Ordering::Equal => {
+                    previous_key.clone_from(&entry.key);
+                    entries.push(entry);
+                }
+                std::cmp::Ordering::Greater => break,
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Is a decompressed block an entry block with restart points?
+    fn seekable(data: &[u8], flags: u32) -> bool {
+        flags & FLAG_BLOCK_TYPES != 0
+            && flags & FLAG_RESTART_POINTS != 0
+            && data.first() == Some(&BLOCK_TYPE_ENTRIES)
+    }
+
+    /// Key of the entry at a restart point, which shares no prefix
+    fn restart_key(data: &[u8], offset: usize) -> Result<&[u8]> {
+        let mut cursor = data.get(offset..).ok_or_else(|| {
+            StorageError::CorruptedData(format!("Invalid restart offset {}", offset))
+        })?;
+        let shared_len = Self::decode_varint(&mut cursor)?;
+        let unshared_len = Self::decode_varint(&mut cursor)?;
+        Self::decode_varint(&mut cursor)?;
+        if shared_len != 0 {
+            return Err(StorageError::CorruptedData(format!(
+                "Restart point at offset {} shares a key prefix",
+                offset
+            )));
+        }
+        cursor
+            .get(..unshared_len)
+            .ok_or_else(|| StorageError::CorruptedData("Truncated key delta".into()))
+    }
+
+    /// Decode a block of entries
     ///
     /// Handles prefix compression: each entry stores shared prefix length
-    /// with previous key, then only the differing suffix
-    fn decode_block(data: &[u8]) -> Result<Vec<BlockEntry>> {
+    /// with previous key, then only the differing suffix.
+    fn decode_entries(data: &[u8], flags: u32) -> Result<Vec<BlockEntry>> {
+        let format = EntryFormat::new(flags);
         let mut entries = Vec::new();
         let mut cursor = data;
         let mut previous_key = Vec::new();
 
         while cursor.remaining() > 0 {
-            // Read prefix compression metadata
-            let shared_len = Self::decode_varint(&mut cursor)?;
-            let unshared_len = Self::decode_varint(&mut cursor)?;
-            let value_len = Self::decode_varint(&mut cursor)?;
-
-            // Validate shared_len (detect corruption)
-            if shared_len > previous_key.len() {
-                return Err(StorageError::CorruptedData(format!(
-                    "Invalid shared_len {} > previous key len {}",
-                    shared_len,
-                    previous_key.len()
-                )));
+            let entry = Self::decode_entry(&mut cursor, &previous_key, &format)?;
+
+            // Validate sort order (keys must be sorted; only tables with
+            // sequence numbers may hold several versions of a key)
+            let out_of_order = if format.has_sequence_numbers {
+                entry.key < previous_key
+            } else {
+                entry.key <= previous_key
+            };
+            if !previous_key.is_empty() && out_of_order {
+                return Err(StorageError::CorruptedData(
+                    "Keys not in sorted order".into(),
+                ));
             }
 
-            // Reconstruct full key: reuse prefix + append suffix
-            let mut key = Vec::with_capacity(shared_len + unshared_len);
-            key.extend_from_slice(&previous_key[..shared_len]);
+            previous_key.clone_from(&entry.key);
+            entries.push(entry);
+        }
 
-            if cursor.remaining() < unshared_len {
-                return Err(StorageError::CorruptedData("Truncated key delta".into()));
-            }
-            key.extend_from_slice(&cursor[..unshared_len]);
-            cursor.advance(unshared_len);
+        Ok(entries)
+    }
 
-            // Read value
-            if cursor.remaining() < value_len {
-                return Err(StorageError::CorruptedData("Truncated value".into()));
-            }
-            let value = cursor[..value_len].to_vec();
-            cursor.advance(value_len);
+    /// Decode the entry at the front of `cursor`, sharing a prefix of
+    /// `previous_key`
+    fn decode_entry(
+        cursor: &mut &[u8],
+        previous_key: &[u8],
+        format: &EntryFormat,
+    ) -> Result<BlockEntry> {
+        // Read prefix compression metadata
+        let shared_len = Self::decode_varint(cursor)?;
+        let unshared_len = Self::decode_varint(cursor)?;
+        let value_len = Self::decode_varint(cursor)?;
+
+        // Validate shared_len (detect corruption)
+        if shared_len > previous_key.len() {
+            return Err(StorageError::CorruptedData(format!(
+                "Invalid shared_len {} > previous key len {}",
+                shared_len,
+                previous_key.len()
+            )));
+        }
 
-            // Read timestamp
-            if cursor.remaining() < 8 {
-                return Err(StorageError::CorruptedData("Truncated timestamp".into()));
+        // Reconstruct full key: reuse prefix + append suffix
+        let mut key = Vec::with_capacity(shared_len + unshared_len);
+        key.extend_from_slice(&previous_key[..shared_len]);
+
+        if cursor.remaining() < unshared_len {
+            return Err(StorageError::CorruptedData("Truncated key delta".into()));
+        }
+        key.extend_from_slice(&cursor[..unshared_len]);
+        cursor.advance(unshared_len);
+
+        // Read value
+        if cursor.remaining() < value_len {
+            return Err(StorageError::CorruptedData("Truncated value".into()));
+        }
+        let value = cursor[..value_len].to_vec();
+        cursor.advance(value_len);
+
+        // Read timestamp
+        if cursor.remaining() < 8 {
+            return Err(StorageError::CorruptedData("Truncated timestamp".into()));
+        }
+        let timestamp = cursor.get_u64_le().saturating_mul(format.scale);
+
+        // Read value type (files without the flag only hold puts)
+        let op = if format.has_op_types {
+            if cursor.remaining() < 1 {
+                return Err(StorageError::CorruptedData("Truncated value type".into()));
             }
-            let timestamp = cursor.get_u64_le();
+            let type_byte = cursor.get_u8();
+            OpType::from_u8(type_byte).ok_or_else(|| {
+                StorageError::CorruptedData(format!("Invalid value type: {}", type_byte))
+            })?
+        } else {
+            OpType::Put
+        };
 
-            // Validate sort order (keys must be sorted)
-            if !previous_key.is_empty() && key <= previous_key {
+        // Read sequence number (files without the flag predate them)
+        let seq = if format.has_sequence_numbers {
+            if cursor.remaining() < 8 {
                 return Err(StorageError::CorruptedData(
-                    "Keys not in sorted order".into(),
+                    "Truncated sequence number".into(),
                 ));
             }
+            cursor.get_u64_le()
+        } else {
+            0
+        };
 
-            previous_key = key.clone();
-            entries.push(BlockEntry {
-                key,
-                value,
-                timestamp,
-            });
-        }
+        // Read TTL (files without the flag never expire)
+        let ttl = if format.has_ttls {
+            (Self::decode_varint(cursor)? as Timestamp).saturating_mul(format.scale)
+        } else {
+            0
+        };
 
-        Ok(entries)
+        Ok(BlockEntry {
+            key,
+            value,
+            timestamp,
+            op,
+            seq,
+            ttl,
+        })
     }
 
     /// Decode a variable-length integer (varint)
@@ -349,10 +906,17 @@ impl SsTableReader {
     pub fn info(&self) -> SsTableInfo {
         SsTableInfo {
             path: self.path.clone(),
+            format_version: self.header.version,
             num_blocks: self.header.num_blocks,
             min_timestamp: self.header.min_timestamp,
             max_timestamp: self.header.max_timestamp,
             index_entries: self.index.len(),
+            tombstones: self.tombstone_count(),
+            compression: self.compression,
+            dictionary_size: self
+                .dictionary
+                .as_ref()
+                .map_or(0, |dictionary| dictionary.as_bytes().len()),
         }
     }
 }
@@ -363,22 +927,140 @@ struct BlockEntry {
     key: Vec<u8>,
     value: Vec<u8>,
     timestamp: u64,
+    op: OpType,
+    seq: SequenceNumber,
+    ttl: Timestamp,
+}
+
+impl BlockEntry {
+    fn into_record(self) -> Record {
+        Record {
+            key: self.key,
+            value: self.value,
+            timestamp: self.timestamp,
+            op: self.op,
+            seq: self.seq,
+            ttl: self.ttl,
+        }
+    }
+}
+
+/// Which fields each entry of a block carries, from the header flags
+struct EntryFormat {
+    has_op_types: bool,
+    has_sequence_numbers: bool,
+    has_ttls: bool,
+    /// Multiplier taking stored timestamps to nanoseconds
+    scale: Timestamp,
+}
+
+impl EntryFormat {
+    fn new(flags: u32) -> Self {
+        EntryFormat {
+            has_op_types: flags & FLAG_OP_TYPES != 0,
+            has_sequence_numbers: flags & FLAG_SEQUENCE_NUMBERS != 0,
+            has_ttls: flags & FLAG_TTLS != 0,
+            scale: timestamp_scale(flags),
+        }
+    }
+}
+
+/// The records of `key` in a decoded block, newest first
+fn key_versions(records: &[Record], key: &[u8]) -> Vec<Record> {
+    let first = records.partition_point(|record| record.key.as_slice() < key);
+    records[first..]
+        .iter()
+        .take_while(|record| record.key.as_slice() == key)
+        .cloned()
+        .collect()
+}
+
+/// Could a table with `key_range` hold a key in `[start, end]`?
+///
+/// An unknown key range (older files) always may.
+pub(crate) fn may_overlap(key_range: Option<(&[u8], &[u8])>, start: &[u8], end: &[u8]) -> bool {
+    key_range.is_none_or(|(smallest, largest)| smallest <= end && start <= largest)
+}
+
+/// Read a section of `size` bytes at `offset`
+///
+/// In v2 files the section is followed by its CRC32C, checked if `verify`
+/// is set; `what` names the section in the error.
+fn read_section(
+    file: &File,
+    header: &Header,
+    offset: u64,
+    size: u32,
+    verify: bool,
+    what: &str,
+) -> Result<Vec<u8>> {
+    if !header.has_checksums() {
+        return read_exact_at(file, offset, size as usize);
+    }
+    let mut buf = read_exact_at(file, offset, size as usize + CHECKSUM_SIZE)?;
+    if verify {
+        verify_checksum(&buf, what)?;
+    }
+    buf.truncate(size as usize);
+    Ok(buf)
+}
+
+fn read_meta_block(file: &File, header: &Header, entry: &MetaIndexEntry) -> Result<Vec<u8>> {
+    read_section(file, header, entry.offset, entry.size, true, &entry.name)
+}
+
+/// Read `len` bytes at `offset` without touching a shared file cursor
+///
+/// Positional reads let concurrent readers share one `File`.
+fn read_exact_at(file: &File, offset: u64, len: usize) -> Result<Vec<u8>> {
+    let mut buf = vec![0u8; len];
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileExt;
+        file.read_exact_at(&mut buf, offset)?;
+    }
+
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::FileExt;
+        let mut filled = 0;
+        while filled < len {
+            let n = file.seek_read(&mut buf[filled..], offset + filled as u64)?;
+            if n == 0 {
+                return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+            }
+            filled += n;
+        }
+    }
+
+    Ok(buf)
 }
 
 /// Diagnostic information about an SSTable
 #[derive(Debug)]
 pub struct SsTableInfo {
     pub path: PathBuf,
+    /// Format version (2 and up carry checksums)
+    pub format_version: u32,
     pub num_blocks: u32,
     pub min_timestamp: u64,
     pub max_timestamp: u64,
     pub index_entries: usize,
+    /// Point tombstones and expiring puts (None for older files)
+    pub tombstones: Option<u32>,
+    /// Codec of the data blocks
+    pub compression: CompressionType,
+    /// Size of the Zstd dictionary the blocks share (0 = none)
+    pub dictionary_size: usize,
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::sstable::block::RESTART_INTERVAL;
     use crate::sstable::writer::SsTableWriter;
+    use crate::NANOS_PER_SECOND;
     use tempfile::TempDir;
 
     #[test]
@@ -394,7 +1076,7 @@ mod tests {
         writer.finish()?;
 
         // Read it back
-        let mut reader = SsTableReader::open(path)?;
+        let reader = SsTableReader::open(path)?;
 
         assert_eq!(reader.get(b"key1")?, Some((b"value1".to_vec(), 100)));
         assert_eq!(reader.get(b"key2")?, Some((b"value2".to_vec(), 200)));
@@ -404,6 +1086,197 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_reader_info_reports_time_range_and_tombstones() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let path = temp_dir.path().join("test.sst");
+
+        let mut writer = SsTableWriter::new(path.clone(), DEFAULT_BLOCK_SIZE)?;
+        writer.add(b"a", b"v", 300)?;
+        writer.add_record(&Record::delete(b"b".to_vec(), 100))?;
+        writer.add_record(&Record::put(b"c".to_vec(), b"v".to_vec(), 200).with_ttl(50))?;
+        writer.finish()?;
+
+        let info = SsTableReader::open(path)?.info();
+        assert_eq!((info.min_timestamp, info.max_timestamp), (100, 300));
+        // The delete and the expiring put
+        assert_eq!(info.tombstones, Some(2));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reader_loads_key_range() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let path = temp_dir.path().join("test.sst");
+
+        let mut writer = SsTableWriter::new(path.clone(), DEFAULT_BLOCK_SIZE)?;
+        writer.add(b"key2", b"v", 100)?;
+        writer.add(b"key5", b"v", 100)?;
+        writer.add_range_tombstone(RangeTombstone::new(b"a".to_vec(), b"z".to_vec(), 50));
+        writer.finish()?;
+
+        let reader = SsTableReader::open(path)?;
+        // Range tombstones do not widen it
+        assert_eq!(reader.key_range(), Some((&b"key2"[..], &b"key5"[..])));
+        assert!(reader.may_overlap(b"key0", b"key2"));
+        assert!(reader.may_overlap(b"key3", b"key4"));
+        assert!(!reader.may_overlap(b"key6", b"key9"));
+        assert!(!reader.may_overlap(b"a", b"key1"));
+        assert_eq!(reader.get(b"key1")?, None);
+
+        // Older files have no key range and may hold anything
+        assert!(may_overlap(None, b"x", b"y"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reader_decodes_numeric_blocks() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+
+        // Two sensors sampled every 10s, newest first; then a key that is
+        // not numeric. "20.50" parses but would not read back as written.
+        let write = |name: &str, format: fn(f64) -> String| -> Result<SsTableReader> {
+            let path = temp_dir.path().join(name);
+            let mut writer = SsTableWriter::new(path.clone(), DEFAULT_BLOCK_SIZE)?;
+            for sensor in [b"sensor.a", b"sensor.b"] {
+                for i in (0..2000u64).rev() {
+                    let value = format(20.0 + (i % 8) as f64 * 0.25);
+                    let record =
+                        Record::put(sensor.to_vec(), value.into_bytes(), i * 10_000_000_000);
+                    writer.add_record(&record.with_seq(i + 1))?;
+                }
+            }
+            writer.add(b"status", b"ok", 5)?;
+            writer.add_record(&Record::delete(b"status".to_vec(), 4))?;
+            writer.finish()?;
+            SsTableReader::open(path)
+        };
+        let numeric = write("numeric.sst", |value| value.to_string())?;
+        let text = write("text.sst", |value| format!("{:.2}", value))?;
+
+        assert!(
+            numeric.file_size() * 4 < text.file_size(),
+            "{} vs {} bytes",
+            numeric.file_size(),
+            text.file_size()
+        );
+
+        let versions = numeric.versions_of(b"sensor.b")?;
+        assert_eq!(versions.len(), 2000);
+        assert_eq!(
+            versions[0],
+            Record::put(b"sensor.b".to_vec(), b"21.75".to_vec(), 19_990_000_000_000).with_seq(2000)
+        );
+        assert_eq!(versions[1999].value, b"20");
+        assert_eq!(
+            numeric.get(b"sensor.a")?.map(|(value, _)| value),
+            Some(b"21.75".to_vec())
+        );
+        assert_eq!(numeric.versions_of(b"status")?.len(), 2);
+        assert_eq!(
+            text.get(b"sensor.a")?.map(|(value, _)| value),
+            Some(b"21.75".to_vec())
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reader_loads_compression_dictionary() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+
+        // Small blocks repeat each other's key prefixes and value layout
+        let write = |name: &str, dictionary_size: usize| -> Result<SsTableReader> {
+            let path = temp_dir.path().join(name);
+            let mut writer = SsTableWriter::new(path.clone(), 1024)?
+                .with_compression(CompressionType::Zstd)
+                .with_zstd_dictionary(dictionary_size);
+            for i in 0..5000u64 {
+                let key = format!("system.cpu.host{:03}.core{:02}", i / 50, i % 50);
+                let value = format!("{{\"user\":{},\"system\":{}}}", i * 7 % 100, i % 13);
+                writer.add(key.as_bytes(), value.as_bytes(), i)?;
+            }
+            writer.finish()?;
+            SsTableReader::open(path)
+        };
+        let plain = write("plain.sst", 0)?;
+        let trained = write("trained.sst", 4096)?;
+
+        assert_eq!(plain.info().dictionary_size, 0);
+        let info = trained.info();
+        assert_eq!(info.compression, CompressionType::Zstd);
+        assert!(info.dictionary_size > 0 && info.dictionary_size <= 4096);
+        assert!(
+            trained.file_size() < plain.file_size(),
+            "{} vs {} bytes",
+            trained.file_size(),
+            plain.file_size()
+        );
+
+        assert_eq!(
+            trained.get(b"system.cpu.host042.core07")?,
+            Some((b"{\"user\":49,\"system\":1}".to_vec(), 2107))
+        );
+        assert_eq!(
+            trained
+                .scan(b"system.cpu.host099", b"system.cpu.host1")?
+                .len(),
+            50
+        );
+
+        // Too little data to pay for a dictionary
+        let path = temp_dir.path().join("small.sst");
+        let mut writer = SsTableWriter::new(path.clone(), 1024)?.with_zstd_dictionary(4096);
+        writer.add(b"key", b"value", 1)?;
+        writer.finish()?;
+        let small = SsTableReader::open(path)?;
+        assert_eq!(small.info().dictionary_size, 0);
+        assert_eq!(small.get(b"key")?, Some((b"value".to_vec(), 1)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reader_serves_repeat_reads_from_block_cache() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let path = temp_dir.path().join("test.sst");
+
+        // Small blocks so the keys span several of them
+        let mut writer = SsTableWriter::new(path.clone(), 256)?;
+        for i in 0..100 {
+            writer.add(format!("key{:03}", i).as_bytes(), b"value", i)?;
+        }
+        writer.finish()?;
+
+        let cache = Arc::new(BlockCache::new(1024 * 1024));
+        let reader =
+            SsTableReader::open_with_cache(path, Arc::clone(&cache), cache.next_table_id())?;
+        assert!(reader.block_count() > 1);
+        assert!(cache.is_empty());
+
+        assert_eq!(reader.get(b"key050")?, Some((b"value".to_vec(), 50)));
+        let cached = cache.len();
+        assert!(cached > 0);
+
+        // Same blocks: served from the cache, nothing new decoded
+        assert_eq!(reader.get(b"key050")?, Some((b"value".to_vec(), 50)));
+        assert_eq!(cache.len(), cached);
+
+        // A full scan caches every block, charged for every record's own
+        // allocations rather than the few bytes each takes in the block
+        assert_eq!(reader.scan(b"key000", b"key999")?.len(), 100);
+        assert_eq!(cache.len(), reader.block_count());
+        assert!(cache.size_bytes() >= 100 * (size_of::<Record>() + 11));
+
+        cache.remove_table(reader.cache_id().unwrap());
+        assert!(cache.is_empty());
+        assert_eq!(reader.get(b"key099")?, Some((b"value".to_vec(), 99)));
+
+        Ok(())
+    }
+
     #[test]
     fn test_reader_scan() -> Result<()> {
         let temp_dir = TempDir::new()?;
@@ -419,7 +1292,7 @@ mod tests {
         writer.finish()?;
 
         // Scan a range
-        let mut reader = SsTableReader::open(path)?;
+        let reader = SsTableReader::open(path)?;
         let results = reader.scan(b"key03", b"key07")?;
 
         assert_eq!(results.len(), 5);
@@ -428,6 +1301,297 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_reader_tombstones() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let path = temp_dir.path().join("test.sst");
+
+        let mut writer = SsTableWriter::new(path.clone(), DEFAULT_BLOCK_SIZE)?;
+        writer.add(b"key1", b"value1", 100)?;
+        writer.add_record(&Record::delete(b"key2".to_vec(), 200))?;
+        writer.add(b"key3", b"value3", 300)?;
+        writer.finish()?;
+
+        let reader = SsTableReader::open(path)?;
+
+        // Tombstones hide the key from plain reads but are still visible as records
+        assert_eq!(reader.get(b"key2")?, None);
+        assert_eq!(
+            reader.get_record(b"key2")?,
+            Some(Record::delete(b"key2".to_vec(), 200))
+        );
+        assert_eq!(reader.scan(b"key1", b"key3")?.len(), 2);
+        assert_eq!(reader.scan_records(b"key1", b"key3")?.len(), 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reader_range_tombstones() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let path = temp_dir.path().join("test.sst");
+
+        let mut writer = SsTableWriter::new(path.clone(), DEFAULT_BLOCK_SIZE)?;
+        writer.add(b"key1", b"value1", 100)?;
+        writer.add_range_tombstone(RangeTombstone::new(b"a".to_vec(), b"b".to_vec(), 50));
+        writer.add_range_tombstone(RangeTombstone::for_prefix(b"user:", 60));
+        writer.finish()?;
+
+        let reader = SsTableReader::open(path)?;
+
+        assert_eq!(
+            reader.range_tombstones(),
+            &[
+                RangeTombstone::new(b"a".to_vec(), b"b".to_vec(), 50),
+                RangeTombstone::new(b"user:".to_vec(), b"user;".to_vec(), 60),
+            ]
+        );
+        assert_eq!(reader.get(b"key1")?, Some((b"value1".to_vec(), 100)));
+        assert_eq!(reader.info().min_timestamp, 50);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reader_versions_across_blocks() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let path = temp_dir.path().join("test.sst");
+
+        // Tiny blocks so the versions of "key" span several of them
+        let mut writer = SsTableWriter::new(path.clone(), 64)?;
+        writer.add(b"a", b"first", 1)?;
+        for seq in (1..=20).rev() {
+            let value = format!("v{}", seq);
+            writer.add_record(
+                &Record::put(b"key".to_vec(), value.into_bytes(), 100 + seq).with_seq(seq),
+            )?;
+        }
+        writer.add(b"z", b"last", 1)?;
+        writer.finish()?;
+
+        let reader = SsTableReader::open(path)?;
+        assert!(reader.block_count() > 2);
+        assert_eq!(reader.max_sequence(), 20);
+
+        assert_eq!(reader.get(b"key")?, Some((b"v20".to_vec(), 120)));
+        assert_eq!(reader.get_record_at(b"key", 3)?.unwrap().value, b"v3");
+        assert_eq!(reader.get_record_at(b"key", 0)?, None);
+        assert_eq!(reader.scan(b"a", b"z")?.len(), 3);
+        assert_eq!(reader.scan_records(b"key", b"key")?.len(), 20);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reader_seeks_restart_points() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let path = temp_dir.path().join("test.sst");
+
+        // One block of many restart intervals; every 7th key has three
+        // versions, so some of them straddle a restart point
+        let mut writer = SsTableWriter::new(path.clone(), DEFAULT_BLOCK_SIZE)?;
+        for i in 0..200u64 {
+            let key = format!("key{:04}", i * 2).into_bytes();
+            let versions = if i % 7 == 0 { 3 } else { 1 };
+            for version in (0..versions).rev() {
+                let value = format!("value{}.{}", i, version).into_bytes();
+                writer.add_record(
+                    &Record::put(key.clone(), value, 100 + i).with_seq(i * 10 + version + 1),
+                )?;
+            }
+        }
+        writer.finish()?;
+
+        let reader = SsTableReader::open(path)?;
+        assert_eq!(reader.block_count(), 1);
+        let flags = reader.header.flags;
+        let data = reader.read_decompressed(0)?;
+        let count = u32::from_le_bytes(data[data.len() - 4..].try_into().unwrap());
+        assert_eq!(count as usize, 258usize.div_ceil(RESTART_INTERVAL));
+
+        // Seeking finds what decoding the whole block does, for present and
+        // missing keys on either side of every restart point
+        let entries = SsTableReader::decode_block(&data, flags)?;
+        assert_eq!(entries.len(), 258);
+        for key in (0..=400).map(|i| format!("key{:04}", i).into_bytes()) {
+            let expected: Vec<Record> = entries
+                .iter()
+                .filter(|entry| entry.key == key)
+                .map(|entry| entry.clone().into_record())
+                .collect();
+            let found: Vec<Record> = SsTableReader::seek_block(&data, flags, &key)?
+                .into_iter()
+                .map(BlockEntry::into_record)
+                .collect();
+            assert_eq!(found, expected, "{}", String::from_utf8_lossy(&key));
+        }
+        assert!(SsTableReader::seek_block(&data, flags, b"a")?.is_empty());
+
+        // Lookups seek, with or without the default block cache: a miss
+        // caches the decompressed block rather than decoding all of it
+        let cache = Arc::new(BlockCache::default());
+        let cached = SsTableReader::open_with_cache(
+            temp_dir.path().join("test.sst"),
+            Arc::clone(&cache),
+            cache.next_table_id(),
+        )?;
+        for reader in [&reader, &cached] {
+            assert_eq!(reader.get(b"key0000")?, Some((b"value0.2".to_vec(), 100)));
+            assert_eq!(reader.get(b"key0398")?, Some((b"value199.0".to_vec(), 299)));
+            assert_eq!(reader.get(b"key0001")?, None);
+            assert_eq!(
+                reader.get_record_at(b"key0014", 72)?.unwrap().value,
+                b"value7.1"
+            );
+            assert_eq!(reader.versions_of(b"key0014")?.len(), 3);
+        }
+        let offset = cached.index[0].offset;
+        assert!(matches!(
+            cache.get(cached.cache_id().unwrap(), offset),
+            Some(BlockData::Raw(_))
+        ));
+        assert_eq!(cache.size_bytes(), data.len());
+
+        // Once a scan has cached it decoded, lookups search the records
+        assert_eq!(cached.scan(b"key0000", b"key9999")?.len(), 200);
+        assert!(matches!(
+            cache.get(cached.cache_id().unwrap(), offset),
+            Some(BlockData::Decoded(_))
+        ));
+        assert_eq!(
+            cached.get_record_at(b"key0014", 72)?.unwrap().value,
+            b"value7.1"
+        );
+        assert_eq!(cached.versions_of(b"key0014")?.len(), 3);
+
+        // Blocks written before restart points are decoded whole
+        let mut block = vec![BLOCK_TYPE_ENTRIES];
+        for key in [b"a", b"b"] {
+            block.extend_from_slice(&[0, 1, 1]);
+            block.extend_from_slice(key);
+            block.extend_from_slice(b"1");
+            block.extend_from_slice(&1u64.to_le_bytes());
+        }
+        let old_flags = FLAG_BLOCK_TYPES | FLAG_NANOSECOND_TIMESTAMPS;
+        let found = SsTableReader::seek_block(&block, old_flags, b"b")?;
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].key, b"b");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reader_checks_checksums() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+
+        // Uncompressed, so a flipped bit in the value still decodes
+        let write = |name: &str, version: u32| -> Result<PathBuf> {
+            let path = temp_dir.path().join(name);
+            let mut writer = SsTableWriter::new(path.clone(), DEFAULT_BLOCK_SIZE)?
+                .with_compression(CompressionType::None)
+                .with_format_version(version)?;
+            writer.add(b"key", b"value", 1)?;
+            writer.finish()?;
+            Ok(path)
+        };
+        let flip = |path: &PathBuf, offset: Option<usize>| -> Result<()> {
+            let mut data = std::fs::read(path)?;
+            let value = data.windows(5).position(|w| w == b"value").unwrap();
+            data[offset.unwrap_or(value)] ^= 0x02;
+            std::fs::write(path, data)?;
+            Ok(())
+        };
+        fn is_checksum_error<T>(result: Result<T>) -> bool {
+            matches!(result, Err(StorageError::CorruptedData(msg)) if msg.contains("Checksum"))
+        }
+
+        let path = write("v2.sst", VERSION)?;
+        assert_eq!(SsTableReader::open(path.clone())?.info().format_version, 2);
+        flip(&path, None)?;
+        let mut reader = SsTableReader::open(path)?;
+        assert!(is_checksum_error(reader.get(b"key")));
+        reader.set_verify_checksums(false);
+        assert_eq!(reader.get(b"key")?, Some((b"talue".to_vec(), 1)));
+
+        // Header and footer are checked on open
+        let path = write("header.sst", VERSION)?;
+        flip(&path, Some(20))?;
+        assert!(is_checksum_error(SsTableReader::open(path)));
+        let path = write("footer.sst", VERSION)?;
+        let footer = std::fs::metadata(&path)?.len() as usize - FOOTER_SIZE;
+        flip(&path, Some(footer + 2))?;
+        assert!(is_checksum_error(SsTableReader::open(path)));
+
+        // Version 1 files have no checksums: the damage goes unnoticed
+        let path = write("v1.sst", 1)?;
+        flip(&path, None)?;
+        let reader = SsTableReader::open(path)?;
+        assert_eq!(reader.info().format_version, 1);
+        assert_eq!(reader.get(b"key")?, Some((b"talue".to_vec(), 1)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reader_scales_second_timestamps_to_nanoseconds() -> Result<()> {
+        let temp_dir = TempDir::new()?;
+        let path = temp_dir.path().join("test.sst");
+
+        let mut writer = SsTableWriter::new(path.clone(), DEFAULT_BLOCK_SIZE)?;
+        writer.add_record(&Record::put(b"a".to_vec(), b"1".to_vec(), 100).with_ttl(60))?;
+        writer.add(b"b", b"2", 200)?;
+        writer.add_range_tombstone(RangeTombstone::new(b"c".to_vec(), b"d".to_vec(), 150));
+        writer.finish()?;
+
+        // Clear the flag, as in a file written with second timestamps
+        let mut data = std::fs::read(&path)?;
+        let mut header = Header::decode(&data)?;
+        header.flags &= !FLAG_NANOSECOND_TIMESTAMPS;
+        data[..HEADER_SIZE].copy_from_slice(&header.encode());
+        std::fs::write(&path, data)?;
+
+        let reader = SsTableReader::open(path)?;
+        assert_eq!(reader.info().min_timestamp, 100 * NANOS_PER_SECOND);
+        assert_eq!(reader.max_timestamp(), 200 * NANOS_PER_SECOND);
+
+        let record = reader.get_record(b"a")?.unwrap();
+        assert_eq!(record.timestamp, 100 * NANOS_PER_SECOND);
+        assert_eq!(record.ttl, 60 * NANOS_PER_SECOND);
+        assert_eq!(
+            reader.get(b"b")?,
+            Some((b"2".to_vec(), 200 * NANOS_PER_SECOND))
+        );
+        assert_eq!(
+            reader.range_tombstones()[0].timestamp,
+            150 * NANOS_PER_SECOND
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reader_decodes_blocks_without_op_types() -> Result<()> {
+        // Entry layout used before the FLAG_OP_TYPES header flag existed
+        let mut block = Vec::new();
+        for (key, value, ts) in [(b"a", b"1", 1u64), (b"b", b"2", 2u64)] {
+            block.extend_from_slice(&[0, key.len() as u8, value.len() as u8]);
+            block.extend_from_slice(key);
+            block.extend_from_slice(value);
+            block.extend_from_slice(&ts.to_le_bytes());
+        }
+
+        let entries = SsTableReader::decode_block(&block, 0)?;
+        assert_eq!(entries.len(), 2);
+        assert!(entries.iter().all(|e| e.op == OpType::Put));
+        // ...which also predates nanosecond timestamps
+        assert_eq!(
+            entries[1].clone().into_record(),
+            Record::put(b"b".to_vec(), b"2".to_vec(), 2 * NANOS_PER_SECOND)
+        );
+
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -456,7 +1620,7 @@ mod benchmark {
         writer.finish()?;
 
         // Open reader
-        let mut reader = SsTableReader::open(path)?;
+        let reader = SsTableReader::open(path)?;
 
         println!("SSTable created with 1,000 keys");
         println!("Bloom filter stats: {:?}\n", reader.bloom_filter.stats());
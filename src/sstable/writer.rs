@@ -1,16 +1,32 @@
 //! SSTable Writer
-use super::block::BlockBuilder;
+use super::block::{BlockBuilder, MIN_NUMERIC_BLOCK_SAMPLES};
 use super::bloom::BloomFilterBuilder;
-use super::format::{Footer, Header, IndexEntry, HEADER_SIZE};
+use super::checksum::crc32c;
+use super::compression::{CompressionType, ZstdDictionary};
+use super::format::{
+    encode_key_range, encode_range_tombstones, Footer, Header, IndexEntry, MetaIndexEntry,
+    CHECKSUM_SIZE, HEADER_SIZE, META_COMPRESSION_DICTIONARY, META_KEY_RANGE, META_RANGE_TOMBSTONES,
+    VERSION,
+};
 ///
 /// Writes sorted key-value-timestamp tuples to disk in an immutable format.
 ///
 /// # Format
 /// - Data is written in blocks (default 16KB)
-/// - Each block is independently compressed with Snappy
+/// - Each block is independently compressed, with Snappy unless another
+///   codec is chosen; with Zstd, optionally through a dictionary trained on
+///   the first blocks and stored in a meta block
 /// - Keys within blocks use prefix compression
+/// - A block of numeric samples of one key is stored as a column of
+///   numbers instead (Gorilla encoding)
+/// - Each entry records its `OpType`, so tombstones survive a flush
+/// - Each entry records its sequence number; a key may have several
+///   versions, added newest first
 /// - Index allows binary search over blocks
 /// - Bloom filter enables fast "key not found" checks
+/// - Range tombstones go in a meta block, located through the meta index
+/// - Every section carries a CRC32C trailer (format v2)
+/// - So do the smallest and largest keys, letting readers skip the table
 ///
 /// # Usage
 /// ```ignore
@@ -18,13 +34,35 @@ use super::format::{Footer, Header, IndexEntry, HEADER_SIZE};
 /// // Keys MUST be added in sorted order
 /// writer.add(b"key1", b"value1", 1000)?;
 /// writer.add(b"key2", b"value2", 2000)?;
+/// writer.add_record(&Record::delete(b"key3".to_vec(), 3000))?;
+/// writer.add_range_tombstone(RangeTombstone::new(b"a".to_vec(), b"c".to_vec(), 900));
 /// writer.finish()?;  // Flushes remaining data and writes metadata
 /// ```
-use crate::{Result, Timestamp};
+use crate::{RangeTombstone, Record, Result, SequenceNumber, StorageError, Timestamp};
 use std::fs::File;
 use std::io::Write;
 use std::path::PathBuf;
 
+/// Uncompressed data to sample per byte of dictionary: zstd's advice of
+/// about 100x
+const DICTIONARY_SAMPLE_RATIO: usize = 100;
+
+/// Less sampled data than this many times the dictionary size means a
+/// small file, which the dictionary would not pay for
+const MIN_DICTIONARY_RATIO: usize = 8;
+
+/// Zstd dictionary of the file being written
+enum DictionaryState {
+    None,
+    /// Holding back the first blocks, uncompressed, to train on
+    Sampling {
+        max_size: usize,
+        blocks: Vec<(Vec<u8>, Vec<u8>)>,
+        sampled: usize,
+    },
+    Trained(ZstdDictionary),
+}
+
 pub struct SsTableWriter {
     file: File,
     #[allow(dead_code)]
@@ -32,9 +70,14 @@ pub struct SsTableWriter {
     block_builder: BlockBuilder,
     index_entries: Vec<IndexEntry>,
     bloom_filter: BloomFilterBuilder,
+    range_tombstones: Vec<RangeTombstone>,
+    /// Smallest and largest key added so far
+    key_range: Option<(Vec<u8>, Vec<u8>)>,
     block_size: usize,
     offset: u64,
     header: Header,
+    compression: CompressionType,
+    dictionary: DictionaryState,
 }
 
 impl SsTableWriter {
@@ -57,28 +100,98 @@ impl SsTableWriter {
             block_builder: BlockBuilder::new(),
             index_entries: Vec::new(),
             bloom_filter: BloomFilterBuilder::new(10000, 0.01),
+            range_tombstones: Vec::new(),
+            key_range: None,
             block_size,
             offset: HEADER_SIZE as u64, // ← Start AFTER header!
             header: Header::new(),
+            compression: CompressionType::default(),
+            dictionary: DictionaryState::None,
         })
     }
 
+    /// Compress data blocks with `compression` instead of Snappy
+    ///
+    /// Call before adding anything; the codec is recorded in the header.
+    pub fn with_compression(mut self, compression: CompressionType) -> Self {
+        self.header.compression = compression as u8;
+        self.compression = compression;
+        self
+    }
+
+    /// Write format `version` instead of the current [`VERSION`]
+    ///
+    /// Version 1 has no checksums; it is what releases before v2 can read.
+    /// Call before adding anything. Versions this writer cannot produce are
+    /// an `InvalidFormat` error.
+    pub fn with_format_version(mut self, version: u32) -> Result<Self> {
+        if !(1..=VERSION).contains(&version) {
+            return Err(StorageError::InvalidFormat(format!(
+                "Unsupported SSTable format version {}",
+                version
+            )));
+        }
+        self.header.version = version;
+        Ok(self)
+    }
+
+    /// Compress data blocks with Zstd and a dictionary of up to `max_size`
+    /// bytes, trained on the first blocks written
+    ///
+    /// Blocks are held back uncompressed until about 100x `max_size` bytes
+    /// have been sampled (or the table is finished), then trained on and
+    /// written. Tables too small to pay for the dictionary, or to train one
+    /// on, are compressed without. Call before adding anything.
+    pub fn with_zstd_dictionary(self, max_size: usize) -> Self {
+        let mut writer = self.with_compression(CompressionType::Zstd);
+        if max_size > 0 {
+            writer.dictionary = DictionaryState::Sampling {
+                max_size,
+                blocks: Vec::new(),
+                sampled: 0,
+            };
+        }
+        writer
+    }
+
     /// Add a key-value pair with timestamp
     /// Keys MUST be added in sorted order!
     pub fn add(&mut self, key: &[u8], value: &[u8], timestamp: Timestamp) -> Result<()> {
+        self.add_record(&Record::put(key.to_vec(), value.to_vec(), timestamp))
+    }
+
+    /// Add a record (put or tombstone)
+    /// Keys MUST be added in sorted order, versions of a key newest first!
+    pub fn add_record(&mut self, record: &Record) -> Result<()> {
         // Add to bloom filter
-        self.bloom_filter.add(key);
+        self.bloom_filter.add(&record.key);
+
+        // Keys arrive sorted: the first is the smallest, the latest the largest
+        match &mut self.key_range {
+            Some((_, largest)) => {
+                if *largest != record.key {
+                    *largest = record.key.clone();
+                }
+            }
+            None => self.key_range = Some((record.key.clone(), record.key.clone())),
+        }
 
-        // Update min/max timestamps
-        if timestamp < self.header.min_timestamp {
-            self.header.min_timestamp = timestamp;
+        self.track_timestamp(record.timestamp);
+        self.track_sequence(record.seq);
+        if record.is_tombstone() || record.ttl != 0 {
+            self.header.tombstones = self.header.tombstones.saturating_add(1);
         }
-        if timestamp > self.header.max_timestamp {
-            self.header.max_timestamp = timestamp;
+
+        // A long enough run of numeric samples ends with its key: close the
+        // block so the run can be stored in the numeric encoding
+        if self.block_builder.numeric_samples() >= MIN_NUMERIC_BLOCK_SAMPLES
+            && self.block_builder.first_key() != Some(record.key.as_slice())
+        {
+            self.flush_block()?;
         }
 
         // Add to current block
-        self.block_builder.add(key, value, timestamp);
+        self.block_builder.add(record);
 
         // Flush block if it's full
         if self.block_builder.size() >= self.block_size {
@@ -88,6 +201,28 @@ impl SsTableWriter {
         Ok(())
     }
 
+    /// Add a range tombstone (any order; stored in a meta block)
+    pub fn add_range_tombstone(&mut self, tombstone: RangeTombstone) {
+        self.track_timestamp(tombstone.timestamp);
+        self.track_sequence(tombstone.seq);
+        self.range_tombstones.push(tombstone);
+    }
+
+    /// Update min/max timestamps
+    fn track_timestamp(&mut self, timestamp: Timestamp) {
+        if timestamp < self.header.min_timestamp {
+            self.header.min_timestamp = timestamp;
+        }
+        if timestamp > self.header.max_timestamp {
+            self.header.max_timestamp = timestamp;
+        }
+    }
+
+    /// Update the highest sequence number
+    fn track_sequence(&mut self, seq: SequenceNumber) {
+        self.header.max_sequence = self.header.max_sequence.max(seq);
+    }
+
     /// Flush current block to disk
     fn flush_block(&mut self) -> Result<()> {
         if self.block_builder.is_empty() {
@@ -101,25 +236,76 @@ impl SsTableWriter {
             .ok_or_else(|| crate::StorageError::InvalidFormat("Block has no first key".into()))?
             .to_vec();
 
-        // Build and compress block
-        let compressed = self.block_builder.finish()?;
+        let block = self.block_builder.finish();
+
+        // Reset block builder for next block
+        self.block_builder.reset();
+
+        // Still sampling for the dictionary: hold the block back
+        if let DictionaryState::Sampling {
+            max_size,
+            blocks,
+            sampled,
+        } = &mut self.dictionary
+        {
+            *sampled += block.len();
+            blocks.push((first_key, block));
+            if *sampled >= *max_size * DICTIONARY_SAMPLE_RATIO {
+                self.train_dictionary()?;
+            }
+            return Ok(());
+        }
+
+        self.write_block(first_key, &block)
+    }
+
+    /// Compress a finished block and write it at the current offset
+    fn write_block(&mut self, first_key: Vec<u8>, block: &[u8]) -> Result<()> {
+        let compressed = match &self.dictionary {
+            DictionaryState::Trained(dictionary) => dictionary.compress(block)?,
+            _ => self.compression.compress(block)?,
+        };
 
         // Write block data at current offset
-        self.file.write_all(&compressed)?;
+        let offset = self.write_section(&compressed)?;
 
         // Add index entry (remembers where this block is)
         self.index_entries.push(IndexEntry {
             first_key,
-            offset: self.offset,
+            offset,
             size: compressed.len() as u32,
         });
-
-        self.offset += compressed.len() as u64;
         self.header.num_blocks += 1;
 
-        // Reset block builder for next block
-        self.block_builder.reset();
+        Ok(())
+    }
+
+    /// Train the dictionary on the held-back blocks, then write them
+    fn train_dictionary(&mut self) -> Result<()> {
+        let (max_size, blocks, sampled) =
+            match std::mem::replace(&mut self.dictionary, DictionaryState::None) {
+                DictionaryState::Sampling {
+                    max_size,
+                    blocks,
+                    sampled,
+                } => (max_size, blocks, sampled),
+                other => {
+                    self.dictionary = other;
+                    return Ok(());
+                }
+            };
+
+        if sampled >= max_size * MIN_DICTIONARY_RATIO {
+            let samples: Vec<&[u8]> = blocks.iter().map(|(_, block)| block.as_slice()).collect();
+            // Data zstd cannot train on is compressed without a dictionary
+            if let Ok(dictionary) = ZstdDictionary::train(&samples, max_size) {
+                self.dictionary = DictionaryState::Trained(dictionary);
+            }
+        }
 
+        for (first_key, block) in blocks {
+            self.write_block(first_key, &block)?;
+        }
         Ok(())
     }
 
@@ -133,47 +319,115 @@ impl SsTableWriter {
     /// [Data Block N]
     /// [Bloom filter]         ← bloom_offset
     /// \[Index]                ← index_offset
+    /// [Meta blocks]          ← e.g. range tombstones, dictionary (optional)
+    /// [Meta index]           ← meta_index_offset (optional)
     /// [Footer: 64 bytes]     ← End of file (contains pointers)
+    ///
+    /// From format v2 on, each section between header and footer is
+    /// followed by its CRC32C.
     pub fn finish(&mut self) -> Result<()> {
-        // 1. Flush any remaining data in current block
+        // 1. Flush any remaining data in current block, and any blocks held
+        //    back for the dictionary
         self.flush_block()?;
+        self.train_dictionary()?;
 
         // 2. Write bloom filter at current offset
-        let bloom_offset = self.offset;
         let bloom_data = self.bloom_filter.finish();
-        self.file.write_all(&bloom_data)?;
+        let bloom_offset = self.write_section(&bloom_data)?;
         let bloom_size = bloom_data.len() as u32;
-        self.offset += bloom_data.len() as u64;
 
         // 3. Write index at current offset
-        let index_offset = self.offset;
         let index_data = self.encode_index()?;
-        self.file.write_all(&index_data)?;
+        let index_offset = self.write_section(&index_data)?;
         let index_size = index_data.len() as u32;
-        self.offset += index_data.len() as u64;
 
-        // 4. Write footer at end of file
+        // 4. Write meta blocks, then the meta index that names them
+        let (meta_index_offset, meta_index_size) = self.write_meta_blocks()?;
+
+        // 5. Write footer at end of file
         let footer = Footer {
             index_offset,
             bloom_offset,
             index_size,
             bloom_size,
-            checksum: 0, // TODO: Calculate checksum in Week 2
+            checksum: 0, // Set by encode_with_checksum from v2 on
+            meta_index_offset,
+            meta_index_size,
         };
-        self.file.write_all(&footer.encode())?;
+        if self.header.has_checksums() {
+            self.file.write_all(&footer.encode_with_checksum())?;
+        } else {
+            self.file.write_all(&footer.encode())?;
+        }
 
-        // 5. CRITICAL FIX: Go back to position 0 and overwrite header
+        // 6. CRITICAL FIX: Go back to position 0 and overwrite header
         //    with real values (num_blocks, min/max timestamps)
         use std::io::Seek;
         self.file.seek(std::io::SeekFrom::Start(0))?;
         self.file.write_all(&self.header.encode())?;
 
-        // 6. Ensure everything is persisted to disk
+        // 7. Ensure everything is persisted to disk
         self.file.sync_all()?;
 
         Ok(())
     }
 
+    /// Write meta blocks and the meta index
+    ///
+    /// Returns (meta_index_offset, meta_index_size); a size of 0 means the
+    /// table has no meta blocks.
+    fn write_meta_blocks(&mut self) -> Result<(u64, u32)> {
+        let mut blocks = Vec::new();
+        if !self.range_tombstones.is_empty() {
+            blocks.push((
+                META_RANGE_TOMBSTONES,
+                encode_range_tombstones(&self.range_tombstones),
+            ));
+        }
+        if let Some((smallest, largest)) = &self.key_range {
+            blocks.push((META_KEY_RANGE, encode_key_range(smallest, largest)));
+        }
+        if let DictionaryState::Trained(dictionary) = &self.dictionary {
+            blocks.push((META_COMPRESSION_DICTIONARY, dictionary.as_bytes().to_vec()));
+        }
+
+        if blocks.is_empty() {
+            return Ok((0, 0));
+        }
+
+        let mut meta_index = Vec::new();
+        for (name, data) in blocks {
+            meta_index.push(MetaIndexEntry {
+                name: name.to_string(),
+                offset: self.write_section(&data)?,
+                size: data.len() as u32,
+            });
+        }
+
+        let mut buf = Vec::new();
+        for entry in &meta_index {
+            buf.extend_from_slice(&entry.encode());
+        }
+        let meta_index_offset = self.write_section(&buf)?;
+
+        Ok((meta_index_offset, buf.len() as u32))
+    }
+
+    /// Write a section at the current offset, followed by its checksum
+    /// from format v2 on
+    ///
+    /// Returns the section's offset; its size does not count the trailer.
+    fn write_section(&mut self, data: &[u8]) -> Result<u64> {
+        let offset = self.offset;
+        self.file.write_all(data)?;
+        self.offset += data.len() as u64;
+        if self.header.has_checksums() {
+            self.file.write_all(&crc32c(data).to_le_bytes())?;
+            self.offset += CHECKSUM_SIZE as u64;
+        }
+        Ok(offset)
+    }
+
     /// Encode all index entries into a byte buffer
     fn encode_index(&self) -> Result<Vec<u8>> {
         let mut buf = Vec::new();
@@ -246,4 +500,23 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_writer_rejects_unknown_format_versions() -> Result<()> {
+        let dir = tempdir().unwrap();
+        let writer = |version| {
+            SsTableWriter::new(dir.path().join("v.sst"), 1024)?.with_format_version(version)
+        };
+
+        assert_eq!(writer(1)?.header.version, 1);
+        assert_eq!(writer(VERSION)?.header.version, VERSION);
+        for version in [0, VERSION + 1] {
+            assert!(matches!(
+                writer(version),
+                Err(StorageError::InvalidFormat(_))
+            ));
+        }
+
+        Ok(())
+    }
 }
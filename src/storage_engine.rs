@@ -1,23 +1,53 @@
-use crate::compaction::{compact_sstables, select_sstables_for_compaction};
+use crate::aggregate::{parse_number, AggregateQuery, Aggregator, Bucket, Summary};
+use crate::compaction::{
+    compact_sstables_with_options, expire_versions, keep_either, select_sstables_for_compaction,
+    CompactionOptions, NewerSource,
+};
+use crate::iterator::{DbIterator, SourceIterator};
+use crate::memtable::{SharedMemTable, SharedMemTableIterator};
+use crate::merge::{collapse_merges, MergeOperator};
 use crate::metrics::metrics;
-use crate::sstable::{SsTableReader, SsTableWriter};
-use crate::{Entry, MemTable, Result, Wal};
+use crate::rollup::{
+    decode_since, decode_summary, dirty_key, encode_since, encode_summary, parse_rollup_key,
+    rollup_key, rollup_range, since_key, RollupRule, ROLLUP_KEYSPACE,
+};
+use crate::series::{self, SeriesFilter, SeriesId, SeriesKey, SeriesSamples, SERIES_KEYSPACE};
+use crate::snapshot::{history_versions, needed_versions, resolve_visible, SnapshotList};
+use crate::sstable::{
+    BlockCache, CompressionType, SsTableHandle, SsTableWriter, TableCache, DEFAULT_MAX_OPEN_FILES,
+};
+use crate::version::Version;
+use crate::{
+    now_timestamp, prefix_successor, Key, MemTable, OpType, RangeTombstone, Record, Result,
+    ScanEntry, SequenceNumber, Snapshot, StorageError, Timestamp, Wal, WriteBatch,
+};
+use arc_swap::ArcSwap;
 use crossbeam::channel::{self, Receiver, Sender};
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::thread;
-use std::time::{Duration, Instant, SystemTime};
+use std::time::{Duration, Instant};
 
 const DEFAULT_BLOCK_SIZE: usize = 16 * 1024;
 
+/// Keyspaces the engine keeps its own keys in: hidden from scans and
+/// iterators, and closed to writes through the public API
+const RESERVED_KEYSPACES: &[&[u8]] = &[ROLLUP_KEYSPACE, SERIES_KEYSPACE];
+
 /// Message for background flush thread
 enum FlushMessage {
     Flush {
-        memtable: MemTable,
+        memtable: SharedMemTable,
         path: PathBuf,
         sstable_id: u64,
+        snapshots: Vec<SequenceNumber>,
+        merge_operator: Option<Arc<dyn MergeOperator>>,
+        keep_history: bool,
+        compression: CompressionType,
     },
     Shutdown,
 }
@@ -29,16 +59,53 @@ struct FlushResult {
     path: PathBuf,
 }
 
+/// State owned by whoever holds the writer lock
+struct WriterState {
+    last_compaction_check: Instant,
+    /// Whether the rollup rules have been started since the engine opened
+    rollups_started: bool,
+    /// Newest timestamp written; writes the engine stamps go after it
+    last_timestamp: Timestamp,
+}
+
+/// The storage engine
+///
+/// Every method takes `&self`, so one engine can be shared across threads
+/// behind an `Arc`. Reads load the current [`Version`] and never wait for
+/// writes, flushes or compactions; those are serialised by an internal
+/// writer lock.
 pub struct StorageEngine {
     wal: Arc<RwLock<Wal>>,
-    memtable: MemTable,
-    immutable_memtable: Option<MemTable>,
-    sstables: Vec<SsTableReader>,
+    /// MemTables and SSTables as readers see them, swapped on flush and compaction
+    version: ArcSwap<Version>,
+    /// Serialises writes, flushes and compactions
+    writer: Mutex<WriterState>,
     #[allow(dead_code)]
     wal_path: PathBuf,
     data_dir: PathBuf,
     memtable_max_size: usize,
     sstable_counter: AtomicU64,
+    /// Sequence number of the newest write visible to readers
+    last_sequence: AtomicU64,
+    snapshots: SnapshotList,
+    /// Open SSTable readers, and the block cache they share
+    table_cache: Arc<TableCache>,
+    /// Folds merge operands; `merge` is refused without one
+    merge_operator: Option<Arc<dyn MergeOperator>>,
+    /// Default TTL of puts under each key prefix
+    prefix_ttls: Vec<(Key, Timestamp)>,
+    /// Keep every sample of a key through flush and compaction
+    series_mode: bool,
+    /// Aggregates maintained as samples are written
+    rollups: Vec<RollupRule>,
+    /// IDs of the series seen so far; an ID never changes once assigned
+    series_ids: RwLock<HashMap<SeriesKey, SeriesId>>,
+    /// Codec of flushed and compacted SSTables
+    compression: CompressionType,
+    /// Codec of compaction outputs that hold the oldest data, if different
+    bottommost_compression: Option<CompressionType>,
+    /// Largest Zstd dictionary to train for a compaction output (0 = none)
+    zstd_dictionary_size: usize,
 
     flush_tx: Option<Sender<FlushMessage>>,
     flush_rx: Option<Receiver<FlushResult>>,
@@ -46,7 +113,6 @@ pub struct StorageEngine {
     background_flush_enabled: bool,
 
     compaction_enabled: bool,
-    last_compaction_check: Instant,
 }
 
 #[derive(Debug, serde::Serialize)] // Added serde::Serialize
@@ -55,6 +121,8 @@ pub struct EngineStats {
     pub memtable_bytes: usize,
     pub num_sstables: usize,
     pub immutable_memtable_entries: usize,
+    pub block_cache_bytes: usize,
+    pub open_sstables: usize,
 }
 
 impl StorageEngine {
@@ -74,11 +142,10 @@ impl StorageEngine {
 
         let wal_path = dir.join("data.wal");
 
-        let entries = Wal::recover(&wal_path)?;
-        let mut memtable = MemTable::new(memtable_max_size);
-        for entry in entries {
-            memtable.put(entry.key, entry.value, entry.timestamp)?;
-        }
+        let table_cache = Arc::new(TableCache::new(
+            DEFAULT_MAX_OPEN_FILES,
+            Arc::new(BlockCache::default()),
+        ));
 
         // Load SSTables
         let mut sstables = Vec::new();
@@ -87,26 +154,57 @@ impl StorageEngine {
             let entry = entry?;
             let path = entry.path();
             if path.extension().and_then(|s| s.to_str()) == Some("sst") {
-                if let Ok(reader) = SsTableReader::open(path.clone()) {
-                    if let Some(stem) = path.file_stem() {
-                        if let Some(id_str) = stem.to_str() {
-                            if let Ok(id) = id_str.parse::<u64>() {
-                                max_sstable_id = max_sstable_id.max(id);
-                            }
-                        }
+                if let Some(id) = sstable_id(&path) {
+                    max_sstable_id = max_sstable_id.max(id);
+                }
+                match SsTableHandle::open(path.clone(), Arc::clone(&table_cache)) {
+                    Ok(table) => sstables.push(Arc::new(table)),
+                    // A flush cut short by a crash, or a damaged file: keep
+                    // it for inspection, out of the way of the next open.
+                    // Unflushed records are still in the WAL.
+                    Err(e) => {
+                        let aside = path.with_extension("sst.corrupt");
+                        eprintln!(
+                            "Error: cannot open SSTable {:?} ({}), moving it to {:?}",
+                            path, e, aside
+                        );
+                        std::fs::rename(&path, &aside)?;
                     }
-                    sstables.push(reader);
                 }
             }
         }
-        sstables.sort_by_key(|r| {
-            r.info()
-                .path
-                .file_stem()
-                .and_then(|s| s.to_str())
-                .and_then(|s| s.parse::<u64>().ok())
-                .unwrap_or(0)
-        });
+        sstables.sort_by_key(|table| sstable_id(table.path()).unwrap_or(0));
+
+        // Replay the WAL on top. Records keep the sequence number they were
+        // written with; legacy records without one are numbered after
+        // everything seen so far. Records the SSTables already hold are
+        // skipped: a merge operand applied twice would be counted twice.
+        let flushed_sequence = sstables.iter().map(|r| r.max_sequence()).max().unwrap_or(0);
+        let mut last_sequence = flushed_sequence;
+        let records = Wal::recover_records(&wal_path)?;
+        let mut memtable = MemTable::new(memtable_max_size);
+        for record in records {
+            if record.seq != 0 && record.seq <= flushed_sequence {
+                continue;
+            }
+            let seq = if record.seq == 0 {
+                last_sequence + 1
+            } else {
+                record.seq
+            };
+            last_sequence = last_sequence.max(seq);
+            memtable.apply(record.with_seq(seq))?;
+        }
+
+        // Writes stamped from now on go after everything already stored,
+        // even if the clock has stepped back since
+        let last_timestamp = sstables
+            .iter()
+            .map(|table| table.max_timestamp())
+            .chain(memtable.max_timestamp())
+            .chain(memtable.range_tombstones().iter().map(|rt| rt.timestamp))
+            .max()
+            .unwrap_or(0);
 
         // Setup background flush
         let (flush_tx, flush_rx, flush_thread) = if background_flush {
@@ -118,21 +216,40 @@ impl StorageEngine {
             (None, None, None)
         };
 
-        Ok(StorageEngine {
-            wal,
-            memtable,
+        let version = Version {
+            memtable: Arc::new(RwLock::new(memtable)),
             immutable_memtable: None,
             sstables,
+        };
+
+        Ok(StorageEngine {
+            wal,
+            version: ArcSwap::from_pointee(version),
+            writer: Mutex::new(WriterState {
+                last_compaction_check: Instant::now(),
+                rollups_started: false,
+                last_timestamp,
+            }),
             wal_path,
             data_dir: dir,
             memtable_max_size,
             sstable_counter: AtomicU64::new(max_sstable_id + 1),
+            last_sequence: AtomicU64::new(last_sequence),
+            snapshots: SnapshotList::default(),
             flush_tx,
             flush_rx,
             _flush_thread: flush_thread,
             background_flush_enabled: background_flush,
             compaction_enabled: true,
-            last_compaction_check: Instant::now(),
+            table_cache,
+            merge_operator: None,
+            prefix_ttls: Vec::new(),
+            series_mode: false,
+            rollups: Vec::new(),
+            series_ids: RwLock::new(HashMap::new()),
+            compression: CompressionType::default(),
+            bottommost_compression: None,
+            zstd_dictionary_size: 0,
         })
     }
 
@@ -142,6 +259,103 @@ impl StorageEngine {
         self
     }
 
+    /// Set the block cache capacity in bytes (0 disables it)
+    pub fn with_block_cache_size(self, capacity_bytes: usize) -> Self {
+        self.table_cache.block_cache().set_capacity(capacity_bytes);
+        self
+    }
+
+    /// Set how many SSTable readers are kept open; others are reopened on demand
+    pub fn with_max_open_files(self, max_open_files: usize) -> Self {
+        self.table_cache.set_max_open_files(max_open_files);
+        self
+    }
+
+    /// Check the CRC32C of every SSTable data block read from disk (on by default)
+    ///
+    /// Table metadata is always checked when a table is opened, and
+    /// compaction always checks what it reads, so corruption is not copied
+    /// into new tables. Files written before format v2 have no checksums.
+    pub fn with_verify_checksums(self, verify: bool) -> Self {
+        self.table_cache.set_verify_checksums(verify);
+        self
+    }
+
+    /// Register the operator that folds [`StorageEngine::merge`] operands
+    ///
+    /// Operands already stored are folded with it too, so an engine must be
+    /// reopened with the same operator it wrote them with.
+    pub fn with_merge_operator(mut self, operator: Arc<dyn MergeOperator>) -> Self {
+        self.merge_operator = Some(operator);
+        self
+    }
+
+    /// Give puts under `prefix` a default time to live
+    ///
+    /// Applies to puts written without a TTL of their own; the longest
+    /// matching prefix wins. Expired keys read as deleted and are dropped
+    /// by compaction.
+    pub fn with_prefix_ttl(mut self, prefix: Vec<u8>, ttl: Duration) -> Self {
+        self.prefix_ttls.push((prefix, ttl_nanos(ttl)));
+        self
+    }
+
+    /// Keep the full history of every key instead of only its newest value
+    ///
+    /// Each write is a sample at its timestamp, and flush and compaction
+    /// keep all of them (a rewrite at the same timestamp still replaces the
+    /// sample, and a delete drops the history before it), so
+    /// [`StorageEngine::range_query`] can return them.
+    pub fn with_series_mode(mut self, enabled: bool) -> Self {
+        self.series_mode = enabled;
+        self
+    }
+
+    /// Compress the data blocks of new SSTables with `compression` (Snappy by default)
+    ///
+    /// Each file records its codec, so tables written with another one
+    /// stay readable.
+    pub fn with_compression(mut self, compression: CompressionType) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Compress compaction outputs that hold the oldest data with `compression`
+    ///
+    /// Those tables are the largest and the least often rewritten, so a
+    /// slower, denser codec (Zstd) pays off there while flushes stay fast.
+    /// Defaults to the codec of [`StorageEngine::with_compression`].
+    pub fn with_bottommost_compression(mut self, compression: CompressionType) -> Self {
+        self.bottommost_compression = Some(compression);
+        self
+    }
+
+    /// Train a Zstd dictionary of up to `max_size` bytes for each compaction
+    /// output compressed with Zstd
+    ///
+    /// Each block is compressed on its own, so the key prefixes and value
+    /// layouts it shares with the rest of the file cost it every time; the
+    /// dictionary, sampled from the data being written and stored once in
+    /// the file, holds them instead. 16KB is a good size; 0 disables it.
+    pub fn with_zstd_dictionary(mut self, max_size: usize) -> Self {
+        self.zstd_dictionary_size = max_size;
+        self
+    }
+
+    /// Maintain a rollup of the keys matched by `rule` as they are written
+    ///
+    /// Rollups need series mode (see [`StorageEngine::with_series_mode`]) and
+    /// only count samples written while the rule is registered, so register
+    /// it every time the engine is opened. [`StorageEngine::aggregate`] then
+    /// reads the rollup instead of the raw samples when its buckets line up
+    /// with the query's, from the first write after the rule was first
+    /// registered on, and only for keys none of whose samples were deleted,
+    /// rewritten, merged into or given a TTL.
+    pub fn with_rollup(mut self, rule: RollupRule) -> Self {
+        self.rollups.push(rule);
+        self
+    }
+
     fn spawn_flush_thread(
         rx: Receiver<FlushMessage>,
         result_tx: Sender<FlushResult>,
@@ -153,8 +367,20 @@ impl StorageEngine {
                         memtable,
                         path,
                         sstable_id,
+                        snapshots,
+                        merge_operator,
+                        keep_history,
+                        compression,
                     } => {
-                        if let Err(e) = Self::flush_memtable_to_disk(memtable, &path) {
+                        let result = Self::flush_memtable_to_disk(
+                            &memtable.read(),
+                            &path,
+                            &snapshots,
+                            merge_operator.as_deref(),
+                            keep_history,
+                            compression,
+                        );
+                        if let Err(e) = result {
                             eprintln!("Background flush FAILED: {}", e);
                         } else {
                             let _ = result_tx.send(FlushResult { sstable_id, path });
@@ -166,15 +392,66 @@ impl StorageEngine {
         })
     }
 
-    fn flush_memtable_to_disk(memtable: MemTable, path: &Path) -> Result<()> {
+    /// Write a MemTable out as an SSTable
+    ///
+    /// Only the versions that the current state or a live snapshot in
+    /// `snapshots` can read are written, with runs of merge operands folded
+    /// by `merge_operator`. With `keep_history` (series mode) the history of
+    /// every key is written as well, operands unfolded. Expired puts are
+    /// written as tombstones. Blocks are compressed with `compression`.
+    fn flush_memtable_to_disk(
+        memtable: &MemTable,
+        path: &Path,
+        snapshots: &[SequenceNumber],
+        merge_operator: Option<&dyn MergeOperator>,
+        keep_history: bool,
+        compression: CompressionType,
+    ) -> Result<()> {
         let start = Instant::now();
 
         if memtable.is_empty() {
             return Ok(());
         }
-        let mut writer = SsTableWriter::new(path.to_path_buf(), DEFAULT_BLOCK_SIZE)?;
-        for (key, value, timestamp) in memtable.entries_with_timestamps() {
-            writer.add(&key, &value, timestamp)?;
+        let mut writer = SsTableWriter::new(path.to_path_buf(), DEFAULT_BLOCK_SIZE)?
+            .with_compression(compression);
+
+        let now = now_timestamp()?;
+        let range_tombstones = memtable.range_tombstones();
+        let mut records = memtable.versions().peekable();
+        let mut versions: Vec<Record> = Vec::new();
+        while let Some(record) = records.next() {
+            let key_done = records.peek().is_none_or(|next| next.key != record.key);
+            versions.push(record);
+            if !key_done {
+                continue;
+            }
+
+            let covering: Vec<&RangeTombstone> = range_tombstones
+                .iter()
+                .filter(|rt| rt.covers(&versions[0].key))
+                .collect();
+            expire_versions(&mut versions, now);
+            let keep = if keep_history {
+                keep_either(
+                    needed_versions(&versions, &covering, snapshots),
+                    history_versions(&versions, &covering),
+                )
+            } else {
+                if let Some(operator) = merge_operator {
+                    // Older SSTables may still hold the base of an operand run
+                    collapse_merges(&mut versions, &covering, snapshots, operator, false);
+                }
+                needed_versions(&versions, &covering, snapshots)
+            };
+            for (version, kept) in versions.drain(..).zip(keep) {
+                if kept {
+                    writer.add_record(&version)?;
+                }
+            }
+        }
+
+        for tombstone in range_tombstones {
+            writer.add_range_tombstone(tombstone.clone());
         }
         writer.finish()?;
 
@@ -188,89 +465,609 @@ impl StorageEngine {
         Ok(())
     }
 
-    pub fn put(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
+    pub fn put(&self, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
+        check_unreserved_key(&key)?;
         let start = Instant::now();
 
         // Increment write counters
         metrics().writes_total.inc();
         metrics().writes_bytes.add((key.len() + value.len()) as u64);
 
-        let timestamp = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)?
-            .as_secs();
-        let entry = Entry {
-            key: key.clone(),
-            value: value.clone(),
-            timestamp,
-        };
+        self.commit_now(|timestamp| vec![Record::put(key, value, timestamp)])?;
+
+        // Record latency
+        metrics().write_latency.observe(start.elapsed());
+
+        Ok(())
+    }
+
+    /// Write a key with a caller-supplied timestamp (Unix nanoseconds)
+    ///
+    /// For backfilling history or keeping a device's own clock. Versions of
+    /// a key are ordered by timestamp, not by arrival: a write older than
+    /// the key's current version (or a delete covering it) is stored but
+    /// stays hidden behind it, and same-second writes go by arrival order.
+    /// A prefix TTL counts from `timestamp`. Timestamps later than now are
+    /// refused, since they would shadow every write the engine stamps until
+    /// the clock caught up.
+    pub fn put_with_timestamp(
+        &self,
+        key: Vec<u8>,
+        value: Vec<u8>,
+        timestamp: Timestamp,
+    ) -> Result<()> {
+        check_unreserved_key(&key)?;
+        self.put_at(key, value, timestamp)
+    }
+
+    /// [`StorageEngine::put_with_timestamp`], to any key
+    fn put_at(&self, key: Vec<u8>, value: Vec<u8>, timestamp: Timestamp) -> Result<()> {
+        let start = Instant::now();
+
+        if timestamp > now_timestamp()? {
+            return Err(StorageError::InvalidFormat(format!(
+                "Timestamp {} is in the future",
+                timestamp
+            )));
+        }
+
+        metrics().writes_total.inc();
+        metrics().writes_bytes.add((key.len() + value.len()) as u64);
+
+        self.commit(vec![Record::put(key, value, timestamp)])?;
+
+        metrics().write_latency.observe(start.elapsed());
+
+        Ok(())
+    }
+
+    /// Write a key that expires `ttl` after now
+    ///
+    /// Once expired the key reads as deleted, and compaction drops it.
+    pub fn put_with_ttl(&self, key: Vec<u8>, value: Vec<u8>, ttl: Duration) -> Result<()> {
+        check_unreserved_key(&key)?;
+        let start = Instant::now();
+
+        metrics().writes_total.inc();
+        metrics().writes_bytes.add((key.len() + value.len()) as u64);
+
+        let ttl = ttl_nanos(ttl);
+        self.commit_now(|timestamp| vec![Record::put(key, value, timestamp).with_ttl(ttl)])?;
+
+        metrics().write_latency.observe(start.elapsed());
+
+        Ok(())
+    }
+
+    /// Delete a key by writing a tombstone
+    ///
+    /// The tombstone shadows every older version of the key in the MemTables
+    /// and SSTables until compaction can prove nothing older remains.
+    pub fn delete(&self, key: Vec<u8>) -> Result<()> {
+        check_unreserved_key(&key)?;
+        let start = Instant::now();
+
+        metrics().deletes_total.inc();
+
+        self.commit_now(|timestamp| vec![Record::delete(key, timestamp)])?;
+
+        metrics().write_latency.observe(start.elapsed());
+
+        Ok(())
+    }
+
+    /// Write a merge operand for a key
+    ///
+    /// The operand is folded into the key's value by the registered
+    /// [`MergeOperator`] when the key is read, so concurrent merges never
+    /// lose an update. Fails if no operator is registered.
+    pub fn merge(&self, key: Vec<u8>, operand: Vec<u8>) -> Result<()> {
+        self.require_merge_operator()?;
+        check_unreserved_key(&key)?;
+
+        let start = Instant::now();
+
+        metrics().writes_total.inc();
+        metrics()
+            .writes_bytes
+            .add((key.len() + operand.len()) as u64);
+
+        self.commit_now(|timestamp| vec![Record::merge(key, operand, timestamp)])?;
+
+        metrics().write_latency.observe(start.elapsed());
+
+        Ok(())
+    }
+
+    fn require_merge_operator(&self) -> Result<&Arc<dyn MergeOperator>> {
+        self.merge_operator
+            .as_ref()
+            .ok_or_else(|| StorageError::MergeFailed("no merge operator registered".into()))
+    }
+
+    /// Delete every key in `[start, end)` with a single range tombstone
+    ///
+    /// An empty `end` deletes everything from `start` onwards. Keys written
+    /// after the call are not affected.
+    pub fn delete_range(&self, start: Vec<u8>, end: Vec<u8>) -> Result<()> {
+        if !end.is_empty() && start >= end {
+            return Err(StorageError::InvalidFormat(
+                "Range delete start must be before end".into(),
+            ));
+        }
+        check_unreserved_range(&start, &end)?;
+
+        self.write_range_tombstone(|timestamp| RangeTombstone::new(start, end, timestamp))
+    }
+
+    /// Delete every key starting with `prefix`
+    pub fn delete_prefix(&self, prefix: &[u8]) -> Result<()> {
+        check_unreserved_range(prefix, &prefix_successor(prefix))?;
+        self.write_range_tombstone(|timestamp| RangeTombstone::for_prefix(prefix, timestamp))
+    }
+
+    fn write_range_tombstone(
+        &self,
+        tombstone: impl FnOnce(Timestamp) -> RangeTombstone,
+    ) -> Result<()> {
+        let start = Instant::now();
+
+        metrics().deletes_total.inc();
+
+        self.commit_now(|timestamp| vec![Record::range_delete(tombstone(timestamp))])?;
+
+        metrics().write_latency.observe(start.elapsed());
+
+        Ok(())
+    }
+
+    /// Apply a [`WriteBatch`] atomically
+    ///
+    /// The batch is logged as one WAL record group and applied to the
+    /// MemTable in one step, so after a crash either every write in it is
+    /// recovered or none is. All writes share one timestamp and take
+    /// consecutive sequence numbers in batch order.
+    pub fn write(&self, batch: WriteBatch) -> Result<()> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+
+        let records = batch.into_records();
+        if records.iter().any(|record| record.op == OpType::Merge) {
+            self.require_merge_operator()?;
+        }
+        for record in &records {
+            match record.op {
+                OpType::RangeDelete => check_unreserved_range(&record.key, &record.value)?,
+                _ => check_unreserved_key(&record.key)?,
+            }
+        }
+
+        let start = Instant::now();
+
+        for record in &records {
+            if record.is_tombstone() {
+                metrics().deletes_total.inc();
+            } else {
+                metrics().writes_total.inc();
+                metrics()
+                    .writes_bytes
+                    .add((record.key.len() + record.value.len()) as u64);
+            }
+        }
+
+        self.commit_now(|timestamp| {
+            records
+                .into_iter()
+                .map(|mut record| {
+                    record.timestamp = timestamp;
+                    record
+                })
+                .collect()
+        })?;
+
+        metrics().write_latency.observe(start.elapsed());
+
+        Ok(())
+    }
+
+    /// Set `key` to `new` only if its current value is `expected`
+    ///
+    /// `expected: None` means the key must be missing (never written or
+    /// deleted). Returns `Ok(false)` without writing on a mismatch. The check
+    /// and the write happen under the writer lock, so no other write can
+    /// land in between: conditional writes are linearisable with respect to
+    /// every other write.
+    pub fn compare_and_swap(
+        &self,
+        key: Vec<u8>,
+        expected: Option<&[u8]>,
+        new: Vec<u8>,
+    ) -> Result<bool> {
+        check_unreserved_key(&key)?;
+        let start = Instant::now();
+        let mut writer = self.writer.lock();
+
+        // Every earlier write is in the current version while we hold the lock
+        let current = self
+            .get_record(&key, SequenceNumber::MAX)?
+            .filter(|record| record.op == OpType::Put);
+        if current.as_ref().map(|record| record.value.as_slice()) != expected {
+            return Ok(false);
+        }
+
+        metrics().writes_total.inc();
+        metrics().writes_bytes.add((key.len() + new.len()) as u64);
+
+        let timestamp = self.next_timestamp(&writer)?;
+        self.commit_locked(&mut writer, vec![Record::put(key, new, timestamp)])?;
+
+        metrics().write_latency.observe(start.elapsed());
+
+        Ok(true)
+    }
+
+    /// Set `key` to `value` only if the key is missing
+    ///
+    /// Returns `Ok(false)` without writing if the key already has a value.
+    /// See [`StorageEngine::compare_and_swap`].
+    pub fn put_if_absent(&self, key: Vec<u8>, value: Vec<u8>) -> Result<bool> {
+        self.compare_and_swap(key, None, value)
+    }
+
+    /// Log records to the WAL and apply them to the MemTable as one write
+    ///
+    /// Sequence numbers are assigned here, under the writer lock, and only
+    /// published once every record is in the MemTable, so readers and
+    /// snapshots see all of the records or none of them. More than one
+    /// record is logged as a batch group.
+    fn commit(&self, records: Vec<Record>) -> Result<()> {
+        let mut writer = self.writer.lock();
+        self.commit_locked(&mut writer, records)
+    }
+
+    /// [`StorageEngine::commit`] for a write the engine stamps: `records`
+    /// builds it from the timestamp taken under the writer lock
+    fn commit_now(&self, records: impl FnOnce(Timestamp) -> Vec<Record>) -> Result<()> {
+        let mut writer = self.writer.lock();
+        let timestamp = self.next_timestamp(&writer)?;
+        self.commit_locked(&mut writer, records(timestamp))
+    }
+
+    /// Timestamp for a write the engine stamps
+    ///
+    /// The clock, unless it has stepped back (e.g. an NTP correction) behind
+    /// the newest write: then just after that write, so the versions of a
+    /// key keep following the order of writes.
+    fn next_timestamp(&self, writer: &WriterState) -> Result<Timestamp> {
+        Ok(now_timestamp()?.max(writer.last_timestamp.saturating_add(1)))
+    }
+
+    /// [`StorageEngine::commit`] for callers already holding the writer lock
+    fn commit_locked(&self, writer: &mut WriterState, mut records: Vec<Record>) -> Result<()> {
+        if !self.rollups.is_empty() && self.series_mode {
+            let rollups = self.rollup_records(writer, &records)?;
+            records.extend(rollups);
+        }
+
+        let mut seq = self.last_sequence.load(Ordering::SeqCst);
+        for record in &mut records {
+            writer.last_timestamp = writer.last_timestamp.max(record.timestamp);
+            seq += 1;
+            record.seq = seq;
+            if record.op == OpType::Put && record.ttl == 0 {
+                record.ttl = self.default_ttl(&record.key);
+            }
+        }
 
         // ✅ Lock WAL, append, unlock
         {
             let mut wal_lock = self.wal.write();
-            wal_lock.append(&entry)?;
+            match records.as_slice() {
+                [record] => wal_lock.append_record(record)?,
+                records => wal_lock.append_batch(records)?,
+            }
             metrics().wal_size_bytes.set(wal_lock.size()?);
         } // Lock released here
 
-        self.check_and_compact()?;
+        self.apply_to_memtable(writer, records, seq)
+    }
+
+    /// Is a version of `key` stored at exactly `timestamp`, tombstones
+    /// included?
+    ///
+    /// A point lookup: only SSTables whose timestamps span `timestamp` and
+    /// whose key range and bloom filter admit `key` are read.
+    fn has_version(&self, key: &[u8], timestamp: Timestamp) -> Result<bool> {
+        let version = self.version.load();
+        if version.memtable.read().has_version(key, timestamp) {
+            return Ok(true);
+        }
+        if let Some(imm) = &version.immutable_memtable {
+            if imm.read().has_version(key, timestamp) {
+                return Ok(true);
+            }
+        }
+        for sstable in version.sstables.iter() {
+            if timestamp < sstable.min_timestamp()
+                || timestamp > sstable.max_timestamp()
+                || !sstable.may_overlap(key, key)
+            {
+                continue;
+            }
+            let reader = sstable.reader()?;
+            if reader.may_contain(key)
+                && reader
+                    .versions_of(key)?
+                    .iter()
+                    .any(|record| record.timestamp == timestamp)
+            {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Updated rollup summaries for the numeric puts in `records`, and
+    /// markers for the keys whose rollups they stop matching
+    ///
+    /// Called under the writer lock, so the stored summaries cannot change
+    /// before the updates are committed along with the samples.
+    fn rollup_records(&self, writer: &mut WriterState, records: &[Record]) -> Result<Vec<Record>> {
+        let now = now_timestamp()?;
+        let mut updates = Vec::new();
+        if !writer.rollups_started {
+            updates = self.start_rollups(now)?;
+            writer.rollups_started = true;
+        }
+
+        // Samples the rollups would count differently from the raw history
+        let mut dirty: BTreeMap<Key, Key> = BTreeMap::new();
+        let mut mark = |start: &[u8], end: Key| {
+            let marked = dirty.entry(start.to_vec()).or_insert_with(|| end.clone());
+            if !marked.is_empty() && (end.is_empty() || end > *marked) {
+                *marked = end;
+            }
+        };
+        let mut written = BTreeSet::new();
+        for record in records {
+            if record.op == OpType::RangeDelete {
+                if self
+                    .rollups
+                    .iter()
+                    .any(|rule| rule.overlaps(&record.key, &record.value))
+                {
+                    mark(&record.key, record.value.clone());
+                }
+                continue;
+            }
+            if !self.rollups.iter().any(|rule| rule.matches(&record.key)) {
+                continue;
+            }
+            let point_end = || [record.key.as_slice(), &[0]].concat();
+            if record.op != OpType::Put || record.ttl != 0 || self.default_ttl(&record.key) != 0 {
+                mark(&record.key, point_end());
+                continue;
+            }
+            // Nothing is stored past the newest timestamp written
+            let rewrite = !written.insert((&record.key, record.timestamp))
+                || (record.timestamp <= writer.last_timestamp
+                    && self.has_version(&record.key, record.timestamp)?);
+            if rewrite {
+                mark(&record.key, point_end());
+            }
+        }
+        for (start, end) in dirty {
+            let derived = dirty_key(&start);
+            let marked = self
+                .get_record(&derived, SequenceNumber::MAX)?
+                .filter(|record| record.op == OpType::Put)
+                .is_some_and(|record| {
+                    record.value.is_empty() || (!end.is_empty() && record.value >= end)
+                });
+            if !marked {
+                updates.push(Record::put(derived, end, now));
+            }
+        }
+
+        let mut summaries: BTreeMap<Key, (Timestamp, Summary)> = BTreeMap::new();
+        for record in records.iter().filter(|record| record.op == OpType::Put) {
+            let number = match parse_number(&record.value) {
+                Some(number) => number,
+                None => continue,
+            };
+
+            // A width shared by two rules is still maintained once
+            let widths: BTreeSet<Timestamp> = self
+                .rollups
+                .iter()
+                .filter(|rule| rule.matches(&record.key))
+                .flat_map(|rule| rule.resolutions().iter().copied())
+                .collect();
+            for width in widths {
+                let bucket = record.timestamp - record.timestamp % width;
+                let derived = rollup_key(width, &record.key, bucket);
+                let summary = match summaries.entry(derived) {
+                    Entry::Occupied(entry) => entry.into_mut(),
+                    Entry::Vacant(entry) => {
+                        let stored = self
+                            .get_record(entry.key(), SequenceNumber::MAX)?
+                            .filter(|record| record.op == OpType::Put);
+                        let summary = match stored {
+                            Some(record) => decode_summary(&record.value)?,
+                            None => Summary::empty(),
+                        };
+                        entry.insert((bucket, summary))
+                    }
+                };
+                summary.1.add(number);
+            }
+        }
+
+        // Each summary is stamped with its bucket start, so in series mode
+        // an update replaces the previous one instead of adding a sample
+        updates.extend(summaries.into_iter().map(|(derived, (bucket, summary))| {
+            Record::put(derived, encode_summary(&summary), bucket)
+        }));
+        Ok(updates)
+    }
+
+    /// Start records for the rule widths that have never been started
+    ///
+    /// Every sample committed from now on is rolled up, but samples already
+    /// stored may carry any timestamp, so a rollup starts past the newest.
+    fn start_rollups(&self, now: Timestamp) -> Result<Vec<Record>> {
+        let version = self.version.load();
+        let mut newest = version.memtable.read().max_timestamp().unwrap_or(0);
+        if let Some(imm) = &version.immutable_memtable {
+            newest = newest.max(imm.read().max_timestamp().unwrap_or(0));
+        }
+        for sstable in version.sstables.iter() {
+            newest = newest.max(sstable.max_timestamp());
+        }
+        let since = encode_since(now.max(newest.saturating_add(1)));
+
+        let mut starts = BTreeSet::new();
+        for rule in &self.rollups {
+            for &width in rule.resolutions() {
+                starts.insert(since_key(rule.prefix(), width));
+            }
+        }
+        let mut records = Vec::new();
+        for derived in starts {
+            let started = self
+                .get_record(&derived, SequenceNumber::MAX)?
+                .is_some_and(|record| record.op == OpType::Put);
+            if !started {
+                records.push(Record::put(derived, since.clone(), now));
+            }
+        }
+        Ok(records)
+    }
+
+    /// TTL for a put to `key` without one: the longest matching prefix's
+    fn default_ttl(&self, key: &[u8]) -> Timestamp {
+        self.prefix_ttls
+            .iter()
+            .filter(|(prefix, _)| key.starts_with(prefix))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map_or(0, |&(_, ttl)| ttl)
+    }
+
+    /// Take a snapshot of the current state
+    ///
+    /// Reads through the snapshot ([`StorageEngine::get_at`],
+    /// [`StorageEngine::iter_at`]) only see writes made before this call.
+    /// Flush and compaction keep the versions it needs until it is dropped.
+    pub fn snapshot(&self) -> Snapshot {
+        self.snapshots.acquire(&self.last_sequence)
+    }
+
+    /// Insert WAL-logged records into the active MemTable under one lock
+    /// acquisition, publish `last_seq`, then flush if the MemTable is full
+    fn apply_to_memtable(
+        &self,
+        writer: &mut WriterState,
+        records: Vec<Record>,
+        last_seq: SequenceNumber,
+    ) -> Result<()> {
+        self.check_and_compact_locked(writer)?;
+
+        let memtable = Arc::clone(&self.version.load().memtable);
+        let is_full = {
+            let mut memtable = memtable.write();
+            let mut is_full = false;
+            for record in records {
+                is_full |= memtable.apply(record)?;
+            }
+            is_full
+        };
+
+        // Visible to new readers and snapshots from here on
+        self.last_sequence.store(last_seq, Ordering::SeqCst);
 
-        let is_full = self.memtable.put(key, value, timestamp)?;
         if is_full {
             if self.background_flush_enabled {
-                self.trigger_background_flush()?;
+                self.trigger_background_flush(writer)?;
             } else {
-                self.flush_memtable_sync()?;
+                self.flush_memtable_sync(writer)?;
             }
         }
 
         // Update memtable metrics
+        let version = self.version.load();
+        let memtable = version.memtable.read();
         metrics()
             .memtable_size_bytes
-            .set(self.memtable.size_bytes() as u64);
-        metrics().memtable_entries.set(self.memtable.len() as u64);
-
-        // Record latency
-        metrics().write_latency.observe(start.elapsed());
+            .set(memtable.size_bytes() as u64);
+        metrics().memtable_entries.set(memtable.len() as u64);
 
         Ok(())
     }
 
-    fn trigger_background_flush(&mut self) -> Result<()> {
+    /// Publish a new version built from the current one
+    ///
+    /// Requires the writer lock, so concurrent updates cannot be lost.
+    /// Readers that already loaded the old version keep using it.
+    fn install<T>(&self, _writer: &mut WriterState, update: impl FnOnce(&mut Version) -> T) -> T {
+        let mut version = Version::clone(&self.version.load());
+        let result = update(&mut version);
+        self.version.store(Arc::new(version));
+        result
+    }
+
+    fn trigger_background_flush(&self, writer: &mut WriterState) -> Result<()> {
         let max_wait = 100;
         let mut waited = 0;
-        while self.immutable_memtable.is_some() && waited < max_wait {
-            self.check_flush_completion()?;
-            if self.immutable_memtable.is_some() {
+        while self.flush_in_progress() && waited < max_wait {
+            self.check_flush_completion(writer)?;
+            if self.flush_in_progress() {
                 thread::sleep(Duration::from_millis(1));
                 waited += 1;
             }
         }
-        if self.immutable_memtable.is_some() {
-            return self.flush_memtable_sync();
+        if self.flush_in_progress() {
+            return self.flush_memtable_sync(writer);
         }
 
-        let old_memtable =
-            std::mem::replace(&mut self.memtable, MemTable::new(self.memtable_max_size));
         let sstable_id = self.sstable_counter.fetch_add(1, Ordering::SeqCst);
         let sstable_path = self.data_dir.join(format!("{:06}.sst", sstable_id));
 
-        let entries_clone = old_memtable.entries_with_timestamps().clone();
-        let mut immutable = MemTable::new(self.memtable_max_size);
-        for (k, v, ts) in entries_clone {
-            let _ = immutable.put(k, v, ts);
-        }
-        self.immutable_memtable = Some(immutable);
+        // Freeze the active MemTable; readers keep finding it as immutable
+        let fresh = self.new_memtable();
+        let old_memtable = self.install(writer, |version| {
+            let old = std::mem::replace(&mut version.memtable, fresh);
+            version.immutable_memtable = Some(Arc::clone(&old));
+            old
+        });
 
         if let Some(ref tx) = self.flush_tx {
             tx.send(FlushMessage::Flush {
                 memtable: old_memtable,
                 path: sstable_path,
                 sstable_id,
+                snapshots: self.snapshots.sequences(),
+                merge_operator: self.merge_operator.clone(),
+                keep_history: self.series_mode,
+                compression: self.compression,
             })?;
         }
         Ok(())
     }
 
-    fn check_flush_completion(&mut self) -> Result<()> {
+    fn open_sstable(&self, path: PathBuf) -> Result<SsTableHandle> {
+        SsTableHandle::open(path, Arc::clone(&self.table_cache))
+    }
+
+    fn flush_in_progress(&self) -> bool {
+        self.version.load().immutable_memtable.is_some()
+    }
+
+    fn new_memtable(&self) -> SharedMemTable {
+        Arc::new(RwLock::new(MemTable::new(self.memtable_max_size)))
+    }
+
+    fn check_flush_completion(&self, writer: &mut WriterState) -> Result<()> {
         // Collect results first to avoid holding reference to self.flush_rx
         let mut results = Vec::new();
         if let Some(ref rx) = self.flush_rx {
@@ -281,9 +1078,11 @@ impl StorageEngine {
 
         // Process results without holding any borrows
         for result in results {
-            let reader = SsTableReader::open(result.path)?;
-            self.sstables.push(reader);
-            self.immutable_memtable = None;
+            let reader = Arc::new(self.open_sstable(result.path)?);
+            self.install(writer, |version| {
+                version.sstables.push(reader);
+                version.immutable_memtable = None;
+            });
 
             // ✅ NEW: Clean up WAL after successful flush
             self.cleanup_wal_after_flush()?;
@@ -294,16 +1093,29 @@ impl StorageEngine {
         Ok(())
     }
 
-    fn flush_memtable_sync(&mut self) -> Result<()> {
-        if self.memtable.is_empty() {
+    fn flush_memtable_sync(&self, writer: &mut WriterState) -> Result<()> {
+        let memtable = Arc::clone(&self.version.load().memtable);
+        if memtable.read().is_empty() {
             return Ok(());
         }
         let sstable_id = self.sstable_counter.fetch_add(1, Ordering::SeqCst);
         let sstable_path = self.data_dir.join(format!("{:06}.sst", sstable_id));
-        let memtable_to_flush =
-            std::mem::replace(&mut self.memtable, MemTable::new(self.memtable_max_size));
-        Self::flush_memtable_to_disk(memtable_to_flush, &sstable_path)?;
-        self.sstables.push(SsTableReader::open(sstable_path)?);
+        Self::flush_memtable_to_disk(
+            &memtable.read(),
+            &sstable_path,
+            &self.snapshots.sequences(),
+            self.merge_operator.as_deref(),
+            self.series_mode,
+            self.compression,
+        )?;
+
+        // Swap the MemTable for its SSTable in one step
+        let reader = Arc::new(self.open_sstable(sstable_path)?);
+        let fresh = self.new_memtable();
+        self.install(writer, |version| {
+            version.memtable = fresh;
+            version.sstables.push(reader);
+        });
 
         // ✅ NEW: Clean up WAL after successful flush
         self.cleanup_wal_after_flush()?;
@@ -313,7 +1125,7 @@ impl StorageEngine {
     }
 
     /// ✅ NEW: WAL cleanup helper method
-    fn cleanup_wal_after_flush(&mut self) -> Result<()> {
+    fn cleanup_wal_after_flush(&self) -> Result<()> {
         println!("\n🧹 Starting WAL cleanup after flush...");
 
         // ✅ Lock WAL for all operations
@@ -340,90 +1152,636 @@ impl StorageEngine {
         Ok(())
     }
 
-    pub fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.get_at_sequence(key, SequenceNumber::MAX)
+    }
+
+    /// Read a key as of a snapshot
+    pub fn get_at(&self, snapshot: &Snapshot, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.get_at_sequence(key, snapshot.sequence())
+    }
+
+    fn get_at_sequence(&self, key: &[u8], seq: SequenceNumber) -> Result<Option<Vec<u8>>> {
         let start = Instant::now();
 
         // Track total read operation
         metrics().reads_total.inc();
 
-        // Check active memtable
-        if let Some(value) = self.memtable.get(key) {
-            metrics().reads_hits.inc();
-            metrics().read_latency.observe(start.elapsed());
-            return Ok(Some(value));
-        }
+        let found = self.get_record(key, seq)?;
 
-        // Check immutable memtable
-        if let Some(immut) = &self.immutable_memtable {
-            if let Some(value) = immut.get(key) {
+        // A tombstone means the key was deleted: report it as a miss
+        let value = match found {
+            Some(record) if record.op == OpType::Put => {
                 metrics().reads_hits.inc();
-                metrics().read_latency.observe(start.elapsed());
-                return Ok(Some(value));
+                Some(record.value)
+            }
+            _ => {
+                metrics().reads_misses.inc();
+                None
             }
+        };
+
+        metrics().read_latency.observe(start.elapsed());
+        Ok(value)
+    }
+
+    /// Find the newest record for a key among the writes up to `seq`
+    ///
+    /// Sources of the current version are searched newest to oldest, each
+    /// resolved against its own range tombstones (a covered key comes back
+    /// as a point tombstone). SSTables whose newest version is older than
+    /// what was already found, or whose key range does not hold the key,
+    /// are skipped without reading them. A newest
+    /// version that is a merge operand comes back folded into a put, and an
+    /// expired put comes back as a tombstone.
+    fn get_record(&self, key: &[u8], seq: SequenceNumber) -> Result<Option<Record>> {
+        let version = self.version.load();
+        let mut newest: Option<Record> = None;
+
+        // Check active memtable
+        keep_newer(&mut newest, version.memtable.read().get_record_at(key, seq));
+
+        // Check immutable memtable
+        if let Some(immut) = &version.immutable_memtable {
+            keep_newer(&mut newest, immut.read().get_record_at(key, seq));
         }
 
-        // Check SSTables (bloom filter check is inside sstable.get())
-        for sstable in self.sstables.iter_mut().rev() {
-            match sstable.get(key) {
-                Ok(Some((value, _timestamp))) => {
-                    metrics().reads_hits.inc();
-                    metrics().read_latency.observe(start.elapsed());
-                    return Ok(Some(value));
+        // Check SSTables (bloom filter check is inside sstable.get_record_at())
+        for sstable in version.sstables.iter().rev() {
+            if let Some(found) = &newest {
+                // Compaction outputs join the end of the list, so the list is
+                // not strictly ordered by age: skip rather than stop
+                if found.version() > (sstable.max_timestamp(), sstable.max_sequence()) {
+                    continue;
                 }
-                Ok(None) => {
+            }
+
+            // Outside the table's key range only its range tombstones apply
+            if !sstable.may_overlap(key, key) {
+                keep_newer(
+                    &mut newest,
+                    resolve_visible(key, None, sstable.range_tombstones(), seq),
+                );
+                continue;
+            }
+
+            // A damaged block is an error: an older table's value, or none
+            // at all, would be a wrong answer
+            let point = match sstable.get_record_at(key, seq)? {
+                Some(record) => Some(record),
+                None => {
                     // Bloom filter said "maybe" but key wasn't found
                     metrics().bloom_filter_false_positives.inc();
-                    continue;
+                    None
                 }
-                Err(crate::StorageError::CorruptedData(msg)) => {
-                    eprintln!("Warning: corrupted SSTable, skipping: {}", msg);
-                    continue;
+            };
+            let record = resolve_visible(key, point, sstable.range_tombstones(), seq);
+            keep_newer(&mut newest, record);
+        }
+
+        match newest {
+            Some(record) if record.op == OpType::Merge => self.resolve_merge(key, seq).map(Some),
+            Some(mut record) => {
+                record.expire(now_timestamp()?);
+                Ok(Some(record))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Fold the merge operands of a key into the value visible at `seq`
+    ///
+    /// The slow path of a read: every source may hold part of the chain, so
+    /// this goes through a merging iterator instead of stopping at the
+    /// newest version.
+    fn resolve_merge(&self, key: &[u8], seq: SequenceNumber) -> Result<Record> {
+        let mut iter = self.iter_at_sequence(seq, Some((key, key)));
+        iter.seek(key)?;
+        if iter.valid() && iter.key() == key {
+            Ok(Record::put(
+                key.to_vec(),
+                iter.value().to_vec(),
+                iter.timestamp(),
+            ))
+        } else {
+            Err(StorageError::MergeFailed(format!(
+                "operands of {:?} did not resolve to a value",
+                String::from_utf8_lossy(key)
+            )))
+        }
+    }
+
+    /// Lazy iterator over live keys, merging all MemTables and SSTables
+    ///
+    /// The iterator reads the state at the time of this call; later writes
+    /// are not visible through it. It starts unpositioned; call `seek` or
+    /// `seek_to_first`. The engine's own keys are left out, as in
+    /// [`StorageEngine::scan`].
+    pub fn iter(&self) -> DbIterator {
+        self.iter_at_sequence(self.last_sequence.load(Ordering::SeqCst), None)
+            .hiding(RESERVED_KEYSPACES)
+    }
+
+    /// Lazy iterator over the live keys as of a snapshot
+    pub fn iter_at(&self, snapshot: &Snapshot) -> DbIterator {
+        self.iter_at_sequence(snapshot.sequence(), None)
+            .hiding(RESERVED_KEYSPACES)
+    }
+
+    /// Merging iterator over the state at `seq`
+    ///
+    /// With `key_range` (inclusive), SSTables that cannot hold a key in it
+    /// are left out, so the iterator is only valid within that range.
+    fn iter_at_sequence(
+        &self,
+        seq: SequenceNumber,
+        key_range: Option<(&[u8], &[u8])>,
+    ) -> DbIterator {
+        let version = self.version.load_full();
+
+        // Sources newest first
+        let mut range_tombstones = version.memtable.read().range_tombstones().to_vec();
+        let mut sources = vec![SourceIterator::MemTable(SharedMemTableIterator::new(
+            Arc::clone(&version.memtable),
+        ))];
+        if let Some(imm) = &version.immutable_memtable {
+            range_tombstones.extend_from_slice(imm.read().range_tombstones());
+            sources.push(SourceIterator::MemTable(SharedMemTableIterator::new(
+                Arc::clone(imm),
+            )));
+        }
+        for sstable in version.sstables.iter().rev() {
+            range_tombstones.extend_from_slice(sstable.range_tombstones());
+            if key_range.is_some_and(|(start, end)| !sstable.may_overlap(start, end)) {
+                continue;
+            }
+            sources.push(SourceIterator::SsTable(Arc::clone(sstable), None));
+        }
+
+        DbIterator::new(
+            sources,
+            range_tombstones,
+            seq,
+            self.merge_operator.clone(),
+            now_timestamp().unwrap_or(0),
+        )
+    }
+
+    /// Scan live keys in `[start, end)`
+    ///
+    /// The engine's own keys are left out: rollup summaries (see
+    /// [`StorageEngine::with_rollup`]) and the series catalog and samples
+    /// (see [`StorageEngine::put_series`]).
+    pub fn scan(&self, start: &[u8], end: &[u8]) -> Result<Vec<ScanEntry>> {
+        self.scan_entries(start, end, RESERVED_KEYSPACES)
+    }
+
+    /// [`StorageEngine::scan`] leaving out the keys under `hidden`
+    fn scan_entries(&self, start: &[u8], end: &[u8], hidden: &[&[u8]]) -> Result<Vec<ScanEntry>> {
+        let mut results = Vec::new();
+
+        // SSTables that cannot hold a key in the range are skipped
+        let seq = self.last_sequence.load(Ordering::SeqCst);
+        let mut iter = self
+            .iter_at_sequence(seq, Some((start, end)))
+            .hiding(hidden);
+        iter.seek(start)?;
+        while iter.valid() && iter.key() < end {
+            results.push((iter.key().to_vec(), iter.value().to_vec(), iter.timestamp()));
+            iter.next()?;
+        }
+
+        Ok(results)
+    }
+
+    /// Samples of `key` with timestamps in `[start, end)`, oldest first
+    ///
+    /// Every version written to the key is a sample; a rewrite at the same
+    /// timestamp replaces it, and merge operands read as the value they fold
+    /// to. History before a delete (or an expired put) is gone. Flush and
+    /// compaction only keep history in series mode (see
+    /// [`StorageEngine::with_series_mode`]); otherwise older samples are only
+    /// found until they are flushed.
+    ///
+    /// SSTables that cannot affect the window are skipped, as in
+    /// [`StorageEngine::scan_time_range`].
+    pub fn range_query(
+        &self,
+        key: &[u8],
+        start: Timestamp,
+        end: Timestamp,
+    ) -> Result<Vec<(Timestamp, Vec<u8>)>> {
+        let window = TimeWindow::new(start, end, self.merge_operator.is_some());
+        let version = self.version.load();
+        let mut versions = version.memtable.read().versions_of(key);
+        let mut range_tombstones = version.memtable.read().range_tombstones().to_vec();
+        if let Some(imm) = &version.immutable_memtable {
+            versions.extend(imm.read().versions_of(key));
+            range_tombstones.extend_from_slice(imm.read().range_tombstones());
+        }
+        versions.retain(|record| window.wants(record));
+
+        let mut pruned = 0;
+        for sstable in version.sstables.iter() {
+            range_tombstones.extend_from_slice(sstable.range_tombstones());
+            if !sstable.may_overlap(key, key) {
+                continue;
+            }
+            if window.prunes(sstable) {
+                pruned += 1;
+                continue;
+            }
+            versions.extend(sstable.versions_of(key)?);
+        }
+        metrics().sstables_pruned.add(pruned);
+
+        self.history_samples(key, versions, &range_tombstones, &window)
+    }
+
+    /// Every sample with a key in `[start, end)` and a timestamp in
+    /// `[t_start, t_end)`, ordered by key, then oldest first
+    ///
+    /// Samples are read as in [`StorageEngine::range_query`], so without
+    /// series mode this is the newest value of each key whose timestamp
+    /// falls in the window, plus any history not yet flushed.
+    ///
+    /// Only versions that can affect the window are read. An SSTable whose
+    /// timestamps all fall after the window is skipped unless it may hold a
+    /// tombstone or expiring put hiding older samples, and one whose
+    /// timestamps all fall before it is skipped unless a merge operator is
+    /// registered (an operand in the window folds onto an older value).
+    /// MemTable entries are filtered the same way. Skipped tables are
+    /// counted in the `sstables_pruned` metric. The engine's own keys are
+    /// left out, as in [`StorageEngine::scan`].
+    pub fn scan_time_range(
+        &self,
+        start: &[u8],
+        end: &[u8],
+        t_start: Timestamp,
+        t_end: Timestamp,
+    ) -> Result<Vec<ScanEntry>> {
+        if start >= end || t_start >= t_end {
+            return Ok(Vec::new());
+        }
+        let window = TimeWindow::new(t_start, t_end, self.merge_operator.is_some());
+        let version = self.version.load();
+
+        let mut by_key: BTreeMap<Key, Vec<Record>> = BTreeMap::new();
+        let mut collect = |records: Vec<Record>| {
+            for record in records {
+                if record.key.as_slice() < end
+                    && !RESERVED_KEYSPACES
+                        .iter()
+                        .any(|keyspace| record.key.starts_with(keyspace))
+                    && window.wants(&record)
+                {
+                    by_key.entry(record.key.clone()).or_default().push(record);
                 }
-                Err(e) => return Err(e),
             }
+        };
+
+        let mut range_tombstones = version.memtable.read().range_tombstones().to_vec();
+        collect(version.memtable.read().versions_in(start, end));
+        if let Some(imm) = &version.immutable_memtable {
+            range_tombstones.extend_from_slice(imm.read().range_tombstones());
+            collect(imm.read().versions_in(start, end));
         }
 
-        // Key not found
-        metrics().reads_misses.inc();
-        metrics().read_latency.observe(start.elapsed());
-        Ok(None)
+        let mut pruned = 0;
+        for sstable in version.sstables.iter() {
+            range_tombstones.extend_from_slice(sstable.range_tombstones());
+            if !sstable.may_overlap(start, end) {
+                continue;
+            }
+            if window.prunes(sstable) {
+                pruned += 1;
+                continue;
+            }
+            // SSTable scans include their end key
+            collect(sstable.reader()?.scan_records(start, end)?);
+        }
+        metrics().sstables_pruned.add(pruned);
+
+        let mut results = Vec::new();
+        for (key, versions) in by_key {
+            let samples = self.history_samples(&key, versions, &range_tombstones, &window)?;
+            results.extend(
+                samples
+                    .into_iter()
+                    .map(|(timestamp, value)| (key.clone(), value, timestamp)),
+            );
+        }
+
+        Ok(results)
+    }
+
+    /// Downsample the samples matched by `query` into time buckets
+    ///
+    /// Values are parsed as numbers (others are skipped) and combined per
+    /// bucket; keys under a prefix are aggregated as one series. Only
+    /// non-empty buckets come back, oldest first. Where a rollup (see
+    /// [`StorageEngine::with_rollup`]) holds the same answer, its stored
+    /// summaries are read instead of the samples.
+    pub fn aggregate(&self, query: &AggregateQuery) -> Result<Vec<Bucket>> {
+        let mut aggregator = Aggregator::new(query);
+
+        // Samples from `raw_end` on are counted by the rollup
+        let mut raw_end = query.t_end;
+        if let Some((width, since)) = self.rollup_source(query)? {
+            self.aggregate_rollup(&mut aggregator, query, width, since)?;
+            raw_end = since;
+        }
+
+        let samples = self.scan_time_range(&query.start, &query.end, query.t_start, raw_end)?;
+        for (_, value, timestamp) in &samples {
+            aggregator.add(*timestamp, value);
+        }
+        Ok(aggregator.finish())
+    }
+
+    /// Coarsest rollup width that can answer `query`, and from when on
+    ///
+    /// A rollup can if one of its rules covers every key of the query, its
+    /// buckets nest in the query's and the window ends on its bucket
+    /// boundaries, and none of the keys is marked as no longer matching it.
+    /// It answers from its first whole bucket in the window past the rule's
+    /// start; the earliest start wins among rules of the same width.
+    fn rollup_source(&self, query: &AggregateQuery) -> Result<Option<(Timestamp, Timestamp)>> {
+        if !self.series_mode {
+            return Ok(None);
+        }
+        let aligned =
+            |t: Timestamp, width: Timestamp| t.is_multiple_of(width) || t == Timestamp::MAX;
+        let candidates: Vec<(&[u8], Timestamp)> = self
+            .rollups
+            .iter()
+            .filter(|rule| rule.covers(&query.start, &query.end))
+            .flat_map(|rule| {
+                let prefix = rule.prefix();
+                rule.resolutions().iter().map(move |&width| (prefix, width))
+            })
+            .filter(|&(_, width)| {
+                query.bucket_width == 0 || query.bucket_width.is_multiple_of(width)
+            })
+            .filter(|&(_, width)| aligned(query.t_end, width))
+            .collect();
+        if candidates.is_empty() || self.rollup_dirty(&query.start, &query.end)? {
+            return Ok(None);
+        }
+
+        let mut best: Option<(Timestamp, Timestamp)> = None;
+        for (prefix, width) in candidates {
+            let since = match self
+                .get_record(&since_key(prefix, width), SequenceNumber::MAX)?
+                .filter(|record| record.op == OpType::Put)
+            {
+                Some(record) => decode_since(&record.value)?,
+                None => continue,
+            };
+            let since = match since.max(query.t_start).checked_next_multiple_of(width) {
+                Some(since) if since < query.t_end => since,
+                _ => continue,
+            };
+            if best.is_none_or(|(w, s)| width > w || (width == w && since < s)) {
+                best = Some((width, since));
+            }
+        }
+        Ok(best)
+    }
+
+    /// Is any key in `[start, end)` marked as no longer matching its rollups?
+    fn rollup_dirty(&self, start: &[u8], end: &[u8]) -> Result<bool> {
+        let upper = if end.is_empty() {
+            prefix_successor(&dirty_key(b""))
+        } else {
+            dirty_key(end)
+        };
+        let markers = self.scan_entries(&dirty_key(b""), &upper, &[])?;
+        Ok(markers
+            .iter()
+            .any(|(_, marked_end, _)| marked_end.is_empty() || marked_end.as_slice() > start))
+    }
+
+    /// Add the rollup buckets `width` wide from `since` on to `aggregator`
+    fn aggregate_rollup(
+        &self,
+        aggregator: &mut Aggregator,
+        query: &AggregateQuery,
+        width: Timestamp,
+        since: Timestamp,
+    ) -> Result<()> {
+        let (lower, upper) = rollup_range(width, &query.start, &query.end);
+        for (derived, value, _) in self.scan_entries(&lower, &upper, &[])? {
+            let (_, key, bucket) = match parse_rollup_key(&derived) {
+                Some(parsed) => parsed,
+                None => continue,
+            };
+            let in_range = key >= query.start.as_slice() && key < query.end.as_slice();
+            if in_range && bucket >= since && bucket < query.t_end {
+                aggregator.add_summary(bucket, &decode_summary(&value)?);
+            }
+        }
+        Ok(())
+    }
+
+    /// ID of `series`, assigning the next one if it is new
+    ///
+    /// A new series is registered in one write: its name, its ID and its
+    /// entries in the tag index, so a crash never leaves it half indexed.
+    pub fn series_id(&self, series: &SeriesKey) -> Result<SeriesId> {
+        if let Some(&id) = self.series_ids.read().get(series) {
+            return Ok(id);
+        }
+        series.validate()?;
+
+        let name_key = series::name_key(series);
+        let id = match self.get(&name_key)? {
+            Some(stored) => decode_series_id(&stored)?,
+            None => self.register_series(series, name_key)?,
+        };
+
+        self.series_ids.write().insert(series.clone(), id);
+        Ok(id)
     }
 
-    pub fn scan(&mut self, start: &[u8], end: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>, u64)>> {
-        let mut results = self.memtable.scan_with_timestamps(start, end);
-        if let Some(ref imm) = self.immutable_memtable {
-            results.extend(imm.scan_with_timestamps(start, end));
+    /// Assign `series` the next ID and index it, unless another writer just did
+    fn register_series(&self, series: &SeriesKey, name_key: Key) -> Result<SeriesId> {
+        let mut writer = self.writer.lock();
+        if let Some(stored) = self.get(&name_key)? {
+            return decode_series_id(&stored);
         }
-        for sstable in self.sstables.iter_mut() {
-            results.extend(sstable.scan(start, end)?);
+
+        let id = match self.get(&series::next_id_key())? {
+            Some(stored) => decode_series_id(&stored)?,
+            None => 1,
+        };
+        let timestamp = self.next_timestamp(&writer)?;
+        let mut records = vec![
+            Record::put(name_key, id.to_be_bytes().to_vec(), timestamp),
+            Record::put(series::id_key(id), series.encode(), timestamp),
+            Record::put(
+                series::next_id_key(),
+                (id + 1).to_be_bytes().to_vec(),
+                timestamp,
+            ),
+        ];
+        records.extend(
+            series::index_keys(series, id)
+                .into_iter()
+                .map(|key| Record::put(key, Vec::new(), timestamp)),
+        );
+        self.commit_locked(&mut writer, records)?;
+        Ok(id)
+    }
+
+    /// Write a sample of `series` at `timestamp`, registering the series if new
+    ///
+    /// See [`StorageEngine::put_with_timestamp`].
+    pub fn put_series(
+        &self,
+        series: &SeriesKey,
+        value: Vec<u8>,
+        timestamp: Timestamp,
+    ) -> Result<SeriesId> {
+        let id = self.series_id(series)?;
+        self.put_at(series::data_key(id), value, timestamp)?;
+        Ok(id)
+    }
+
+    /// Series matching `filter`, by ID
+    ///
+    /// Each condition is looked up in the tag index and the ID lists are
+    /// intersected; no samples are read.
+    pub fn find_series(&self, filter: &SeriesFilter) -> Result<Vec<(SeriesId, SeriesKey)>> {
+        let prefixes = filter.index_prefixes();
+        let ids = if prefixes.is_empty() {
+            let (start, end) = series::id_key_range();
+            self.scan_entries(&start, &end, &[])?
+                .iter()
+                .filter_map(|(key, _, _)| series::parse_id(key))
+                .collect()
+        } else {
+            let mut lists = Vec::with_capacity(prefixes.len());
+            for prefix in &prefixes {
+                let list: Vec<SeriesId> = self
+                    .scan_entries(prefix, &prefix_successor(prefix), &[])?
+                    .iter()
+                    .filter_map(|(key, _, _)| series::parse_id(key))
+                    .collect();
+                lists.push(list);
+            }
+            series::intersect(lists)
+        };
+
+        let mut found = Vec::with_capacity(ids.len());
+        for id in ids {
+            if let Some(name) = self.get(&series::id_key(id))? {
+                found.push((id, SeriesKey::decode(&name)?));
+            }
+        }
+        Ok(found)
+    }
+
+    /// Samples in `[start, end)` of every series matching `filter`
+    ///
+    /// Each series is read as in [`StorageEngine::range_query`]; series
+    /// without samples in the window are left out.
+    pub fn series_range_query(
+        &self,
+        filter: &SeriesFilter,
+        start: Timestamp,
+        end: Timestamp,
+    ) -> Result<Vec<SeriesSamples>> {
+        let mut results = Vec::new();
+        for (id, series) in self.find_series(filter)? {
+            let samples = self.range_query(&series::data_key(id), start, end)?;
+            if !samples.is_empty() {
+                results.push((series, samples));
+            }
         }
-        results.sort_by(|a, b| a.0.cmp(&b.0).then(b.2.cmp(&a.2)));
-        results.dedup_by(|a, b| a.0 == b.0);
         Ok(results)
     }
 
+    /// Fold the versions of one key, from any number of sources, into its
+    /// samples within `window`, oldest first
+    fn history_samples(
+        &self,
+        key: &[u8],
+        mut versions: Vec<Record>,
+        range_tombstones: &[RangeTombstone],
+        window: &TimeWindow,
+    ) -> Result<Vec<(Timestamp, Vec<u8>)>> {
+        // Newest first, as if from a single source
+        versions.sort_by_key(|record| std::cmp::Reverse(record.version()));
+        versions.dedup_by_key(|record| record.version());
+        expire_versions(&mut versions, now_timestamp()?);
+
+        let covering: Vec<&RangeTombstone> = range_tombstones
+            .iter()
+            .filter(|rt| rt.covers(key))
+            .collect();
+        let history = history_versions(&versions, &covering);
+
+        // Oldest first, so each operand folds onto the sample before it
+        let mut samples = Vec::new();
+        let mut current: Option<Vec<u8>> = None;
+        for (record, _) in versions
+            .into_iter()
+            .zip(history)
+            .rev()
+            .filter(|(_, kept)| *kept)
+        {
+            current = match record.op {
+                OpType::Put => Some(record.value),
+                OpType::Merge => {
+                    let operator = self.require_merge_operator()?;
+                    let merged =
+                        operator.full_merge(key, current.as_deref(), &[record.value.as_slice()]);
+                    Some(merged.ok_or_else(|| {
+                        StorageError::MergeFailed(format!(
+                            "operand of {:?} at {} did not merge",
+                            String::from_utf8_lossy(key),
+                            record.timestamp
+                        ))
+                    })?)
+                }
+                _ => continue,
+            };
+            if window.contains(record.timestamp) {
+                let value = current.clone().unwrap_or_default();
+                // Operands on top of a put at the same timestamp update its sample
+                match samples.last_mut() {
+                    Some((ts, last)) if *ts == record.timestamp => *last = value,
+                    _ => samples.push((record.timestamp, value)),
+                }
+            }
+        }
+
+        Ok(samples)
+    }
+
     pub fn stats(&self) -> EngineStats {
+        let version = self.version.load();
+        let memtable = version.memtable.read();
         EngineStats {
-            memtable_entries: self.memtable.len(),
-            memtable_bytes: self.memtable.size_bytes(),
-            num_sstables: self.sstables.len(),
-            immutable_memtable_entries: self
+            memtable_entries: memtable.len(),
+            memtable_bytes: memtable.size_bytes(),
+            num_sstables: version.sstables.len(),
+            immutable_memtable_entries: version
                 .immutable_memtable
                 .as_ref()
-                .map(|m| m.len())
+                .map(|m| m.read().len())
                 .unwrap_or(0),
+            block_cache_bytes: self.table_cache.block_cache().size_bytes(),
+            open_sstables: self.table_cache.open_files(),
         }
     }
 
-    fn update_disk_usage(&mut self) {
+    fn update_disk_usage(&self) {
         let mut total = 0u64;
 
-        for sstable in &self.sstables {
-            // Get file size from filesystem
-            let info = sstable.info();
-            if let Ok(metadata) = std::fs::metadata(&info.path) {
-                total += metadata.len();
-            }
+        for sstable in &self.version.load().sstables {
+            total += sstable.file_size();
         }
 
         // Add WAL size
@@ -440,21 +1798,29 @@ impl StorageEngine {
     }
 
     /// Compaction logic (updated)
-    pub fn maybe_compact(&mut self) -> Result<()> {
+    pub fn maybe_compact(&self) -> Result<()> {
+        self.maybe_compact_locked(&mut self.writer.lock())
+    }
+
+    fn maybe_compact_locked(&self, writer: &mut WriterState) -> Result<()> {
         if !self.compaction_enabled {
             return Ok(());
         }
 
         let now = Instant::now();
-        if now.duration_since(self.last_compaction_check) < Duration::from_secs(1) {
+        if now.duration_since(writer.last_compaction_check) < Duration::from_secs(1) {
             return Ok(());
         }
-        self.last_compaction_check = now;
-
-        let sstable_paths: Vec<PathBuf> = std::fs::read_dir(&self.data_dir)?
-            .filter_map(|e| e.ok())
-            .map(|e| e.path())
-            .filter(|p| p.extension().and_then(|s| s.to_str()) == Some("sst"))
+        writer.last_compaction_check = now;
+
+        // Only tables in the current version: a directory listing would also
+        // pick up one the background flush is still writing
+        let sstable_paths: Vec<PathBuf> = self
+            .version
+            .load()
+            .sstables
+            .iter()
+            .map(|table| table.path().to_path_buf())
             .collect();
 
         println!(
@@ -482,68 +1848,136 @@ impl StorageEngine {
             to_compact.len()
         );
 
-        self.compact_sstables_sync(to_compact)?;
+        self.compact_sstables_sync(writer, to_compact)?;
 
         Ok(())
     }
 
-    fn compact_sstables_sync(&mut self, input_paths: Vec<PathBuf>) -> Result<()> {
+    fn compact_sstables_sync(
+        &self,
+        writer: &mut WriterState,
+        mut input_paths: Vec<PathBuf>,
+    ) -> Result<()> {
         println!("🗜️  Starting compaction of {} SSTables", input_paths.len());
 
+        // Merge order matters for range tombstones: oldest input first
+        input_paths.sort_by_key(|path| sstable_id(path).unwrap_or(0));
+
         let sstable_id = self.sstable_counter.fetch_add(1, Ordering::SeqCst);
         let output_path = self
             .data_dir
             .join(format!("{:06}_compacted.sst", sstable_id));
 
-        let stats = compact_sstables(&input_paths, output_path.clone())?;
-        let new_reader = SsTableReader::open(output_path)?;
+        // Tombstones may only be dropped when no table outside the compaction
+        // that is older than the newest input could still hold the key
+        let version = self.version.load_full();
+        let is_input = |table: &SsTableHandle| input_paths.iter().any(|p| p == table.path());
+        let is_loaded = |path: &PathBuf| version.sstables.iter().any(|t| t.path() == path);
+        let newest_input = if input_paths.iter().all(is_loaded) {
+            version
+                .sstables
+                .iter()
+                .rposition(|table| is_input(table))
+                .unwrap_or(0)
+        } else {
+            // Unknown inputs: treat every other table as potentially older
+            version.sstables.len()
+        };
+        let older_paths: Vec<PathBuf> = version.sstables[..newest_input]
+            .iter()
+            .filter(|table| !is_input(table))
+            .map(|table| table.path().to_path_buf())
+            .collect();
 
-        let before_count = self.sstables.len();
-        self.sstables
-            .retain(|reader| !input_paths.contains(&reader.info().path));
-        let after_count = self.sstables.len();
-        println!(
-            "📊 Removed {} old SSTables from list",
-            before_count - after_count
+        // Backfilled writes can leave versions older than the inputs'
+        // tombstones in newer tables and in the MemTables
+        let mut newer: Vec<Arc<dyn NewerSource>> = version
+            .sstables
+            .iter()
+            .skip(newest_input + 1)
+            .map(|table| Arc::clone(table) as Arc<dyn NewerSource>)
+            .collect();
+        newer.extend(
+            version
+                .immutable_memtable
+                .iter()
+                .chain(std::iter::once(&version.memtable))
+                .map(|memtable| Arc::clone(memtable) as Arc<dyn NewerSource>),
         );
 
-        self.sstables.push(new_reader);
+        // Nothing older outside the compaction: the output is the bottom
+        let compression = match self.bottommost_compression {
+            Some(compression) if older_paths.is_empty() => compression,
+            _ => self.compression,
+        };
+        let mut options = CompactionOptions::new()
+            .with_snapshots(self.snapshots.sequences())
+            .with_history(self.series_mode)
+            .with_newer(newer)
+            .with_compression(compression)
+            .with_zstd_dictionary(self.zstd_dictionary_size);
+        if let Some(operator) = &self.merge_operator {
+            options = options.with_merge_operator(Arc::clone(operator));
+        }
+        let stats = compact_sstables_with_options(
+            &input_paths,
+            &older_paths,
+            &options,
+            output_path.clone(),
+        )?;
+        let new_table = Arc::new(self.open_sstable(output_path)?);
+        drop(version);
+
+        let removed: Vec<Arc<SsTableHandle>> = self.install(writer, |version| {
+            let (removed, kept) = std::mem::take(&mut version.sstables)
+                .into_iter()
+                .partition(|table| is_input(table));
+            version.sstables = kept;
+            version.sstables.push(new_table);
+            removed
+        });
+        println!("📊 Removed {} old SSTables from list", removed.len());
         println!("➕ Added compacted SSTable to list");
 
-        for path in &input_paths {
-            match std::fs::remove_file(path) {
-                Ok(_) => println!("🗑️  Deleted: {:?}", path.file_name()),
-                Err(e) => eprintln!("⚠️  Failed to delete {:?}: {}", path, e),
-            }
+        // Each input file is deleted once the last version or reader still
+        // using it lets go: right away, unless a read is in flight
+        for table in &removed {
+            table.mark_obsolete();
         }
+        drop(removed);
 
         println!(
-            "✅ Compaction complete: {} → 1 SSTable, saved {}%",
+            "✅ Compaction complete: {} → 1 SSTable, saved {:.0}%",
             stats.input_sstables,
-            ((stats.input_bytes - stats.output_bytes) * 100 / stats.input_bytes)
+            stats.space_saved_percent()
         );
 
         Ok(())
     }
 
     /// Force compaction (for testing)
-    pub fn force_compact(&mut self) -> Result<()> {
-        self.last_compaction_check = Instant::now() - Duration::from_secs(10);
-        self.maybe_compact()
+    pub fn force_compact(&self) -> Result<()> {
+        let mut writer = self.writer.lock();
+        writer.last_compaction_check = Instant::now() - Duration::from_secs(10);
+        self.maybe_compact_locked(&mut writer)
     }
 
-    pub fn check_and_compact(&mut self) -> Result<()> {
-        self.check_flush_completion()?;
-        self.maybe_compact()?;
+    pub fn check_and_compact(&self) -> Result<()> {
+        self.check_and_compact_locked(&mut self.writer.lock())
+    }
+
+    fn check_and_compact_locked(&self, writer: &mut WriterState) -> Result<()> {
+        self.check_flush_completion(writer)?;
+        self.maybe_compact_locked(writer)?;
         Ok(())
     }
 
     pub fn sstable_count(&self) -> usize {
-        self.sstables.len()
+        self.version.load().sstables.len()
     }
 
     pub fn memtable_size(&self) -> usize {
-        self.memtable.size_bytes()
+        self.version.load().memtable.read().size_bytes()
     }
 
     /// Get formatted metrics summary
@@ -552,7 +1986,7 @@ impl StorageEngine {
     }
 
     /// Print metrics to stdout
-    pub fn print_metrics(&mut self) {
+    pub fn print_metrics(&self) {
         self.update_disk_usage();
         println!("{}", self.metrics());
     }
@@ -563,6 +1997,114 @@ impl StorageEngine {
     }
 }
 
+/// Time window `[start, end)` of a time-bounded read
+struct TimeWindow {
+    start: Timestamp,
+    end: Timestamp,
+    /// Versions before the window may be the base of an operand in it
+    needs_older: bool,
+}
+
+impl TimeWindow {
+    fn new(start: Timestamp, end: Timestamp, needs_older: bool) -> Self {
+        TimeWindow {
+            start,
+            end,
+            needs_older,
+        }
+    }
+
+    fn contains(&self, timestamp: Timestamp) -> bool {
+        (self.start..self.end).contains(&timestamp)
+    }
+
+    /// Can this version change what the window reads?
+    ///
+    /// Later versions only matter if they hide older ones.
+    fn wants(&self, record: &Record) -> bool {
+        if record.timestamp >= self.end {
+            record.is_tombstone() || record.ttl != 0
+        } else if record.timestamp < self.start {
+            self.needs_older
+        } else {
+            true
+        }
+    }
+
+    /// Can a whole SSTable be skipped by its timestamp range?
+    fn prunes(&self, sstable: &SsTableHandle) -> bool {
+        let after = sstable.min_timestamp() >= self.end && !sstable.may_shadow_older();
+        let before = sstable.max_timestamp() < self.start && !self.needs_older;
+        after || before
+    }
+}
+
+/// A TTL in timestamp units, never 0 (no expiry)
+fn ttl_nanos(ttl: Duration) -> Timestamp {
+    Timestamp::try_from(ttl.as_nanos())
+        .unwrap_or(Timestamp::MAX)
+        .max(1)
+}
+
+/// Replace `newest` with `candidate` if the candidate is a newer version
+fn keep_newer(newest: &mut Option<Record>, candidate: Option<Record>) {
+    if let Some(candidate) = candidate {
+        if newest
+            .as_ref()
+            .is_none_or(|found| candidate.version() > found.version())
+        {
+            *newest = Some(candidate);
+        }
+    }
+}
+
+/// Refuse a write to `key` if it falls in a reserved keyspace
+fn check_unreserved_key(key: &[u8]) -> Result<()> {
+    match RESERVED_KEYSPACES
+        .iter()
+        .find(|keyspace| key.starts_with(keyspace))
+    {
+        Some(keyspace) => Err(reserved(keyspace)),
+        None => Ok(()),
+    }
+}
+
+/// Refuse a range delete of `[start, end)` (an empty `end` is unbounded) if
+/// it reaches into a reserved keyspace
+fn check_unreserved_range(start: &[u8], end: &[u8]) -> Result<()> {
+    match RESERVED_KEYSPACES.iter().find(|keyspace| {
+        start < prefix_successor(keyspace).as_slice() && (end.is_empty() || end > **keyspace)
+    }) {
+        Some(keyspace) => Err(reserved(keyspace)),
+        None => Ok(()),
+    }
+}
+
+fn reserved(keyspace: &[u8]) -> StorageError {
+    StorageError::InvalidFormat(format!(
+        "Keys under {:?} are reserved",
+        String::from_utf8_lossy(keyspace)
+    ))
+}
+
+/// Parse a series ID stored by [`StorageEngine::series_id`]
+fn decode_series_id(stored: &[u8]) -> Result<SeriesId> {
+    stored
+        .try_into()
+        .map(SeriesId::from_be_bytes)
+        .map_err(|_| StorageError::InvalidFormat("Invalid series ID".into()))
+}
+
+/// Parse the numeric id from an SSTable file name
+///
+/// Handles both flushed (`000042.sst`) and compacted (`000042_compacted.sst`)
+/// tables, so compaction outputs keep their place in the age order on restart.
+fn sstable_id(path: &Path) -> Option<u64> {
+    let stem = path.file_stem()?.to_str()?;
+    let digits = stem.split('_').next()?;
+    digits.parse::<u64>().ok()
+}
+
 impl Drop for StorageEngine {
     fn drop(&mut self) {
         if let Some(ref tx) = self.flush_tx {
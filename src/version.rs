@@ -0,0 +1,22 @@
+//! Immutable view of the engine's data sources
+//!
+//! Readers load the current [`Version`] once and search it without taking
+//! any engine lock. Flush and compaction never modify a version in place:
+//! they build a new one and swap it in, so a reader keeps a consistent set
+//! of MemTables and SSTables for as long as it holds on to its version.
+//! SSTables dropped from the list stay readable through the old version
+//! until its last reader lets go; only then are their files deleted.
+
+use crate::memtable::SharedMemTable;
+use crate::sstable::SsTableHandle;
+use std::sync::Arc;
+
+#[derive(Clone)]
+pub(crate) struct Version {
+    /// Active MemTable, the only source still being written to
+    pub(crate) memtable: SharedMemTable,
+    /// MemTable being flushed in the background, if any
+    pub(crate) immutable_memtable: Option<SharedMemTable>,
+    /// SSTables, oldest first
+    pub(crate) sstables: Vec<Arc<SsTableHandle>>,
+}
@@ -15,7 +15,7 @@
 //! - **Cleanup**: After flush, delete all segments before flush point
 //! - **Recovery**: Replay all segments in order
 
-use crate::{Entry, OpType, Result, StorageError};
+use crate::{Entry, OpType, Record, Result, StorageError, NANOS_PER_SECOND};
 use bytes::{Buf, BufMut, BytesMut};
 use crc32fast::Hasher;
 use std::fs::{File, OpenOptions};
@@ -24,6 +24,24 @@ use std::path::{Path, PathBuf};
 
 const DEFAULT_SEGMENT_SIZE: usize = 100 * 1024 * 1024; // 100MB
 
+/// Set in the type byte when the record carries a sequence number.
+/// Records written before sequence numbers existed don't have it and
+/// are numbered on replay.
+const SEQUENCE_FLAG: u8 = 0x80;
+
+/// Type byte of the header record that starts a write batch group; its data
+/// is the number of member records that follow (u32)
+const BATCH_TYPE: u8 = 0x40;
+
+/// Set in the type byte when the record carries a TTL (u64, right after the
+/// timestamp). Records without one never expire.
+const TTL_FLAG: u8 = 0x20;
+
+/// Set in the type byte when the timestamp and TTL are in nanoseconds.
+/// Records written before nanosecond timestamps count in seconds and are
+/// scaled up on replay.
+const NANOS_FLAG: u8 = 0x10;
+
 pub struct Wal {
     dir: PathBuf,
     current_segment: WalSegment,
@@ -93,26 +111,46 @@ impl Wal {
     }
 
     pub fn append(&mut self, entry: &Entry) -> Result<()> {
-        self.append_operation(OpType::Put, entry)
+        self.append_record(&Record::put(
+            entry.key.clone(),
+            entry.value.clone(),
+            entry.timestamp,
+        ))
     }
 
-    pub fn append_delete(&mut self, key: &[u8], timestamp: u64) -> Result<()> {
-        let entry = Entry {
-            key: key.to_vec(),
-            value: Vec::new(),
-            timestamp,
-        };
-        self.append_operation(OpType::Delete, &entry)
+    /// Log any record (put, delete or range delete) with its sequence number
+    ///
+    /// A range delete stores its start in the key slot and its end in the
+    /// value slot.
+    pub fn append_record(&mut self, record: &Record) -> Result<()> {
+        // Check if rotation needed
+        if self.current_segment.should_rotate(self.segment_size_limit) {
+            self.rotate_segment()?;
+        }
+
+        let frame = encode_record(record)?;
+        self.current_segment.append(&frame)?;
+
+        Ok(())
     }
 
-    fn append_operation(&mut self, op_type: OpType, entry: &Entry) -> Result<()> {
-        // Check if rotation needed
+    /// Log a write batch as one record group
+    ///
+    /// A header record holding the member count is followed by the members,
+    /// all written to the same segment in one append. Recovery replays the
+    /// group only if every member is intact.
+    pub fn append_batch(&mut self, records: &[Record]) -> Result<()> {
         if self.current_segment.should_rotate(self.segment_size_limit) {
             self.rotate_segment()?;
         }
 
-        let record = encode_record(op_type, entry)?;
-        self.current_segment.append(&record)?;
+        let count = u32::try_from(records.len())
+            .map_err(|_| StorageError::InvalidFormat("Batch too large".into()))?;
+        let mut group = encode_frame(BATCH_TYPE, &count.to_le_bytes())?;
+        for record in records {
+            group.extend(encode_record(record)?);
+        }
+        self.current_segment.append(&group)?;
 
         Ok(())
     }
@@ -209,7 +247,15 @@ impl Wal {
         Ok(())
     }
 
+    /// Recover the entries written by `append`
+    ///
+    /// Deletions are skipped; use [`Wal::recover_records`] to replay them.
     pub fn recover(path: impl AsRef<Path>) -> Result<Vec<Entry>> {
+        Ok(puts_only(Self::recover_records(path)?))
+    }
+
+    /// Recover every record (puts and tombstones) in write order
+    pub fn recover_records(path: impl AsRef<Path>) -> Result<Vec<Record>> {
         let dir = path
             .as_ref()
             .parent()
@@ -232,31 +278,28 @@ impl Wal {
 
         segments.sort_by_key(|(num, _)| *num);
 
-        let mut entries = Vec::new();
+        let mut records = Vec::new();
         for (segment_num, path) in segments {
             println!("♻️  Recovering from segment {:06}.wal", segment_num);
 
-            let segment_entries = Self::recover_segment(&path)?;
-            entries.extend(segment_entries);
+            let segment_records = Self::recover_segment(&path)?;
+            records.extend(segment_records);
         }
 
-        if !entries.is_empty() {
-            println!("✅ Recovered {} entries from WAL", entries.len());
+        if !records.is_empty() {
+            println!("✅ Recovered {} entries from WAL", records.len());
         }
 
-        Ok(entries)
+        Ok(records)
     }
 
-    fn recover_segment(path: &Path) -> Result<Vec<Entry>> {
+    fn recover_segment(path: &Path) -> Result<Vec<Record>> {
         let mut file = File::open(path)?;
-        let mut entries = Vec::new();
+        let mut records = Vec::new();
 
         loop {
-            match read_record(&mut file) {
-                Ok(Some((op_type, entry))) => match op_type {
-                    OpType::Put => entries.push(entry),
-                    OpType::Delete => entries.push(entry),
-                },
+            match read_group(&mut file) {
+                Ok(Some(group)) => records.extend(group),
                 Ok(None) => break,
                 Err(StorageError::Corruption(msg)) => {
                     eprintln!("WAL corruption detected: {}", msg);
@@ -266,7 +309,7 @@ impl Wal {
             }
         }
 
-        Ok(entries)
+        Ok(records)
     }
 
     pub fn size(&self) -> Result<u64> {
@@ -317,7 +360,7 @@ impl Wal {
     /// * `segment_number` - The segment to read
     ///
     /// # Returns
-    /// * `Ok(Vec<Entry>)` - All entries from the segment
+    /// * `Ok(Vec<Entry>)` - All put entries from the segment
     /// * `Err` - If segment doesn't exist or is corrupted
     pub fn read_segment(&self, segment_number: u64) -> Result<Vec<Entry>> {
         let path = self.dir.join(format!("{:06}.wal", segment_number));
@@ -329,7 +372,7 @@ impl Wal {
             )));
         }
 
-        Self::recover_segment(&path)
+        Ok(puts_only(Self::recover_segment(&path)?))
     }
 
     /// Get current active segment number
@@ -395,23 +438,49 @@ impl Drop for Wal {
     }
 }
 
-fn encode_record(op_type: OpType, entry: &Entry) -> Result<Vec<u8>> {
+fn puts_only(records: Vec<Record>) -> Vec<Entry> {
+    records
+        .into_iter()
+        .filter(|r| r.op == OpType::Put)
+        .map(|r| Entry::new(r.key, r.value, r.timestamp))
+        .collect()
+}
+
+fn encode_record(record: &Record) -> Result<Vec<u8>> {
+    let key = record.key.as_slice();
+    let value = record.value.as_slice();
     let mut data = BytesMut::new();
 
-    data.put_u64_le(entry.timestamp);
+    // Unsequenced records keep the original layout
+    let mut type_byte = record.op as u8 | NANOS_FLAG;
+    if record.seq != 0 {
+        type_byte |= SEQUENCE_FLAG;
+        data.put_u64_le(record.seq);
+    }
+
+    data.put_u64_le(record.timestamp);
+    if record.ttl != 0 {
+        type_byte |= TTL_FLAG;
+        data.put_u64_le(record.ttl);
+    }
 
-    if entry.key.len() > u16::MAX as usize {
+    if key.len() > u16::MAX as usize {
         return Err(StorageError::InvalidFormat("Key too long".into()));
     }
-    data.put_u16_le(entry.key.len() as u16);
-    data.put_slice(&entry.key);
+    data.put_u16_le(key.len() as u16);
+    data.put_slice(key);
 
-    if entry.value.len() > u32::MAX as usize {
+    if value.len() > u32::MAX as usize {
         return Err(StorageError::InvalidFormat("Value too long".into()));
     }
-    data.put_u32_le(entry.value.len() as u32);
-    data.put_slice(&entry.value);
+    data.put_u32_le(value.len() as u32);
+    data.put_slice(value);
 
+    encode_frame(type_byte, &data)
+}
+
+/// Frame `data` with its checksum, length and type byte
+fn encode_frame(type_byte: u8, data: &[u8]) -> Result<Vec<u8>> {
     let data_len = data.len();
     if data_len > u16::MAX as usize {
         return Err(StorageError::InvalidFormat("Record too large".into()));
@@ -419,20 +488,57 @@ fn encode_record(op_type: OpType, entry: &Entry) -> Result<Vec<u8>> {
 
     let mut hasher = Hasher::new();
     hasher.update(&(data_len as u16).to_le_bytes());
-    hasher.update(&[op_type as u8]);
-    hasher.update(&data);
+    hasher.update(&[type_byte]);
+    hasher.update(data);
     let checksum = hasher.finalize();
 
     let mut record = BytesMut::with_capacity(4 + 2 + 1 + data_len);
     record.put_u32_le(checksum);
     record.put_u16_le(data_len as u16);
-    record.put_u8(op_type as u8);
-    record.put_slice(&data);
+    record.put_u8(type_byte);
+    record.put_slice(data);
 
     Ok(record.to_vec())
 }
 
-fn read_record(file: &mut File) -> Result<Option<(OpType, Entry)>> {
+/// Read the next record, or the whole group for a write batch
+fn read_group(file: &mut File) -> Result<Option<Vec<Record>>> {
+    let (type_byte, data) = match read_frame(file)? {
+        Some(frame) => frame,
+        None => return Ok(None),
+    };
+
+    if type_byte != BATCH_TYPE {
+        return Ok(Some(vec![decode_record(type_byte, &data)?]));
+    }
+
+    if data.len() < 4 {
+        return Err(StorageError::Corruption("Truncated batch header".into()));
+    }
+    let count = (&data[..]).get_u32_le() as usize;
+
+    // Every member must be intact, otherwise the batch never happened
+    let mut records = Vec::with_capacity(count);
+    for _ in 0..count {
+        match read_frame(file)? {
+            Some((type_byte, data)) if type_byte != BATCH_TYPE => {
+                records.push(decode_record(type_byte, &data)?);
+            }
+            _ => {
+                return Err(StorageError::Corruption(format!(
+                    "Incomplete batch: {} of {} records",
+                    records.len(),
+                    count
+                )));
+            }
+        }
+    }
+
+    Ok(Some(records))
+}
+
+/// Read one frame and verify its checksum
+fn read_frame(file: &mut File) -> Result<Option<(u8, Vec<u8>)>> {
     let mut header = [0u8; 7];
     match file.read_exact(&mut header) {
         Ok(_) => {}
@@ -447,11 +553,15 @@ fn read_record(file: &mut File) -> Result<Option<(OpType, Entry)>> {
     let length = buf.get_u16_le();
     let type_byte = buf.get_u8();
 
-    let op_type = OpType::from_u8(type_byte)
-        .ok_or_else(|| StorageError::InvalidFormat(format!("Invalid op type: {}", type_byte)))?;
-
     let mut data = vec![0u8; length as usize];
-    file.read_exact(&mut data)?;
+    match file.read_exact(&mut data) {
+        Ok(_) => {}
+        // Torn write at the tail of the segment
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+            return Err(StorageError::Corruption("Truncated record".into()));
+        }
+        Err(e) => return Err(e.into()),
+    }
 
     let mut hasher = Hasher::new();
     hasher.update(&length.to_le_bytes());
@@ -466,9 +576,31 @@ fn read_record(file: &mut File) -> Result<Option<(OpType, Entry)>> {
         )));
     }
 
-    let mut buf = &data[..];
+    Ok(Some((type_byte, data)))
+}
 
-    let timestamp = buf.get_u64_le();
+fn decode_record(type_byte: u8, data: &[u8]) -> Result<Record> {
+    let op_type = OpType::from_u8(type_byte & !(SEQUENCE_FLAG | TTL_FLAG | NANOS_FLAG))
+        .ok_or_else(|| StorageError::InvalidFormat(format!("Invalid op type: {}", type_byte)))?;
+
+    let mut buf = data;
+    let scale = if type_byte & NANOS_FLAG != 0 {
+        1
+    } else {
+        NANOS_PER_SECOND
+    };
+
+    let seq = if type_byte & SEQUENCE_FLAG != 0 {
+        buf.get_u64_le()
+    } else {
+        0
+    };
+    let timestamp = buf.get_u64_le().saturating_mul(scale);
+    let ttl = if type_byte & TTL_FLAG != 0 {
+        buf.get_u64_le().saturating_mul(scale)
+    } else {
+        0
+    };
 
     let key_len = buf.get_u16_le() as usize;
     if buf.remaining() < key_len {
@@ -483,18 +615,20 @@ fn read_record(file: &mut File) -> Result<Option<(OpType, Entry)>> {
     }
     let value = buf[..value_len].to_vec();
 
-    let entry = Entry {
+    Ok(Record {
         key,
         value,
         timestamp,
-    };
-
-    Ok(Some((op_type, entry)))
+        op: op_type,
+        seq,
+        ttl,
+    })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::RangeTombstone;
     use tempfile::tempdir;
 
     #[test]
@@ -668,4 +802,169 @@ mod tests {
 
         println!("\n✅ Replica-aware cleanup working!");
     }
+
+    #[test]
+    fn test_wal_recovers_tombstones() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.wal");
+
+        {
+            let mut wal = Wal::new(&path, 1024).unwrap();
+            wal.append(&Entry::new(b"key".to_vec(), b"value".to_vec(), 1000))
+                .unwrap();
+            wal.append_record(&Record::delete(b"key".to_vec(), 1001))
+                .unwrap();
+            wal.flush().unwrap();
+        }
+
+        let records = Wal::recover_records(&path).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].op, OpType::Put);
+        assert_eq!(records[1], Record::delete(b"key".to_vec(), 1001));
+
+        // The plain entry view must not resurrect the delete as an empty put
+        let entries = Wal::recover(&path).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].value, b"value");
+    }
+
+    #[test]
+    fn test_wal_recovers_range_deletes() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.wal");
+
+        {
+            let mut wal = Wal::new(&path, 1024).unwrap();
+            let tombstone = RangeTombstone::new(b"a".to_vec(), b"m".to_vec(), 1000);
+            wal.append_record(&Record::range_delete(tombstone)).unwrap();
+            wal.flush().unwrap();
+        }
+
+        let records = Wal::recover_records(&path).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].op, OpType::RangeDelete);
+        assert_eq!(
+            (records[0].key.as_slice(), records[0].value.as_slice()),
+            (&b"a"[..], &b"m"[..])
+        );
+        assert!(Wal::recover(&path).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_wal_recovers_sequence_numbers() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.wal");
+
+        {
+            let mut wal = Wal::new(&path, 1024).unwrap();
+            // Legacy layout, then two sequenced writes within the same second
+            wal.append(&Entry::new(b"key".to_vec(), b"v0".to_vec(), 1000))
+                .unwrap();
+            wal.append_record(&Record::put(b"key".to_vec(), b"v1".to_vec(), 1000).with_seq(7))
+                .unwrap();
+            wal.append_record(&Record::delete(b"key".to_vec(), 1000).with_seq(8))
+                .unwrap();
+            wal.flush().unwrap();
+        }
+
+        let records = Wal::recover_records(&path).unwrap();
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].seq, 0);
+        assert_eq!(
+            records[1],
+            Record::put(b"key".to_vec(), b"v1".to_vec(), 1000).with_seq(7)
+        );
+        assert_eq!(
+            records[2],
+            Record::delete(b"key".to_vec(), 1000).with_seq(8)
+        );
+    }
+
+    #[test]
+    fn test_wal_recovers_ttls() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.wal");
+
+        let expiring = Record::put(b"session".to_vec(), b"token".to_vec(), 1000)
+            .with_seq(1)
+            .with_ttl(60);
+        {
+            let mut wal = Wal::new(&path, 1024).unwrap();
+            wal.append_record(&expiring).unwrap();
+            wal.append_record(&Record::put(b"key".to_vec(), b"v".to_vec(), 1000).with_seq(2))
+                .unwrap();
+            wal.flush().unwrap();
+        }
+
+        let records = Wal::recover_records(&path).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0], expiring);
+        assert_eq!(records[1].ttl, 0);
+    }
+
+    #[test]
+    fn test_wal_scales_second_timestamps_to_nanoseconds() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.wal");
+
+        {
+            let mut wal = Wal::new(&path, 1024).unwrap();
+            wal.append_record(&Record::put(b"new".to_vec(), b"v".to_vec(), 1_500).with_seq(2))
+                .unwrap();
+            wal.flush().unwrap();
+        }
+
+        // A record in the layout used before nanosecond timestamps
+        let mut data = BytesMut::new();
+        data.put_u64_le(1);
+        data.put_u64_le(1_000);
+        data.put_u64_le(30);
+        data.put_u16_le(3);
+        data.put_slice(b"old");
+        data.put_u32_le(1);
+        data.put_slice(b"v");
+        let frame = encode_frame(OpType::Put as u8 | SEQUENCE_FLAG | TTL_FLAG, &data).unwrap();
+        let segment = dir.path().join("wal_segments").join("000001.wal");
+        let mut file = OpenOptions::new().append(true).open(&segment).unwrap();
+        file.write_all(&frame).unwrap();
+
+        let records = Wal::recover_records(&path).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].timestamp, 1_500);
+        assert_eq!(records[1].timestamp, 1_000 * NANOS_PER_SECOND);
+        assert_eq!(records[1].ttl, 30 * NANOS_PER_SECOND);
+    }
+
+    #[test]
+    fn test_wal_replays_whole_batches_only() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.wal");
+
+        let batch = vec![
+            Record::put(b"a".to_vec(), b"1".to_vec(), 1000).with_seq(2),
+            Record::delete(b"b".to_vec(), 1000).with_seq(3),
+            Record::put(b"c".to_vec(), b"3".to_vec(), 1000).with_seq(4),
+        ];
+        {
+            let mut wal = Wal::new(&path, 1024).unwrap();
+            wal.append_record(&Record::put(b"x".to_vec(), b"0".to_vec(), 999).with_seq(1))
+                .unwrap();
+            wal.append_batch(&batch).unwrap();
+            wal.flush().unwrap();
+        }
+
+        let records = Wal::recover_records(&path).unwrap();
+        assert_eq!(records.len(), 4);
+        assert_eq!(&records[1..], &batch[..]);
+
+        // Tear the last member: the whole batch is dropped, the write before it kept
+        let segment = dir.path().join("wal_segments").join("000001.wal");
+        let len = std::fs::metadata(&segment).unwrap().len();
+        let file = OpenOptions::new().write(true).open(&segment).unwrap();
+        file.set_len(len - 3).unwrap();
+
+        let records = Wal::recover_records(&path).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].key, b"x");
+    }
 }
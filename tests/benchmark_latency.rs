@@ -16,7 +16,7 @@ fn benchmark_write_latency() -> Result<()> {
     let wal_path = path.join("test.wal");
     let wal = Wal::new(&wal_path, 1024)?;
     let wal = Arc::new(RwLock::new(wal));
-    let mut engine = StorageEngine::new(path, 200, wal)?.with_compaction(false);
+    let engine = StorageEngine::new(path, 200, wal)?.with_compaction(false);
 
     let mut latencies = Vec::new();
 
@@ -1,4 +1,8 @@
-use cityhall::{Result, StorageEngine, Wal};
+use cityhall::{
+    AggregateQuery, Aggregation, AppendOperator, CompressionType, Result, RollupRule, SeriesFilter,
+    SeriesKey, SsTableReader, SsTableWriter, StorageEngine, StorageError, U64AddOperator, Wal,
+    WriteBatch, NANOS_PER_SECOND,
+};
 use parking_lot::RwLock;
 use std::sync::Arc;
 use std::thread;
@@ -14,7 +18,7 @@ fn test_basic_put_and_get() -> Result<()> {
     let wal_path = path.join("test.wal");
     let wal = Wal::new(&wal_path, 1024)?;
     let wal = Arc::new(RwLock::new(wal));
-    let mut engine = StorageEngine::new(path, 1024 * 1024, wal)?;
+    let engine = StorageEngine::new(path, 1024 * 1024, wal)?;
 
     engine.put(b"key1".to_vec(), b"value1".to_vec())?;
     engine.put(b"key2".to_vec(), b"value2".to_vec())?;
@@ -34,7 +38,7 @@ fn test_overwrite() -> Result<()> {
     let wal_path = path.join("test.wal");
     let wal = Wal::new(&wal_path, 1024)?;
     let wal = Arc::new(RwLock::new(wal));
-    let mut engine = StorageEngine::new(path, 1024 * 1024, wal)?;
+    let engine = StorageEngine::new(path, 1024 * 1024, wal)?;
 
     engine.put(b"key".to_vec(), b"value1".to_vec())?;
     assert_eq!(engine.get(b"key")?, Some(b"value1".to_vec()));
@@ -54,7 +58,7 @@ fn test_crash_recovery() -> Result<()> {
         let wal_path = dir_path.join("test.wal");
         let wal = Wal::new(&wal_path, 1024)?;
         let wal = Arc::new(RwLock::new(wal));
-        let mut engine = StorageEngine::new(dir_path.clone(), 1024 * 1024, wal)?;
+        let engine = StorageEngine::new(dir_path.clone(), 1024 * 1024, wal)?;
 
         engine.put(b"key1".to_vec(), b"value1".to_vec())?;
         engine.put(b"key2".to_vec(), b"value2".to_vec())?;
@@ -65,7 +69,7 @@ fn test_crash_recovery() -> Result<()> {
         let wal_path = dir_path.join("test.wal");
         let wal = Wal::new(&wal_path, 1024)?;
         let wal = Arc::new(RwLock::new(wal));
-        let mut engine = StorageEngine::new(dir_path.clone(), 1024 * 1024, wal)?;
+        let engine = StorageEngine::new(dir_path.clone(), 1024 * 1024, wal)?;
 
         assert_eq!(engine.get(b"key1")?, Some(b"value1".to_vec()));
         assert_eq!(engine.get(b"key2")?, Some(b"value2".to_vec()));
@@ -82,7 +86,7 @@ fn test_large_values() -> Result<()> {
     let wal_path = path.join("test.wal");
     let wal = Wal::new(&wal_path, 1024)?;
     let wal = Arc::new(RwLock::new(wal));
-    let mut engine = StorageEngine::new(path, 1024 * 1024, wal)?;
+    let engine = StorageEngine::new(path, 1024 * 1024, wal)?;
 
     let large_value = vec![42u8; 32 * 1024];
     engine.put(b"large1".to_vec(), large_value.clone())?;
@@ -101,7 +105,7 @@ fn test_many_small_writes() -> Result<()> {
     let wal_path = path.join("test.wal");
     let wal = Wal::new(&wal_path, 1024)?;
     let wal = Arc::new(RwLock::new(wal));
-    let mut engine = StorageEngine::new(path, 1024 * 1024, wal)?;
+    let engine = StorageEngine::new(path, 1024 * 1024, wal)?;
 
     for i in 0..1000 {
         let key = format!("key_{:04}", i);
@@ -128,7 +132,7 @@ fn test_memtable_flush() -> Result<()> {
     let wal_path = path.join("test.wal");
     let wal = Wal::new(&wal_path, 1024)?;
     let wal = Arc::new(RwLock::new(wal));
-    let mut engine = StorageEngine::new(path, 10 * 1024, wal)?;
+    let engine = StorageEngine::new(path, 10 * 1024, wal)?;
 
     for i in 0..1000 {
         let key = format!("key_{:04}", i);
@@ -149,7 +153,7 @@ fn test_empty_database() -> Result<()> {
     let wal_path = path.join("test.wal");
     let wal = Wal::new(&wal_path, 1024)?;
     let wal = Arc::new(RwLock::new(wal));
-    let mut engine = StorageEngine::new(path, 1024 * 1024, wal)?;
+    let engine = StorageEngine::new(path, 1024 * 1024, wal)?;
 
     assert_eq!(engine.get(b"any_key")?, None);
     Ok(())
@@ -162,7 +166,7 @@ fn test_binary_keys_and_values() -> Result<()> {
     let wal_path = path.join("test.wal");
     let wal = Wal::new(&wal_path, 1024)?;
     let wal = Arc::new(RwLock::new(wal));
-    let mut engine = StorageEngine::new(path, 1024 * 1024, wal)?;
+    let engine = StorageEngine::new(path, 1024 * 1024, wal)?;
 
     let binary_key = vec![0xFF, 0xFE, 0xFD, 0xFC];
     let binary_value = vec![0x00, 0x01, 0x02, 0x03, 0x04];
@@ -180,7 +184,7 @@ fn test_sequential_time_series_workload() -> Result<()> {
     let wal_path = path.join("test.wal");
     let wal = Wal::new(&wal_path, 1024)?;
     let wal = Arc::new(RwLock::new(wal));
-    let mut engine = StorageEngine::new(path, 1024 * 1024, wal)?;
+    let engine = StorageEngine::new(path, 1024 * 1024, wal)?;
 
     let base_time = SystemTime::now()
         .duration_since(SystemTime::UNIX_EPOCH)?
@@ -211,7 +215,7 @@ fn test_wal_memtable_integration() -> Result<()> {
         let wal_path = dir_path.join("test.wal");
         let wal = Wal::new(&wal_path, 1024)?;
         let wal = Arc::new(RwLock::new(wal));
-        let mut engine = StorageEngine::new(dir_path.clone(), 1024 * 1024, wal)?;
+        let engine = StorageEngine::new(dir_path.clone(), 1024 * 1024, wal)?;
 
         for i in 0..10 {
             let key = format!("metric_{}", i);
@@ -229,7 +233,7 @@ fn test_wal_memtable_integration() -> Result<()> {
         let wal_path = dir_path.join("test.wal");
         let wal = Wal::new(&wal_path, 1024)?;
         let wal = Arc::new(RwLock::new(wal));
-        let mut engine = StorageEngine::new(dir_path.clone(), 1024 * 1024, wal)?;
+        let engine = StorageEngine::new(dir_path.clone(), 1024 * 1024, wal)?;
 
         for i in 0..10 {
             let key = format!("metric_{}", i);
@@ -248,7 +252,7 @@ fn test_compaction_reduces_sstables() -> Result<()> {
     let wal_path = path.join("test.wal");
     let wal = Wal::new(&wal_path, 1024)?;
     let wal = Arc::new(RwLock::new(wal));
-    let mut engine = StorageEngine::new(path, 200, wal)?;
+    let engine = StorageEngine::new(path, 200, wal)?;
 
     for i in 0..100 {
         let key = format!("key{:04}", i);
@@ -290,7 +294,7 @@ fn test_compaction_removes_duplicates() -> Result<()> {
     let wal_path = path.join("test.wal");
     let wal = Wal::new(&wal_path, 1024)?;
     let wal = Arc::new(RwLock::new(wal));
-    let mut engine = StorageEngine::new(path, 200, wal)?;
+    let engine = StorageEngine::new(path, 200, wal)?;
 
     for i in 0..10 {
         engine.put(b"test".to_vec(), format!("version{}", i).into_bytes())?;
@@ -312,3 +316,1409 @@ fn test_compaction_removes_duplicates() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_delete_survives_flush_restart_and_compaction() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let path = temp_dir.path().to_path_buf();
+
+    {
+        let wal = Arc::new(RwLock::new(Wal::new(path.join("test.wal"), 1024)?));
+        let engine = StorageEngine::new_with_config(path.clone(), 200, wal, false)?;
+
+        for i in 0..40 {
+            let key = format!("key{:04}", i);
+            engine.put(key.into_bytes(), format!("value{}", i).into_bytes())?;
+        }
+        // Deletes land in a later SSTable than the puts they shadow
+        for i in 0..20 {
+            engine.delete(format!("key{:04}", i).into_bytes())?;
+        }
+
+        assert_eq!(engine.get(b"key0005")?, None);
+        assert_eq!(engine.get(b"key0025")?, Some(b"value25".to_vec()));
+        assert_eq!(engine.scan(b"key0000", b"key9999")?.len(), 20);
+    }
+
+    // Restart: tombstones come back from the WAL and SSTables
+    let wal = Arc::new(RwLock::new(Wal::new(path.join("test.wal"), 1024)?));
+    let engine = StorageEngine::new_with_config(path, 200, wal, false)?;
+    assert_eq!(engine.get(b"key0005")?, None);
+    assert_eq!(engine.get(b"key0025")?, Some(b"value25".to_vec()));
+
+    engine.force_compact()?;
+    for i in 0..20 {
+        assert_eq!(engine.get(format!("key{:04}", i).as_bytes())?, None);
+    }
+    for i in 20..40 {
+        let expected = format!("value{}", i).into_bytes();
+        assert_eq!(
+            engine.get(format!("key{:04}", i).as_bytes())?,
+            Some(expected)
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_same_second_overwrites_survive_flush_restart_and_compaction() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let path = temp_dir.path().to_path_buf();
+
+    {
+        let wal = Arc::new(RwLock::new(Wal::new(path.join("test.wal"), 1024)?));
+        let engine = StorageEngine::new_with_config(path.clone(), 200, wal, false)?;
+
+        // Rapid overwrites share a timestamp and end up spread over several
+        // SSTables plus the MemTable; only sequence numbers order them
+        for i in 0..30 {
+            engine.put(b"hot".to_vec(), format!("v{}", i).into_bytes())?;
+            engine.put(format!("filler{:02}", i).into_bytes(), vec![b'x'; 40])?;
+        }
+        assert!(engine.sstable_count() > 1);
+        assert_eq!(engine.get(b"hot")?, Some(b"v29".to_vec()));
+    }
+
+    // Restart: WAL records keep their sequence numbers, so the unflushed
+    // tail still beats the SSTables
+    let wal = Arc::new(RwLock::new(Wal::new(path.join("test.wal"), 1024)?));
+    let engine = StorageEngine::new_with_config(path, 200, wal, false)?;
+    assert_eq!(engine.get(b"hot")?, Some(b"v29".to_vec()));
+
+    // New writes are numbered after everything recovered
+    engine.put(b"hot".to_vec(), b"v30".to_vec())?;
+    engine.force_compact()?;
+    assert_eq!(engine.get(b"hot")?, Some(b"v30".to_vec()));
+
+    Ok(())
+}
+
+#[test]
+fn test_write_batch_survives_restart() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let path = temp_dir.path().to_path_buf();
+
+    {
+        let wal = Arc::new(RwLock::new(Wal::new(path.join("test.wal"), 1024)?));
+        let engine = StorageEngine::new_with_config(path.clone(), 1024 * 1024, wal, false)?;
+        engine.put(b"cpu:host2".to_vec(), b"7".to_vec())?;
+
+        let mut batch = WriteBatch::new();
+        batch.put(b"cpu:host1".to_vec(), b"41".to_vec());
+        batch.put(b"mem:host1".to_vec(), b"1024".to_vec());
+        batch.delete(b"cpu:host2".to_vec());
+        batch.put(b"cpu:host1".to_vec(), b"42".to_vec());
+        engine.write(batch)?;
+        engine.write(WriteBatch::new())?;
+
+        assert_eq!(engine.get(b"cpu:host1")?, Some(b"42".to_vec()));
+        assert_eq!(engine.get(b"cpu:host2")?, None);
+    }
+
+    // Restart: the batch is replayed from the WAL as a whole
+    let wal = Arc::new(RwLock::new(Wal::new(path.join("test.wal"), 1024)?));
+    let engine = StorageEngine::new_with_config(path, 1024 * 1024, wal, false)?;
+    assert_eq!(engine.get(b"cpu:host1")?, Some(b"42".to_vec()));
+    assert_eq!(engine.get(b"mem:host1")?, Some(b"1024".to_vec()));
+    assert_eq!(engine.get(b"cpu:host2")?, None);
+
+    Ok(())
+}
+
+#[test]
+fn test_range_delete_survives_flush_restart_and_compaction() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let path = temp_dir.path().to_path_buf();
+
+    {
+        let wal = Arc::new(RwLock::new(Wal::new(path.join("test.wal"), 1024)?));
+        let engine = StorageEngine::new_with_config(path.clone(), 200, wal, false)?;
+
+        for i in 0..20 {
+            engine.put(format!("user:{:04}", i).into_bytes(), b"old".to_vec())?;
+            engine.put(format!("video:{:04}", i).into_bytes(), b"kept".to_vec())?;
+        }
+        engine.delete_prefix(b"user:")?;
+        engine.delete_range(b"video:0000".to_vec(), b"video:0010".to_vec())?;
+        // Writes after the range delete stay visible
+        engine.put(b"user:0003".to_vec(), b"new".to_vec())?;
+
+        assert_eq!(engine.get(b"user:0005")?, None);
+        assert_eq!(engine.get(b"user:0003")?, Some(b"new".to_vec()));
+        assert_eq!(engine.get(b"video:0005")?, None);
+        assert_eq!(engine.get(b"video:0015")?, Some(b"kept".to_vec()));
+        assert_eq!(engine.scan(b"user:", b"video:9999")?.len(), 11);
+    }
+
+    // Restart: range tombstones come back from the SSTable meta blocks
+    let wal = Arc::new(RwLock::new(Wal::new(path.join("test.wal"), 1024)?));
+    let engine = StorageEngine::new_with_config(path, 200, wal, false)?;
+    assert_eq!(engine.get(b"user:0005")?, None);
+    assert_eq!(engine.get(b"user:0003")?, Some(b"new".to_vec()));
+
+    engine.force_compact()?;
+    assert_eq!(engine.get(b"user:0005")?, None);
+    assert_eq!(engine.get(b"user:0003")?, Some(b"new".to_vec()));
+    assert_eq!(engine.get(b"video:0015")?, Some(b"kept".to_vec()));
+    assert_eq!(engine.scan(b"user:", b"video:9999")?.len(), 11);
+
+    Ok(())
+}
+
+#[test]
+fn test_iterator_merges_memtable_and_sstables() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let path = temp_dir.path().to_path_buf();
+    let wal = Arc::new(RwLock::new(Wal::new(path.join("test.wal"), 1024)?));
+    let engine = StorageEngine::new_with_config(path, 500, wal, false)?.with_compaction(false);
+
+    // Old versions end up in SSTables, newer ones overwrite them later
+    for i in 0..30 {
+        engine.put(format!("key{:04}", i).into_bytes(), b"old".to_vec())?;
+    }
+    for i in (0..30).step_by(2) {
+        engine.put(format!("key{:04}", i).into_bytes(), b"new".to_vec())?;
+    }
+    engine.delete(b"key0001".to_vec())?;
+    assert!(engine.sstable_count() > 1);
+
+    let mut iter = engine.iter();
+    assert!(!iter.valid());
+
+    iter.seek_to_first()?;
+    let mut seen = Vec::new();
+    while iter.valid() {
+        seen.push((iter.key().to_vec(), iter.value().to_vec()));
+        iter.next()?;
+    }
+
+    // One entry per live key, in key order, newest value wins
+    assert_eq!(seen.len(), 29);
+    assert!(seen.windows(2).all(|w| w[0].0 < w[1].0));
+    assert_eq!(seen[0], (b"key0000".to_vec(), b"new".to_vec()));
+    assert_eq!(seen[1], (b"key0002".to_vec(), b"new".to_vec()));
+    assert_eq!(seen[2], (b"key0003".to_vec(), b"old".to_vec()));
+
+    // Seek and stop early, LIMIT-style
+    iter.seek(b"key0010")?;
+    let mut limited = Vec::new();
+    while iter.valid() && limited.len() < 3 {
+        limited.push(iter.key().to_vec());
+        iter.next()?;
+    }
+    assert_eq!(
+        limited,
+        vec![
+            b"key0010".to_vec(),
+            b"key0011".to_vec(),
+            b"key0012".to_vec()
+        ]
+    );
+
+    // Scans are half-open: [start, end)
+    assert_eq!(engine.scan(b"key0010", b"key0020")?.len(), 10);
+
+    Ok(())
+}
+
+#[test]
+fn test_snapshot_sees_consistent_state_through_flush_and_compaction() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let path = temp_dir.path().to_path_buf();
+    let wal = Arc::new(RwLock::new(Wal::new(path.join("test.wal"), 1024)?));
+    let engine = StorageEngine::new_with_config(path, 200, wal, false)?;
+
+    for i in 0..10 {
+        engine.put(format!("key{:02}", i).into_bytes(), b"before".to_vec())?;
+    }
+    let snapshot = engine.snapshot();
+
+    // Overwrite, delete and range-delete after the snapshot, forcing
+    // several flushes and a compaction along the way
+    for i in 0..10 {
+        engine.put(format!("key{:02}", i).into_bytes(), b"after".to_vec())?;
+    }
+    engine.delete(b"key03".to_vec())?;
+    engine.delete_prefix(b"key0")?;
+    engine.put(b"key10".to_vec(), b"new".to_vec())?;
+    let before_compaction = engine.sstable_count();
+    engine.force_compact()?;
+    assert!(engine.sstable_count() < before_compaction);
+
+    // The current state sees every later write
+    assert_eq!(engine.get(b"key03")?, None);
+    assert_eq!(engine.get(b"key05")?, None);
+    assert_eq!(engine.get(b"key10")?, Some(b"new".to_vec()));
+
+    // The snapshot still reads the state at the time it was taken
+    assert_eq!(
+        engine.get_at(&snapshot, b"key03")?,
+        Some(b"before".to_vec())
+    );
+    assert_eq!(engine.get_at(&snapshot, b"key10")?, None);
+
+    let mut iter = engine.iter_at(&snapshot);
+    iter.seek_to_first()?;
+    let mut seen = Vec::new();
+    while iter.valid() {
+        assert_eq!(iter.value(), b"before");
+        seen.push(iter.key().to_vec());
+        iter.next()?;
+    }
+    assert_eq!(seen.len(), 10);
+
+    Ok(())
+}
+
+#[test]
+fn test_concurrent_reads_during_writes_flushes_and_compaction() -> Result<()> {
+    let dir = tempdir()?;
+    let path = dir.path().to_path_buf();
+    let wal = Arc::new(RwLock::new(Wal::new(path.join("test.wal"), 1024)?));
+    let engine = Arc::new(StorageEngine::new(path, 500, wal)?);
+
+    // Keys every reader must always find, whatever the writer is doing
+    for i in 0..20 {
+        engine.put(format!("stable:{:02}", i).into_bytes(), b"v".to_vec())?;
+    }
+
+    let readers: Vec<_> = (0..4)
+        .map(|_| {
+            let engine = Arc::clone(&engine);
+            thread::spawn(move || -> Result<()> {
+                for round in 0..200 {
+                    let key = format!("stable:{:02}", round % 20);
+                    assert_eq!(engine.get(key.as_bytes())?, Some(b"v".to_vec()));
+
+                    let mut iter = engine.iter();
+                    iter.seek(b"stable:")?;
+                    let mut count = 0;
+                    while iter.valid() && iter.key().starts_with(b"stable:") {
+                        count += 1;
+                        iter.next()?;
+                    }
+                    assert_eq!(count, 20);
+                }
+                Ok(())
+            })
+        })
+        .collect();
+
+    // Enough writes to flush many times, compacting along the way
+    for i in 0..300 {
+        engine.put(format!("churn:{:03}", i).into_bytes(), vec![b'x'; 32])?;
+        if i % 100 == 99 {
+            engine.force_compact()?;
+        }
+    }
+
+    for reader in readers {
+        reader.join().expect("reader panicked")?;
+    }
+
+    assert_eq!(engine.get(b"churn:299")?, Some(vec![b'x'; 32]));
+    assert_eq!(engine.get(b"stable:00")?, Some(b"v".to_vec()));
+
+    Ok(())
+}
+
+#[test]
+fn test_block_cache_fills_on_reads_and_drops_compacted_tables() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let path = temp_dir.path().to_path_buf();
+    let wal = Arc::new(RwLock::new(Wal::new(path.join("test.wal"), 1024)?));
+    let engine = StorageEngine::new_with_config(path, 200, wal, false)?;
+
+    for i in 0..40 {
+        engine.put(format!("sensor:{:03}", i).into_bytes(), b"reading".to_vec())?;
+    }
+    assert!(engine.sstable_count() >= 4);
+    assert_eq!(engine.stats().block_cache_bytes, 0);
+
+    for i in 0..40 {
+        let key = format!("sensor:{:03}", i);
+        assert_eq!(engine.get(key.as_bytes())?, Some(b"reading".to_vec()));
+    }
+    assert!(engine.stats().block_cache_bytes > 0);
+
+    // The compacted inputs are deleted, and so are their cached blocks
+    engine.force_compact()?;
+    assert_eq!(engine.stats().block_cache_bytes, 0);
+    assert_eq!(engine.get(b"sensor:007")?, Some(b"reading".to_vec()));
+    assert!(engine.stats().block_cache_bytes > 0);
+
+    // A zero-sized cache keeps nothing
+    let engine = engine.with_block_cache_size(0);
+    assert_eq!(engine.stats().block_cache_bytes, 0);
+    assert_eq!(engine.get(b"sensor:008")?, Some(b"reading".to_vec()));
+    assert_eq!(engine.stats().block_cache_bytes, 0);
+
+    Ok(())
+}
+
+#[test]
+fn test_table_cache_bounds_open_files() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let path = temp_dir.path().to_path_buf();
+    let wal = Arc::new(RwLock::new(Wal::new(path.join("test.wal"), 1024)?));
+    let engine =
+        StorageEngine::new_with_config(path.clone(), 200, wal, false)?.with_max_open_files(2);
+
+    for i in 0..40 {
+        engine.put(format!("sensor:{:03}", i).into_bytes(), b"reading".to_vec())?;
+    }
+    assert!(engine.sstable_count() >= 4);
+    assert!(engine.stats().open_sstables <= 2);
+
+    // Every table is still readable, reopened on demand
+    for i in 0..40 {
+        let key = format!("sensor:{:03}", i);
+        assert_eq!(engine.get(key.as_bytes())?, Some(b"reading".to_vec()));
+    }
+    assert!(engine.stats().open_sstables <= 2);
+
+    // An iterator created before compaction keeps its tables' files alive,
+    // even though none of them are open yet
+    let mut iter = engine.iter();
+    engine.force_compact()?;
+    iter.seek_to_first()?;
+    let mut count = 0;
+    while iter.valid() {
+        count += 1;
+        iter.next()?;
+    }
+    assert_eq!(count, 40);
+
+    // Once it is gone, the compacted inputs are deleted
+    let sst_files = || -> Result<usize> {
+        Ok(std::fs::read_dir(&path)?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "sst"))
+            .count())
+    };
+    assert!(sst_files()? > engine.sstable_count());
+    drop(iter);
+    assert_eq!(sst_files()?, engine.sstable_count());
+
+    Ok(())
+}
+
+#[test]
+fn test_compare_and_swap_and_put_if_absent() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let path = temp_dir.path().to_path_buf();
+    let wal = Arc::new(RwLock::new(Wal::new(path.join("test.wal"), 1024)?));
+    let engine = StorageEngine::new_with_config(path, 200, wal, false)?;
+
+    assert!(engine.put_if_absent(b"device:1".to_vec(), b"owner-a".to_vec())?);
+    assert!(!engine.put_if_absent(b"device:1".to_vec(), b"owner-b".to_vec())?);
+    assert_eq!(engine.get(b"device:1")?, Some(b"owner-a".to_vec()));
+
+    // Mismatch leaves the value alone
+    assert!(!engine.compare_and_swap(b"device:1".to_vec(), Some(b"owner-b"), b"x".to_vec())?);
+    assert!(!engine.compare_and_swap(b"device:1".to_vec(), None, b"x".to_vec())?);
+    assert!(engine.compare_and_swap(
+        b"device:1".to_vec(),
+        Some(b"owner-a"),
+        b"owner-b".to_vec()
+    )?);
+    assert_eq!(engine.get(b"device:1")?, Some(b"owner-b".to_vec()));
+
+    // A deleted key counts as missing, also once flushed to SSTables
+    engine.delete(b"device:1".to_vec())?;
+    for i in 0..20 {
+        engine.put(format!("filler:{:02}", i).into_bytes(), b"v".to_vec())?;
+    }
+    assert!(engine.sstable_count() > 0);
+    assert!(!engine.compare_and_swap(b"device:1".to_vec(), Some(b"owner-b"), b"x".to_vec())?);
+    assert!(engine.put_if_absent(b"device:1".to_vec(), b"owner-c".to_vec())?);
+    assert!(!engine.put_if_absent(b"filler:00".to_vec(), b"x".to_vec())?);
+
+    Ok(())
+}
+
+#[test]
+fn test_concurrent_compare_and_swap_loses_no_updates() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let path = temp_dir.path().to_path_buf();
+    let wal = Arc::new(RwLock::new(Wal::new(path.join("test.wal"), 1024)?));
+    let engine = Arc::new(StorageEngine::new(path, 500, wal)?);
+    engine.put(b"counter".to_vec(), b"0".to_vec())?;
+
+    // Each thread increments the counter with a read / CAS retry loop
+    let workers: Vec<_> = (0..4)
+        .map(|_| {
+            let engine = Arc::clone(&engine);
+            thread::spawn(move || -> Result<()> {
+                for _ in 0..50 {
+                    loop {
+                        let current = engine.get(b"counter")?.expect("counter exists");
+                        let n: u64 = String::from_utf8_lossy(&current).parse().unwrap();
+                        let next = (n + 1).to_string().into_bytes();
+                        if engine.compare_and_swap(b"counter".to_vec(), Some(&current), next)? {
+                            break;
+                        }
+                    }
+                }
+                Ok(())
+            })
+        })
+        .collect();
+
+    for worker in workers {
+        worker.join().expect("worker panicked")?;
+    }
+
+    assert_eq!(engine.get(b"counter")?, Some(b"200".to_vec()));
+
+    Ok(())
+}
+
+#[test]
+fn test_merge_counters_survive_flush_compaction_and_restart() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let path = temp_dir.path().to_path_buf();
+    let open = || -> Result<StorageEngine> {
+        let wal = Arc::new(RwLock::new(Wal::new(path.join("test.wal"), 1024)?));
+        Ok(
+            StorageEngine::new_with_config(path.clone(), 200, wal, false)?
+                .with_merge_operator(Arc::new(U64AddOperator)),
+        )
+    };
+    let engine = open()?;
+
+    engine.put(b"hits".to_vec(), b"10".to_vec())?;
+    engine.merge(b"hits".to_vec(), b"5".to_vec())?;
+    assert_eq!(engine.get(b"hits")?, Some(b"15".to_vec()));
+
+    // Operands spread over the MemTable and several SSTables
+    let before = engine.snapshot();
+    for i in 0..30 {
+        engine.merge(b"hits".to_vec(), b"1".to_vec())?;
+        engine.put(format!("filler:{:02}", i).into_bytes(), b"v".to_vec())?;
+    }
+    assert!(engine.sstable_count() >= 4);
+    assert_eq!(engine.get(b"hits")?, Some(b"45".to_vec()));
+    assert_eq!(engine.get_at(&before, b"hits")?, Some(b"15".to_vec()));
+
+    engine.force_compact()?;
+    assert_eq!(engine.get(b"hits")?, Some(b"45".to_vec()));
+    assert_eq!(engine.get_at(&before, b"hits")?, Some(b"15".to_vec()));
+
+    // Scans fold operands too; a delete resets the counter
+    engine.merge(b"fresh".to_vec(), b"3".to_vec())?;
+    let counters = engine.scan(b"fresh", b"hitz")?;
+    let values: Vec<&[u8]> = counters.iter().map(|(_, v, _)| v.as_slice()).collect();
+    assert_eq!(values, vec![&b"3"[..], &b"45"[..]]);
+    engine.delete(b"hits".to_vec())?;
+    engine.merge(b"hits".to_vec(), b"2".to_vec())?;
+    assert_eq!(engine.get(b"hits")?, Some(b"2".to_vec()));
+
+    drop(before);
+    drop(engine);
+    let engine = open()?;
+    assert_eq!(engine.get(b"hits")?, Some(b"2".to_vec()));
+    assert_eq!(engine.get(b"fresh")?, Some(b"3".to_vec()));
+
+    Ok(())
+}
+
+#[test]
+fn test_merge_requires_an_operator() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let path = temp_dir.path().to_path_buf();
+    let wal = Arc::new(RwLock::new(Wal::new(path.join("test.wal"), 1024)?));
+    let engine = StorageEngine::new_with_config(path.clone(), 1024, wal, false)?;
+    assert!(engine.merge(b"k".to_vec(), b"1".to_vec()).is_err());
+
+    let mut batch = WriteBatch::new();
+    batch.put(b"a".to_vec(), b"1".to_vec());
+    batch.merge(b"k".to_vec(), b"1".to_vec());
+    assert!(engine.write(batch).is_err());
+    assert_eq!(engine.get(b"a")?, None);
+
+    let engine = engine.with_merge_operator(Arc::new(AppendOperator::new(b",")));
+    let mut batch = WriteBatch::new();
+    batch.put(b"log".to_vec(), b"boot".to_vec());
+    batch.merge(b"log".to_vec(), b"online".to_vec());
+    engine.write(batch)?;
+    engine.merge(b"log".to_vec(), b"alarm".to_vec())?;
+    assert_eq!(engine.get(b"log")?, Some(b"boot,online,alarm".to_vec()));
+
+    Ok(())
+}
+
+#[test]
+fn test_concurrent_merges_lose_no_increments() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let path = temp_dir.path().to_path_buf();
+    let wal = Arc::new(RwLock::new(Wal::new(path.join("test.wal"), 1024)?));
+    let engine =
+        Arc::new(StorageEngine::new(path, 500, wal)?.with_merge_operator(Arc::new(U64AddOperator)));
+
+    let workers: Vec<_> = (0..4)
+        .map(|_| {
+            let engine = Arc::clone(&engine);
+            thread::spawn(move || -> Result<()> {
+                for _ in 0..100 {
+                    engine.merge(b"counter".to_vec(), b"1".to_vec())?;
+                }
+                Ok(())
+            })
+        })
+        .collect();
+
+    for worker in workers {
+        worker.join().expect("worker panicked")?;
+    }
+
+    assert_eq!(engine.get(b"counter")?, Some(b"400".to_vec()));
+
+    Ok(())
+}
+
+#[test]
+fn test_ttl_hides_expired_keys_and_compaction_drops_them() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let path = temp_dir.path().to_path_buf();
+    let open = || -> Result<StorageEngine> {
+        let wal = Arc::new(RwLock::new(Wal::new(path.join("test.wal"), 1024)?));
+        Ok(
+            StorageEngine::new_with_config(path.clone(), 200, wal, false)?
+                .with_prefix_ttl(b"tmp:".to_vec(), Duration::from_secs(1))
+                .with_prefix_ttl(b"tmp:keep:".to_vec(), Duration::from_secs(3600)),
+        )
+    };
+    let engine = open()?;
+
+    engine.put_with_ttl(
+        b"session".to_vec(),
+        b"token".to_vec(),
+        Duration::from_secs(1),
+    )?;
+    engine.put(b"tmp:a".to_vec(), b"1".to_vec())?;
+    engine.put(b"tmp:keep:b".to_vec(), b"2".to_vec())?;
+    engine.put(b"durable".to_vec(), b"3".to_vec())?;
+    for i in 0..20 {
+        engine.put_with_ttl(
+            format!("tmp:{:02}", i).into_bytes(),
+            b"v".to_vec(),
+            Duration::from_secs(3600),
+        )?;
+    }
+    assert!(engine.sstable_count() >= 2);
+    assert_eq!(engine.get(b"session")?, Some(b"token".to_vec()));
+    assert_eq!(engine.get(b"tmp:a")?, Some(b"1".to_vec()));
+
+    // TTLs are whole seconds after the write's timestamp
+    thread::sleep(Duration::from_millis(2100));
+    assert_eq!(engine.get(b"session")?, None);
+    assert_eq!(engine.get(b"tmp:a")?, None);
+    assert_eq!(engine.get(b"tmp:keep:b")?, Some(b"2".to_vec()));
+    assert_eq!(engine.scan(b"tmp:", b"tmp:a~")?.len(), 20);
+
+    // Expired keys stay gone through compaction and restart
+    engine.force_compact()?;
+    assert_eq!(engine.get(b"session")?, None);
+    assert_eq!(engine.get(b"durable")?, Some(b"3".to_vec()));
+    drop(engine);
+    let engine = open()?;
+    assert_eq!(engine.get(b"tmp:a")?, None);
+    assert_eq!(engine.get(b"tmp:keep:b")?, Some(b"2".to_vec()));
+
+    Ok(())
+}
+
+#[test]
+fn test_backfilled_writes_order_by_timestamp_not_arrival() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let path = temp_dir.path().to_path_buf();
+    let open = || -> Result<StorageEngine> {
+        let wal = Arc::new(RwLock::new(Wal::new(path.join("test.wal"), 1024)?));
+        StorageEngine::new_with_config(path.clone(), 200, wal, false)
+    };
+    let engine = open()?;
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_nanos() as u64;
+
+    // A late-arriving older reading does not replace the current one
+    engine.put(b"temp".to_vec(), b"new".to_vec())?;
+    engine.put_with_timestamp(
+        b"temp".to_vec(),
+        b"old".to_vec(),
+        now - 3600 * NANOS_PER_SECOND,
+    )?;
+    assert_eq!(engine.get(b"temp")?, Some(b"new".to_vec()));
+
+    // Nor does it resurrect a key deleted after it
+    engine.put_with_timestamp(
+        b"door".to_vec(),
+        b"open".to_vec(),
+        now - 60 * NANOS_PER_SECOND,
+    )?;
+    engine.delete(b"door".to_vec())?;
+    engine.put_with_timestamp(
+        b"door".to_vec(),
+        b"closed".to_vec(),
+        now - 30 * NANOS_PER_SECOND,
+    )?;
+    assert_eq!(engine.get(b"door")?, None);
+
+    // Backfill history out of order, spread over several SSTables
+    for i in (0..30).map(|i| (i * 7) % 30) {
+        engine.put_with_timestamp(
+            format!("hist:{:02}", i).into_bytes(),
+            b"v".to_vec(),
+            1_000 + i,
+        )?;
+    }
+    assert!(engine.sstable_count() >= 2);
+    let history = engine.scan(b"hist:", b"hist;")?;
+    let timestamps: Vec<u64> = history.iter().map(|(_, _, ts)| *ts).collect();
+    assert_eq!(timestamps, (1_000..1_030).collect::<Vec<_>>());
+
+    // Table headers bracket the timestamps they hold, whatever the write order
+    let timestamp_range = || -> Result<(u64, u64)> {
+        let mut range = (u64::MAX, 0);
+        for entry in std::fs::read_dir(&path)? {
+            let file = entry?.path();
+            if file.extension().is_some_and(|ext| ext == "sst") {
+                let info = SsTableReader::open(file)?.info();
+                range = (
+                    range.0.min(info.min_timestamp),
+                    range.1.max(info.max_timestamp),
+                );
+            }
+        }
+        Ok(range)
+    };
+    assert_eq!(timestamp_range()?.0, 1_000);
+
+    assert!(engine
+        .put_with_timestamp(
+            b"temp".to_vec(),
+            b"future".to_vec(),
+            now + 3600 * NANOS_PER_SECOND,
+        )
+        .is_err());
+
+    engine.force_compact()?;
+    let (min, max) = timestamp_range()?;
+    assert_eq!(min, 1_000);
+    assert!(max >= now && max <= now + NANOS_PER_SECOND);
+    drop(engine);
+    let engine = open()?;
+    assert_eq!(engine.get(b"temp")?, Some(b"new".to_vec()));
+    assert_eq!(engine.get(b"door")?, None);
+    assert_eq!(engine.scan(b"hist:", b"hist;")?.len(), 30);
+
+    Ok(())
+}
+
+#[test]
+fn test_compaction_keeps_tombstones_hiding_backfilled_writes() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let path = temp_dir.path().to_path_buf();
+    let wal = Arc::new(RwLock::new(Wal::new(path.join("test.wal"), 1024)?));
+    // Uncompressed, so table sizes follow what is written
+    let engine = StorageEngine::new_with_config(path.clone(), 200, wal, false)?
+        .with_compression(CompressionType::None);
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_nanos() as u64;
+    let flush_with = |filler: &[u8]| -> Result<()> {
+        let tables = engine.sstable_count();
+        for i in 0.. {
+            engine.put(format!("filler:{}", i).into_bytes(), filler.to_vec())?;
+            if engine.sstable_count() > tables {
+                return Ok(());
+            }
+        }
+        unreachable!()
+    };
+
+    // Four similar tables, the first with the tombstones
+    engine.delete(b"door".to_vec())?;
+    engine.delete_range(b"gate:".to_vec(), b"gate;".to_vec())?;
+    for _ in 0..4 {
+        flush_with(b"x")?;
+    }
+
+    // Backfills older than the deletes: one in a newer table, too large to
+    // be compacted with them, and one still in the MemTable
+    engine.put_with_timestamp(
+        b"door".to_vec(),
+        b"closed".to_vec(),
+        now - 30 * NANOS_PER_SECOND,
+    )?;
+    flush_with(&[b'y'; 40_000])?;
+    engine.put_with_timestamp(
+        b"gate:1".to_vec(),
+        b"open".to_vec(),
+        now - 30 * NANOS_PER_SECOND,
+    )?;
+    let tables = engine.sstable_count();
+    assert_eq!(engine.get(b"door")?, None);
+    assert_eq!(engine.get(b"gate:1")?, None);
+
+    engine.force_compact()?;
+    assert!(engine.sstable_count() < tables);
+    assert_eq!(engine.get(b"door")?, None);
+    assert_eq!(engine.get(b"gate:1")?, None);
+
+    Ok(())
+}
+
+#[test]
+fn test_sub_second_samples_keep_their_order() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let path = temp_dir.path().to_path_buf();
+    let wal = Arc::new(RwLock::new(Wal::new(path.join("test.wal"), 1024)?));
+    let engine = StorageEngine::new_with_config(path.clone(), 200, wal, false)?;
+    let second = 1_700_000_000 * NANOS_PER_SECOND;
+
+    // Samples within one second, arriving out of order
+    for ms in [500, 250, 750, 100] {
+        engine.put_with_timestamp(
+            b"sensor:1".to_vec(),
+            format!("{}ms", ms).into_bytes(),
+            second + ms * 1_000_000,
+        )?;
+    }
+    assert_eq!(engine.get(b"sensor:1")?, Some(b"750ms".to_vec()));
+
+    // Engine-stamped writes in a tight loop get distinct, rising timestamps
+    for i in 0..20 {
+        engine.put(format!("tick:{:02}", i).into_bytes(), b"v".to_vec())?;
+    }
+    let ticks = engine.scan(b"tick:", b"tick;")?;
+    assert_eq!(ticks.len(), 20);
+    assert!(ticks.windows(2).all(|pair| pair[0].2 < pair[1].2));
+
+    Ok(())
+}
+
+#[test]
+fn test_series_mode_keeps_every_sample() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let path = temp_dir.path().to_path_buf();
+    let wal = Arc::new(RwLock::new(Wal::new(path.join("test.wal"), 1024)?));
+    let engine = StorageEngine::new_with_config(path.clone(), 300, wal, false)?
+        .with_series_mode(true)
+        .with_merge_operator(Arc::new(U64AddOperator));
+
+    // Samples arrive out of order and spill into several SSTables
+    for i in (0..40).map(|i| (i * 7) % 40) {
+        engine.put_with_timestamp(b"temp".to_vec(), i.to_string().into_bytes(), 1_000 + i)?;
+        engine.put_with_timestamp(b"noise".to_vec(), b"x".to_vec(), 1_000 + i)?;
+    }
+    // A late correction replaces the sample at its timestamp
+    engine.put_with_timestamp(b"temp".to_vec(), b"99".to_vec(), 1_010)?;
+    assert!(engine.sstable_count() >= 2);
+
+    let check = |engine: &StorageEngine| -> Result<()> {
+        let samples = engine.range_query(b"temp", 1_005, 1_015)?;
+        let expected: Vec<(u64, Vec<u8>)> = (5..15)
+            .map(|i| {
+                let value = if i == 10 {
+                    "99".to_string()
+                } else {
+                    i.to_string()
+                };
+                (1_000 + i, value.into_bytes())
+            })
+            .collect();
+        assert_eq!(samples, expected);
+        assert_eq!(engine.range_query(b"temp", 0, u64::MAX)?.len(), 40);
+        assert!(engine.range_query(b"temp", 2_000, 3_000)?.is_empty());
+        Ok(())
+    };
+    check(&engine)?;
+    engine.force_compact()?;
+    assert_eq!(engine.sstable_count(), 1);
+    check(&engine)?;
+
+    // Counter operands read as the running total at each sample
+    engine.put_with_timestamp(b"hits".to_vec(), b"10".to_vec(), 2_000)?;
+    engine.delete(b"hits".to_vec())?;
+    engine.put(b"hits".to_vec(), b"1".to_vec())?;
+    engine.merge(b"hits".to_vec(), b"2".to_vec())?;
+    let totals: Vec<Vec<u8>> = engine
+        .range_query(b"hits", 0, u64::MAX)?
+        .into_iter()
+        .map(|(_, value)| value)
+        .collect();
+    assert_eq!(totals.first(), Some(&b"1".to_vec()));
+    assert_eq!(totals.last(), Some(&b"3".to_vec()));
+    // History before the delete is gone
+    assert!(!totals.contains(&b"10".to_vec()));
+
+    Ok(())
+}
+
+#[test]
+fn test_time_range_scan_prunes_sstables() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let path = temp_dir.path().to_path_buf();
+    let wal = Arc::new(RwLock::new(Wal::new(path.join("test.wal"), 1024)?));
+    let engine = StorageEngine::new_with_config(path, 200, wal, false)?
+        .with_compaction(false)
+        .with_series_mode(true);
+
+    // One hour of history per SSTable, oldest first
+    for hour in 0..4u64 {
+        for i in 0..10u64 {
+            engine.put_with_timestamp(
+                format!("sensor:{}", i % 2).into_bytes(),
+                format!("{}-{}", hour, i).into_bytes(),
+                hour * 3_600 + i,
+            )?;
+        }
+    }
+    engine.put_with_timestamp(b"sensor:9".to_vec(), b"gone".to_vec(), 7_205)?;
+    engine.delete(b"sensor:9".to_vec())?;
+    assert!(engine.sstable_count() >= 4);
+
+    let pruned = || cityhall::metrics::metrics().sstables_pruned.get();
+    let before = pruned();
+    let entries = engine.scan_time_range(b"sensor:", b"sensor;", 7_200, 7_210)?;
+    let rows: Vec<(Vec<u8>, u64)> = entries.iter().map(|(k, _, ts)| (k.clone(), *ts)).collect();
+    let mut expected: Vec<(Vec<u8>, u64)> = (0..10u64)
+        .map(|i| (format!("sensor:{}", i % 2).into_bytes(), 7_200 + i))
+        .collect();
+    expected.sort();
+    // sensor:9 was deleted after the window, which still hides its samples
+    assert_eq!(rows, expected);
+    assert_eq!(entries[0].1, b"2-0".to_vec());
+    // At least the two tables entirely before the window were skipped
+    assert!(pruned() - before >= 2);
+
+    // Samples outside the key range or the window are left out
+    assert!(engine
+        .scan_time_range(b"sensor:", b"sensor;", 20_000, 30_000)?
+        .is_empty());
+    assert_eq!(
+        engine
+            .scan_time_range(b"sensor:1", b"sensor:2", 0, 3_600)?
+            .len(),
+        5
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_reads_skip_sstables_outside_their_key_range() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let path = temp_dir.path().to_path_buf();
+    let wal = Arc::new(RwLock::new(Wal::new(path.join("test.wal"), 1024)?));
+    let engine =
+        StorageEngine::new_with_config(path.clone(), 200, wal, false)?.with_compaction(false);
+
+    // Each prefix lands in its own SSTables
+    for prefix in ["a", "m", "x"] {
+        for i in 0..10 {
+            engine.put(format!("{}:{}", prefix, i).into_bytes(), b"v".to_vec())?;
+        }
+    }
+    // The range tombstone is flushed with later keys, outside its range
+    engine.delete_range(b"m:".to_vec(), b"m;".to_vec())?;
+    for i in 0..10 {
+        engine.put(format!("y:{}", i).into_bytes(), b"v".to_vec())?;
+    }
+    assert!(engine.sstable_count() >= 4);
+
+    // Every table records the keys it holds
+    for entry in std::fs::read_dir(&path)? {
+        let file = entry?.path();
+        if file.extension().is_some_and(|ext| ext == "sst") {
+            assert!(SsTableReader::open(file)?.key_range().is_some());
+        }
+    }
+
+    assert_eq!(engine.get(b"a:3")?, Some(b"v".to_vec()));
+    assert_eq!(engine.get(b"x:9")?, Some(b"v".to_vec()));
+    assert_eq!(engine.get(b"b:0")?, None);
+    // Skipping a table's data does not skip its range tombstones
+    assert_eq!(engine.get(b"m:3")?, None);
+    assert!(engine.scan(b"m:", b"m;")?.is_empty());
+    assert_eq!(engine.scan(b"x:", b"x;")?.len(), 10);
+    assert_eq!(engine.scan(b"a:", b"z")?.len(), 30);
+
+    Ok(())
+}
+
+#[test]
+fn test_aggregate_downsamples_history() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let path = temp_dir.path().to_path_buf();
+    let wal = Arc::new(RwLock::new(Wal::new(path.join("test.wal"), 1024)?));
+    let engine = StorageEngine::new_with_config(path, 300, wal, false)?.with_series_mode(true);
+
+    // Two hosts, one sample a minute for 20 minutes
+    let minute = 60 * NANOS_PER_SECOND;
+    for i in 0..20u64 {
+        for (host, load) in [("a", i), ("b", i + 100)] {
+            engine.put_with_timestamp(
+                format!("cpu.load.{}", host).into_bytes(),
+                load.to_string().into_bytes(),
+                i * minute,
+            )?;
+        }
+    }
+    assert!(engine.sstable_count() >= 2);
+
+    // Average per 5 minutes of host a, over the first 15 minutes
+    let query = AggregateQuery::key(b"cpu.load.a", Aggregation::Avg)
+        .with_time_range(0, 15 * minute)
+        .with_bucket_width(5 * minute);
+    let buckets: Vec<(u64, f64)> = engine
+        .aggregate(&query)?
+        .into_iter()
+        .map(|bucket| (bucket.start, bucket.value))
+        .collect();
+    assert_eq!(
+        buckets,
+        vec![(0, 2.0), (5 * minute, 7.0), (10 * minute, 12.0)]
+    );
+
+    // Both hosts as one series
+    let query = AggregateQuery::prefix(b"cpu.load.", Aggregation::Max)
+        .with_time_range(0, 20 * minute)
+        .with_bucket_width(10 * minute);
+    let buckets = engine.aggregate(&query)?;
+    assert_eq!(buckets.len(), 2);
+    assert_eq!((buckets[1].value, buckets[1].count), (119.0, 20));
+
+    Ok(())
+}
+
+#[test]
+fn test_rollups_answer_aligned_aggregations() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let path = temp_dir.path().to_path_buf();
+    let ms = NANOS_PER_SECOND / 1000;
+    let rule = RollupRule::new(b"system.")
+        .with_resolution(Duration::from_millis(10))
+        .with_resolution(Duration::from_millis(100));
+    let open = |rollup: bool| -> Result<StorageEngine> {
+        let wal = Arc::new(RwLock::new(Wal::new(path.join("test.wal"), 1024)?));
+        let engine =
+            StorageEngine::new_with_config(path.clone(), 4096, wal, false)?.with_series_mode(true);
+        Ok(if rollup {
+            engine.with_rollup(rule.clone())
+        } else {
+            engine
+        })
+    };
+    let now = || {
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos() as u64
+    };
+    let summary_key = |width: u64, timestamp: u64| {
+        [
+            cityhall::rollup::ROLLUP_KEYSPACE,
+            &width.to_be_bytes(),
+            b"system.cpu",
+            &(timestamp - timestamp % width).to_be_bytes(),
+        ]
+        .concat()
+    };
+
+    // Samples written before the rule exists are not rolled up...
+    let first = now() - 1000 * ms;
+    {
+        let engine = open(false)?;
+        for i in 0..50 {
+            engine.put_with_timestamp(b"system.cpu".to_vec(), b"1".to_vec(), first + i * ms)?;
+        }
+    }
+
+    // ...and those written once it does are
+    let mut last = 0;
+    {
+        let engine = open(true)?;
+        engine.put(b"system.mem".to_vec(), b"0".to_vec())?;
+        thread::sleep(Duration::from_millis(200));
+        for i in 0..50 {
+            thread::sleep(Duration::from_millis(1));
+            last = now();
+            engine.put_with_timestamp(
+                b"system.cpu".to_vec(),
+                (i % 5).to_string().into_bytes(),
+                last,
+            )?;
+        }
+        assert!(engine.sstable_count() >= 1);
+    }
+    let engine = open(true)?;
+    assert_eq!(engine.get(&summary_key(100 * ms, first))?, None);
+    assert!(engine.get(&summary_key(100 * ms, last))?.is_some());
+
+    // Older buckets are read raw, so answers cover every sample
+    let start = first - first % (100 * ms);
+    let end = last - last % (100 * ms) + 100 * ms;
+    let total = AggregateQuery::key(b"system.cpu", Aggregation::Sum).with_time_range(start, end);
+    let buckets = engine.aggregate(&total)?;
+    assert_eq!((buckets[0].count, buckets[0].value), (100, 150.0));
+
+    // Buckets agree with the raw samples whether a rollup tiles them or not
+    let samples = engine.range_query(b"system.cpu", start, end)?;
+    for width in [10, 20, 100, 15] {
+        let width = width * ms;
+        let mut expected: Vec<(u64, u64)> = Vec::new();
+        for (timestamp, _) in &samples {
+            let bucket = timestamp - timestamp % width;
+            match expected.last_mut() {
+                Some((start, count)) if *start == bucket => *count += 1,
+                _ => expected.push((bucket, 1)),
+            }
+        }
+        let query = AggregateQuery::key(b"system.cpu", Aggregation::Count)
+            .with_time_range(start, end)
+            .with_bucket_width(width);
+        let buckets: Vec<(u64, u64)> = engine
+            .aggregate(&query)?
+            .into_iter()
+            .map(|bucket| (bucket.start, bucket.count))
+            .collect();
+        assert_eq!(buckets, expected);
+    }
+
+    // Summaries stay out of scans and iterators
+    let keys: Vec<Vec<u8>> = engine
+        .scan(b"", b"\xff")?
+        .into_iter()
+        .map(|(key, _, _)| key)
+        .collect();
+    assert_eq!(keys, vec![b"system.cpu".to_vec(), b"system.mem".to_vec()]);
+    let mut iter = engine.iter();
+    iter.seek_to_first()?;
+    assert_eq!(iter.key(), b"system.cpu");
+    let mut keys: Vec<Vec<u8>> = engine
+        .scan_time_range(b"", b"\xff", 0, u64::MAX)?
+        .into_iter()
+        .map(|(key, _, _)| key)
+        .collect();
+    keys.dedup();
+    assert_eq!(keys, vec![b"system.cpu".to_vec(), b"system.mem".to_vec()]);
+
+    // A rewritten sample no longer matches the rollup, so the key is read raw
+    engine.put_with_timestamp(b"system.cpu".to_vec(), b"1000".to_vec(), last)?;
+    let buckets = engine.aggregate(&total)?;
+    assert_eq!((buckets[0].count, buckets[0].value), (100, 1146.0));
+
+    // Nor does a deleted one
+    engine.delete(b"system.cpu".to_vec())?;
+    assert!(engine.aggregate(&total)?.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_series_tag_index() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let path = temp_dir.path().to_path_buf();
+    let open = || -> Result<StorageEngine> {
+        let wal = Arc::new(RwLock::new(Wal::new(path.join("test.wal"), 1024)?));
+        Ok(StorageEngine::new_with_config(path.clone(), 1024, wal, false)?.with_series_mode(true))
+    };
+    let series = |metric: &str, host: &str, region: &str| {
+        SeriesKey::new(metric)
+            .with_tag("host", host)
+            .with_tag("region", region)
+    };
+
+    {
+        let engine = open()?;
+        for (i, host) in ["a", "b", "c"].iter().enumerate() {
+            let region = if i < 2 { "eu" } else { "us" };
+            for t in 1..=20u64 {
+                let value = (t * 10 + i as u64).to_string().into_bytes();
+                engine.put_series(&series("cpu", host, region), value.clone(), t)?;
+                engine.put_series(&series("mem", host, region), value, t)?;
+            }
+        }
+        assert_eq!(engine.series_id(&series("cpu", "a", "eu"))?, 1);
+        assert!(engine.sstable_count() >= 1);
+    }
+
+    // IDs and the index survive a restart
+    let engine = open()?;
+    assert_eq!(engine.series_id(&series("cpu", "a", "eu"))?, 1);
+    assert_eq!(engine.series_id(&series("disk", "a", "eu"))?, 7);
+
+    let names = |filter: &SeriesFilter| -> Result<Vec<String>> {
+        Ok(engine
+            .find_series(filter)?
+            .into_iter()
+            .map(|(_, series)| series.to_string())
+            .collect())
+    };
+    assert_eq!(
+        names(
+            &SeriesFilter::new()
+                .with_metric("cpu")
+                .with_tag("region", "eu")
+        )?,
+        vec!["cpu{host=a,region=eu}", "cpu{host=b,region=eu}"]
+    );
+    assert_eq!(
+        names(&SeriesFilter::new().with_tag("host", "c"))?,
+        vec!["cpu{host=c,region=us}", "mem{host=c,region=us}"]
+    );
+    assert!(names(&SeriesFilter::new().with_tag("host", "d"))?.is_empty());
+    assert_eq!(names(&SeriesFilter::new())?.len(), 7);
+
+    // `disk` has no samples, so it is left out of range queries
+    let results = engine.series_range_query(&SeriesFilter::new().with_tag("host", "a"), 5, 8)?;
+    assert_eq!(results.len(), 2);
+    assert_eq!(results[0].0, series("cpu", "a", "eu"));
+    assert_eq!(
+        results[0].1,
+        vec![
+            (5, b"50".to_vec()),
+            (6, b"60".to_vec()),
+            (7, b"70".to_vec())
+        ]
+    );
+
+    assert!(engine.series_id(&SeriesKey::new("")).is_err());
+
+    // The catalog and samples stay out of scans and iterators...
+    assert!(engine.scan(b"", b"\xff")?.is_empty());
+    assert!(engine.scan_time_range(b"", b"\xff", 0, 100)?.is_empty());
+    let mut iter = engine.iter();
+    iter.seek_to_first()?;
+    assert!(!iter.valid());
+
+    // ...and out of reach of plain writes and deletes
+    let reserved = |result: Result<()>| matches!(result, Err(StorageError::InvalidFormat(_)));
+    let data_key = [cityhall::series::SERIES_KEYSPACE, b"d"].concat();
+    assert!(reserved(engine.put(data_key.clone(), b"0".to_vec())));
+    assert!(reserved(engine.delete(data_key.clone())));
+    assert!(reserved(engine.delete_prefix(b"\x00")));
+    assert!(reserved(engine.delete_range(Vec::new(), Vec::new())));
+    let mut batch = WriteBatch::new();
+    batch.put(b"cpu".to_vec(), b"0".to_vec());
+    batch.delete(data_key);
+    assert!(reserved(engine.write(batch)));
+    assert_eq!(engine.get(b"cpu")?, None);
+    assert!(reserved(engine.put(
+        [cityhall::rollup::ROLLUP_KEYSPACE, b"x"].concat(),
+        b"0".to_vec()
+    )));
+    engine.delete_range(b"\x01".to_vec(), Vec::new())?;
+    assert_eq!(names(&SeriesFilter::new())?.len(), 7);
+
+    Ok(())
+}
+
+#[test]
+fn test_flush_and_bottommost_compression() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let path = temp_dir.path().to_path_buf();
+    let wal = Arc::new(RwLock::new(Wal::new(path.join("test.wal"), 1024)?));
+    let engine = StorageEngine::new_with_config(path.clone(), 200, wal, false)?
+        .with_compression(CompressionType::Lz4)
+        .with_bottommost_compression(CompressionType::Zstd);
+
+    for i in 0..40 {
+        engine.put(format!("key:{:02}", i).into_bytes(), b"value".to_vec())?;
+    }
+    assert!(engine.sstable_count() >= 2);
+
+    let codecs = || -> Result<Vec<CompressionType>> {
+        let mut codecs = Vec::new();
+        for entry in std::fs::read_dir(&path)? {
+            let file = entry?.path();
+            if file.extension().is_some_and(|ext| ext == "sst") {
+                codecs.push(SsTableReader::open(file)?.info().compression);
+            }
+        }
+        Ok(codecs)
+    };
+    assert!(codecs()?.contains(&CompressionType::Lz4));
+
+    // Compacting the oldest tables produces a bottom-most table
+    engine.force_compact()?;
+    assert!(codecs()?.contains(&CompressionType::Zstd));
+    assert_eq!(engine.get(b"key:07")?, Some(b"value".to_vec()));
+    assert_eq!(engine.scan(b"key:", b"key;")?.len(), 40);
+
+    Ok(())
+}
+
+#[test]
+fn test_compaction_trains_zstd_dictionary() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let path = temp_dir.path().to_path_buf();
+    let wal = Arc::new(RwLock::new(Wal::new(path.join("test.wal"), 1024)?));
+    let engine = StorageEngine::new_with_config(path.clone(), 32 * 1024, wal, false)?
+        .with_compression(CompressionType::Zstd)
+        .with_zstd_dictionary(4096);
+
+    for i in 0..4000 {
+        let key = format!("sensor.temp.building{:02}.room{:03}", i % 40, i / 40);
+        let value = format!("{{\"celsius\":{}.5,\"status\":\"ok\"}}", i % 30);
+        engine.put(key.into_bytes(), value.into_bytes())?;
+    }
+    assert!(engine.sstable_count() >= 4);
+    engine.force_compact()?;
+
+    let mut dictionaries = 0;
+    for entry in std::fs::read_dir(&path)? {
+        let file = entry?.path();
+        if file.extension().is_some_and(|ext| ext == "sst") {
+            let info = SsTableReader::open(file)?.info();
+            assert_eq!(info.compression, CompressionType::Zstd);
+            if info.dictionary_size > 0 {
+                dictionaries += 1;
+            }
+        }
+    }
+    // Flushes compress without one; compaction outputs train their own
+    assert!(dictionaries >= 1);
+
+    assert_eq!(
+        engine.get(b"sensor.temp.building07.room042")?,
+        Some(b"{\"celsius\":7.5,\"status\":\"ok\"}".to_vec())
+    );
+    assert_eq!(
+        engine
+            .scan(b"sensor.temp.building00", b"sensor.temp.building01")?
+            .len(),
+        100
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_open_moves_unreadable_sstables_aside() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let path = temp_dir.path().to_path_buf();
+    let open = || -> Result<StorageEngine> {
+        let wal = Arc::new(RwLock::new(Wal::new(path.join("test.wal"), 1024)?));
+        Ok(StorageEngine::new_with_config(path.clone(), 200, wal, false)?.with_compaction(false))
+    };
+
+    let tables = {
+        let engine = open()?;
+        for i in 0..20 {
+            engine.put(format!("key:{:02}", i).into_bytes(), b"value".to_vec())?;
+        }
+        engine.sstable_count()
+    };
+    assert!(tables >= 2);
+
+    // Damage the footer checksum of one table
+    let damaged = std::fs::read_dir(&path)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<Vec<_>>>()?
+        .into_iter()
+        .find(|file| file.extension().is_some_and(|ext| ext == "sst"))
+        .unwrap();
+    let mut data = std::fs::read(&damaged)?;
+    let last = data.len() - 1;
+    data[last] ^= 0x01;
+    std::fs::write(&damaged, data)?;
+
+    // The engine opens without it, and leaves it where it can be inspected
+    let engine = open()?;
+    assert_eq!(engine.sstable_count(), tables - 1);
+    assert!(!damaged.exists());
+    assert!(damaged.with_extension("sst.corrupt").exists());
+    drop(engine);
+    assert_eq!(open()?.sstable_count(), tables - 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_writes_win_after_clock_steps_back() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let path = temp_dir.path().to_path_buf();
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_nanos() as u64;
+
+    // Data written before the clock was set back an hour
+    let mut writer = SsTableWriter::new(path.join("000001.sst"), 4096)?;
+    writer.add(b"temp", b"old", now + 3600 * NANOS_PER_SECOND)?;
+    writer.finish()?;
+
+    let wal = Arc::new(RwLock::new(Wal::new(path.join("test.wal"), 1024)?));
+    let engine = StorageEngine::new_with_config(path.clone(), 200, wal, false)?;
+    assert_eq!(engine.get(b"temp")?, Some(b"old".to_vec()));
+
+    // Later writes still replace it, in the order they were made
+    engine.put(b"temp".to_vec(), b"new".to_vec())?;
+    assert_eq!(engine.get(b"temp")?, Some(b"new".to_vec()));
+    engine.delete(b"temp".to_vec())?;
+    assert_eq!(engine.get(b"temp")?, None);
+    engine.delete_prefix(b"te")?;
+    engine.put(b"temp".to_vec(), b"newest".to_vec())?;
+    assert_eq!(engine.get(b"temp")?, Some(b"newest".to_vec()));
+
+    Ok(())
+}
+
+#[test]
+fn test_reads_verify_block_checksums() -> Result<()> {
+    let temp_dir = TempDir::new()?;
+    let path = temp_dir.path().to_path_buf();
+    let wal = Arc::new(RwLock::new(Wal::new(path.join("test.wal"), 1024)?));
+    // Every read reopens its table and decodes the block from disk
+    let engine = StorageEngine::new_with_config(path.clone(), 200, wal, false)?
+        .with_compaction(false)
+        .with_compression(CompressionType::None)
+        .with_block_cache_size(0)
+        .with_max_open_files(0);
+
+    for i in 0..20 {
+        engine.put(
+            format!("key:{:02}", i).into_bytes(),
+            format!("value:{:02}", i).into_bytes(),
+        )?;
+    }
+    assert!(engine.sstable_count() >= 1);
+
+    // Flip a bit of a stored value, behind the engine's back
+    let mut corrupted = false;
+    for entry in std::fs::read_dir(&path)? {
+        let file = entry?.path();
+        if file.extension().is_some_and(|ext| ext == "sst") {
+            let mut data = std::fs::read(&file)?;
+            if let Some(at) = data.windows(8).position(|w| w == b"value:00") {
+                data[at] ^= 0x02;
+                std::fs::write(&file, data)?;
+                corrupted = true;
+            }
+        }
+    }
+    assert!(corrupted);
+
+    // The damaged block is reported, by point reads and scans alike
+    assert!(matches!(
+        engine.get(b"key:00"),
+        Err(StorageError::CorruptedData(_))
+    ));
+    assert!(matches!(
+        engine.scan(b"key:00", b"key:01"),
+        Err(StorageError::CorruptedData(_))
+    ));
+    assert_eq!(engine.get(b"key:19")?, Some(b"value:19".to_vec()));
+
+    let engine = engine.with_verify_checksums(false);
+    assert_eq!(engine.get(b"key:00")?, Some(b"talue:00".to_vec()));
+
+    Ok(())
+}
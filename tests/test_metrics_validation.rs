@@ -20,7 +20,7 @@ fn test_metrics_validation() {
     let wal_path = path.join("test.wal");
     let wal = Wal::new(&wal_path, 1024).unwrap();
     let wal = Arc::new(RwLock::new(wal));
-    let mut engine = StorageEngine::new(path, 1024 * 1024, wal).unwrap();
+    let engine = StorageEngine::new(path, 1024 * 1024, wal).unwrap();
     metrics().reset();
 
     // ========================================================================
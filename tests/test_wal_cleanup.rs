@@ -16,7 +16,7 @@ fn test_wal_cleanup() {
     let wal_path = path.join("test.wal");
     let wal = Wal::new(&wal_path, 1024).unwrap();
     let wal = Arc::new(RwLock::new(wal));
-    let mut engine = StorageEngine::new(path, memtable_size, wal).unwrap();
+    let engine = StorageEngine::new(path, memtable_size, wal).unwrap();
 
     // Reset metrics
     metrics().reset();
@@ -129,7 +129,7 @@ fn test_wal_recovery_after_cleanup() {
         let wal_path = path.join("test.wal");
         let wal = Wal::new(&wal_path, 1024).unwrap();
         let wal = Arc::new(RwLock::new(wal));
-        let mut engine = StorageEngine::new(path, memtable_size, wal).unwrap();
+        let engine = StorageEngine::new(path, memtable_size, wal).unwrap();
 
         // Write first batch
         for i in 0..1000 {
@@ -172,7 +172,7 @@ fn test_wal_recovery_after_cleanup() {
         let wal_path = path.join("test.wal");
         let wal = Wal::new(&wal_path, 1024).unwrap();
         let wal = Arc::new(RwLock::new(wal));
-        let mut engine = StorageEngine::new(path, memtable_size, wal).unwrap();
+        let engine = StorageEngine::new(path, memtable_size, wal).unwrap();
 
         // Should recover data from remaining WAL segments
         println!("  Checking recovered data...");
@@ -218,7 +218,7 @@ fn test_wal_segment_rotation() {
     let wal_path = path.join("test.wal");
     let wal = Wal::new(&wal_path, 1024).unwrap();
     let wal = Arc::new(RwLock::new(wal));
-    let mut engine = StorageEngine::new(path, memtable_size, wal).unwrap();
+    let engine = StorageEngine::new(path, memtable_size, wal).unwrap();
 
     println!("\n=== Testing WAL Segment Rotation ===\n");
 
@@ -15,7 +15,7 @@ fn test_wal_diagnostic() {
     let wal_path = path.join("test.wal");
     let wal = Wal::new(&wal_path, 1024).unwrap();
     let wal = Arc::new(RwLock::new(wal));
-    let mut engine = StorageEngine::new(path, memtable_size, wal).unwrap();
+    let engine = StorageEngine::new(path, memtable_size, wal).unwrap();
 
     println!("\n=== WAL Diagnostic Test ===\n");
 
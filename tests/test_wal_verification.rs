@@ -26,7 +26,7 @@ fn test_wal_success_criteria() {
         let wal_path = path.join("test.wal");
         let wal = Wal::new(&wal_path, 1024).unwrap();
         let wal = Arc::new(RwLock::new(wal));
-        let mut engine = StorageEngine::new(path.clone(), memtable_size, wal).unwrap();
+        let engine = StorageEngine::new(path.clone(), memtable_size, wal).unwrap();
         metrics().reset();
 
         // Write ~120MB to force rotation
@@ -59,7 +59,7 @@ fn test_wal_success_criteria() {
         let wal_path = path.join("test.wal");
         let wal = Wal::new(&wal_path, 1024).unwrap();
         let wal = Arc::new(RwLock::new(wal));
-        let mut engine = StorageEngine::new(path.clone(), memtable_size, wal).unwrap();
+        let engine = StorageEngine::new(path.clone(), memtable_size, wal).unwrap();
 
         // Write enough to create multiple segments (120MB)
         for i in 0..60_000 {
@@ -107,7 +107,7 @@ fn test_wal_success_criteria() {
         let wal_path = path.join("test.wal");
         let wal = Wal::new(&wal_path, 1024).unwrap();
         let wal = Arc::new(RwLock::new(wal));
-        let mut engine = StorageEngine::new(path.clone(), memtable_size, wal).unwrap();
+        let engine = StorageEngine::new(path.clone(), memtable_size, wal).unwrap();
         metrics().reset();
 
         // Write 500MB of data with periodic flushes
@@ -149,7 +149,7 @@ fn test_wal_success_criteria() {
             let wal_path = dir_path.join("test.wal");
             let wal = Wal::new(&wal_path, 1024).unwrap();
             let wal = Arc::new(RwLock::new(wal));
-            let mut engine = StorageEngine::new(dir_path.clone(), memtable_size, wal).unwrap();
+            let engine = StorageEngine::new(dir_path.clone(), memtable_size, wal).unwrap();
 
             for i in 0..5_000 {
                 engine
@@ -179,7 +179,7 @@ fn test_wal_success_criteria() {
             let wal_path = dir_path.join("test.wal");
             let wal = Wal::new(&wal_path, 1024).unwrap();
             let wal = Arc::new(RwLock::new(wal));
-            let mut engine = StorageEngine::new(dir_path.clone(), memtable_size, wal).unwrap();
+            let engine = StorageEngine::new(dir_path.clone(), memtable_size, wal).unwrap();
 
             let mut recovered = 0;
             for i in 5_000..10_000 {
@@ -212,7 +212,7 @@ fn test_wal_success_criteria() {
         let wal_path = path.join("test.wal");
         let wal = Wal::new(&wal_path, 1024).unwrap();
         let wal = Arc::new(RwLock::new(wal));
-        let mut engine = StorageEngine::new(path.clone(), memtable_size, wal).unwrap();
+        let engine = StorageEngine::new(path.clone(), memtable_size, wal).unwrap();
         metrics().reset();
 
         for i in 0..10_000 {